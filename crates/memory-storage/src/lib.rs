@@ -1,9 +1,10 @@
 use anyhow::bail;
 use essential_lock::StdLock;
+use essential_server_types::state_root::StateTree;
 use essential_state_read_vm::StateRead;
 use essential_storage::{
     failed_solution::{CheckOutcome, FailedSolution, SolutionFailReason, SolutionOutcomes},
-    key_range, CommitData, QueryState, StateStorage, Storage,
+    key_range, BlockHeader, CommitData, QueryState, StateChange, StateStorage, Storage,
 };
 use essential_types::{
     contract::{Contract, SignedContract},
@@ -12,14 +13,20 @@ use essential_types::{
     ContentAddress, Hash, Key, PredicateAddress, Signature, Word,
 };
 use futures::{future::FutureExt, StreamExt};
+use persist::{Op, PendingSnapshot, Wal};
+use serde::{Deserialize, Serialize};
 use std::{
     collections::{BTreeMap, HashMap, HashSet},
+    ops::Bound,
     pin::Pin,
     sync::Arc,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use thiserror::Error;
 
+pub use persist::PersistenceConfig;
+
+mod persist;
 mod values;
 
 /// Amount of values returned in a single page.
@@ -28,6 +35,7 @@ const PAGE_SIZE: usize = 100;
 #[derive(Clone)]
 pub struct MemoryStorage {
     inner: Arc<StdLock<Inner>>,
+    wal: Option<Arc<StdLock<Wal>>>,
     streams: essential_storage::streams::Notify,
 }
 
@@ -37,7 +45,7 @@ impl Default for MemoryStorage {
     }
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Serialize, Deserialize)]
 struct Inner {
     contracts: HashMap<ContentAddress, ContractWithAddresses>,
     predicates: HashMap<ContentAddress, Predicate>,
@@ -52,16 +60,35 @@ struct Inner {
     block_number_index: HashMap<u64, Duration>,
     solution_block_time_index: HashMap<Hash, Vec<Duration>>,
     state: HashMap<ContentAddress, BTreeMap<Key, Vec<Word>>>,
+    /// Every value written to state by the block number it is part of.
+    state_history: HashMap<ContentAddress, BTreeMap<Key, BTreeMap<u64, Vec<Word>>>>,
+    /// The tree of the current state.
+    ///
+    /// Rebuilt from `state` when a snapshot is read.
+    #[serde(skip)]
+    state_tree: StateTree,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 struct Block {
     number: u64,
     timestamp: Duration,
     hashes: Vec<Hash>,
+    gas_used: u64,
+    state_root: Hash,
 }
 
-#[derive(Debug)]
+/// A block that is about to be committed.
+///
+/// The state root isn't known until the state updates are applied.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct NewBlock {
+    number: u64,
+    timestamp: Duration,
+    gas_used: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 struct ContractWithAddresses {
     salt: Hash,
     data: HashSet<ContentAddress>,
@@ -104,9 +131,73 @@ impl MemoryStorage {
     pub fn new() -> Self {
         Self {
             inner: Arc::new(StdLock::new(Inner::default())),
+            wal: None,
             streams: essential_storage::streams::Notify::new(),
         }
     }
+
+    /// Create a memory storage that persists to disk.
+    ///
+    /// Any existing snapshot and write-ahead log in the configured
+    /// directory are replayed before the storage is returned.
+    pub fn with_persistence(config: PersistenceConfig) -> anyhow::Result<Self> {
+        let (wal, inner) = Wal::open(config)?;
+        Ok(Self {
+            inner: Arc::new(StdLock::new(inner)),
+            wal: Some(Arc::new(StdLock::new(wal))),
+            streams: essential_storage::streams::Notify::new(),
+        })
+    }
+
+    /// Build the log entry for a mutation, only if persistence is enabled.
+    fn record(&self, op: impl FnOnce() -> Op) -> Option<Op> {
+        self.wal.as_ref().map(|_| op())
+    }
+
+    /// Apply a mutation to the inner storage.
+    ///
+    /// The op is appended to the log before the mutation is applied
+    /// so the log and the in-memory state can't diverge.
+    /// Once the op is logged the write is durable, so failing to take a
+    /// snapshot doesn't fail the write. The snapshot is retried on the next write.
+    fn write<R>(
+        &self,
+        op: Option<Op>,
+        f: impl FnOnce(&mut Inner) -> anyhow::Result<R>,
+    ) -> anyhow::Result<R> {
+        let (Some(wal), Some(op)) = (&self.wal, op) else {
+            return self.inner.apply(f);
+        };
+        let (r, snapshot) = self.inner.apply(|i| {
+            wal.apply(|w| {
+                w.append(&op)?;
+                let r = f(i);
+                let snapshot = w.snapshot_due().then(|| w.take_snapshot(i));
+                anyhow::Ok((r, snapshot))
+            })
+        })?;
+        if let Some(snapshot) = snapshot {
+            write_snapshot(wal.clone(), snapshot);
+        }
+        r
+    }
+}
+
+/// Write the snapshot on a blocking thread if there is a runtime
+/// so neither the storage nor the runtime wait for it.
+fn write_snapshot(wal: Arc<StdLock<Wal>>, snapshot: anyhow::Result<PendingSnapshot>) {
+    let write = move || {
+        if let Err(_err) = snapshot.and_then(|s| s.write(&wal)) {
+            #[cfg(feature = "tracing")]
+            tracing::warn!("Failed to snapshot memory storage: {:#}", _err);
+        }
+    };
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) => {
+            handle.spawn_blocking(write);
+        }
+        Err(_) => write(),
+    }
 }
 
 impl StateStorage for MemoryStorage {
@@ -116,26 +207,26 @@ impl StateStorage for MemoryStorage {
         key: &Key,
         value: Vec<Word>,
     ) -> anyhow::Result<Vec<Word>> {
-        self.inner.apply(|i| {
-            let Some(map) = i.state.get_mut(address) else {
-                bail!("No state for address, {:?}", address);
-            };
-            let v = if value.is_empty() {
-                map.remove(key)
-            } else {
-                map.insert(key.clone(), value)
-            };
-            let v = v.unwrap_or_default();
-            Ok(v)
-        })
+        let op = self.record(|| Op::UpdateState {
+            address: address.clone(),
+            key: key.clone(),
+            value: value.clone(),
+        });
+        self.write(op, |i| update_state(i, address, key, value))
     }
 
     async fn update_state_batch<U>(&self, updates: U) -> anyhow::Result<Vec<Vec<Word>>>
     where
         U: IntoIterator<Item = (ContentAddress, Key, Vec<Word>)> + Send,
     {
-        let v = self.inner.apply(|i| update_state_batch(i, updates));
-        Ok(v)
+        let updates: Vec<_> = updates.into_iter().collect();
+        let op = self.record(|| Op::UpdateStateBatch {
+            updates: updates.clone(),
+        });
+        self.write(op, |i| {
+            let block_number = next_block_number(i);
+            Ok(update_state_batch(i, updates, block_number))
+        })
     }
 }
 
@@ -148,44 +239,62 @@ impl QueryState for MemoryStorage {
         });
         Ok(v.unwrap_or_default())
     }
+
+    async fn query_state_batch(
+        &self,
+        address: &ContentAddress,
+        keys: &[Key],
+    ) -> anyhow::Result<Vec<Vec<Word>>> {
+        Ok(self.inner.apply(|i| {
+            let map = i.state.get(address);
+            keys.iter()
+                .map(|key| {
+                    map.and_then(|map| map.get(key))
+                        .cloned()
+                        .unwrap_or_default()
+                })
+                .collect()
+        }))
+    }
+
+    async fn query_state_range(
+        &self,
+        address: &ContentAddress,
+        start_key: &Key,
+        end_key: Option<&Key>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<(Key, Vec<Word>)>> {
+        // An empty range would panic.
+        if end_key.is_some_and(|end_key| end_key <= start_key) {
+            return Ok(Vec::new());
+        }
+        let range = (
+            Bound::Included(start_key),
+            end_key.map_or(Bound::Unbounded, Bound::Excluded),
+        );
+        Ok(self.inner.apply(|i| {
+            i.state
+                .get(address)
+                .map(|map| {
+                    map.range::<Key, _>(range)
+                        .take(limit)
+                        .map(|(key, value)| (key.clone(), value.clone()))
+                        .collect()
+                })
+                .unwrap_or_default()
+        }))
+    }
 }
 
 impl Storage for MemoryStorage {
     async fn insert_contract(&self, signed: SignedContract) -> anyhow::Result<()> {
-        let SignedContract {
-            contract,
-            signature,
-        } = signed;
-
-        let salt = contract.salt;
-
-        let data: HashMap<_, _> = contract
-            .predicates
-            .into_iter()
-            .map(|p| (essential_hash::content_addr(&p), p))
-            .collect();
-
-        let contract_addr =
-            essential_hash::contract_addr::from_predicate_addrs(data.keys().cloned(), &salt);
-
-        let contract_with_addrs = ContractWithAddresses {
-            salt,
-            data: data.keys().cloned().collect(),
-            signature,
-        };
         let time = SystemTime::now().duration_since(UNIX_EPOCH)?;
-        let r = self.inner.apply(|i| {
-            i.predicates.extend(data);
-            let contains = i
-                .contracts
-                .insert(contract_addr.clone(), contract_with_addrs);
-            if contains.is_none() {
-                i.contract_time_index
-                    .entry(time)
-                    .or_default()
-                    .push(contract_addr.clone());
-            }
-            i.state.entry(contract_addr).or_default();
+        let op = self.record(|| Op::InsertContract {
+            contract: signed.clone(),
+            time,
+        });
+        let r = self.write(op, |i| {
+            insert_contract(i, signed, time);
             Ok(())
         });
 
@@ -196,17 +305,14 @@ impl Storage for MemoryStorage {
 
     async fn insert_solution_into_pool(&self, solution: Solution) -> anyhow::Result<()> {
         let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
-        let hash = essential_hash::hash(&solution);
-        self.inner.apply(|i| {
-            if i.solution_pool.insert(hash) {
-                i.solution_time_index
-                    .entry(timestamp)
-                    .or_default()
-                    .push(hash);
-            }
-            i.solutions.insert(hash, solution);
+        let op = self.record(|| Op::InsertSolution {
+            solution: solution.clone(),
+            time: timestamp,
         });
-        Ok(())
+        self.write(op, |i| {
+            insert_solution(i, solution, timestamp);
+            Ok(())
+        })
     }
 
     async fn move_solutions_to_solved(
@@ -216,14 +322,20 @@ impl Storage for MemoryStorage {
         solutions: &[Hash],
     ) -> anyhow::Result<()> {
         let new_block = !solutions.is_empty();
-        let hashes: HashSet<_> = solutions.iter().collect();
-        let r = self.inner.apply(|i| {
-            move_solutions_to_solved(i, block_number, block_timestamp, solutions, hashes)
+        let op = self.record(|| Op::MoveSolutionsToSolved {
+            block_number,
+            block_timestamp,
+            solutions: solutions.to_vec(),
+        });
+        // The gas the solutions used isn't known so the block records none.
+        let r = self.write(op, |i| {
+            move_solutions_to_solved(i, block_number, block_timestamp, solutions, 0, &[])
         });
 
-        if new_block {
+        if new_block && r.is_ok() {
             // There is a new block.
             self.streams.notify_new_blocks();
+            self.streams.notify_new_outcomes(solutions.iter().copied());
         }
         r
     }
@@ -232,16 +344,25 @@ impl Storage for MemoryStorage {
         &self,
         solutions: &[(Hash, SolutionFailReason)],
     ) -> anyhow::Result<()> {
-        let hashes: HashSet<_> = solutions.iter().map(|(h, _)| h).collect();
-        self.inner
-            .apply(|i| move_solutions_to_failed(i, solutions, hashes))
+        let time = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let op = self.record(|| Op::MoveSolutionsToFailed {
+            solutions: solutions.to_vec(),
+            time,
+        });
+        let r = self.write(op, |i| {
+            move_solutions_to_failed(i, solutions, time);
+            Ok(())
+        });
+        self.streams
+            .notify_new_outcomes(solutions.iter().map(|(hash, _)| *hash));
+        r
     }
 
     async fn get_predicate(&self, address: &PredicateAddress) -> anyhow::Result<Option<Predicate>> {
         let v = self.inner.apply(|i| {
             if i.contracts
                 .get(&address.contract)
-                .map_or(true, |c| !c.data.contains(&address.predicate))
+                .is_none_or(|c| !c.data.contains(&address.predicate))
             {
                 return None;
             }
@@ -340,6 +461,34 @@ impl Storage for MemoryStorage {
         }))
     }
 
+    fn stream_solutions_pool(
+        self,
+        limit: Option<usize>,
+    ) -> impl futures::Stream<Item = anyhow::Result<Solution>> + Send + 'static {
+        // The pool is read in a single lock so no paging is needed.
+        let solutions: Vec<_> = self.inner.apply(|i| {
+            i.solution_time_index
+                .values()
+                .flatten()
+                .filter(|h| i.solution_pool.contains(*h))
+                .filter_map(|h| i.solutions.get(h).cloned())
+                .take(limit.unwrap_or(usize::MAX))
+                .map(Ok)
+                .collect()
+        });
+        futures::stream::iter(solutions)
+    }
+
+    async fn pool_size(&self) -> anyhow::Result<usize> {
+        Ok(self.inner.apply(|i| i.solution_pool.len()))
+    }
+
+    async fn is_in_pool(&self, solution_hash: Hash) -> anyhow::Result<bool> {
+        Ok(self
+            .inner
+            .apply(|i| i.solution_pool.contains(&solution_hash)))
+    }
+
     async fn list_failed_solutions_pool(
         &self,
         page: Option<usize>,
@@ -361,6 +510,7 @@ impl Storage for MemoryStorage {
                     Some(FailedSolution {
                         solution,
                         reason: r.0,
+                        timestamp: r.1,
                     })
                 },
                 page.unwrap_or(0),
@@ -389,6 +539,23 @@ impl Storage for MemoryStorage {
         })
     }
 
+    async fn list_block_headers(
+        &self,
+        block_number: Option<u64>,
+        page: Option<usize>,
+    ) -> anyhow::Result<Vec<BlockHeader>> {
+        let page = page.unwrap_or(0);
+        Ok(self.inner.apply(|i| {
+            values::page_block_headers(
+                &i.solved,
+                &i.block_number_index,
+                block_number,
+                page,
+                PAGE_SIZE,
+            )
+        }))
+    }
+
     fn subscribe_blocks(
         self,
         start_time: Option<Duration>,
@@ -423,6 +590,22 @@ impl Storage for MemoryStorage {
         .flat_map(futures::stream::iter)
     }
 
+    fn subscribe_solution_outcomes(
+        self,
+        solution_hashes: Vec<Hash>,
+    ) -> impl futures::Stream<Item = anyhow::Result<(Hash, CheckOutcome)>> + Send + 'static {
+        let new_outcomes = self.streams.subscribe_outcomes();
+        essential_storage::streams::solution_outcomes(new_outcomes, solution_hashes, move |hash| {
+            let storage = self.clone();
+            async move {
+                let outcomes = storage.get_solution(hash).await?;
+                Ok(outcomes
+                    .map(|s| s.outcome.into_iter().zip(s.timestamps).collect())
+                    .unwrap_or_default())
+            }
+        })
+    }
+
     async fn get_solution(&self, solution_hash: Hash) -> anyhow::Result<Option<SolutionOutcomes>> {
         let r = self.inner.apply(|i| {
             i.solutions.get(&solution_hash).cloned().map(|s| {
@@ -445,10 +628,11 @@ impl Storage for MemoryStorage {
                     )
                     .collect();
                 outcomes.sort_by_key(|(t, _)| *t);
-                let outcome = outcomes.into_iter().map(|(_, o)| o).collect();
+                let (timestamps, outcome) = outcomes.into_iter().unzip();
                 SolutionOutcomes {
                     solution: s.clone(),
                     outcome,
+                    timestamps,
                 }
             })
         });
@@ -456,20 +640,66 @@ impl Storage for MemoryStorage {
     }
 
     async fn prune_failed_solutions(&self, older_than: Duration) -> anyhow::Result<()> {
-        self.inner.apply(|i| {
-            i.failed_solution_time_index.retain(|timestamp, hash| {
-                let retain = *timestamp >= older_than;
-                if !retain {
-                    for hash in hash {
-                        i.failed_solution_pool.remove(hash);
-                    }
-                }
-                retain
-            });
+        let op = self.record(|| Op::PruneFailedSolutions { older_than });
+        self.write(op, |i| {
+            prune_failed_solutions(i, older_than);
+            Ok(())
+        })
+    }
+
+    async fn prune_excess_failed_solutions(&self, max_count: usize) -> anyhow::Result<()> {
+        let op = self.record(|| Op::PruneExcessFailedSolutions { max_count });
+        self.write(op, |i| {
+            prune_excess_failed_solutions(i, max_count);
             Ok(())
         })
     }
 
+    async fn list_prunable_failed_solutions(
+        &self,
+        older_than: Duration,
+        max_count: Option<usize>,
+    ) -> anyhow::Result<Vec<FailedSolution>> {
+        Ok(self.inner.apply(|i| {
+            prunable_failed(i, older_than, max_count)
+                .into_iter()
+                .filter_map(|(time, hash)| {
+                    let solution = i.solutions.get(&hash).cloned()?;
+                    let (reason, _) = i
+                        .failed_solution_pool
+                        .get(&hash)?
+                        .iter()
+                        .find(|(_, t)| *t == time)?;
+                    Some(FailedSolution {
+                        solution,
+                        reason: reason.clone(),
+                        timestamp: time,
+                    })
+                })
+                .collect()
+        }))
+    }
+
+    async fn expire_solutions(&self, added_before: Duration) -> anyhow::Result<Vec<Hash>> {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let op = self.record(|| Op::ExpireSolutions { added_before, time });
+        let r = self.write(op, |i| Ok(expire_solutions(i, added_before, time)));
+        if let Ok(expired) = &r {
+            self.streams.notify_new_outcomes(expired.iter().copied());
+        }
+        r
+    }
+
+    async fn evict_solutions(&self, capacity: usize) -> anyhow::Result<Vec<Hash>> {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let op = self.record(|| Op::EvictSolutions { capacity, time });
+        let r = self.write(op, |i| Ok(evict_solutions(i, capacity, time)));
+        if let Ok(evicted) = &r {
+            self.streams.notify_new_outcomes(evicted.iter().copied());
+        }
+        r
+    }
+
     fn commit_block(
         &self,
         data: CommitData,
@@ -480,27 +710,153 @@ impl Storage for MemoryStorage {
             state_updates,
             block_number,
             block_timestamp,
+            gas_used,
         } = data;
-        let hashes: HashSet<_> = failed.iter().map(|(h, _)| h).collect();
-        let solved_hashes: HashSet<_> = solved.iter().collect();
-        let r = self.inner.apply(|i| {
-            let new_block = !solved_hashes.is_empty();
-            move_solutions_to_failed(i, failed, hashes)?;
-            move_solutions_to_solved(i, block_number, block_timestamp, solved, solved_hashes)?;
-            update_state_batch(i, state_updates);
-            Ok(new_block)
-        });
+        let r = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(anyhow::Error::from)
+            .and_then(|failed_at| {
+                let state_updates: Vec<_> = state_updates.collect();
+                let block = NewBlock {
+                    number: block_number,
+                    timestamp: block_timestamp,
+                    gas_used,
+                };
+                let op = self.record(|| Op::CommitBlock {
+                    block: block.clone(),
+                    failed: failed.to_vec(),
+                    failed_at,
+                    solved: solved.to_vec(),
+                    state_updates: state_updates.clone(),
+                });
+                self.write(op, |i| {
+                    commit_block(i, block, failed, failed_at, solved, state_updates)
+                })
+            });
 
         if let Ok(r) = &r {
             if *r {
                 // There is a new block.
                 self.streams.notify_new_blocks();
             }
+            self.streams.notify_new_outcomes(
+                solved
+                    .iter()
+                    .copied()
+                    .chain(failed.iter().map(|(hash, _)| *hash)),
+            );
         }
 
         async { r.map(|_| ()) }
     }
 
+    async fn revert_to_block(&self, block_number: u64) -> anyhow::Result<()> {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let op = self.record(|| Op::RevertToBlock { block_number, time });
+        let r = self.write(op, |i| {
+            revert_to_block(i, block_number, time);
+            Ok(())
+        });
+
+        // Blocks were removed.
+        // Solutions lose outcomes but don't gain any so outcome streams aren't notified.
+        self.streams.notify_new_blocks();
+        r
+    }
+
+    async fn query_state_at(
+        &self,
+        address: &ContentAddress,
+        key: &Key,
+        block_number: u64,
+    ) -> anyhow::Result<Vec<Word>> {
+        Ok(self.inner.apply(|i| {
+            i.state_history
+                .get(address)
+                .and_then(|history| history.get(key))
+                .and_then(|versions| versions.range(..=block_number).next_back())
+                .map(|(_, value)| value.clone())
+                .unwrap_or_default()
+        }))
+    }
+
+    async fn query_state_range_at(
+        &self,
+        address: &ContentAddress,
+        start_key: &Key,
+        end_key: Option<&Key>,
+        limit: usize,
+        block_number: u64,
+    ) -> anyhow::Result<Vec<(Key, Vec<Word>)>> {
+        // An empty range would panic.
+        if end_key.is_some_and(|end_key| end_key <= start_key) {
+            return Ok(Vec::new());
+        }
+        let range = (
+            Bound::Included(start_key),
+            end_key.map_or(Bound::Unbounded, Bound::Excluded),
+        );
+        Ok(self.inner.apply(|i| {
+            i.state_history
+                .get(address)
+                .map(|history| {
+                    history
+                        .range::<Key, _>(range)
+                        .filter_map(|(key, versions)| {
+                            let (_, value) = versions.range(..=block_number).next_back()?;
+                            (!value.is_empty()).then(|| (key.clone(), value.clone()))
+                        })
+                        .take(limit)
+                        .collect()
+                })
+                .unwrap_or_default()
+        }))
+    }
+
+    async fn list_state_at(
+        &self,
+        block_number: u64,
+    ) -> anyhow::Result<Vec<(ContentAddress, Key, Vec<Word>)>> {
+        Ok(self.inner.apply(|i| {
+            i.state_history
+                .iter()
+                .flat_map(|(address, history)| {
+                    history.iter().filter_map(move |(key, versions)| {
+                        let (_, value) = versions.range(..=block_number).next_back()?;
+                        (!value.is_empty()).then(|| (address.clone(), key.clone(), value.clone()))
+                    })
+                })
+                .collect()
+        }))
+    }
+
+    async fn get_block_state_diff(&self, block_number: u64) -> anyhow::Result<Vec<StateChange>> {
+        Ok(self.inner.apply(|i| {
+            let mut diff: Vec<_> = i
+                .state_history
+                .iter()
+                .flat_map(|(address, history)| {
+                    history.iter().filter_map(move |(key, versions)| {
+                        let new_value = versions.get(&block_number)?.clone();
+                        let old_value = versions
+                            .range(..block_number)
+                            .next_back()
+                            .map(|(_, value)| value.clone())
+                            .unwrap_or_default();
+                        Some(StateChange {
+                            address: address.clone(),
+                            key: key.clone(),
+                            old_value,
+                            new_value,
+                        })
+                    })
+                })
+                .collect();
+            diff.sort();
+            diff
+        }))
+    }
+
     async fn get_latest_block(&self) -> anyhow::Result<Option<essential_types::Block>> {
         let r = self.inner.apply(|i| match i.solved.last_key_value() {
             Some((_, block)) => {
@@ -521,12 +877,214 @@ impl Storage for MemoryStorage {
     }
 }
 
+fn insert_contract(i: &mut Inner, signed: SignedContract, time: Duration) {
+    let SignedContract {
+        contract,
+        signature,
+    } = signed;
+
+    let salt = contract.salt;
+
+    let data: HashMap<_, _> = contract
+        .predicates
+        .into_iter()
+        .map(|p| (essential_hash::content_addr(&p), p))
+        .collect();
+
+    let contract_addr =
+        essential_hash::contract_addr::from_predicate_addrs(data.keys().cloned(), &salt);
+
+    let contract_with_addrs = ContractWithAddresses {
+        salt,
+        data: data.keys().cloned().collect(),
+        signature,
+    };
+    i.predicates.extend(data);
+    let contains = i
+        .contracts
+        .insert(contract_addr.clone(), contract_with_addrs);
+    if contains.is_none() {
+        i.contract_time_index
+            .entry(time)
+            .or_default()
+            .push(contract_addr.clone());
+    }
+    i.state.entry(contract_addr).or_default();
+}
+
+fn insert_solution(i: &mut Inner, solution: Solution, timestamp: Duration) {
+    let hash = essential_hash::hash(&solution);
+    insert_into_pool(i, hash, timestamp);
+    i.solutions.insert(hash, solution);
+}
+
+/// Add a solution to the back of the pool if it isn't already in it.
+fn insert_into_pool(i: &mut Inner, hash: Hash, timestamp: Duration) {
+    if i.solution_pool.insert(hash) {
+        i.solution_time_index
+            .entry(timestamp)
+            .or_default()
+            .push(hash);
+    }
+}
+
+/// Hashes of the solutions in the pool, oldest first.
+fn pool_by_age(i: &Inner) -> impl Iterator<Item = (&Duration, &Hash)> {
+    i.solution_time_index
+        .iter()
+        .flat_map(|(time, hashes)| hashes.iter().map(move |h| (time, h)))
+        .filter(|(_, h)| i.solution_pool.contains(*h))
+}
+
+fn expire_solutions(i: &mut Inner, added_before: Duration, time: Duration) -> Vec<Hash> {
+    let expired: Vec<_> = pool_by_age(i)
+        .take_while(|(added, _)| **added < added_before)
+        .map(|(_, h)| *h)
+        .collect();
+    fail_pool_solutions(i, expired, SolutionFailReason::Expired, time)
+}
+
+fn evict_solutions(i: &mut Inner, capacity: usize, time: Duration) -> Vec<Hash> {
+    let excess = i.solution_pool.len().saturating_sub(capacity);
+    let evicted: Vec<_> = pool_by_age(i).take(excess).map(|(_, h)| *h).collect();
+    fail_pool_solutions(i, evicted, SolutionFailReason::Evicted, time)
+}
+
+/// Move solutions from the pool to failed for the same reason.
+fn fail_pool_solutions(
+    i: &mut Inner,
+    hashes: Vec<Hash>,
+    reason: SolutionFailReason,
+    time: Duration,
+) -> Vec<Hash> {
+    let failed: Vec<_> = hashes.iter().map(|h| (*h, reason.clone())).collect();
+    move_solutions_to_failed(i, &failed, time);
+    hashes
+}
+
+fn commit_block(
+    i: &mut Inner,
+    block: NewBlock,
+    failed: &[(Hash, SolutionFailReason)],
+    failed_at: Duration,
+    solved: &[Hash],
+    state_updates: Vec<(ContentAddress, Key, Vec<Word>)>,
+) -> anyhow::Result<bool> {
+    let new_block = !solved.is_empty();
+    move_solutions_to_failed(i, failed, failed_at);
+    move_solutions_to_solved(
+        i,
+        block.number,
+        block.timestamp,
+        solved,
+        block.gas_used,
+        &state_updates,
+    )?;
+    update_state_batch(i, state_updates, block.number);
+    Ok(new_block)
+}
+
+fn revert_to_block(i: &mut Inner, block_number: u64, time: Duration) {
+    let after = block_number.saturating_add(1);
+
+    // Restore each key written after the block to its value at the end of the block.
+    for (address, history) in &mut i.state_history {
+        let state = i.state.entry(address.clone()).or_default();
+        history.retain(|key, versions| {
+            if versions.split_off(&after).is_empty() {
+                return true;
+            }
+            match versions.last_key_value() {
+                Some((_, value)) if !value.is_empty() => {
+                    i.state_tree.update(address, key, value);
+                    state.insert(key.clone(), value.clone());
+                }
+                _ => {
+                    i.state_tree.update(address, key, &vec![]);
+                    state.remove(key);
+                }
+            }
+            !versions.is_empty()
+        });
+    }
+
+    // Blocks are ordered by time and numbered in the same order.
+    let first_reverted = i
+        .solved
+        .values()
+        .find(|block| block.number > block_number)
+        .map(|block| block.timestamp);
+    let Some(first_reverted) = first_reverted else {
+        return;
+    };
+    for block in i.solved.split_off(&first_reverted).into_values() {
+        i.block_number_index.remove(&block.number);
+        for hash in block.hashes {
+            if let Some(times) = i.solution_block_time_index.get_mut(&hash) {
+                times.retain(|t| *t != block.timestamp);
+                if times.is_empty() {
+                    i.solution_block_time_index.remove(&hash);
+                }
+            }
+            insert_into_pool(i, hash, time);
+        }
+    }
+}
+
+fn prune_failed_solutions(i: &mut Inner, older_than: Duration) {
+    let pruned = prunable_failed(i, older_than, None);
+    remove_failed(i, &pruned);
+}
+
+fn prune_excess_failed_solutions(i: &mut Inner, max_count: usize) {
+    let pruned = prunable_failed(i, Duration::ZERO, Some(max_count));
+    remove_failed(i, &pruned);
+}
+
+/// The time and hash of each failure that failed before `older_than`
+/// or is older than the newest `max_count`, oldest first.
+fn prunable_failed(
+    i: &Inner,
+    older_than: Duration,
+    max_count: Option<usize>,
+) -> Vec<(Duration, Hash)> {
+    let total: usize = i.failed_solution_time_index.values().map(Vec::len).sum();
+    let excess = max_count.map_or(0, |max_count| total.saturating_sub(max_count));
+    i.failed_solution_time_index
+        .iter()
+        .flat_map(|(time, hashes)| hashes.iter().map(|h| (*time, *h)))
+        .enumerate()
+        .take_while(|(n, (time, _))| *n < excess || *time < older_than)
+        .map(|(_, failure)| failure)
+        .collect()
+}
+
+/// Remove each failure of a solution at a time.
+fn remove_failed(i: &mut Inner, failures: &[(Duration, Hash)]) {
+    for (time, hash) in failures {
+        if let Some(hashes) = i.failed_solution_time_index.get_mut(time) {
+            if let Some(pos) = hashes.iter().position(|h| h == hash) {
+                hashes.remove(pos);
+            }
+            if hashes.is_empty() {
+                i.failed_solution_time_index.remove(time);
+            }
+        }
+        if let Some(reasons) = i.failed_solution_pool.get_mut(hash) {
+            reasons.retain(|(_, t)| t != time);
+            if reasons.is_empty() {
+                i.failed_solution_pool.remove(hash);
+            }
+        }
+    }
+}
+
 fn move_solutions_to_failed(
     i: &mut Inner,
     solutions: &[(Hash, SolutionFailReason)],
-    hashes: HashSet<&Hash>,
-) -> Result<(), anyhow::Error> {
-    let time = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+    time: Duration,
+) {
+    let hashes: HashSet<_> = solutions.iter().map(|(h, _)| h).collect();
     let solutions = solutions.iter().filter_map(|(h, r)| {
         if i.solution_pool.remove(h) {
             Some((*h, r.clone()))
@@ -550,8 +1108,6 @@ fn move_solutions_to_failed(
             .or_default()
             .push(hash);
     }
-
-    Ok(())
 }
 
 fn move_solutions_to_solved(
@@ -559,11 +1115,13 @@ fn move_solutions_to_solved(
     block_number: u64,
     block_timestamp: Duration,
     solutions: &[Hash],
-    hashes: HashSet<&Hash>,
+    gas_used: u64,
+    pending_updates: &[(ContentAddress, Key, Vec<Word>)],
 ) -> Result<(), anyhow::Error> {
     if solutions.is_empty() {
         return Ok(());
     }
+    let hashes: HashSet<_> = solutions.iter().collect();
 
     if solutions.iter().all(|s| !i.solution_pool.contains(s)) {
         return Ok(());
@@ -594,30 +1152,95 @@ fn move_solutions_to_solved(
         number: block_number,
         timestamp: block_timestamp,
         hashes: solutions,
+        gas_used,
+        state_root: state_root(i, pending_updates),
     };
     i.solved.insert(block_timestamp, block);
     i.block_number_index.insert(block_number, block_timestamp);
     Ok(())
 }
 
-fn update_state_batch<U>(i: &mut Inner, updates: U) -> Vec<Vec<i64>>
+/// The root of the state once `pending_updates` are applied.
+fn state_root(i: &Inner, pending_updates: &[(ContentAddress, Key, Vec<Word>)]) -> Hash {
+    let mut tree = i.state_tree.clone();
+    for (address, key, value) in pending_updates {
+        tree.update(address, key, value);
+    }
+    tree.root()
+}
+
+/// Build the tree of the current state.
+fn state_tree(i: &Inner) -> StateTree {
+    StateTree::from_state(
+        i.state
+            .iter()
+            .flat_map(|(address, map)| map.iter().map(move |(key, value)| (address, key, value))),
+    )
+}
+
+fn update_state(
+    i: &mut Inner,
+    address: &ContentAddress,
+    key: &Key,
+    value: Vec<Word>,
+) -> anyhow::Result<Vec<Word>> {
+    let Some(map) = i.state.get_mut(address) else {
+        bail!("No state for address, {:?}", address);
+    };
+    let v = if value.is_empty() {
+        map.remove(key)
+    } else {
+        map.insert(key.clone(), value.clone())
+    };
+    i.state_tree.update(address, key, &value);
+    let block_number = next_block_number(i);
+    record_state(i, address.clone(), key.clone(), value, block_number);
+    Ok(v.unwrap_or_default())
+}
+
+fn update_state_batch<U>(i: &mut Inner, updates: U, block_number: u64) -> Vec<Vec<i64>>
 where
     U: IntoIterator<Item = (ContentAddress, Key, Vec<Word>)>,
 {
     updates
         .into_iter()
         .map(|(address, key, value)| {
-            let map = i.state.entry(address).or_default();
+            let map = i.state.entry(address.clone()).or_default();
             let v = if value.is_empty() {
                 map.remove(&key)
             } else {
-                map.insert(key, value)
+                map.insert(key.clone(), value.clone())
             };
+            i.state_tree.update(&address, &key, &value);
+            record_state(i, address, key, value, block_number);
             v.unwrap_or_default()
         })
         .collect()
 }
 
+/// The number of the block that state written now is part of.
+fn next_block_number(i: &Inner) -> u64 {
+    i.solved
+        .last_key_value()
+        .map_or(0, |(_, block)| block.number + 1)
+}
+
+/// Record a state write in the history of the key.
+fn record_state(
+    i: &mut Inner,
+    address: ContentAddress,
+    key: Key,
+    value: Vec<Word>,
+    block_number: u64,
+) {
+    i.state_history
+        .entry(address)
+        .or_default()
+        .entry(key)
+        .or_default()
+        .insert(block_number, value);
+}
+
 #[derive(Debug, Error)]
 pub enum MemoryStorageError {
     #[error("failed to read from memory storage")]
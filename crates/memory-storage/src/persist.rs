@@ -0,0 +1,445 @@
+//! Optional persistence for the memory storage.
+//!
+//! Every mutation is appended to a write-ahead log as an [`Op`].
+//! Every `snapshot_interval` ops the whole of [`Inner`] is serialized and
+//! written to a snapshot file in the background, then the ops it includes
+//! are dropped from the log.
+//! On startup the snapshot is loaded and the log is replayed on top of it.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+use essential_lock::StdLock;
+use essential_storage::failed_solution::SolutionFailReason;
+use essential_types::{
+    contract::SignedContract, solution::Solution, ContentAddress, Hash, Key, Word,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{Inner, NewBlock};
+
+#[cfg(test)]
+mod tests;
+
+const SNAPSHOT_FILE: &str = "snapshot";
+const LOG_FILE: &str = "wal";
+
+/// Version of the format of the snapshot and the log.
+/// Bumped whenever [`Op`] or [`Inner`] change in a way that isn't backwards compatible.
+const FORMAT_VERSION: u32 = 1;
+
+/// Start of the snapshot file, followed by the format version.
+const SNAPSHOT_MAGIC: &[u8; 4] = b"ESSN";
+
+/// Start of the log file, followed by the format version.
+const LOG_MAGIC: &[u8; 4] = b"ESWL";
+
+/// Length of the header at the start of both files.
+const HEADER_LEN: u64 = 8;
+
+/// Largest entry that is written to or read from the log.
+///
+/// A longer length prefix can only come from a corrupt log so it isn't allocated.
+const MAX_ENTRY_LEN: usize = 64 * 1024 * 1024;
+
+/// Configuration for persisting the memory storage to disk.
+#[derive(Debug, Clone)]
+pub struct PersistenceConfig {
+    /// Directory the snapshot and write-ahead log are stored in.
+    /// It is created if it doesn't exist.
+    pub dir: PathBuf,
+    /// Number of ops appended to the log before a new snapshot is taken.
+    pub snapshot_interval: u64,
+    /// Sync the log to disk after every op.
+    ///
+    /// Without this an op may be lost if the machine (rather than the process) crashes.
+    pub sync: bool,
+}
+
+impl PersistenceConfig {
+    /// Default number of ops between snapshots.
+    pub const DEFAULT_SNAPSHOT_INTERVAL: u64 = 1000;
+
+    /// Persist to the given directory with the default settings.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            snapshot_interval: Self::DEFAULT_SNAPSHOT_INTERVAL,
+            sync: true,
+        }
+    }
+}
+
+/// A mutation of the memory storage.
+///
+/// Ops carry any timestamps they use so replaying them is deterministic.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) enum Op {
+    InsertContract {
+        contract: SignedContract,
+        time: Duration,
+    },
+    InsertSolution {
+        solution: Solution,
+        time: Duration,
+    },
+    UpdateState {
+        address: ContentAddress,
+        key: Key,
+        value: Vec<Word>,
+    },
+    UpdateStateBatch {
+        updates: Vec<(ContentAddress, Key, Vec<Word>)>,
+    },
+    MoveSolutionsToSolved {
+        block_number: u64,
+        block_timestamp: Duration,
+        solutions: Vec<Hash>,
+    },
+    MoveSolutionsToFailed {
+        solutions: Vec<(Hash, SolutionFailReason)>,
+        time: Duration,
+    },
+    PruneFailedSolutions {
+        older_than: Duration,
+    },
+    CommitBlock {
+        block: NewBlock,
+        failed: Vec<(Hash, SolutionFailReason)>,
+        failed_at: Duration,
+        solved: Vec<Hash>,
+        state_updates: Vec<(ContentAddress, Key, Vec<Word>)>,
+    },
+    RevertToBlock {
+        block_number: u64,
+        time: Duration,
+    },
+    ExpireSolutions {
+        added_before: Duration,
+        time: Duration,
+    },
+    EvictSolutions {
+        capacity: usize,
+        time: Duration,
+    },
+    PruneExcessFailedSolutions {
+        max_count: usize,
+    },
+}
+
+impl Op {
+    /// Apply the op to the inner storage.
+    ///
+    /// An op that failed when it was first applied will fail
+    /// in the same way when it is replayed so errors are ignored.
+    fn replay(self, i: &mut Inner) {
+        match self {
+            Op::InsertContract { contract, time } => crate::insert_contract(i, contract, time),
+            Op::InsertSolution { solution, time } => crate::insert_solution(i, solution, time),
+            Op::UpdateState {
+                address,
+                key,
+                value,
+            } => {
+                let _ = crate::update_state(i, &address, &key, value);
+            }
+            Op::UpdateStateBatch { updates } => {
+                let block_number = crate::next_block_number(i);
+                crate::update_state_batch(i, updates, block_number);
+            }
+            Op::MoveSolutionsToSolved {
+                block_number,
+                block_timestamp,
+                solutions,
+            } => {
+                let _ = crate::move_solutions_to_solved(
+                    i,
+                    block_number,
+                    block_timestamp,
+                    &solutions,
+                    0,
+                    &[],
+                );
+            }
+            Op::MoveSolutionsToFailed { solutions, time } => {
+                crate::move_solutions_to_failed(i, &solutions, time)
+            }
+            Op::PruneFailedSolutions { older_than } => crate::prune_failed_solutions(i, older_than),
+            Op::CommitBlock {
+                block,
+                failed,
+                failed_at,
+                solved,
+                state_updates,
+            } => {
+                let _ = crate::commit_block(i, block, &failed, failed_at, &solved, state_updates);
+            }
+            Op::RevertToBlock { block_number, time } => {
+                crate::revert_to_block(i, block_number, time)
+            }
+            Op::ExpireSolutions { added_before, time } => {
+                crate::expire_solutions(i, added_before, time);
+            }
+            Op::EvictSolutions { capacity, time } => {
+                crate::evict_solutions(i, capacity, time);
+            }
+            Op::PruneExcessFailedSolutions { max_count } => {
+                crate::prune_excess_failed_solutions(i, max_count)
+            }
+        }
+    }
+}
+
+/// The write-ahead log.
+///
+/// Both files start with a 4 byte magic followed by the little endian `u32` [`FORMAT_VERSION`].
+/// After that each log entry is a little endian `u32` length followed by the
+/// postcard encoded `(seq, Op)`.
+/// The snapshot is the postcard encoded `(seq, Inner)` where `seq` is
+/// the last op included in the snapshot.
+#[derive(Debug)]
+pub(crate) struct Wal {
+    config: PersistenceConfig,
+    log: File,
+    /// Sequence number of the last op appended.
+    seq: u64,
+    /// Number of ops appended since the last snapshot.
+    since_snapshot: u64,
+    /// Whether a snapshot is being written.
+    snapshotting: bool,
+}
+
+/// A snapshot that has been serialized but not yet written.
+///
+/// Taken while the storage is locked and written after it's unlocked.
+#[must_use]
+pub(crate) struct PendingSnapshot {
+    /// The serialized `(seq, Inner)`.
+    bytes: Vec<u8>,
+    /// Length of the log when the snapshot was taken.
+    /// Only the entries after this aren't in the snapshot.
+    log_len: u64,
+    /// Number of ops in the snapshot that were appended since the last snapshot.
+    ops: u64,
+}
+
+impl Wal {
+    /// Open the log, loading the snapshot and replaying the log on top of it.
+    pub(crate) fn open(config: PersistenceConfig) -> anyhow::Result<(Self, Inner)> {
+        std::fs::create_dir_all(&config.dir)?;
+
+        let (mut seq, mut inner) = read_snapshot(&config.dir.join(SNAPSHOT_FILE))?;
+
+        let mut log = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(config.dir.join(LOG_FILE))?;
+        if log.metadata()?.len() == 0 {
+            log.write_all(&header(LOG_MAGIC))?;
+            log.seek(SeekFrom::Start(0))?;
+        }
+
+        let mut since_snapshot = 0;
+        let mut reader = BufReader::new(&mut log);
+        check_header(&mut reader, LOG_MAGIC).context("invalid write-ahead log")?;
+        let mut valid_len = HEADER_LEN;
+        while let Some((entry_seq, op, len)) = read_entry(&mut reader)
+            .with_context(|| format!("invalid write-ahead log entry at byte {valid_len}"))?
+        {
+            valid_len += len;
+            // The log may not have been truncated after the last snapshot.
+            if entry_seq <= seq {
+                continue;
+            }
+            op.replay(&mut inner);
+            seq = entry_seq;
+            since_snapshot += 1;
+        }
+
+        // Drop any partially written entry at the end of the log.
+        log.set_len(valid_len)?;
+        log.seek(SeekFrom::End(0))?;
+
+        Ok((
+            Self {
+                config,
+                log,
+                seq,
+                since_snapshot,
+                snapshotting: false,
+            },
+            inner,
+        ))
+    }
+
+    /// Append an op to the log.
+    pub(crate) fn append(&mut self, op: &Op) -> anyhow::Result<()> {
+        let seq = self.seq + 1;
+        let bytes = postcard::to_allocvec(&(seq, op))?;
+        anyhow::ensure!(
+            bytes.len() <= MAX_ENTRY_LEN,
+            "op of {} bytes is too large to log",
+            bytes.len()
+        );
+        let len = u32::try_from(bytes.len())?;
+        let mut entry = Vec::with_capacity(4 + bytes.len());
+        entry.extend_from_slice(&len.to_le_bytes());
+        entry.extend_from_slice(&bytes);
+        self.log.write_all(&entry)?;
+        if self.config.sync {
+            self.log.sync_data()?;
+        }
+        self.seq = seq;
+        self.since_snapshot += 1;
+        Ok(())
+    }
+
+    /// Whether enough ops have been logged to take a new snapshot
+    /// and one isn't already being written.
+    pub(crate) fn snapshot_due(&self) -> bool {
+        !self.snapshotting && self.since_snapshot >= self.config.snapshot_interval
+    }
+
+    /// Serialize a snapshot of the inner storage to write with [`PendingSnapshot::write`].
+    pub(crate) fn take_snapshot(&mut self, inner: &Inner) -> anyhow::Result<PendingSnapshot> {
+        let bytes = postcard::to_allocvec(&(self.seq, inner))?;
+        let log_len = self.log.metadata()?.len();
+        self.snapshotting = true;
+        Ok(PendingSnapshot {
+            bytes,
+            log_len,
+            ops: self.since_snapshot,
+        })
+    }
+
+    /// Replace the log with the entries from `offset` on.
+    ///
+    /// The new log is written to a temporary file then renamed
+    /// so a crash leaves either the old or the new log.
+    fn truncate_front(&mut self, offset: u64) -> anyhow::Result<()> {
+        let path = self.config.dir.join(LOG_FILE);
+        let tmp = path.with_extension("tmp");
+        {
+            let mut file = BufWriter::new(File::create(&tmp)?);
+            file.write_all(&header(LOG_MAGIC))?;
+            self.log.seek(SeekFrom::Start(offset))?;
+            std::io::copy(&mut self.log, &mut file)?;
+            file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        }
+        std::fs::rename(&tmp, &path)?;
+        self.log = OpenOptions::new().read(true).append(true).open(&path)?;
+        self.log.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+}
+
+impl PendingSnapshot {
+    /// Write the snapshot then drop the ops it includes from the log.
+    ///
+    /// The snapshot is written to a temporary file then renamed
+    /// so a crash can't leave a partially written snapshot.
+    /// The log is only locked once the snapshot is written.
+    pub(crate) fn write(self, wal: &StdLock<Wal>) -> anyhow::Result<()> {
+        let dir = wal.apply(|wal| wal.config.dir.clone());
+        let r = write_snapshot(&dir, &self.bytes);
+        wal.apply(|wal| {
+            wal.snapshotting = false;
+            r?;
+            wal.truncate_front(self.log_len)?;
+            wal.since_snapshot -= self.ops;
+            Ok(())
+        })
+    }
+}
+
+fn write_snapshot(dir: &Path, bytes: &[u8]) -> anyhow::Result<()> {
+    let path = dir.join(SNAPSHOT_FILE);
+    let tmp = path.with_extension("tmp");
+    {
+        let mut file = BufWriter::new(File::create(&tmp)?);
+        file.write_all(&header(SNAPSHOT_MAGIC))?;
+        file.write_all(bytes)?;
+        file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    }
+    std::fs::rename(&tmp, &path)?;
+    Ok(())
+}
+
+fn read_snapshot(path: &Path) -> anyhow::Result<(u64, Inner)> {
+    match std::fs::read(path) {
+        Ok(bytes) => {
+            let mut reader = &bytes[..];
+            check_header(&mut reader, SNAPSHOT_MAGIC).context("invalid snapshot")?;
+            let (seq, mut inner): (u64, Inner) = postcard::from_bytes(reader)?;
+            inner.state_tree = crate::state_tree(&inner);
+            Ok((seq, inner))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok((0, Inner::default())),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The header at the start of a file with the given magic.
+fn header(magic: &[u8; 4]) -> [u8; HEADER_LEN as usize] {
+    let mut header = [0u8; HEADER_LEN as usize];
+    header[..4].copy_from_slice(magic);
+    header[4..].copy_from_slice(&FORMAT_VERSION.to_le_bytes());
+    header
+}
+
+/// Check the file starts with the magic and is in the current format version.
+fn check_header(reader: &mut impl Read, magic: &[u8; 4]) -> anyhow::Result<()> {
+    let mut found = [0u8; HEADER_LEN as usize];
+    reader.read_exact(&mut found)?;
+    anyhow::ensure!(found[..4] == magic[..], "unrecognized file format");
+    let version = u32::from_le_bytes(found[4..].try_into().expect("header is 8 bytes"));
+    anyhow::ensure!(
+        version == FORMAT_VERSION,
+        "format version {version} is not supported, expected {FORMAT_VERSION}"
+    );
+    Ok(())
+}
+
+/// Read the next entry from the log along with its length in bytes.
+///
+/// Returns `None` at the end of the log or if the last entry was torn,
+/// which is when it's partially written, its length is longer than any entry
+/// that could have been written or it doesn't decode.
+/// An entry like that anywhere but the end of the log is an error
+/// as the entries after it were durably written.
+fn read_entry(reader: &mut impl BufRead) -> anyhow::Result<Option<(u64, Op, u64)>> {
+    let mut len = [0u8; 4];
+    if !read_exact_or_eof(reader, &mut len)? {
+        return Ok(None);
+    }
+    let len = u32::from_le_bytes(len) as usize;
+    // Read into a growing buffer so a torn entry doesn't allocate its whole length.
+    let mut bytes = Vec::new();
+    reader
+        .by_ref()
+        .take(len.min(MAX_ENTRY_LEN) as u64)
+        .read_to_end(&mut bytes)?;
+    let entry = (bytes.len() == len)
+        .then(|| postcard::from_bytes::<(u64, Op)>(&bytes).ok())
+        .flatten();
+    match entry {
+        Some((seq, op)) => Ok(Some((seq, op, 4 + len as u64))),
+        None if reader.fill_buf()?.is_empty() => Ok(None),
+        None => anyhow::bail!("entry of {len} bytes is corrupt"),
+    }
+}
+
+/// Fill the buffer, returning false if the reader ends first.
+fn read_exact_or_eof(reader: &mut impl Read, buf: &mut [u8]) -> anyhow::Result<bool> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,374 @@
+use super::*;
+use crate::MemoryStorage;
+use essential_storage::{CommitData, QueryState, Storage};
+use essential_types::PredicateAddress;
+use std::time::{SystemTime, UNIX_EPOCH};
+use test_utils::{
+    predicate_with_salt, sign_contract_with_random_keypair, solution_with_decision_variables,
+    solution_with_predicate,
+};
+
+fn config(dir: &Path, snapshot_interval: u64) -> PersistenceConfig {
+    PersistenceConfig {
+        snapshot_interval,
+        ..PersistenceConfig::new(dir)
+    }
+}
+
+/// Insert a contract, a solution and commit a block with a state update.
+async fn populate(storage: &MemoryStorage) -> (SignedContract, Solution) {
+    let contract = sign_contract_with_random_keypair(vec![predicate_with_salt(0)]);
+    let address = essential_hash::contract_addr::from_contract(&contract.contract);
+    let predicate = PredicateAddress {
+        contract: address.clone(),
+        predicate: essential_hash::content_addr(&contract.contract.predicates[0]),
+    };
+    let solution = solution_with_predicate(predicate);
+
+    storage.insert_contract(contract.clone()).await.unwrap();
+    storage
+        .insert_solution_into_pool(solution.clone())
+        .await
+        .unwrap();
+    storage
+        .commit_block(CommitData {
+            block_number: 0,
+            block_timestamp: Duration::from_secs(1),
+            gas_used: 0,
+            failed: &[],
+            solved: &[essential_hash::hash(&solution)],
+            state_updates: Box::new(std::iter::once((address, vec![0], vec![42]))),
+        })
+        .await
+        .unwrap();
+    (contract, solution)
+}
+
+/// Wait for the snapshot being written in the background, if any.
+async fn wait_for_snapshot(storage: &MemoryStorage) {
+    let wal = storage.wal.as_ref().unwrap();
+    while wal.apply(|w| w.snapshotting) {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+}
+
+async fn check(storage: &MemoryStorage, contract: &SignedContract, solution: &Solution) {
+    let address = essential_hash::contract_addr::from_contract(&contract.contract);
+    assert_eq!(
+        storage.get_contract(&address).await.unwrap().unwrap(),
+        *contract
+    );
+    assert_eq!(
+        storage.query_state(&address, &vec![0]).await.unwrap(),
+        vec![42]
+    );
+    assert!(storage.list_solutions_pool(None).await.unwrap().is_empty());
+    let blocks = storage.list_blocks(None, None, None).await.unwrap();
+    assert_eq!(blocks.len(), 1);
+    assert_eq!(blocks[0].solutions, vec![solution.clone()]);
+    // The state tree is rebuilt from a snapshot.
+    let root = storage.inner.apply(|i| i.state_tree.root());
+    let headers = storage.list_block_headers(None, None).await.unwrap();
+    assert_eq!(root, headers[0].state_root);
+    assert_ne!(root, essential_server_types::state_root::EMPTY);
+}
+
+#[tokio::test]
+async fn test_replay_log() {
+    let dir = tempfile::tempdir().unwrap();
+    let (contract, solution) = {
+        let storage = MemoryStorage::with_persistence(config(dir.path(), u64::MAX)).unwrap();
+        populate(&storage).await
+    };
+    assert!(!dir.path().join(SNAPSHOT_FILE).exists());
+
+    let storage = MemoryStorage::with_persistence(config(dir.path(), u64::MAX)).unwrap();
+    check(&storage, &contract, &solution).await;
+}
+
+#[tokio::test]
+async fn test_replay_snapshot() {
+    let dir = tempfile::tempdir().unwrap();
+    let (contract, solution) = {
+        // A snapshot is taken after the last of the three ops populate logs.
+        let storage = MemoryStorage::with_persistence(config(dir.path(), 3)).unwrap();
+        let r = populate(&storage).await;
+        wait_for_snapshot(&storage).await;
+        r
+    };
+    assert!(dir.path().join(SNAPSHOT_FILE).exists());
+    // Only the header is left in the log.
+    assert_eq!(
+        std::fs::metadata(dir.path().join(LOG_FILE)).unwrap().len(),
+        HEADER_LEN
+    );
+
+    let storage = MemoryStorage::with_persistence(config(dir.path(), 1)).unwrap();
+    check(&storage, &contract, &solution).await;
+}
+
+#[tokio::test]
+async fn test_skip_ops_in_snapshot() {
+    let dir = tempfile::tempdir().unwrap();
+    let (contract, solution) = {
+        let storage = MemoryStorage::with_persistence(config(dir.path(), u64::MAX)).unwrap();
+        populate(&storage).await
+    };
+
+    // Simulate a crash after the snapshot was written but before the log was truncated.
+    let log = std::fs::read(dir.path().join(LOG_FILE)).unwrap();
+    {
+        let storage = MemoryStorage::with_persistence(config(dir.path(), u64::MAX)).unwrap();
+        let wal = storage.wal.as_ref().unwrap();
+        let snapshot = storage
+            .inner
+            .apply(|i| wal.apply(|w| w.take_snapshot(i)))
+            .unwrap();
+        snapshot.write(wal).unwrap();
+    }
+    std::fs::write(dir.path().join(LOG_FILE), log).unwrap();
+
+    let storage = MemoryStorage::with_persistence(config(dir.path(), u64::MAX)).unwrap();
+    check(&storage, &contract, &solution).await;
+    assert_eq!(storage.list_contracts(None, None).await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_write_during_snapshot() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = MemoryStorage::with_persistence(config(dir.path(), u64::MAX)).unwrap();
+    let (contract, solution) = populate(&storage).await;
+    let wal = storage.wal.as_ref().unwrap();
+    let snapshot = storage
+        .inner
+        .apply(|i| wal.apply(|w| w.take_snapshot(i)))
+        .unwrap();
+    let log_len = std::fs::metadata(dir.path().join(LOG_FILE)).unwrap().len();
+
+    // Ops logged while the snapshot is written are kept in the log.
+    let other = solution_with_decision_variables(1);
+    storage
+        .insert_solution_into_pool(other.clone())
+        .await
+        .unwrap();
+    snapshot.write(wal).unwrap();
+    let log = std::fs::metadata(dir.path().join(LOG_FILE)).unwrap().len();
+    assert!(log > HEADER_LEN && log < log_len, "{log}");
+    drop(storage);
+
+    let storage = MemoryStorage::with_persistence(config(dir.path(), u64::MAX)).unwrap();
+    let address = essential_hash::contract_addr::from_contract(&contract.contract);
+    assert!(storage.get_contract(&address).await.unwrap().is_some());
+    let blocks = storage.list_blocks(None, None, None).await.unwrap();
+    assert_eq!(blocks[0].solutions, vec![solution]);
+    assert_eq!(
+        storage.list_solutions_pool(None).await.unwrap(),
+        vec![other]
+    );
+}
+
+#[tokio::test]
+async fn test_torn_entry() {
+    let dir = tempfile::tempdir().unwrap();
+    let (contract, solution) = {
+        let storage = MemoryStorage::with_persistence(config(dir.path(), u64::MAX)).unwrap();
+        populate(&storage).await
+    };
+
+    // Simulate a crash part way through writing an entry.
+    let path = dir.path().join(LOG_FILE);
+    let len = std::fs::metadata(&path).unwrap().len();
+    let mut log = OpenOptions::new().append(true).open(&path).unwrap();
+    log.write_all(&100u32.to_le_bytes()).unwrap();
+    log.write_all(&[1, 2, 3]).unwrap();
+    drop(log);
+
+    let storage = MemoryStorage::with_persistence(config(dir.path(), u64::MAX)).unwrap();
+    check(&storage, &contract, &solution).await;
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), len);
+
+    // New ops are appended after the last valid entry.
+    let other = solution_with_decision_variables(1);
+    storage
+        .insert_solution_into_pool(other.clone())
+        .await
+        .unwrap();
+    drop(storage);
+
+    let storage = MemoryStorage::with_persistence(config(dir.path(), u64::MAX)).unwrap();
+    assert_eq!(
+        storage.list_solutions_pool(None).await.unwrap(),
+        vec![other]
+    );
+}
+
+#[tokio::test]
+async fn test_corrupt_entry() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let storage = MemoryStorage::with_persistence(config(dir.path(), u64::MAX)).unwrap();
+        populate(&storage).await;
+    }
+
+    // Corrupt the first entry, which has durable entries after it.
+    let path = dir.path().join(LOG_FILE);
+    let mut log = std::fs::read(&path).unwrap();
+    let len = log.len();
+    let start = HEADER_LEN as usize;
+    let entry_len = u32::from_le_bytes(log[start..start + 4].try_into().unwrap()) as usize;
+    log[start + 4..start + 4 + entry_len].fill(0xff);
+    std::fs::write(&path, &log).unwrap();
+
+    let Err(err) = MemoryStorage::with_persistence(config(dir.path(), u64::MAX)) else {
+        panic!("opened a log with a corrupt entry");
+    };
+    assert!(
+        format!("{err:#}").contains("invalid write-ahead log entry"),
+        "{err:#}"
+    );
+    // The log isn't truncated so the entries after the corrupt one aren't lost.
+    assert_eq!(std::fs::read(&path).unwrap().len(), len);
+}
+
+#[tokio::test]
+async fn test_replay_revert() {
+    let dir = tempfile::tempdir().unwrap();
+    let (contract, solution, other) = {
+        let storage = MemoryStorage::with_persistence(config(dir.path(), u64::MAX)).unwrap();
+        let (contract, solution) = populate(&storage).await;
+        let address = essential_hash::contract_addr::from_contract(&contract.contract);
+        let other = solution_with_decision_variables(1);
+        storage
+            .insert_solution_into_pool(other.clone())
+            .await
+            .unwrap();
+        storage
+            .commit_block(CommitData {
+                block_number: 1,
+                block_timestamp: Duration::from_secs(2),
+                gas_used: 0,
+                failed: &[],
+                solved: &[essential_hash::hash(&other)],
+                state_updates: Box::new(std::iter::once((address, vec![0], vec![43]))),
+            })
+            .await
+            .unwrap();
+        storage.revert_to_block(0).await.unwrap();
+        (contract, solution, other)
+    };
+
+    let storage = MemoryStorage::with_persistence(config(dir.path(), u64::MAX)).unwrap();
+    let address = essential_hash::contract_addr::from_contract(&contract.contract);
+    assert_eq!(
+        storage.query_state(&address, &vec![0]).await.unwrap(),
+        vec![42]
+    );
+    let blocks = storage.list_blocks(None, None, None).await.unwrap();
+    assert_eq!(blocks.len(), 1);
+    assert_eq!(blocks[0].solutions, vec![solution]);
+    assert_eq!(
+        storage.list_solutions_pool(None).await.unwrap(),
+        vec![other]
+    );
+}
+
+#[tokio::test]
+async fn test_replay_expire_and_evict() {
+    let dir = tempfile::tempdir().unwrap();
+    let solutions: Vec<_> = (0..4).map(solution_with_decision_variables).collect();
+    {
+        let storage = MemoryStorage::with_persistence(config(dir.path(), u64::MAX)).unwrap();
+        storage
+            .insert_solution_into_pool(solutions[0].clone())
+            .await
+            .unwrap();
+        let added_before = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        for solution in &solutions[1..] {
+            storage
+                .insert_solution_into_pool(solution.clone())
+                .await
+                .unwrap();
+        }
+        let expired = storage.expire_solutions(added_before).await.unwrap();
+        assert_eq!(expired, vec![essential_hash::hash(&solutions[0])]);
+        let evicted = storage.evict_solutions(2).await.unwrap();
+        assert_eq!(evicted, vec![essential_hash::hash(&solutions[1])]);
+    }
+
+    let storage = MemoryStorage::with_persistence(config(dir.path(), u64::MAX)).unwrap();
+    assert_eq!(
+        storage.list_solutions_pool(None).await.unwrap(),
+        solutions[2..]
+    );
+    let failed = storage.list_failed_solutions_pool(None).await.unwrap();
+    let reasons: Vec<_> = failed.into_iter().map(|f| f.reason).collect();
+    assert_eq!(
+        reasons,
+        vec![SolutionFailReason::Expired, SolutionFailReason::Evicted]
+    );
+}
+
+#[tokio::test]
+async fn test_oversized_entry() {
+    let dir = tempfile::tempdir().unwrap();
+    let (contract, solution) = {
+        let storage = MemoryStorage::with_persistence(config(dir.path(), u64::MAX)).unwrap();
+        populate(&storage).await
+    };
+
+    // A corrupt length prefix is treated like a torn entry rather than allocated.
+    let path = dir.path().join(LOG_FILE);
+    let len = std::fs::metadata(&path).unwrap().len();
+    let mut log = OpenOptions::new().append(true).open(&path).unwrap();
+    log.write_all(&u32::MAX.to_le_bytes()).unwrap();
+    log.write_all(&[1, 2, 3]).unwrap();
+    drop(log);
+
+    let storage = MemoryStorage::with_persistence(config(dir.path(), u64::MAX)).unwrap();
+    check(&storage, &contract, &solution).await;
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), len);
+}
+
+#[tokio::test]
+async fn test_format_version() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let storage = MemoryStorage::with_persistence(config(dir.path(), 1)).unwrap();
+        populate(&storage).await;
+        wait_for_snapshot(&storage).await;
+    }
+    MemoryStorage::with_persistence(config(dir.path(), 1)).unwrap();
+
+    // Files from another version of the format are refused rather than misread.
+    for file in [LOG_FILE, SNAPSHOT_FILE] {
+        let path = dir.path().join(file);
+        let original = std::fs::read(&path).unwrap();
+        let mut bytes = original.clone();
+        bytes[4..8].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        std::fs::write(&path, bytes).unwrap();
+        let Err(err) = MemoryStorage::with_persistence(config(dir.path(), 1)) else {
+            panic!("{file} with another format version was loaded");
+        };
+        assert!(format!("{err:#}").contains("not supported"), "{err:#}");
+        std::fs::write(&path, original).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_snapshot_failure() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = MemoryStorage::with_persistence(config(dir.path(), 1)).unwrap();
+
+    // The snapshot can't be written while its temporary file is a directory.
+    let tmp = dir.path().join(SNAPSHOT_FILE).with_extension("tmp");
+    std::fs::create_dir(&tmp).unwrap();
+    let (contract, solution) = populate(&storage).await;
+    wait_for_snapshot(&storage).await;
+    assert!(!dir.path().join(SNAPSHOT_FILE).exists());
+    drop(storage);
+
+    // The writes were still logged.
+    std::fs::remove_dir(&tmp).unwrap();
+    let storage = MemoryStorage::with_persistence(config(dir.path(), 1)).unwrap();
+    check(&storage, &contract, &solution).await;
+}
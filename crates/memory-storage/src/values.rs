@@ -4,6 +4,7 @@ use std::{
     time::Duration,
 };
 
+use essential_storage::BlockHeader;
 use essential_types::{
     contract::Contract, predicate::Predicate, solution::Solution, ContentAddress, Word,
 };
@@ -109,6 +110,7 @@ pub fn page_blocks(
                         number,
                         timestamp,
                         hashes,
+                        ..
                     } = block;
                     let solutions = hashes
                         .iter()
@@ -133,6 +135,7 @@ pub fn page_blocks(
                     number,
                     timestamp,
                     hashes,
+                    ..
                 } = block;
                 let solutions = hashes
                     .iter()
@@ -148,3 +151,28 @@ pub fn page_blocks(
             .collect(),
     }
 }
+
+pub fn page_block_headers(
+    blocks: &BTreeMap<Duration, super::Block>,
+    block_number_index: &HashMap<u64, Duration>,
+    block_number: Option<u64>,
+    page: usize,
+    page_size: usize,
+) -> Vec<BlockHeader> {
+    let start = page * page_size;
+    let block_number = block_number.unwrap_or(0);
+    let Some(block_number_time) = block_number_index.get(&block_number).copied() else {
+        return Vec::new();
+    };
+    blocks
+        .range(block_number_time..)
+        .skip(start)
+        .take(page_size)
+        .map(|(_, block)| BlockHeader {
+            number: block.number,
+            timestamp: block.timestamp,
+            gas_used: block.gas_used,
+            state_root: block.state_root,
+        })
+        .collect()
+}
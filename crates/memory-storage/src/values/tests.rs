@@ -221,6 +221,8 @@ fn test_paging_blocks() {
                         .map(|(h, _)| h)
                         .copied()
                         .collect(),
+                    gas_used: i,
+                    state_root: [0; 32],
                 },
             )
         })
@@ -388,6 +390,7 @@ fn test_page_solutions() {
             Some(essential_storage::failed_solution::FailedSolution {
                 solution,
                 reason: r,
+                timestamp: Duration::ZERO,
             })
         },
         0,
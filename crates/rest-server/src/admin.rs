@@ -0,0 +1,327 @@
+//! Routes for operating the server.
+//!
+//! Every request to these routes must be authenticated with either
+//! the bearer token or a signature from one of the admin keys.
+
+use crate::{
+    extract::{Json, Path},
+    Error,
+};
+use anyhow::{anyhow, bail, ensure};
+use axum::{
+    extract::{Request, State},
+    http::HeaderMap,
+    middleware::{self, Next},
+    response::Response,
+    routing::{get, post},
+    Router,
+};
+use essential_server::{Controller, Essential, StateRead, Storage};
+use essential_server_types::AdminRequest;
+use essential_sign::secp256k1::PublicKey;
+use essential_types::{Block, ContentAddress, Hash, Signature};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// Header with the time a signed admin request was signed in seconds since the unix epoch.
+pub const TIMESTAMP_HEADER: &str = "x-essential-timestamp";
+
+/// Header with the signature of a signed admin request.
+///
+/// The 64 byte compact signature followed by the recovery id byte, encoded as hex.
+pub const SIGNATURE_HEADER: &str = "x-essential-signature";
+
+/// How far the timestamp of a signed admin request may be from the server's time.
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(60);
+
+/// Maximum size of the body of a signed admin request.
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Default)]
+/// How requests to the admin routes are authenticated.
+///
+/// If there is no token and no keys the admin routes are disabled.
+pub struct AdminAuth {
+    /// Requests with an `Authorization: Bearer <token>` header are allowed.
+    pub token: Option<String>,
+    /// Requests signed by any of these keys are allowed.
+    ///
+    /// See [`AdminRequest`] for what is signed.
+    pub keys: Vec<PublicKey>,
+}
+
+/// State of the authentication middleware.
+struct Authenticator {
+    auth: AdminAuth,
+    /// Hashes of the signed requests that have been accepted and their timestamps.
+    ///
+    /// Kept until the timestamp is too old to be accepted again so a signed
+    /// request can't be replayed.
+    seen: Mutex<HashMap<Hash, u64>>,
+}
+
+/// State of the admin routes.
+#[derive(Clone)]
+struct Admin<S>
+where
+    S: Storage + Clone,
+{
+    essential: Essential<S>,
+    /// Controls the main loop if the server is building blocks.
+    controller: Option<Controller>,
+}
+
+impl AdminAuth {
+    /// Whether the admin routes are enabled.
+    pub fn is_enabled(&self) -> bool {
+        self.token.is_some() || !self.keys.is_empty()
+    }
+}
+
+/// The admin routes with authentication.
+pub(crate) fn router<S, T>(
+    essential: Essential<S>,
+    controller: Option<Controller>,
+    auth: AdminAuth,
+) -> Router<T>
+where
+    S: Storage + StateRead + Clone + Send + Sync + 'static,
+    <S as StateRead>::Future: Send,
+    <S as StateRead>::Error: Send,
+{
+    Router::new()
+        .route("/admin/config", get(config))
+        .route("/admin/pause", post(pause))
+        .route("/admin/resume", post(resume))
+        .route("/admin/build-block", post(build_block))
+        .route("/admin/prune", post(prune))
+        .route("/admin/evict-solution/:hash", post(evict_solution))
+        .route("/admin/revert-to-block/:number", post(revert_to_block))
+        .route_layer(middleware::from_fn_with_state(
+            Arc::new(Authenticator {
+                auth,
+                seen: Mutex::default(),
+            }),
+            authenticate,
+        ))
+        .with_state(Admin {
+            essential,
+            controller,
+        })
+}
+
+/// Reject requests that aren't authenticated.
+async fn authenticate(
+    State(authenticator): State<Arc<Authenticator>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, Error> {
+    let request = verify(&authenticator, request)
+        .await
+        .map_err(Error::Unauthorized)?;
+    Ok(next.run(request).await)
+}
+
+/// Check the bearer token or the signature of the request.
+///
+/// The body of a signed request is read to check the signature
+/// so the request is rebuilt from it.
+/// A signed request is only accepted once.
+async fn verify(authenticator: &Authenticator, request: Request) -> anyhow::Result<Request> {
+    let auth = &authenticator.auth;
+    if let Some(bearer) = bearer_token(request.headers()) {
+        let Some(token) = &auth.token else {
+            bail!("Bearer tokens are not accepted");
+        };
+        ensure!(
+            constant_time_eq(bearer.as_bytes(), token.as_bytes()),
+            "Invalid bearer token"
+        );
+        return Ok(request);
+    }
+
+    let headers = request.headers();
+    let (Some(timestamp), Some(signature)) =
+        (headers.get(TIMESTAMP_HEADER), headers.get(SIGNATURE_HEADER))
+    else {
+        bail!("Missing bearer token or signature");
+    };
+    let timestamp: u64 = timestamp
+        .to_str()?
+        .parse()
+        .map_err(|e| anyhow!("failed to parse timestamp: {e}"))?;
+    let signature = parse_signature(signature.to_str()?)?;
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+    ensure!(
+        now.as_secs().abs_diff(timestamp) <= MAX_CLOCK_SKEW.as_secs(),
+        "Signed request has expired"
+    );
+
+    let (parts, body) = request.into_parts();
+    let body = axum::body::to_bytes(body, MAX_BODY_SIZE).await?;
+    let signed = AdminRequest {
+        method: parts.method.to_string(),
+        path: parts
+            .uri
+            .path_and_query()
+            .map_or_else(|| parts.uri.path(), |p| p.as_str())
+            .to_string(),
+        timestamp,
+        body: body.to_vec(),
+    };
+    let hash = essential_hash::hash(&signed);
+    let key = essential_sign::recover_hash(hash, &signature)?;
+    ensure!(
+        auth.keys.contains(&key),
+        "Request is not signed by an admin key"
+    );
+    authenticator.accept_once(hash, timestamp, now.as_secs())?;
+    Ok(Request::from_parts(parts, body.into()))
+}
+
+impl Authenticator {
+    /// Remember the signed request, failing if it has already been accepted.
+    ///
+    /// Forgets requests whose timestamps are now too old to be accepted.
+    fn accept_once(&self, hash: Hash, timestamp: u64, now: u64) -> anyhow::Result<()> {
+        let mut seen = self
+            .seen
+            .lock()
+            .map_err(|_| anyhow!("admin lock poisoned"))?;
+        seen.retain(|_, t| now.saturating_sub(*t) <= MAX_CLOCK_SKEW.as_secs());
+        ensure!(
+            seen.insert(hash, timestamp).is_none(),
+            "Signed request has already been used"
+        );
+        Ok(())
+    }
+}
+
+/// The token of an `Authorization: Bearer <token>` header.
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(http::header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+/// Parse a hex encoded compact signature followed by its recovery id.
+fn parse_signature(hex: &str) -> anyhow::Result<Signature> {
+    let bytes = hex::decode(hex).map_err(|e| anyhow!("failed to parse signature: {e}"))?;
+    let (recovery_id, compact) = bytes
+        .split_last()
+        .ok_or_else(|| anyhow!("empty signature"))?;
+    let compact = compact
+        .try_into()
+        .map_err(|_| anyhow!("signature must be 65 bytes"))?;
+    Ok(Signature(compact, *recovery_id))
+}
+
+/// Compare without returning early so the time taken doesn't leak the token.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+impl<S> Admin<S>
+where
+    S: Storage + Clone,
+{
+    fn controller(&self) -> Result<&Controller, Error> {
+        self.controller.as_ref().ok_or_else(|| {
+            essential_server::Error::Conflict("Block building is disabled".to_string()).into()
+        })
+    }
+}
+
+/// The configuration get endpoint.
+///
+/// Returns the configuration the main loop is running with.
+async fn config<S>(State(admin): State<Admin<S>>) -> Result<Json<essential_server::Config>, Error>
+where
+    S: Storage + Clone,
+{
+    Ok(Json(admin.controller()?.config().clone()))
+}
+
+/// The pause post endpoint.
+///
+/// Stops the main loop building blocks on its own.
+async fn pause<S>(State(admin): State<Admin<S>>) -> Result<(), Error>
+where
+    S: Storage + Clone,
+{
+    admin.controller()?.pause();
+    Ok(())
+}
+
+/// The resume post endpoint.
+///
+/// Resumes the main loop building blocks on its own.
+async fn resume<S>(State(admin): State<Admin<S>>) -> Result<(), Error>
+where
+    S: Storage + Clone,
+{
+    admin.controller()?.resume();
+    Ok(())
+}
+
+/// The build block post endpoint.
+///
+/// Builds a block now and returns it, or null if the pool was empty.
+async fn build_block<S>(State(admin): State<Admin<S>>) -> Result<Json<Option<Block>>, Error>
+where
+    S: Storage + Clone,
+{
+    let block = admin.controller()?.build_block().await?;
+    Ok(Json(block))
+}
+
+/// The prune post endpoint.
+///
+/// Expires and evicts solutions from the pool and prunes failed solutions now.
+async fn prune<S>(State(admin): State<Admin<S>>) -> Result<(), Error>
+where
+    S: Storage + Clone,
+{
+    admin.controller()?.prune().await?;
+    Ok(())
+}
+
+/// The evict solution post endpoint.
+///
+/// Takes a solution content address (encoded as hex) as a path parameter.
+async fn evict_solution<S>(
+    State(admin): State<Admin<S>>,
+    Path(hash): Path<String>,
+) -> Result<(), Error>
+where
+    S: Storage + StateRead + Clone + Send + Sync + 'static,
+    <S as StateRead>::Future: Send,
+    <S as StateRead>::Error: Send,
+{
+    let hash: ContentAddress = hash
+        .parse()
+        .map_err(|e| Error::BadRequest(anyhow!("failed to parse solution content address: {e}")))?;
+    admin.essential.evict_solution(hash.0).await?;
+    Ok(())
+}
+
+/// The revert to block post endpoint.
+///
+/// Reverts the chain to the end of the block with the given number.
+async fn revert_to_block<S>(
+    State(admin): State<Admin<S>>,
+    Path(number): Path<u64>,
+) -> Result<(), Error>
+where
+    S: Storage + StateRead + Clone + Send + Sync + 'static,
+    <S as StateRead>::Future: Send,
+    <S as StateRead>::Error: Send,
+{
+    admin.essential.revert_to_block(number).await?;
+    Ok(())
+}
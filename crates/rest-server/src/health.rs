@@ -0,0 +1,83 @@
+//! Liveness and readiness routes for orchestrators.
+
+use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
+use essential_server::{Controller, Essential, StateRead, Storage};
+use essential_server_types::Readiness;
+use std::time::Duration;
+
+/// How long the storage has to answer before it's considered unavailable.
+const STORAGE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// State of the health routes.
+#[derive(Clone)]
+struct Health<S>
+where
+    S: Storage + Clone,
+{
+    essential: Essential<S>,
+    /// Controls the main loop if the server is building blocks.
+    controller: Option<Controller>,
+}
+
+/// The health routes.
+pub(crate) fn router<S, T>(essential: Essential<S>, controller: Option<Controller>) -> Router<T>
+where
+    S: Storage + StateRead + Clone + Send + Sync + 'static,
+    <S as StateRead>::Future: Send,
+    <S as StateRead>::Error: Send,
+{
+    Router::new()
+        .route("/health/live", get(live))
+        .route("/health/ready", get(ready))
+        .with_state(Health {
+            essential,
+            controller,
+        })
+}
+
+/// The liveness get endpoint.
+///
+/// Returns 503 if the main loop has stopped, otherwise 200.
+async fn live<S>(State(health): State<Health<S>>) -> StatusCode
+where
+    S: Storage + Clone,
+{
+    match &health.controller {
+        Some(controller) if !controller.is_running() => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::OK,
+    }
+}
+
+/// The readiness get endpoint.
+///
+/// Returns the readiness as JSON with 200 if the server is ready, otherwise 503.
+async fn ready<S>(State(health): State<Health<S>>) -> (StatusCode, Json<Readiness>)
+where
+    S: Storage + StateRead + Clone + Send + Sync + 'static,
+    <S as StateRead>::Future: Send,
+    <S as StateRead>::Error: Send,
+{
+    let essential = &health.essential;
+    let checks = async { tokio::try_join!(essential.get_latest_block(), essential.pool_size()) };
+    let (storage, last_block, pool_size) = match tokio::time::timeout(STORAGE_TIMEOUT, checks).await
+    {
+        Ok(Ok((last_block, pool_size))) => (true, last_block, Some(pool_size)),
+        _ => (false, None, None),
+    };
+    let main_loop_running = health.controller.as_ref().map(Controller::is_running);
+    let readiness = Readiness {
+        ready: storage && main_loop_running != Some(false),
+        storage,
+        main_loop_running,
+        last_block_number: last_block.as_ref().map(|block| block.number as u64),
+        last_block_time: last_block.as_ref().map(|block| block.timestamp.as_secs()),
+        pool_size,
+        main_loop: health.controller.as_ref().map(Controller::status),
+    };
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(readiness))
+}
@@ -5,23 +5,32 @@
 
 use anyhow::anyhow;
 use axum::{
-    extract::{Path, Query, State},
+    extract::State,
+    http::StatusCode,
     response::{
         sse::{Event, KeepAlive},
         IntoResponse, Sse,
     },
     routing::{get, post},
-    Json, Router,
+    Router,
+};
+use essential_server::{
+    BlockHeader, BlockUpdate, CheckSolutionOutput, Controller, Essential, FailedSolution,
+    SolutionOutcome, SolutionOutcomes, StateChange, StateRead, Storage, MAX_STATE_RANGE,
+    MAX_SUBSCRIBED_SOLUTIONS,
+};
+use essential_server_types::{
+    CheckSolution, ErrorCode, ErrorResponse, QueryStateProof, QueryStateReads,
+    QueryStateReadsOutput, SolutionOutcomeUpdate, SolutionRejection,
 };
-use essential_server::{CheckSolutionOutput, Essential, SolutionOutcome, StateRead, Storage};
-use essential_server_types::{CheckSolution, QueryStateReads, QueryStateReadsOutput};
 use essential_types::{
     contract::{Contract, SignedContract},
     convert::word_from_bytes,
     predicate::Predicate,
     solution::Solution,
-    Block, ContentAddress, PredicateAddress, Word,
+    Block, ContentAddress, Key, PredicateAddress, Word,
 };
+use extract::{Json, Path, Query};
 use futures::{Stream, StreamExt};
 use hyper::body::Incoming;
 use hyper_util::rt::{TokioExecutor, TokioIo};
@@ -32,11 +41,22 @@ use tokio::{
     sync::oneshot,
     task::JoinSet,
 };
-use tower::Service;
+use tower::{limit::ConcurrencyLimitLayer, Service};
 use tower_http::cors::CorsLayer;
 
+pub use admin::{AdminAuth, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+
+mod admin;
+mod extract;
+mod health;
+mod metrics;
+
 const MAX_CONNECTIONS: usize = 2000;
 
+/// The number of state proof queries served at once.
+/// Other queries wait as a proof may build the state tree of a block.
+const MAX_STATE_PROOF_QUERIES: usize = 8;
+
 #[derive(Debug, Clone)]
 /// Server configuration.
 pub struct Config {
@@ -44,8 +64,14 @@ pub struct Config {
     /// or just serve requests.
     /// Default is `true`.
     pub build_blocks: bool,
+    /// Strategy used to choose and order the solutions in each block.
+    /// Default is FIFO.
+    pub block_builder: essential_server::BlockBuilderKind,
     /// Essential server configuration.
     pub server_config: essential_server::Config,
+    /// Authentication for the `/admin` routes.
+    /// Default is no authentication which disables them.
+    pub admin_auth: AdminAuth,
 }
 
 #[derive(Deserialize)]
@@ -71,6 +97,13 @@ struct Page {
     page: u64,
 }
 
+#[derive(Deserialize)]
+/// Type to deserialize a list of solution hashes query parameter.
+struct SolutionHashes {
+    /// Comma separated solution content addresses encoded as hex.
+    hashes: String,
+}
+
 #[derive(Deserialize)]
 /// Type to deserialize a block number query parameter.
 struct BlockNumber {
@@ -78,6 +111,19 @@ struct BlockNumber {
     block: u64,
 }
 
+#[derive(Deserialize)]
+/// Type to deserialize a state range query parameters.
+struct StateRange {
+    /// The first key encoded as hex. The default is the empty key.
+    start: Option<String>,
+    /// The key to stop before encoded as hex.
+    end: Option<String>,
+    /// Prefix of the keys encoded as hex. Can't be used with `start` or `end`.
+    prefix: Option<String>,
+    /// Maximum number of keys to return.
+    limit: Option<usize>,
+}
+
 /// Run the server.
 ///
 /// - Takes the essential library to run it.
@@ -99,19 +145,35 @@ where
 {
     // Spawn essential and get the handle.
     let handle = if config.build_blocks {
-        Some(essential.clone().spawn(config.server_config)?)
+        Some(
+            essential
+                .clone()
+                .spawn(config.server_config, config.block_builder)?,
+        )
     } else {
         None
     };
 
+    let mut allow_headers = vec![http::header::CONTENT_TYPE];
+    if config.admin_auth.is_enabled() {
+        // Admin requests are authenticated with these headers.
+        allow_headers.extend([
+            http::header::AUTHORIZATION,
+            http::HeaderName::from_static(TIMESTAMP_HEADER),
+            http::HeaderName::from_static(SIGNATURE_HEADER),
+        ]);
+    }
     let cors = CorsLayer::new()
         .allow_origin(tower_http::cors::Any)
         .allow_methods([http::Method::GET, http::Method::POST, http::Method::OPTIONS])
-        .allow_headers([http::header::CONTENT_TYPE]);
+        .allow_headers(allow_headers);
+
+    let controller = handle.as_ref().map(|handle| handle.controller());
 
     // Create all the endpoints.
-    let app = Router::new()
+    let mut app = Router::new()
         .route("/", get(health_check))
+        .route("/metrics", get(metrics))
         .route("/deploy-contract", post(deploy_contract))
         .route("/get-contract/:address", get(get_contract))
         .route("/get-predicate/:contract/:address", get(get_predicate))
@@ -119,16 +181,43 @@ where
         .route("/subscribe-contracts", get(subscribe_contracts))
         .route("/submit-solution", post(submit_solution))
         .route("/list-solutions-pool", get(list_solutions_pool))
+        .route("/list-failed-solutions", get(list_failed_solutions))
         .route("/query-state/:address/:key", get(query_state))
+        .route(
+            "/query-state-proof/:address/:key",
+            get(query_state_proof).layer(ConcurrencyLimitLayer::new(MAX_STATE_PROOF_QUERIES)),
+        )
+        .route("/query-state-range/:address", get(query_state_range))
         .route("/list-blocks", get(list_blocks))
+        .route("/list-block-headers", get(list_block_headers))
+        .route("/block/:number/state-diff", get(get_block_state_diff))
         .route("/subscribe-blocks", get(subscribe_blocks))
         .route("/solution-outcome/:hash", get(solution_outcome))
+        .route("/solution/:hash", get(get_solution))
+        .route(
+            "/subscribe-solution-outcome/:hash",
+            get(subscribe_solution_outcome),
+        )
+        .route(
+            "/subscribe-solution-outcomes",
+            get(subscribe_solution_outcomes),
+        )
         .route("/check-solution", post(check_solution))
         .route(
             "/check-solution-with-contracts",
             post(check_solution_with_contracts),
         )
         .route("/query-state-reads", post(query_state_reads))
+        .merge(health::router(essential.clone(), controller.clone()));
+    if config.admin_auth.is_enabled() {
+        app = app.merge(admin::router(
+            essential.clone(),
+            controller.clone(),
+            config.admin_auth,
+        ));
+    }
+    let app = app
+        .fallback(not_found)
         .layer(cors)
         .with_state(essential.clone());
 
@@ -142,10 +231,11 @@ where
         .send(addr)
         .map_err(|_| anyhow::anyhow!("Failed to send local address"))?;
 
-    // Serve the app.
-    serve(app, listener, shutdown_rx).await;
+    // Serve the app until it's shut down or the main loop stops.
+    serve(app, listener, shutdown_rx, controller).await;
 
     // After the server is done, shutdown essential.
+    // This returns the error the main loop stopped with if it stopped on its own.
     if let Some(handle) = handle {
         handle.shutdown().await?;
     }
@@ -153,8 +243,14 @@ where
     Ok(())
 }
 
-async fn serve(app: Router, listener: TcpListener, shutdown_rx: Option<oneshot::Receiver<()>>) {
-    let shut = shutdown(shutdown_rx);
+async fn serve(
+    app: Router,
+    listener: TcpListener,
+    shutdown_rx: Option<oneshot::Receiver<()>>,
+    controller: Option<Controller>,
+) {
+    metrics::CONNECTION_LIMIT.set(MAX_CONNECTIONS as i64);
+    let shut = shutdown(shutdown_rx, controller);
     tokio::pin!(shut);
 
     let mut conn_contract = JoinSet::new();
@@ -187,6 +283,8 @@ async fn serve(app: Router, listener: TcpListener, shutdown_rx: Option<oneshot::
         // concurrently.
 
         conn_contract.spawn(async move {
+            let _connection = metrics::Active::new(&metrics::CONNECTIONS);
+
             // Hyper has its own `AsyncRead` and `AsyncWrite` traits and doesn't use tokio.
             // `TokioIo` converts between them.
             let socket = TokioIo::new(socket);
@@ -228,6 +326,18 @@ async fn serve(app: Router, listener: TcpListener, shutdown_rx: Option<oneshot::
 /// The return a health check response.
 async fn health_check() {}
 
+/// The metrics get endpoint.
+///
+/// Returns the metrics of the server in the Prometheus text format.
+async fn metrics() -> Result<impl IntoResponse, Error> {
+    let encoder = prometheus::TextEncoder::new();
+    let metrics = encoder.encode_to_string(&prometheus::gather())?;
+    Ok((
+        [(http::header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+        metrics,
+    ))
+}
+
 /// The deploy contract post endpoint.
 ///
 /// Takes a signed vector of contract as a json payload.
@@ -274,7 +384,7 @@ where
 {
     let address: ContentAddress = address
         .parse()
-        .map_err(|e| anyhow!("failed to parse contract content address: {e}"))?;
+        .map_err(|e| Error::BadRequest(anyhow!("failed to parse contract content address: {e}")))?;
     let contract = essential.get_contract(&address).await?;
     Ok(Json(contract))
 }
@@ -294,10 +404,10 @@ where
 {
     let contract: ContentAddress = contract
         .parse()
-        .map_err(|e| anyhow!("failed to parse contract content address: {e}"))?;
-    let predicate: ContentAddress = address
-        .parse()
-        .map_err(|e| anyhow!("failed to parse predicate content address: {e}"))?;
+        .map_err(|e| Error::BadRequest(anyhow!("failed to parse contract content address: {e}")))?;
+    let predicate: ContentAddress = address.parse().map_err(|e| {
+        Error::BadRequest(anyhow!("failed to parse predicate content address: {e}"))
+    })?;
     let predicate = essential
         .get_predicate(&PredicateAddress {
             contract,
@@ -345,11 +455,11 @@ where
     let time = time.map(|t| Duration::from_secs(t.time));
 
     let contracts = essential.subscribe_contracts(time, page.map(|p| p.page as usize));
-    Sse::new(
+    Sse::new(metrics::subscriber(
         contracts
             .map::<Result<_, Error>, _>(|contract| Ok(Event::default().json_data(contract?)?))
             .map(|r| r.map_err(StdError)),
-    )
+    ))
     .keep_alive(KeepAlive::default())
 }
 
@@ -380,6 +490,41 @@ where
     Ok(Json(blocks))
 }
 
+/// The list block headers get endpoint.
+///
+/// Takes optional block number and page as query parameters.
+async fn list_block_headers<S>(
+    State(essential): State<Essential<S>>,
+    block: Option<Query<BlockNumber>>,
+    page: Option<Query<Page>>,
+) -> Result<Json<Vec<BlockHeader>>, Error>
+where
+    S: Storage + StateRead + Clone + Send + Sync + 'static,
+    <S as StateRead>::Future: Send,
+    <S as StateRead>::Error: Send,
+{
+    let headers = essential
+        .list_block_headers(block.map(|b| b.block), page.map(|p| p.page as usize))
+        .await?;
+    Ok(Json(headers))
+}
+
+/// The block state diff get endpoint.
+///
+/// Takes a block number as a path parameter.
+async fn get_block_state_diff<S>(
+    State(essential): State<Essential<S>>,
+    Path(number): Path<u64>,
+) -> Result<Json<Vec<StateChange>>, Error>
+where
+    S: Storage + StateRead + Clone + Send + Sync + 'static,
+    <S as StateRead>::Future: Send,
+    <S as StateRead>::Error: Send,
+{
+    let diff = essential.get_block_state_diff(number).await?;
+    Ok(Json(diff))
+}
+
 /// The subscribe blocks get endpoint.
 ///
 /// Takes optional time and page as query parameters.
@@ -398,11 +543,16 @@ where
 
     let blocks =
         essential.subscribe_blocks(time, block.map(|b| b.block), page.map(|p| p.page as usize));
-    Sse::new(
+    Sse::new(metrics::subscriber(
         blocks
-            .map::<Result<_, Error>, _>(|block| Ok(Event::default().json_data(block?)?))
+            .map::<Result<_, Error>, _>(|update| match update? {
+                BlockUpdate::Block(block) => Ok(Event::default().json_data(block)?),
+                BlockUpdate::Reorg(reorg) => {
+                    Ok(Event::default().event("reorg").json_data(reorg)?)
+                }
+            })
             .map(|r| r.map_err(StdError)),
-    )
+    ))
     .keep_alive(KeepAlive::default())
 }
 
@@ -422,32 +572,148 @@ where
     Ok(Json(solutions))
 }
 
+/// The list failed solutions get endpoint.
+///
+/// Takes an optional page as a query parameter.
+async fn list_failed_solutions<S>(
+    State(essential): State<Essential<S>>,
+    page: Option<Query<Page>>,
+) -> Result<Json<Vec<FailedSolution>>, Error>
+where
+    S: Storage + StateRead + Clone + Send + Sync + 'static,
+    <S as StateRead>::Future: Send,
+    <S as StateRead>::Error: Send,
+{
+    let solutions = essential
+        .list_failed_solutions_pool(page.map(|p| p.page as usize))
+        .await?;
+    Ok(Json(solutions))
+}
+
 /// The query state get endpoint.
 ///
 /// Takes a content address and a byte array key as path parameters.
 /// Both are encoded as hex.
+/// Takes an optional block number as a query parameter
+/// to read the state as it was at the end of that block.
 async fn query_state<S>(
     State(essential): State<Essential<S>>,
     Path((address, key)): Path<(String, String)>,
+    block: Option<Query<BlockNumber>>,
 ) -> Result<Json<Vec<Word>>, Error>
 where
     S: Storage + StateRead + Clone + Send + Sync + 'static,
     <S as StateRead>::Future: Send,
     <S as StateRead>::Error: Send,
 {
-    let address: ContentAddress = address
+    let (address, key) = parse_state_key(address, key)?;
+    let state = match block {
+        Some(block) => {
+            essential
+                .query_state_at(&address, &key, block.block)
+                .await?
+        }
+        None => essential.query_state(&address, &key).await?,
+    };
+    Ok(Json(state))
+}
+
+/// The query state proof get endpoint.
+///
+/// Takes a content address and a byte array key as path parameters.
+/// Both are encoded as hex.
+/// Takes an optional block number as a query parameter.
+async fn query_state_proof<S>(
+    State(essential): State<Essential<S>>,
+    Path((address, key)): Path<(String, String)>,
+    block: Option<Query<BlockNumber>>,
+) -> Result<Json<QueryStateProof>, Error>
+where
+    S: Storage + StateRead + Clone + Send + Sync + 'static,
+    <S as StateRead>::Future: Send,
+    <S as StateRead>::Error: Send,
+{
+    let (address, key) = parse_state_key(address, key)?;
+    let proof = essential
+        .query_state_proof(&address, &key, block.map(|b| b.block))
+        .await?;
+    Ok(Json(proof))
+}
+
+/// The query state range get endpoint.
+///
+/// Takes a content address as a path parameter encoded as hex.
+/// Takes either a start and optional end key or a key prefix
+/// and an optional limit as query parameters.
+/// Keys are encoded as hex.
+async fn query_state_range<S>(
+    State(essential): State<Essential<S>>,
+    Path(address): Path<String>,
+    Query(range): Query<StateRange>,
+) -> Result<Json<Vec<(Key, Vec<Word>)>>, Error>
+where
+    S: Storage + StateRead + Clone + Send + Sync + 'static,
+    <S as StateRead>::Future: Send,
+    <S as StateRead>::Error: Send,
+{
+    let address = parse_address(address)?;
+    let limit = range.limit.unwrap_or(MAX_STATE_RANGE);
+    let state = match range {
+        StateRange {
+            prefix: Some(prefix),
+            start: None,
+            end: None,
+            ..
+        } => {
+            let prefix = parse_key(prefix)?;
+            essential
+                .query_state_prefix(&address, &prefix, limit)
+                .await?
+        }
+        StateRange {
+            prefix: None,
+            start,
+            end,
+            ..
+        } => {
+            let start = start.map(parse_key).transpose()?.unwrap_or_default();
+            let end = end.map(parse_key).transpose()?;
+            essential
+                .query_state_range(&address, &start, end.as_ref(), limit)
+                .await?
+        }
+        _ => {
+            return Err(Error::BadRequest(anyhow!(
+                "prefix can't be used with start or end"
+            )))
+        }
+    };
+    Ok(Json(state))
+}
+
+/// Parse a hex content address and a hex byte array key into words.
+fn parse_state_key(address: String, key: String) -> Result<(ContentAddress, Key), Error> {
+    Ok((parse_address(address)?, parse_key(key)?))
+}
+
+/// Parse a hex content address.
+fn parse_address(address: String) -> Result<ContentAddress, Error> {
+    address
         .parse()
-        .map_err(|e| anyhow!("failed to parse contract content address: {e}"))?;
-    let key: Vec<u8> = hex::decode(key).map_err(|e| anyhow!("failed to decode key: {e}"))?;
+        .map_err(|e| Error::BadRequest(anyhow!("failed to parse contract content address: {e}")))
+}
+
+/// Parse a hex byte array key into words.
+fn parse_key(key: String) -> Result<Key, Error> {
+    let key: Vec<u8> =
+        hex::decode(key).map_err(|e| Error::BadRequest(anyhow!("failed to decode key: {e}")))?;
 
     // Convert the key to words.
     let key = key
         .chunks_exact(8)
         .map(|chunk| word_from_bytes(chunk.try_into().expect("Safe due to chunk size")))
         .collect::<Vec<_>>();
-
-    let state = essential.query_state(&address, &key).await?;
-    Ok(Json(state))
+    Ok(key)
 }
 
 /// The solution outcome get endpoint.
@@ -464,11 +730,98 @@ where
 {
     let address: ContentAddress = address
         .parse()
-        .map_err(|e| anyhow!("failed to parse solution content address: {e}"))?;
+        .map_err(|e| Error::BadRequest(anyhow!("failed to parse solution content address: {e}")))?;
     let outcome = essential.solution_outcome(&address.0).await?;
     Ok(Json(outcome))
 }
 
+/// The get solution get endpoint.
+///
+/// Takes a solution content address as a path parameter encoded hex.
+async fn get_solution<S>(
+    State(essential): State<Essential<S>>,
+    Path(address): Path<String>,
+) -> Result<Json<Option<SolutionOutcomes>>, Error>
+where
+    S: Storage + StateRead + Clone + Send + Sync + 'static,
+    <S as StateRead>::Future: Send,
+    <S as StateRead>::Error: Send,
+{
+    let address: ContentAddress = address
+        .parse()
+        .map_err(|e| Error::BadRequest(anyhow!("failed to parse solution content address: {e}")))?;
+    let solution = essential.get_solution(&address.0).await?;
+    Ok(Json(solution))
+}
+
+/// The subscribe solution outcome get endpoint.
+///
+/// Takes a solution content address as a path parameter encoded hex.
+/// Streams each outcome of the solution as it is recorded.
+async fn subscribe_solution_outcome<S>(
+    State(essential): State<Essential<S>>,
+    Path(address): Path<String>,
+) -> Result<Sse<impl Stream<Item = Result<Event, StdError>>>, Error>
+where
+    S: Storage + StateRead + Clone + Send + Sync + 'static,
+    <S as StateRead>::Future: Send,
+    <S as StateRead>::Error: Send,
+{
+    let address: ContentAddress = address
+        .parse()
+        .map_err(|e| Error::BadRequest(anyhow!("failed to parse solution content address: {e}")))?;
+    let outcomes = essential.subscribe_solution_outcomes(vec![address.0]);
+    Ok(Sse::new(metrics::subscriber(
+        outcomes
+            .map::<Result<_, Error>, _>(|outcome| {
+                let (_, outcome) = outcome?;
+                Ok(Event::default().json_data(outcome)?)
+            })
+            .map(|r| r.map_err(StdError)),
+    ))
+    .keep_alive(KeepAlive::default()))
+}
+
+/// The subscribe solution outcomes get endpoint.
+///
+/// Takes comma separated solution content addresses encoded as hex as the `hashes` query parameter.
+/// At most [`MAX_SUBSCRIBED_SOLUTIONS`] solutions can be subscribed to.
+/// Streams each outcome of the solutions as it is recorded.
+async fn subscribe_solution_outcomes<S>(
+    State(essential): State<Essential<S>>,
+    Query(SolutionHashes { hashes }): Query<SolutionHashes>,
+) -> Result<Sse<impl Stream<Item = Result<Event, StdError>>>, Error>
+where
+    S: Storage + StateRead + Clone + Send + Sync + 'static,
+    <S as StateRead>::Future: Send,
+    <S as StateRead>::Error: Send,
+{
+    let hashes = hashes
+        .split(',')
+        .map(|hash| hash.trim().parse::<ContentAddress>().map(|a| a.0))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| Error::BadRequest(anyhow!("failed to parse solution content address: {e}")))?;
+    if hashes.len() > MAX_SUBSCRIBED_SOLUTIONS {
+        return Err(Error::BadRequest(anyhow!(
+            "can't subscribe to more than {MAX_SUBSCRIBED_SOLUTIONS} solutions"
+        )));
+    }
+    let outcomes = essential.subscribe_solution_outcomes(hashes);
+    Ok(Sse::new(metrics::subscriber(
+        outcomes
+            .map::<Result<_, Error>, _>(|outcome| {
+                let (hash, outcome) = outcome?;
+                let update = SolutionOutcomeUpdate {
+                    solution: ContentAddress(hash),
+                    outcome,
+                };
+                Ok(Event::default().json_data(update)?)
+            })
+            .map(|r| r.map_err(StdError)),
+    ))
+    .keep_alive(KeepAlive::default()))
+}
+
 /// The check solution post endpoint.
 ///
 /// Takes a signed solution as a json payload.
@@ -505,9 +858,12 @@ where
 
 /// The query state reads post endpoint.
 ///
-/// Takes a json state read query and returns the outcome
+/// Takes a json state read query and returns the outcome.
+/// Takes an optional block number as a query parameter
+/// to read the state as it was at the end of that block.
 async fn query_state_reads<S>(
     State(essential): State<Essential<S>>,
+    block: Option<Query<BlockNumber>>,
     Json(payload): Json<QueryStateReads>,
 ) -> Result<Json<QueryStateReadsOutput>, Error>
 where
@@ -515,12 +871,15 @@ where
     <S as StateRead>::Future: Send,
     <S as StateRead>::Error: Send,
 {
-    let out = essential.query_state_reads(payload).await?;
+    let out = match block {
+        Some(block) => essential.query_state_reads_at(payload, block.block).await?,
+        None => essential.query_state_reads(payload).await?,
+    };
     Ok(Json(out))
 }
 
 /// Shutdown the server manually or on ctrl-c.
-async fn shutdown(rx: Option<oneshot::Receiver<()>>) {
+async fn shutdown(rx: Option<oneshot::Receiver<()>>, controller: Option<Controller>) {
     // The manual signal is used to shutdown the server.
     let manual = async {
         match rx {
@@ -538,27 +897,108 @@ async fn shutdown(rx: Option<oneshot::Receiver<()>>) {
             .expect("Failed to listen for ctrl-c");
     };
 
-    // Wait for either signal.
+    // The server is shutdown if the main loop stops on its own
+    // so whatever supervises it can restart it.
+    let stopped = async {
+        match controller {
+            Some(controller) => controller.wait_until_stopped().await,
+            None => futures::future::pending().await,
+        }
+    };
+
+    // Wait for any signal.
     tokio::select! {
         _ = manual => {},
         _ = ctrl_c => {},
+        _ = stopped => {},
     }
 }
 
+/// The fallback for routes that don't exist.
+async fn not_found() -> Error {
+    essential_server::Error::NotFound("No such route".to_string()).into()
+}
+
 #[derive(Debug)]
-struct Error(anyhow::Error);
+enum Error {
+    /// The request couldn't be parsed.
+    BadRequest(anyhow::Error),
+    /// The request to an admin route isn't authenticated.
+    Unauthorized(anyhow::Error),
+    /// An error returned by the essential server.
+    Server(anyhow::Error),
+}
 
 #[derive(Debug)]
 struct StdError(Error);
 
+impl Error {
+    /// The error code for this error.
+    ///
+    /// Server errors are classified by the [`essential_server::Error`]
+    /// in their chain if there is one.
+    fn code(&self) -> ErrorCode {
+        let err = match self {
+            Error::BadRequest(_) => return ErrorCode::BadRequest,
+            Error::Unauthorized(_) => return ErrorCode::Unauthorized,
+            Error::Server(err) => err,
+        };
+        let server_err = err
+            .chain()
+            .find_map(|e| e.downcast_ref::<essential_server::Error>());
+        match server_err {
+            Some(essential_server::Error::Invalid(_)) => ErrorCode::Invalid,
+            Some(essential_server::Error::NotFound(_)) => ErrorCode::NotFound,
+            Some(essential_server::Error::Conflict(_)) => ErrorCode::Conflict,
+            Some(essential_server::Error::Rejected(SolutionRejection::Failed(_))) => {
+                ErrorCode::Invalid
+            }
+            Some(essential_server::Error::Rejected(_)) => ErrorCode::Conflict,
+            Some(essential_server::Error::Storage(_)) => ErrorCode::Unavailable,
+            None => ErrorCode::Internal,
+        }
+    }
+
+    /// Why a submitted solution was rejected, if it was.
+    fn rejection(&self) -> Option<SolutionRejection> {
+        self.inner().chain().find_map(|e| match e.downcast_ref() {
+            Some(essential_server::Error::Rejected(rejection)) => Some(rejection.clone()),
+            _ => None,
+        })
+    }
+
+    fn inner(&self) -> &anyhow::Error {
+        match self {
+            Error::BadRequest(err) | Error::Unauthorized(err) | Error::Server(err) => err,
+        }
+    }
+}
+
+/// The HTTP status code for an error code.
+fn status_code(code: ErrorCode) -> StatusCode {
+    match code {
+        ErrorCode::BadRequest => StatusCode::BAD_REQUEST,
+        ErrorCode::NotFound => StatusCode::NOT_FOUND,
+        ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+        ErrorCode::Conflict => StatusCode::CONFLICT,
+        ErrorCode::Invalid => StatusCode::UNPROCESSABLE_ENTITY,
+        ErrorCode::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
 impl IntoResponse for Error {
     fn into_response(self) -> axum::response::Response {
-        // Return an internal server error with the error message.
-        (
-            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            format!("{}", self.0),
-        )
-            .into_response()
+        let code = self.code();
+        let rejection = self.rejection();
+        let err = self.inner();
+        let body = ErrorResponse {
+            code,
+            message: err.to_string(),
+            details: err.chain().skip(1).map(|e| e.to_string()).collect(),
+            rejection,
+        };
+        (status_code(code), Json(body)).into_response()
     }
 }
 
@@ -567,7 +1007,7 @@ where
     E: Into<anyhow::Error>,
 {
     fn from(err: E) -> Self {
-        Self(err.into())
+        Self::Server(err.into())
     }
 }
 
@@ -581,7 +1021,7 @@ impl std::error::Error for StdError {}
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.0.fmt(f)
+        self.inner().fmt(f)
     }
 }
 
@@ -595,7 +1035,9 @@ impl Default for Config {
     fn default() -> Self {
         Self {
             build_blocks: true,
+            block_builder: Default::default(),
             server_config: Default::default(),
+            admin_auth: Default::default(),
         }
     }
 }
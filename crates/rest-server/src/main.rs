@@ -1,10 +1,12 @@
-use std::{sync::Arc, time::Duration};
+use std::{path::PathBuf, sync::Arc, time::Duration};
 
 use clap::{Parser, ValueEnum};
-use essential_memory_storage::MemoryStorage;
-use essential_rest_server::Config;
+use essential_memory_storage::{MemoryStorage, PersistenceConfig};
+use essential_rest_server::{AdminAuth, Config};
 use essential_rqlite_storage::RqliteStorage;
-use essential_server::TimeConfig;
+use essential_server::{BlockBuilderKind, BlockProduction, TimeConfig};
+use essential_sign::secp256k1::PublicKey;
+use essential_sqlite_storage::SqliteStorage;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -25,6 +27,11 @@ struct Cli {
     /// Address of the rqlite server, if using rqlite.
     rqlite_address: String,
 
+    #[arg(long, required_if_eq("db", "sqlite"))]
+    /// Path to the database file, if using sqlite.
+    /// If using memory, the directory to persist the database to.
+    db_path: Option<PathBuf>,
+
     #[arg(long)]
     /// Disable tracing.
     disable_tracing: bool,
@@ -33,6 +40,103 @@ struct Cli {
     /// Frequency at which to run the main loop in seconds.
     loop_freq: Option<u64>,
 
+    #[arg(long)]
+    /// Maximum total gas the solutions in a single block may use.
+    block_gas_limit: Option<u64>,
+
+    #[arg(long)]
+    /// Maximum gas a single solution may use.
+    solution_gas_limit: Option<u64>,
+
+    #[arg(long)]
+    /// Maximum number of solutions read from the pool when building a block.
+    /// By default the whole pool is read.
+    max_pool_solutions: Option<usize>,
+
+    #[arg(long)]
+    /// Leave solutions that are not composable with the rest of a block
+    /// in the pool to retry in the next block rather than failing them.
+    retry_not_composable: bool,
+
+    #[arg(long, requires = "retry_not_composable")]
+    /// How many blocks in a row a not composable solution is retried in
+    /// before it is failed anyway. The default is 10.
+    max_not_composable_retries: Option<usize>,
+
+    #[arg(long)]
+    /// Maximum number of solutions kept in the pool.
+    /// The oldest solutions beyond this are failed as evicted.
+    /// By default the pool is unbounded.
+    pool_capacity: Option<usize>,
+
+    #[arg(long)]
+    /// Time in seconds a solution may wait in the pool before it is failed as expired.
+    /// By default solutions never expire.
+    solution_ttl: Option<u64>,
+
+    #[arg(long)]
+    /// Time in seconds after which failed solutions are pruned.
+    /// The default is one week.
+    failed_solution_max_age: Option<u64>,
+
+    #[arg(long, conflicts_with = "failed_solution_max_age")]
+    /// Never prune failed solutions by age.
+    disable_failed_solution_max_age: bool,
+
+    #[arg(long)]
+    /// Maximum number of failed solutions kept.
+    /// The oldest failed solutions beyond this are pruned.
+    /// By default failed solutions are not pruned by count.
+    failed_solution_max_count: Option<usize>,
+
+    #[arg(long)]
+    /// File that pruned failed solutions are appended to as JSON lines before they are deleted.
+    failed_solution_archive: Option<PathBuf>,
+
+    #[arg(long)]
+    /// Time in milliseconds the main loop waits before trying again after building a block fails.
+    /// Doubles with each failure in a row. The default is one second.
+    failure_backoff_ms: Option<u64>,
+
+    #[arg(long)]
+    /// The longest time in seconds the main loop waits before trying again
+    /// after building a block fails. The default is five minutes.
+    max_failure_backoff_secs: Option<u64>,
+
+    #[arg(long)]
+    /// Shut down the server after building a block fails this many times in a row
+    /// so whatever supervises it can restart it.
+    /// By default the main loop keeps trying.
+    max_consecutive_failures: Option<usize>,
+
+    #[arg(long)]
+    /// Check each submitted solution against the state and the pool before
+    /// adding it to the pool. Duplicates, solutions that mutate the same keys
+    /// as a pending solution and solutions that fail are rejected.
+    admission_check: bool,
+
+    #[arg(long)]
+    /// Bearer token that authenticates requests to the `/admin` routes.
+    /// The admin routes are disabled unless a token or key is given.
+    admin_token: Option<String>,
+
+    #[arg(long)]
+    /// Public key encoded as hex that may sign requests to the `/admin` routes.
+    /// Can be given more than once.
+    admin_key: Vec<PublicKey>,
+
+    #[arg(long, default_value = "interval", value_parser = parse_block_production)]
+    /// When blocks are built other than on the main loop's interval:
+    /// `interval` only builds on the interval,
+    /// `manual` only builds on demand through the admin routes,
+    /// `pool-size:<N>` builds when the pool reaches N solutions and
+    /// `on-submit:<MS>` builds once no solution has been submitted for MS milliseconds.
+    block_production: BlockProduction,
+
+    #[arg(long, default_value_t = BlockBuilder::Fifo, value_enum)]
+    /// Strategy used to choose and order the solutions in each block.
+    block_builder: BlockBuilder,
+
     #[arg(long)]
     /// Disable time being included in state for each block.
     disable_time: bool,
@@ -47,6 +151,38 @@ struct Cli {
 enum Db {
     Memory,
     Rqlite,
+    Sqlite,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum BlockBuilder {
+    /// Apply solutions in the order they were submitted.
+    Fifo,
+    /// Apply the solutions that use the most gas first.
+    HighestGas,
+    /// Fit as many solutions into each block as possible.
+    MaxSolutions,
+}
+
+/// Parse a block production mode from `interval`, `manual`, `pool-size:<N>` or `on-submit:<MS>`.
+fn parse_block_production(s: &str) -> Result<BlockProduction, String> {
+    match s.split_once(':') {
+        None if s == "interval" => Ok(BlockProduction::Interval),
+        None if s == "manual" => Ok(BlockProduction::Manual),
+        Some(("pool-size", size)) => size
+            .parse()
+            .map(BlockProduction::PoolSize)
+            .map_err(|e| format!("invalid pool size: {e}")),
+        Some(("on-submit", debounce)) => debounce
+            .parse()
+            .map(|ms| BlockProduction::OnSubmit {
+                debounce: Duration::from_millis(ms),
+            })
+            .map_err(|e| format!("invalid debounce: {e}")),
+        _ => Err(format!(
+            "expected `interval`, `manual`, `pool-size:<N>` or `on-submit:<MS>`, got `{s}`"
+        )),
+    }
 }
 
 #[tokio::main]
@@ -56,8 +192,28 @@ async fn main() {
         db,
         disable_block_building,
         rqlite_address,
+        db_path,
         disable_tracing,
         loop_freq,
+        block_gas_limit,
+        solution_gas_limit,
+        max_pool_solutions,
+        retry_not_composable,
+        max_not_composable_retries,
+        pool_capacity,
+        solution_ttl,
+        failed_solution_max_age,
+        disable_failed_solution_max_age,
+        failed_solution_max_count,
+        failed_solution_archive,
+        failure_backoff_ms,
+        max_failure_backoff_secs,
+        max_consecutive_failures,
+        admission_check,
+        admin_token,
+        admin_key,
+        block_production,
+        block_builder,
         disable_time,
         allow_time_submission,
     } = Cli::parse();
@@ -80,18 +236,61 @@ async fn main() {
 
     let mut config = Config {
         build_blocks: !disable_block_building,
+        block_builder: match block_builder {
+            BlockBuilder::Fifo => BlockBuilderKind::Fifo,
+            BlockBuilder::HighestGas => BlockBuilderKind::HighestGasFirst,
+            BlockBuilder::MaxSolutions => BlockBuilderKind::GreedyMaxSolutions,
+        },
+        admin_auth: AdminAuth {
+            token: admin_token,
+            keys: admin_key,
+        },
         ..Default::default()
     };
     if let Some(run_loop_interval) = loop_freq {
         config.server_config.run_loop_interval = Duration::from_secs(run_loop_interval);
     }
+    if let Some(block_gas_limit) = block_gas_limit {
+        config.server_config.block_gas_limit = block_gas_limit;
+    }
+    if let Some(solution_gas_limit) = solution_gas_limit {
+        config.server_config.solution_gas_limit = solution_gas_limit;
+    }
+    config.server_config.block_production = block_production;
+    config.server_config.max_pool_solutions = max_pool_solutions;
+    config.server_config.retry_not_composable = retry_not_composable;
+    if let Some(max_retries) = max_not_composable_retries {
+        config.server_config.max_not_composable_retries = max_retries;
+    }
+    config.server_config.pool_capacity = pool_capacity;
+    config.server_config.solution_ttl = solution_ttl.map(Duration::from_secs);
+    if let Some(max_age) = failed_solution_max_age {
+        config.server_config.failed_solution_max_age = Some(Duration::from_secs(max_age));
+    }
+    if disable_failed_solution_max_age {
+        config.server_config.failed_solution_max_age = None;
+    }
+    config.server_config.failed_solution_max_count = failed_solution_max_count;
+    config.server_config.failed_solution_archive = failed_solution_archive;
+    if let Some(backoff) = failure_backoff_ms {
+        config.server_config.failure_backoff = Duration::from_millis(backoff);
+    }
+    if let Some(max_backoff) = max_failure_backoff_secs {
+        config.server_config.max_failure_backoff = Duration::from_secs(max_backoff);
+    }
+    config.server_config.max_consecutive_failures = max_consecutive_failures;
 
     let jh = tokio::task::spawn(async move {
         match db {
             Db::Memory => {
-                let storage = MemoryStorage::new();
+                let storage = match db_path {
+                    Some(dir) => MemoryStorage::with_persistence(PersistenceConfig::new(dir))
+                        .expect("Failed to load persisted memory database"),
+                    None => MemoryStorage::new(),
+                };
                 let essential =
-                    essential_server::Essential::new(storage, check_config, time_config);
+                    essential_server::Essential::new(storage, check_config, time_config)
+                        .with_admission_check(admission_check);
                 essential_rest_server::run(essential, address, local_addr, None, config).await
             }
             Db::Rqlite => {
@@ -99,7 +298,16 @@ async fn main() {
                     .await
                     .expect("Failed to connect to rqlite");
                 let essential =
-                    essential_server::Essential::new(storage, check_config, time_config);
+                    essential_server::Essential::new(storage, check_config, time_config)
+                        .with_admission_check(admission_check);
+                essential_rest_server::run(essential, address, local_addr, None, config).await
+            }
+            Db::Sqlite => {
+                let path = db_path.expect("The db path is required when using sqlite");
+                let storage = SqliteStorage::new(path).expect("Failed to open sqlite database");
+                let essential =
+                    essential_server::Essential::new(storage, check_config, time_config)
+                        .with_admission_check(admission_check);
                 essential_rest_server::run(essential, address, local_addr, None, config).await
             }
         }
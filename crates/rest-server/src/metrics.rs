@@ -0,0 +1,60 @@
+//! Metrics for connections and subscriptions to the server.
+//!
+//! These are registered with the default [`prometheus`] registry
+//! along with the metrics of the essential server and its storage.
+
+use futures::{Stream, StreamExt};
+use prometheus::{register_int_gauge, IntGauge};
+use std::sync::LazyLock;
+
+/// Number of open connections.
+pub(crate) static CONNECTIONS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!("essential_connections", "Number of open connections.")
+        .expect("metric is only registered once")
+});
+
+/// Maximum number of connections accepted at once.
+pub(crate) static CONNECTION_LIMIT: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "essential_connection_limit",
+        "Maximum number of connections accepted at once."
+    )
+    .expect("metric is only registered once")
+});
+
+/// Number of open server sent event subscriptions.
+pub(crate) static SSE_SUBSCRIBERS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "essential_sse_subscribers",
+        "Number of open server sent event subscriptions."
+    )
+    .expect("metric is only registered once")
+});
+
+/// Counts something as active in a gauge until it's dropped.
+pub(crate) struct Active(&'static IntGauge);
+
+impl Active {
+    pub(crate) fn new(gauge: &'static IntGauge) -> Self {
+        gauge.inc();
+        Self(gauge)
+    }
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+/// Count the stream as a subscriber until it's dropped.
+pub(crate) fn subscriber<S>(stream: S) -> impl Stream<Item = S::Item>
+where
+    S: Stream,
+{
+    let active = Active::new(&SSE_SUBSCRIBERS);
+    stream.map(move |item| {
+        let _active = &active;
+        item
+    })
+}
@@ -1,17 +1,23 @@
 use std::{time::Duration, vec};
 
 use essential_memory_storage::MemoryStorage;
-use essential_server::{CheckSolutionOutput, SolutionOutcome};
+use essential_rest_server::{AdminAuth, Config, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+use essential_server::{
+    CheckOutcome, CheckSolutionOutput, FailedSolution, SolutionFailReason, SolutionOutcome,
+    SolutionOutcomes,
+};
 use essential_server_types::{
-    CheckSolution, QueryStateReads, QueryStateReadsOutput, Slots, StateReadRequestType,
+    verify_state_proof, AdminRequest, CheckSolution, ErrorCode, ErrorResponse, QueryStateProof,
+    QueryStateReads, QueryStateReadsOutput, Readiness, Reorg, Slots, SolutionOutcomeUpdate,
+    SolutionRejection, StateReadRequestType,
 };
-use essential_storage::{StateStorage, Storage};
+use essential_storage::{BlockHeader, CommitData, StateChange, StateStorage, Storage};
 use essential_types::{
     contract::{Contract, SignedContract},
     convert::{bytes_from_word, word_4_from_u8_32},
     predicate::Predicate,
     solution::{Solution, SolutionData},
-    Block, ContentAddress, PredicateAddress, Word,
+    Block, ContentAddress, Key, PredicateAddress, Word,
 };
 use futures::{StreamExt, TryStreamExt};
 use test_utils::{
@@ -24,7 +30,10 @@ use tokio_util::{
     codec::{Decoder, FramedRead},
     io::StreamReader,
 };
-use utils::{setup, setup_with_mem, TestServer};
+use utils::{
+    essential, setup, setup_with_admin, setup_with_config, setup_with_essential, setup_with_mem,
+    TestServer, ADMIN_TOKEN,
+};
 
 mod utils;
 
@@ -153,6 +162,149 @@ async fn test_submit_solution() {
     jh.await.unwrap().unwrap();
 }
 
+#[tokio::test]
+async fn test_submit_solution_admission_check() {
+    let predicate = Predicate::empty();
+    let predicate_addr = essential_hash::content_addr(&predicate);
+    let contract = sign_contract_with_random_keypair(vec![predicate]);
+    let contract_addr = essential_hash::contract_addr::from_contract(&contract.contract);
+
+    let mem = MemoryStorage::new();
+    mem.insert_contract(contract).await.unwrap();
+
+    let TestServer {
+        client,
+        url,
+        shutdown,
+        jh,
+    } = setup_with_essential(essential(mem).with_admission_check(true)).await;
+    let mut solution = solution_with_decision_variables(1);
+    solution.data[0].predicate_to_solve = PredicateAddress {
+        contract: contract_addr,
+        predicate: predicate_addr,
+    };
+    let response = client
+        .post(url.join("/submit-solution").unwrap())
+        .json(&solution)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    // The same solution is already in the pool.
+    let response = client
+        .post(url.join("/submit-solution").unwrap())
+        .json(&solution)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 409);
+    let err = response.json::<ErrorResponse>().await.unwrap();
+    assert_eq!(err.code, ErrorCode::Conflict);
+    assert_eq!(err.rejection, Some(SolutionRejection::Duplicate));
+
+    shutdown.send(()).unwrap();
+    jh.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_error_responses() {
+    let TestServer {
+        client,
+        url,
+        shutdown,
+        jh,
+    } = setup().await;
+
+    // Malformed hex address.
+    let response = client
+        .get(url.join("/get-contract/not-hex").unwrap())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    let err = response.json::<ErrorResponse>().await.unwrap();
+    assert_eq!(err.code, ErrorCode::BadRequest);
+
+    // Solution for a predicate that isn't deployed.
+    let solution = solution_with_decision_variables(1);
+    let response = client
+        .post(url.join("/submit-solution").unwrap())
+        .json(&solution)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+    let err = response.json::<ErrorResponse>().await.unwrap();
+    assert_eq!(err.code, ErrorCode::NotFound);
+    assert_eq!(err.rejection, None);
+
+    // Contract with more predicates than allowed.
+    let contract = sign_contract_with_random_keypair(vec![Predicate::empty(); 101]);
+    let response = client
+        .post(url.join("/deploy-contract").unwrap())
+        .json(&contract)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 422);
+    let err = response.json::<ErrorResponse>().await.unwrap();
+    assert_eq!(err.code, ErrorCode::Invalid);
+    assert!(!err.details.is_empty());
+
+    // Requests that axum can't extract are also JSON errors.
+    let response = client
+        .post(url.join("/submit-solution").unwrap())
+        .header("content-type", "application/json")
+        .body("{not json")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    let err = response.json::<ErrorResponse>().await.unwrap();
+    assert_eq!(err.code, ErrorCode::BadRequest);
+
+    let response = client
+        .post(url.join("/submit-solution").unwrap())
+        .body("{}")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    let err = response.json::<ErrorResponse>().await.unwrap();
+    assert_eq!(err.code, ErrorCode::BadRequest);
+
+    let response = client
+        .get(url.join("/block/not-a-number/state-diff").unwrap())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    let err = response.json::<ErrorResponse>().await.unwrap();
+    assert_eq!(err.code, ErrorCode::BadRequest);
+
+    let response = client
+        .get(url.join("/query-state-range/00?limit=many").unwrap())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    let err = response.json::<ErrorResponse>().await.unwrap();
+    assert_eq!(err.code, ErrorCode::BadRequest);
+
+    let response = client
+        .get(url.join("/no-such-route").unwrap())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+    let err = response.json::<ErrorResponse>().await.unwrap();
+    assert_eq!(err.code, ErrorCode::NotFound);
+
+    shutdown.send(()).unwrap();
+    jh.await.unwrap().unwrap();
+}
+
 #[tokio::test]
 async fn test_query_state() {
     let contract = sign_contract_with_random_keypair(vec![Predicate::empty()]);
@@ -190,6 +342,235 @@ async fn test_query_state() {
     jh.await.unwrap().unwrap();
 }
 
+#[tokio::test]
+async fn test_query_state_at_block() {
+    let contract = sign_contract_with_random_keypair(vec![Predicate::empty()]);
+    let address = essential_hash::contract_addr::from_contract(&contract.contract);
+    let addr_words = word_4_from_u8_32(address.0);
+    let key = vec![0];
+    let solution = Solution::empty();
+
+    let mem = MemoryStorage::new();
+    mem.insert_contract(contract).await.unwrap();
+    mem.insert_solution_into_pool(solution.clone())
+        .await
+        .unwrap();
+    mem.commit_block(CommitData {
+        block_number: 0,
+        block_timestamp: Duration::from_secs(1),
+        gas_used: 0,
+        failed: &[],
+        solved: &[essential_hash::hash(&solution)],
+        state_updates: Box::new([(address.clone(), key.clone(), vec![42])].into_iter()),
+    })
+    .await
+    .unwrap();
+    mem.update_state(&address, &key, vec![43]).await.unwrap();
+
+    let TestServer {
+        client,
+        url,
+        shutdown,
+        jh,
+    } = setup_with_mem(mem).await;
+
+    let path = format!(
+        "/query-state/{address}/{}",
+        hex::encode_upper(
+            key.iter()
+                .copied()
+                .flat_map(bytes_from_word)
+                .collect::<Vec<u8>>()
+        ),
+    );
+    for (block, expected) in [(None, vec![43]), (Some("0"), vec![42])] {
+        let mut a = url.join(&path).unwrap();
+        if let Some(block) = block {
+            a.query_pairs_mut().append_pair("block", block);
+        }
+        let response = client.get(a).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.json::<Vec<Word>>().await.unwrap(), expected);
+    }
+
+    // The update outside of a block belongs to a block that doesn't exist yet.
+    let mut a = url.join(&path).unwrap();
+    a.query_pairs_mut().append_pair("block", "1");
+    let response = client.get(a).send().await.unwrap();
+    assert_eq!(response.status(), 404);
+
+    let read_key: Vec<u8> = essential_state_read_vm::asm::to_bytes(vec![
+        essential_state_read_vm::asm::Stack::Push(1).into(),
+        essential_state_read_vm::asm::StateMemory::AllocSlots.into(),
+        essential_state_read_vm::asm::Stack::Push(addr_words[0]).into(),
+        essential_state_read_vm::asm::Stack::Push(addr_words[1]).into(),
+        essential_state_read_vm::asm::Stack::Push(addr_words[2]).into(),
+        essential_state_read_vm::asm::Stack::Push(addr_words[3]).into(),
+        essential_state_read_vm::asm::Stack::Push(0).into(),
+        essential_state_read_vm::asm::Stack::Push(1).into(), // key length
+        essential_state_read_vm::asm::Stack::Push(1).into(), // num values to read
+        essential_state_read_vm::asm::Stack::Push(0).into(), // slot index
+        essential_state_read_vm::asm::StateRead::KeyRangeExtern,
+        essential_state_read_vm::asm::TotalControlFlow::Halt.into(),
+    ])
+    .collect();
+    let query = QueryStateReads::inline_empty(vec![read_key], StateReadRequestType::default());
+
+    let mut a = url.join("/query-state-reads").unwrap();
+    a.query_pairs_mut().append_pair("block", "0");
+    let response = client.post(a).json(&query).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    let outcome = response.json::<QueryStateReadsOutput>().await.unwrap();
+    let expect = QueryStateReadsOutput::All(
+        [(
+            address.clone(),
+            [(key.clone(), vec![42])].into_iter().collect(),
+        )]
+        .into_iter()
+        .collect(),
+        Slots {
+            pre: vec![vec![42]],
+            post: vec![vec![42]],
+        },
+    );
+    assert_eq!(outcome, expect);
+
+    shutdown.send(()).unwrap();
+    jh.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_query_state_range() {
+    let contract = sign_contract_with_random_keypair(vec![Predicate::empty()]);
+    let address = essential_hash::contract_addr::from_contract(&contract.contract);
+
+    let mem = MemoryStorage::new();
+    mem.insert_contract(contract).await.unwrap();
+    for key in [vec![1], vec![1, 2], vec![2], vec![3]] {
+        mem.update_state(&address, &key, key.clone()).await.unwrap();
+    }
+
+    let TestServer {
+        client,
+        url,
+        shutdown,
+        jh,
+    } = setup_with_mem(mem).await;
+
+    let hex_key = |key: Vec<Word>| {
+        hex::encode_upper(
+            key.into_iter()
+                .flat_map(bytes_from_word)
+                .collect::<Vec<u8>>(),
+        )
+    };
+    let path = format!("/query-state-range/{address}");
+    let get = |params: Vec<(&'static str, String)>| {
+        let mut a = url.join(&path).unwrap();
+        for (name, value) in params {
+            a.query_pairs_mut().append_pair(name, &value);
+        }
+        client.get(a).send()
+    };
+
+    let response = get(vec![]).await.unwrap();
+    assert_eq!(response.status(), 200);
+    let state = response.json::<Vec<(Key, Vec<Word>)>>().await.unwrap();
+    assert_eq!(state.len(), 4);
+
+    let response = get(vec![
+        ("start", hex_key(vec![1, 2])),
+        ("end", hex_key(vec![3])),
+    ])
+    .await
+    .unwrap();
+    assert_eq!(response.status(), 200);
+    let state = response.json::<Vec<(Key, Vec<Word>)>>().await.unwrap();
+    assert_eq!(state, vec![(vec![1, 2], vec![1, 2]), (vec![2], vec![2])]);
+
+    let response = get(vec![("prefix", hex_key(vec![1])), ("limit", "1".into())])
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let state = response.json::<Vec<(Key, Vec<Word>)>>().await.unwrap();
+    assert_eq!(state, vec![(vec![1], vec![1])]);
+
+    let response = get(vec![
+        ("prefix", hex_key(vec![1])),
+        ("start", hex_key(vec![1])),
+    ])
+    .await
+    .unwrap();
+    assert_eq!(response.status(), 400);
+
+    shutdown.send(()).unwrap();
+    jh.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_query_state_proof() {
+    let contract = sign_contract_with_random_keypair(vec![Predicate::empty()]);
+    let address = essential_hash::contract_addr::from_contract(&contract.contract);
+    let key = vec![0; 4];
+    let solution = Solution::empty();
+
+    let mem = MemoryStorage::new();
+    mem.insert_contract(contract).await.unwrap();
+    mem.insert_solution_into_pool(solution.clone())
+        .await
+        .unwrap();
+    mem.commit_block(CommitData {
+        block_number: 0,
+        block_timestamp: Duration::from_secs(1),
+        gas_used: 0,
+        failed: &[],
+        solved: &[essential_hash::hash(&solution)],
+        state_updates: Box::new([(address.clone(), key.clone(), vec![42])].into_iter()),
+    })
+    .await
+    .unwrap();
+
+    let TestServer {
+        client,
+        url,
+        shutdown,
+        jh,
+    } = setup_with_mem(mem).await;
+
+    let path = format!(
+        "/query-state-proof/{address}/{}",
+        hex::encode_upper(
+            key.iter()
+                .copied()
+                .flat_map(bytes_from_word)
+                .collect::<Vec<u8>>()
+        ),
+    );
+    let mut a = url.join(&path).unwrap();
+    a.query_pairs_mut().append_pair("block", "0");
+    let response = client.get(a).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    let r = response.json::<QueryStateProof>().await.unwrap();
+    assert_eq!(r.block_number, 0);
+    assert_eq!(r.value, vec![42]);
+    assert!(verify_state_proof(
+        &r.state_root,
+        &address,
+        &key,
+        &r.value,
+        &r.proof
+    ));
+
+    // A block that doesn't exist.
+    let mut a = url.join(&path).unwrap();
+    a.query_pairs_mut().append_pair("block", "1");
+    let response = client.get(a).send().await.unwrap();
+    assert_eq!(response.status(), 404);
+
+    shutdown.send(()).unwrap();
+    jh.await.unwrap().unwrap();
+}
+
 #[tokio::test]
 async fn test_query_state_reads() {
     let contract = sign_contract_with_random_keypair(vec![Predicate::empty()]);
@@ -287,15 +668,27 @@ async fn test_list_winning_blocks() {
 }
 
 #[tokio::test]
-async fn test_solution_outcome() {
+async fn test_get_block_state_diff() {
+    let contract = sign_contract_with_random_keypair(vec![Predicate::empty()]);
+    let address = essential_hash::contract_addr::from_contract(&contract.contract);
     let solution = Solution::empty();
-    let ca = essential_hash::content_addr(&solution);
 
     let mem = MemoryStorage::new();
-    mem.insert_solution_into_pool(solution).await.unwrap();
-    mem.move_solutions_to_solved(0, Duration::from_secs(1), &[ca.0])
+    mem.insert_contract(contract).await.unwrap();
+    mem.update_state(&address, &vec![0], vec![1]).await.unwrap();
+    mem.insert_solution_into_pool(solution.clone())
         .await
         .unwrap();
+    mem.commit_block(CommitData {
+        block_number: 0,
+        block_timestamp: Duration::from_secs(1),
+        gas_used: 0,
+        failed: &[],
+        solved: &[essential_hash::hash(&solution)],
+        state_updates: Box::new([(address.clone(), vec![0], vec![2])].into_iter()),
+    })
+    .await
+    .unwrap();
 
     let TestServer {
         client,
@@ -304,22 +697,45 @@ async fn test_solution_outcome() {
         jh,
     } = setup_with_mem(mem).await;
 
-    let a = url.join(&format!("/solution-outcome/{ca}")).unwrap();
+    let a = url.join("/block/0/state-diff").unwrap();
     let response = client.get(a).send().await.unwrap();
     assert_eq!(response.status(), 200);
-    let value = response.json::<Vec<SolutionOutcome>>().await.unwrap();
+    let diff = response.json::<Vec<StateChange>>().await.unwrap();
+    assert_eq!(
+        diff,
+        vec![StateChange {
+            address,
+            key: vec![0],
+            old_value: vec![],
+            new_value: vec![2],
+        }]
+    );
 
-    assert_eq!(value, vec![SolutionOutcome::Success(0)]);
+    let a = url.join("/block/1/state-diff").unwrap();
+    let response = client.get(a).send().await.unwrap();
+    assert_eq!(response.status(), 404);
 
     shutdown.send(()).unwrap();
     jh.await.unwrap().unwrap();
 }
 
 #[tokio::test]
-async fn test_check_solution() {
-    let contract = sign_contract_with_random_keypair(vec![Predicate::empty()]);
+async fn test_list_block_headers() {
+    let solution = Solution::empty();
+    let hash = essential_hash::hash(&solution);
+
     let mem = MemoryStorage::new();
-    mem.insert_contract(contract.clone()).await.unwrap();
+    mem.insert_solution_into_pool(solution).await.unwrap();
+    mem.commit_block(CommitData {
+        block_number: 0,
+        block_timestamp: Duration::from_secs(1),
+        gas_used: 42,
+        failed: &[],
+        solved: &[hash],
+        state_updates: Box::new(std::iter::empty()),
+    })
+    .await
+    .unwrap();
 
     let TestServer {
         client,
@@ -328,26 +744,176 @@ async fn test_check_solution() {
         jh,
     } = setup_with_mem(mem).await;
 
-    let contract_addr = essential_hash::contract_addr::from_contract(&contract.contract);
-    let address = essential_hash::content_addr(&contract.contract[0]);
-    let mut solution = Solution::empty();
-    solution.data.push(SolutionData {
-        predicate_to_solve: PredicateAddress {
-            contract: contract_addr,
-            predicate: address,
-        },
-        decision_variables: vec![],
-        state_mutations: vec![],
-        transient_data: vec![],
-    });
-    let response = client
-        .post(url.join("/check-solution").unwrap())
-        .json(&solution)
-        .send()
-        .await
-        .unwrap();
+    let mut a = url.join("/list-block-headers").unwrap();
+    a.query_pairs_mut()
+        .append_pair("block", "0")
+        .append_pair("page", "0");
+    let response = client.get(a).send().await.unwrap();
     assert_eq!(response.status(), 200);
-    let value = response
+    let headers = response.json::<Vec<BlockHeader>>().await.unwrap();
+    assert_eq!(
+        headers,
+        vec![BlockHeader {
+            number: 0,
+            timestamp: Duration::from_secs(1),
+            gas_used: 42,
+            state_root: essential_server_types::state_root::EMPTY,
+        }]
+    );
+
+    shutdown.send(()).unwrap();
+    jh.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_solution_outcome() {
+    let solution = Solution::empty();
+    let ca = essential_hash::content_addr(&solution);
+
+    let mem = MemoryStorage::new();
+    mem.insert_solution_into_pool(solution).await.unwrap();
+    mem.move_solutions_to_solved(0, Duration::from_secs(1), &[ca.0])
+        .await
+        .unwrap();
+
+    let TestServer {
+        client,
+        url,
+        shutdown,
+        jh,
+    } = setup_with_mem(mem).await;
+
+    let a = url.join(&format!("/solution-outcome/{ca}")).unwrap();
+    let response = client.get(a).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    let value = response.json::<Vec<SolutionOutcome>>().await.unwrap();
+
+    assert_eq!(value, vec![SolutionOutcome::Success(0)]);
+
+    shutdown.send(()).unwrap();
+    jh.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_get_solution() {
+    let solution = Solution::empty();
+    let ca = essential_hash::content_addr(&solution);
+
+    let mem = MemoryStorage::new();
+    mem.insert_solution_into_pool(solution.clone())
+        .await
+        .unwrap();
+    mem.move_solutions_to_solved(0, Duration::from_secs(1), &[ca.0])
+        .await
+        .unwrap();
+
+    let TestServer {
+        client,
+        url,
+        shutdown,
+        jh,
+    } = setup_with_mem(mem).await;
+
+    let a = url.join(&format!("/solution/{ca}")).unwrap();
+    let response = client.get(a).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    let value = response
+        .json::<Option<SolutionOutcomes>>()
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(value.solution, solution);
+    assert_eq!(value.outcome, vec![CheckOutcome::Success(0)]);
+    assert_eq!(value.timestamps, vec![Duration::from_secs(1)]);
+
+    let a = url
+        .join(&format!("/solution/{}", ContentAddress([1; 32])))
+        .unwrap();
+    let response = client.get(a).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    let value = response.json::<Option<SolutionOutcomes>>().await.unwrap();
+    assert!(value.is_none());
+
+    shutdown.send(()).unwrap();
+    jh.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_list_failed_solutions() {
+    let solution = Solution::empty();
+    let ca = essential_hash::content_addr(&solution);
+
+    let mem = MemoryStorage::new();
+    mem.insert_solution_into_pool(solution.clone())
+        .await
+        .unwrap();
+    mem.move_solutions_to_failed(&[(ca.0, SolutionFailReason::NotComposable)])
+        .await
+        .unwrap();
+
+    let TestServer {
+        client,
+        url,
+        shutdown,
+        jh,
+    } = setup_with_mem(mem).await;
+
+    let response = client
+        .get(url.join("/list-failed-solutions").unwrap())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let failed = response.json::<Vec<FailedSolution>>().await.unwrap();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].solution, solution);
+    assert_eq!(failed[0].reason, SolutionFailReason::NotComposable);
+    assert!(failed[0].timestamp > Duration::ZERO);
+
+    let mut a = url.join("/list-failed-solutions").unwrap();
+    a.query_pairs_mut().append_pair("page", "1");
+    let response = client.get(a).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    let failed = response.json::<Vec<FailedSolution>>().await.unwrap();
+    assert!(failed.is_empty());
+
+    shutdown.send(()).unwrap();
+    jh.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_check_solution() {
+    let contract = sign_contract_with_random_keypair(vec![Predicate::empty()]);
+    let mem = MemoryStorage::new();
+    mem.insert_contract(contract.clone()).await.unwrap();
+
+    let TestServer {
+        client,
+        url,
+        shutdown,
+        jh,
+    } = setup_with_mem(mem).await;
+
+    let contract_addr = essential_hash::contract_addr::from_contract(&contract.contract);
+    let address = essential_hash::content_addr(&contract.contract[0]);
+    let mut solution = Solution::empty();
+    solution.data.push(SolutionData {
+        predicate_to_solve: PredicateAddress {
+            contract: contract_addr,
+            predicate: address,
+        },
+        decision_variables: vec![],
+        state_mutations: vec![],
+        transient_data: vec![],
+    });
+    let response = client
+        .post(url.join("/check-solution").unwrap())
+        .json(&solution)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let value = response
         .json::<Option<CheckSolutionOutput>>()
         .await
         .unwrap()
@@ -530,6 +1096,539 @@ async fn test_subscribe_blocks() {
     jh.await.unwrap().unwrap();
 }
 
+#[tokio::test]
+async fn test_revert_to_block() {
+    let solutions: Vec<_> = (0..3)
+        .map(|i| solution_with_all_inputs_fixed_size(i, 4))
+        .collect();
+    let mem = MemoryStorage::new();
+    for (i, solution) in solutions.iter().enumerate() {
+        mem.insert_solution_into_pool(solution.clone())
+            .await
+            .unwrap();
+        mem.move_solutions_to_solved(
+            i as u64,
+            Duration::from_secs(i as u64 + 1),
+            &[essential_hash::hash(solution)],
+        )
+        .await
+        .unwrap();
+    }
+
+    let TestServer {
+        client,
+        url,
+        shutdown,
+        jh,
+    } = setup_with_admin(essential(mem.clone()), vec![]).await;
+
+    let a = url.join("/subscribe-blocks").unwrap();
+    let response = client.get(a).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    let mut s = make_event_stream(response);
+    for i in 0..3 {
+        let (event, data) = s.try_next().await.unwrap().unwrap();
+        assert_eq!(event, None);
+        assert_eq!(serde_json::from_str::<Block>(&data).unwrap().number, i);
+    }
+
+    let a = url.join("/admin/revert-to-block/3").unwrap();
+    let response = client
+        .post(a)
+        .bearer_auth(ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+
+    let a = url.join("/admin/revert-to-block/0").unwrap();
+    let response = client
+        .post(a)
+        .bearer_auth(ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200, "{}", response.text().await.unwrap());
+
+    let (event, data) = s.try_next().await.unwrap().unwrap();
+    assert_eq!(event.as_deref(), Some("reorg"));
+    assert_eq!(
+        serde_json::from_str::<Reorg>(&data).unwrap(),
+        Reorg { block_number: 0 }
+    );
+
+    let a = url.join("/list-solutions-pool").unwrap();
+    let response = client.get(a).send().await.unwrap();
+    let pool = response.json::<Vec<Solution>>().await.unwrap();
+    assert_eq!(pool, solutions[1..]);
+
+    // The stream continues from the block after the reorg.
+    mem.move_solutions_to_solved(
+        1,
+        Duration::from_secs(4),
+        &[essential_hash::hash(&solutions[2])],
+    )
+    .await
+    .unwrap();
+    let (event, data) = s.try_next().await.unwrap().unwrap();
+    assert_eq!(event, None);
+    let block = serde_json::from_str::<Block>(&data).unwrap();
+    assert_eq!(block.number, 1);
+    assert_eq!(block.solutions, solutions[2..]);
+
+    shutdown.send(()).unwrap();
+    jh.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_health() {
+    let TestServer {
+        client,
+        url,
+        shutdown,
+        jh,
+    } = setup().await;
+
+    let response = client
+        .get(url.join("/health/live").unwrap())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    let response = client
+        .get(url.join("/health/ready").unwrap())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let mut readiness = response.json::<Readiness>().await.unwrap();
+    let main_loop = readiness.main_loop.take().unwrap();
+    assert_eq!(main_loop.consecutive_failures, 0);
+    assert!(main_loop.last_error.is_none());
+    assert_eq!(
+        readiness,
+        Readiness {
+            ready: true,
+            storage: true,
+            main_loop_running: Some(true),
+            last_block_number: None,
+            last_block_time: None,
+            pool_size: Some(0),
+            main_loop: None,
+        }
+    );
+
+    shutdown.send(()).unwrap();
+    jh.await.unwrap().unwrap();
+
+    // Without a main loop only the storage is checked.
+    let mem = MemoryStorage::new();
+    let solution = solution_with_all_inputs_fixed_size(0, 4);
+    mem.insert_solution_into_pool(solution.clone())
+        .await
+        .unwrap();
+    mem.move_solutions_to_solved(
+        0,
+        Duration::from_secs(7),
+        &[essential_hash::hash(&solution)],
+    )
+    .await
+    .unwrap();
+    mem.insert_solution_into_pool(solution_with_all_inputs_fixed_size(1, 4))
+        .await
+        .unwrap();
+    let config = Config {
+        build_blocks: false,
+        ..Default::default()
+    };
+    let TestServer {
+        client,
+        url,
+        shutdown,
+        jh,
+    } = setup_with_config(essential(mem), config).await;
+
+    let response = client
+        .get(url.join("/health/ready").unwrap())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let readiness = response.json::<Readiness>().await.unwrap();
+    assert_eq!(
+        readiness,
+        Readiness {
+            ready: true,
+            storage: true,
+            main_loop_running: None,
+            last_block_number: Some(0),
+            last_block_time: Some(7),
+            pool_size: Some(1),
+            main_loop: None,
+        }
+    );
+
+    shutdown.send(()).unwrap();
+    jh.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_main_loop_failures() {
+    // Archiving a pruned failed solution to a directory fails every block.
+    let mem = MemoryStorage::new();
+    let solution = solution_with_all_inputs_fixed_size(0, 4);
+    let hash = essential_hash::hash(&solution);
+    mem.insert_solution_into_pool(solution).await.unwrap();
+    mem.move_solutions_to_failed(&[(hash, SolutionFailReason::NotComposable)])
+        .await
+        .unwrap();
+    let config = Config {
+        server_config: essential_server::Config {
+            failed_solution_max_count: Some(0),
+            failed_solution_archive: Some(std::env::temp_dir()),
+            max_consecutive_failures: Some(1),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let TestServer {
+        shutdown: _shutdown,
+        jh,
+        ..
+    } = setup_with_config(essential(mem), config).await;
+
+    // The server shuts down with the error when the main loop gives up.
+    let err = tokio::time::timeout(Duration::from_secs(5), jh)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap_err();
+    let err = format!("{err:#}");
+    assert!(err.contains("failed 1 times in a row"), "{err}");
+    assert!(err.contains("error archiving failed solutions"), "{err}");
+}
+
+/// The value of a metric without labels in the Prometheus text format.
+fn metric(metrics: &str, name: &str) -> f64 {
+    metrics
+        .lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+        .unwrap_or_else(|| panic!("missing metric {name}"))
+        .parse()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_metrics() {
+    let TestServer {
+        client,
+        url,
+        shutdown,
+        jh,
+    } = setup().await;
+
+    let a = url.join("/subscribe-blocks").unwrap();
+    let subscription = client.get(a).send().await.unwrap();
+    assert_eq!(subscription.status(), 200);
+
+    let a = url.join("/metrics").unwrap();
+    let response = client.get(a).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        response.headers()[http::header::CONTENT_TYPE],
+        "text/plain; version=0.0.4"
+    );
+    let metrics = response.text().await.unwrap();
+
+    // Other tests share the metrics so only check for this test's connection and subscription.
+    assert_eq!(metric(&metrics, "essential_connection_limit"), 2000.0);
+    assert!(metric(&metrics, "essential_connections") >= 1.0);
+    assert!(metric(&metrics, "essential_sse_subscribers") >= 1.0);
+
+    drop(subscription);
+    shutdown.send(()).unwrap();
+    jh.await.unwrap().unwrap();
+}
+
+/// Headers that sign a post to an admin route with no body.
+fn signed_admin_headers(
+    key: &essential_sign::secp256k1::SecretKey,
+    path: &str,
+    timestamp: u64,
+) -> reqwest::header::HeaderMap {
+    let signed = AdminRequest {
+        method: "POST".to_string(),
+        path: path.to_string(),
+        timestamp,
+        body: vec![],
+    };
+    let signature = essential_sign::sign_hash(essential_hash::hash(&signed), key);
+    let mut bytes = signature.0.to_vec();
+    bytes.push(signature.1);
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert(TIMESTAMP_HEADER, timestamp.into());
+    headers.insert(SIGNATURE_HEADER, hex::encode(bytes).parse().unwrap());
+    headers
+}
+
+#[tokio::test]
+async fn test_admin_auth() {
+    // The admin routes are disabled without any authentication.
+    let TestServer {
+        client,
+        url,
+        shutdown,
+        jh,
+    } = setup().await;
+    let a = url.join("/admin/pause").unwrap();
+    let response = client.post(a).send().await.unwrap();
+    assert_eq!(response.status(), 404);
+    shutdown.send(()).unwrap();
+    jh.await.unwrap().unwrap();
+
+    let (key, public_key) = test_utils::keypair([1; 32]);
+    let (other_key, _) = test_utils::keypair([2; 32]);
+    let TestServer {
+        client,
+        url,
+        shutdown,
+        jh,
+    } = setup_with_admin(essential(MemoryStorage::new()), vec![public_key]).await;
+    let a = url.join("/admin/pause").unwrap();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    let response = client.post(a.clone()).send().await.unwrap();
+    assert_eq!(response.status(), 401);
+    let err = response.json::<ErrorResponse>().await.unwrap();
+    assert_eq!(err.code, ErrorCode::Unauthorized);
+
+    let unauthorized = [
+        client.post(a.clone()).bearer_auth("wrong"),
+        client
+            .post(a.clone())
+            .headers(signed_admin_headers(&other_key, "/admin/pause", now)),
+        // Signed too long ago.
+        client
+            .post(a.clone())
+            .headers(signed_admin_headers(&key, "/admin/pause", now - 120)),
+        // Signed for a different route.
+        client
+            .post(a.clone())
+            .headers(signed_admin_headers(&key, "/admin/resume", now)),
+    ];
+    for request in unauthorized {
+        let response = request.send().await.unwrap();
+        assert_eq!(response.status(), 401);
+    }
+
+    let response = client
+        .post(a.clone())
+        .bearer_auth(ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    // Browsers are allowed to send the authentication headers.
+    let response = client
+        .request(reqwest::Method::OPTIONS, a.clone())
+        .header("origin", "http://example.com")
+        .header("access-control-request-method", "POST")
+        .header(
+            "access-control-request-headers",
+            "authorization,x-essential-timestamp,x-essential-signature",
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let allowed = response
+        .headers()
+        .get("access-control-allow-headers")
+        .unwrap()
+        .to_str()
+        .unwrap();
+    for header in ["authorization", TIMESTAMP_HEADER, SIGNATURE_HEADER] {
+        assert!(allowed.contains(header), "{allowed}");
+    }
+
+    let response = client
+        .post(a.clone())
+        .headers(signed_admin_headers(&key, "/admin/pause", now))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200, "{}", response.text().await.unwrap());
+
+    // The same signed request can't be replayed.
+    let response = client
+        .post(a.clone())
+        .headers(signed_admin_headers(&key, "/admin/pause", now))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+
+    // A new signature is accepted.
+    let response = client
+        .post(a)
+        .headers(signed_admin_headers(&key, "/admin/pause", now - 1))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200, "{}", response.text().await.unwrap());
+
+    shutdown.send(()).unwrap();
+    jh.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_admin_block_building() {
+    let contract = vec![Predicate::empty()];
+    let predicate_address = PredicateAddress {
+        contract: essential_hash::contract_addr::from_contract(&contract.clone().into()),
+        predicate: essential_hash::content_addr(&contract[0]),
+    };
+    let solution = test_utils::solution_with_predicate(predicate_address);
+    let hash = ContentAddress(essential_hash::hash(&solution));
+
+    let essential = essential(MemoryStorage::new());
+    essential
+        .deploy_contract(sign_contract_with_random_keypair(contract))
+        .await
+        .unwrap();
+    let TestServer {
+        client,
+        url,
+        shutdown,
+        jh,
+    } = setup_with_admin(essential.clone(), vec![]).await;
+    let post = |path: &str| {
+        client
+            .post(url.join(path).unwrap())
+            .bearer_auth(ADMIN_TOKEN)
+            .send()
+    };
+
+    let response = post("/admin/pause").await.unwrap();
+    assert_eq!(response.status(), 200);
+
+    let response = client
+        .get(url.join("/admin/config").unwrap())
+        .bearer_auth(ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let config = response.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(config["run_loop_interval"]["secs"], 10);
+
+    // Evict the solution from the pool.
+    essential.submit_solution(solution.clone()).await.unwrap();
+    let response = post(&format!("/admin/evict-solution/{hash}"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200, "{}", response.text().await.unwrap());
+    let response = post(&format!("/admin/evict-solution/{hash}"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+    let failed = essential.list_failed_solutions_pool(None).await.unwrap();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].reason, SolutionFailReason::Evicted);
+
+    // Build a block with it on demand.
+    essential.submit_solution(solution.clone()).await.unwrap();
+    let response = post("/admin/build-block").await.unwrap();
+    assert_eq!(response.status(), 200);
+    let block = response.json::<Option<Block>>().await.unwrap().unwrap();
+    assert_eq!(block.solutions, vec![solution]);
+    let response = post("/admin/build-block").await.unwrap();
+    assert_eq!(response.json::<Option<Block>>().await.unwrap(), None);
+
+    let response = post("/admin/prune").await.unwrap();
+    assert_eq!(response.status(), 200);
+    let response = post("/admin/resume").await.unwrap();
+    assert_eq!(response.status(), 200);
+
+    shutdown.send(()).unwrap();
+    jh.await.unwrap().unwrap();
+
+    // The main loop can't be controlled if it isn't running.
+    let config = Config {
+        build_blocks: false,
+        admin_auth: AdminAuth {
+            token: Some(ADMIN_TOKEN.to_string()),
+            keys: vec![],
+        },
+        ..Default::default()
+    };
+    let TestServer {
+        client,
+        url,
+        shutdown,
+        jh,
+    } = setup_with_config(essential, config).await;
+    let response = client
+        .post(url.join("/admin/build-block").unwrap())
+        .bearer_auth(ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 409);
+
+    shutdown.send(()).unwrap();
+    jh.await.unwrap().unwrap();
+}
+
+/// Decodes server sent events into their event type and data.
+struct EventDecoder {}
+
+impl Decoder for EventDecoder {
+    type Item = (Option<String>, String);
+    type Error = anyhow::Error;
+
+    fn decode(&mut self, buf: &mut bytes::BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let end = buf
+            .iter()
+            .zip(buf.iter().skip(1))
+            .position(|(&a, &b)| a == b'\n' && b == b'\n');
+
+        match end {
+            Some(end) => {
+                let s = std::str::from_utf8(&buf[..end])?;
+                let mut event = None;
+                let mut data = String::new();
+                for line in s.lines() {
+                    if let Some(e) = line.strip_prefix("event: ") {
+                        event = Some(e.to_string());
+                    } else if let Some(d) = line.strip_prefix("data: ") {
+                        data.push_str(d);
+                    }
+                }
+                buf.advance(end + 2);
+                Ok(Some((event, data)))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+fn make_event_stream(
+    response: reqwest::Response,
+) -> impl futures::Stream<Item = anyhow::Result<(Option<String>, String)>> {
+    let stream = StreamReader::new(
+        response
+            .bytes_stream()
+            .map_err(|e| std::io::Error::other(format!("{}", e))),
+    );
+    FramedRead::new(stream, EventDecoder {})
+}
+
 struct BlockDecoder {}
 
 impl Decoder for BlockDecoder {
@@ -559,7 +1658,7 @@ fn make_stream(response: reqwest::Response) -> impl futures::Stream<Item = anyho
     let stream = StreamReader::new(
         response
             .bytes_stream()
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("{}", e))),
+            .map_err(|e| std::io::Error::other(format!("{}", e))),
     );
     FramedRead::new(stream, BlockDecoder {})
 }
@@ -738,7 +1837,125 @@ fn make_contract_stream(
     let stream = StreamReader::new(
         response
             .bytes_stream()
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("{}", e))),
+            .map_err(|e| std::io::Error::other(format!("{}", e))),
     );
     FramedRead::new(stream, ContractDecoder {})
 }
+
+#[tokio::test]
+async fn test_subscribe_solution_outcomes() {
+    let solutions: Vec<_> = (0..2)
+        .map(|i| solution_with_all_inputs_fixed_size(i, 1))
+        .collect();
+    let hashes: Vec<_> = solutions.iter().map(essential_hash::hash).collect();
+
+    let mem = MemoryStorage::new();
+    for solution in &solutions {
+        mem.insert_solution_into_pool(solution.clone())
+            .await
+            .unwrap();
+    }
+    mem.move_solutions_to_solved(0, Duration::from_secs(1), &hashes[0..1])
+        .await
+        .unwrap();
+
+    let TestServer {
+        client,
+        url,
+        shutdown,
+        jh,
+    } = setup_with_mem(mem.clone()).await;
+
+    let a = url
+        .join(&format!(
+            "/subscribe-solution-outcome/{}",
+            ContentAddress(hashes[0])
+        ))
+        .unwrap();
+    let response = client.get(a).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    let mut s = make_json_stream::<SolutionOutcome>(response);
+    let outcome = s.try_next().await.unwrap().unwrap();
+    assert_eq!(outcome, SolutionOutcome::Success(0));
+    drop(s);
+
+    let mut a = url.join("/subscribe-solution-outcomes").unwrap();
+    a.query_pairs_mut().append_pair(
+        "hashes",
+        &format!(
+            "{},{}",
+            ContentAddress(hashes[0]),
+            ContentAddress(hashes[1])
+        ),
+    );
+    let response = client.get(a).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    let mut s = make_json_stream::<SolutionOutcomeUpdate>(response);
+    let update = s.try_next().await.unwrap().unwrap();
+    assert_eq!(update.solution, ContentAddress(hashes[0]));
+    assert_eq!(update.outcome, SolutionOutcome::Success(0));
+
+    let r = tokio::time::timeout(Duration::from_millis(50), s.try_next()).await;
+    assert!(r.is_err());
+
+    mem.move_solutions_to_failed(&[(hashes[1], SolutionFailReason::NotComposable)])
+        .await
+        .unwrap();
+    let update = s.try_next().await.unwrap().unwrap();
+    assert_eq!(update.solution, ContentAddress(hashes[1]));
+    assert_eq!(
+        update.outcome,
+        SolutionOutcome::Fail(SolutionFailReason::NotComposable.to_string())
+    );
+    drop(s);
+
+    let mut a = url.join("/subscribe-solution-outcomes").unwrap();
+    a.query_pairs_mut().append_pair("hashes", "not-hex");
+    let response = client.get(a).send().await.unwrap();
+    assert_eq!(response.status(), 400);
+
+    let too_many = vec![ContentAddress(hashes[0]).to_string(); 129].join(",");
+    let mut a = url.join("/subscribe-solution-outcomes").unwrap();
+    a.query_pairs_mut().append_pair("hashes", &too_many);
+    let response = client.get(a).send().await.unwrap();
+    assert_eq!(response.status(), 400);
+
+    shutdown.send(()).unwrap();
+    jh.await.unwrap().unwrap();
+}
+
+struct JsonDecoder<T>(std::marker::PhantomData<T>);
+
+impl<T: serde::de::DeserializeOwned> Decoder for JsonDecoder<T> {
+    type Item = T;
+    type Error = anyhow::Error;
+
+    fn decode(&mut self, buf: &mut bytes::BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let end = buf
+            .iter()
+            .zip(buf.iter().skip(1))
+            .position(|(&a, &b)| a == b'\n' && b == b'\n');
+
+        match end {
+            Some(end) => {
+                let s = std::str::from_utf8(&buf[..end])?;
+                let s = s.trim_start_matches("data: ").trim();
+                let item = serde_json::from_str::<T>(s)?;
+                buf.advance(end + 2);
+                Ok(Some(item))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+fn make_json_stream<T: serde::de::DeserializeOwned>(
+    response: reqwest::Response,
+) -> impl futures::Stream<Item = anyhow::Result<T>> {
+    let stream = StreamReader::new(
+        response
+            .bytes_stream()
+            .map_err(|e| std::io::Error::other(format!("{}", e))),
+    );
+    FramedRead::new(stream, JsonDecoder(std::marker::PhantomData))
+}
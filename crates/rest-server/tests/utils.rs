@@ -3,13 +3,16 @@
 use std::sync::Arc;
 
 use essential_memory_storage::MemoryStorage;
-use essential_rest_server::run;
-use essential_server::TimeConfig;
+use essential_rest_server::{run, AdminAuth, Config};
+use essential_server::{Essential, TimeConfig};
 use reqwest::{Client, ClientBuilder};
 
 static SERVER: &str = "localhost:0";
 static CLIENT: &str = "http://localhost";
 
+/// The bearer token of the admin routes of [`setup_with_admin`].
+pub static ADMIN_TOKEN: &str = "admin-token";
+
 pub struct TestServer {
     pub client: Client,
     pub url: reqwest::Url,
@@ -22,20 +25,45 @@ pub async fn setup() -> TestServer {
 }
 
 pub async fn setup_with_mem(mem: MemoryStorage) -> TestServer {
-    let config = Default::default();
+    setup_with_essential(essential(mem)).await
+}
+
+/// An essential server without time.
+pub fn essential(mem: MemoryStorage) -> Essential<MemoryStorage> {
+    Essential::new(
+        mem,
+        Default::default(),
+        Arc::new(TimeConfig {
+            enable_time: false,
+            ..Default::default()
+        }),
+    )
+}
+
+pub async fn setup_with_essential(essential: Essential<MemoryStorage>) -> TestServer {
+    setup_with_config(essential, Default::default()).await
+}
+
+/// A server with the admin routes enabled by [`ADMIN_TOKEN`] and the given keys.
+pub async fn setup_with_admin(
+    essential: Essential<MemoryStorage>,
+    keys: Vec<essential_sign::secp256k1::PublicKey>,
+) -> TestServer {
+    let config = Config {
+        admin_auth: AdminAuth {
+            token: Some(ADMIN_TOKEN.to_string()),
+            keys,
+        },
+        ..Default::default()
+    };
+    setup_with_config(essential, config).await
+}
+
+pub async fn setup_with_config(essential: Essential<MemoryStorage>, config: Config) -> TestServer {
     let (tx, rx) = tokio::sync::oneshot::channel();
     let (shutdown, shutdown_rx) = tokio::sync::oneshot::channel();
-    let jh = tokio::task::spawn(async {
-        let essential = essential_server::Essential::new(
-            mem,
-            config,
-            Arc::new(TimeConfig {
-                enable_time: false,
-                ..Default::default()
-            }),
-        );
-        run(essential, SERVER, tx, Some(shutdown_rx), Default::default()).await
-    });
+    let jh =
+        tokio::task::spawn(async { run(essential, SERVER, tx, Some(shutdown_rx), config).await });
     let client = ClientBuilder::new()
         .http2_prior_knowledge()
         .build()
@@ -1,5 +1,7 @@
 CREATE TABLE IF NOT EXISTS batch (
     id INTEGER PRIMARY KEY,
     created_at_seconds INTEGER NOT NULL,
-    created_at_nanos INTEGER NOT NULL
+    created_at_nanos INTEGER NOT NULL,
+    gas_used INTEGER NOT NULL DEFAULT 0,
+    state_root BLOB NOT NULL
 );
\ No newline at end of file
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS contract_state_history (
+    id INTEGER PRIMARY KEY,
+    contract_id INTEGER NOT NULL,
+    key BLOB NOT NULL,
+    value BLOB NOT NULL,
+    block_number INTEGER NOT NULL,
+    FOREIGN KEY (contract_id) REFERENCES contracts (id),
+    UNIQUE(contract_id, key, block_number)
+);
\ No newline at end of file
@@ -1,5 +1,7 @@
 CREATE TABLE IF NOT EXISTS solutions_pool (
     id INTEGER PRIMARY KEY,
     content_hash BLOB NOT NULL UNIQUE,
+    created_at_seconds INTEGER NOT NULL,
+    created_at_nanos INTEGER NOT NULL,
     FOREIGN KEY (content_hash) REFERENCES solutions (content_hash)
 );
\ No newline at end of file
@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS state_history_start (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    block_number INTEGER NOT NULL
+);
\ No newline at end of file
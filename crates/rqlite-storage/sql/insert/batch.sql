@@ -1,4 +1,4 @@
 INSERT
-    OR IGNORE INTO batch (created_at_seconds, created_at_nanos)
+    OR IGNORE INTO batch (created_at_seconds, created_at_nanos, gas_used, state_root)
 VALUES
-    (?, ?);
\ No newline at end of file
+    (?, ?, ?, ?);
\ No newline at end of file
@@ -0,0 +1,14 @@
+INSERT
+    OR IGNORE INTO solutions_pool (content_hash, created_at_seconds, created_at_nanos)
+SELECT
+    content_hash,
+    ?,
+    -- created_at_seconds
+    ? -- created_at_nanos
+FROM
+    solved
+WHERE
+    batch_id > ?
+ORDER BY
+    batch_id,
+    id;
\ No newline at end of file
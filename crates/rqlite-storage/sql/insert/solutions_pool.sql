@@ -1 +1 @@
-INSERT OR IGNORE INTO solutions_pool (content_hash) VALUES (?) 
\ No newline at end of file
+INSERT OR IGNORE INTO solutions_pool (content_hash, created_at_seconds, created_at_nanos) VALUES (?, ?, ?) 
\ No newline at end of file
@@ -0,0 +1,5 @@
+INSERT INTO contract_state_history (contract_id, key, value, block_number)
+SELECT id, ?, ?, (SELECT COALESCE(MAX(id), 0) FROM batch)
+FROM contracts
+WHERE content_hash = ?
+ON CONFLICT (contract_id, key, block_number) DO UPDATE SET value = EXCLUDED.value;
\ No newline at end of file
@@ -0,0 +1 @@
+ALTER TABLE batch ADD COLUMN gas_used INTEGER NOT NULL DEFAULT 0;
\ No newline at end of file
@@ -0,0 +1 @@
+ALTER TABLE batch ADD COLUMN state_root BLOB NOT NULL DEFAULT '0000000000000000000000000000000000000000000000000000000000000000';
\ No newline at end of file
@@ -0,0 +1 @@
+ALTER TABLE solutions_pool ADD COLUMN created_at_nanos INTEGER NOT NULL DEFAULT 0;
\ No newline at end of file
@@ -0,0 +1 @@
+SELECT MAX(id) FROM batch;
\ No newline at end of file
@@ -0,0 +1 @@
+SELECT id, key FROM contract_state_history;
\ No newline at end of file
@@ -0,0 +1 @@
+SELECT id, key FROM contract_state;
\ No newline at end of file
@@ -0,0 +1 @@
+UPDATE contract_state SET key = 'OLD' || key;
\ No newline at end of file
@@ -0,0 +1,3 @@
+INSERT OR IGNORE INTO contract_state_history (contract_id, key, value, block_number)
+SELECT contract_id, key, value, ?
+FROM contract_state;
\ No newline at end of file
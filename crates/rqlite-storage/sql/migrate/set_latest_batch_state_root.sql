@@ -0,0 +1 @@
+UPDATE batch SET state_root = ? WHERE id = (SELECT MAX(id) FROM batch);
\ No newline at end of file
@@ -0,0 +1 @@
+UPDATE solutions_pool SET created_at_seconds = ?, created_at_nanos = ?;
\ No newline at end of file
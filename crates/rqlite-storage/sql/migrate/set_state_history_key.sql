@@ -0,0 +1 @@
+UPDATE contract_state_history SET key = ? WHERE id = ?;
\ No newline at end of file
@@ -0,0 +1 @@
+INSERT INTO state_history_start (id, block_number) VALUES (0, ?);
\ No newline at end of file
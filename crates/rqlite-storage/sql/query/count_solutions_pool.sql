@@ -0,0 +1 @@
+SELECT COUNT(*) FROM solutions_pool;
\ No newline at end of file
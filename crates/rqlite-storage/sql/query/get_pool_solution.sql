@@ -0,0 +1 @@
+SELECT id FROM solutions_pool WHERE content_hash = ?;
\ No newline at end of file
@@ -0,0 +1,3 @@
+SELECT version
+FROM schema_version
+WHERE id = 0;
\ No newline at end of file
@@ -0,0 +1,6 @@
+SELECT contract_state_history.value
+FROM contract_state_history
+JOIN contracts ON contract_state_history.contract_id = contracts.id
+WHERE contracts.content_hash = ? AND contract_state_history.key = ? AND contract_state_history.block_number <= ?
+ORDER BY contract_state_history.block_number DESC
+LIMIT 1;
\ No newline at end of file
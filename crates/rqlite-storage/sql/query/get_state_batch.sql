@@ -0,0 +1,4 @@
+SELECT contract_state.key, contract_state.value
+FROM contract_state
+JOIN contracts ON contract_state.contract_id = contracts.id
+WHERE contracts.content_hash = ? AND contract_state.key IN (SELECT value FROM json_each(?));
\ No newline at end of file
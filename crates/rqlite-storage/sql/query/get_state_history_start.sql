@@ -0,0 +1 @@
+SELECT block_number FROM state_history_start;
\ No newline at end of file
@@ -0,0 +1,4 @@
+SELECT name
+FROM sqlite_master
+WHERE type = 'table'
+    AND name = ?;
\ No newline at end of file
@@ -0,0 +1,14 @@
+SELECT
+    id,
+    created_at_seconds,
+    created_at_nanos,
+    gas_used,
+    state_root
+FROM
+    batch
+WHERE
+    id > :block_number
+ORDER BY
+    id ASC
+LIMIT
+    :page_size OFFSET :page_number * :page_size;
\ No newline at end of file
@@ -0,0 +1,17 @@
+SELECT
+    content_hash
+FROM
+    solutions_pool
+WHERE
+    id NOT IN (
+        SELECT
+            id
+        FROM
+            solutions_pool
+        ORDER BY
+            id DESC
+        LIMIT
+            ?
+    )
+ORDER BY
+    id;
\ No newline at end of file
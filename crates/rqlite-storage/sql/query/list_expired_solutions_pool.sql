@@ -0,0 +1,12 @@
+SELECT
+    content_hash
+FROM
+    solutions_pool
+WHERE
+    created_at_seconds < :seconds
+    OR (
+        created_at_seconds = :seconds
+        AND created_at_nanos < :nanos
+    )
+ORDER BY
+    id;
\ No newline at end of file
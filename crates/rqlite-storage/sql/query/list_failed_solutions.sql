@@ -1,9 +1,13 @@
 SELECT
     solution,
-    reason
+    reason,
+    failed_solutions.created_at_seconds,
+    failed_solutions.created_at_nanos
 FROM
     failed_solutions
 JOIN
     solutions ON failed_solutions.content_hash = solutions.content_hash
+ORDER BY
+    failed_solutions.id
 LIMIT
     :page_size * :page_number, :page_size;
\ No newline at end of file
@@ -0,0 +1,22 @@
+SELECT
+    solution,
+    reason,
+    failed_solutions.created_at_seconds,
+    failed_solutions.created_at_nanos
+FROM
+    failed_solutions
+    JOIN solutions ON failed_solutions.content_hash = solutions.content_hash
+WHERE
+    failed_solutions.created_at_seconds < :older_than
+    OR failed_solutions.id NOT IN (
+        SELECT
+            id
+        FROM
+            failed_solutions
+        ORDER BY
+            id DESC
+        LIMIT
+            :max_count
+    )
+ORDER BY
+    failed_solutions.id;
\ No newline at end of file
@@ -0,0 +1,22 @@
+SELECT
+    DISTINCT contracts.content_hash,
+    history.key,
+    (
+        SELECT
+            previous.value
+        FROM
+            contract_state_history AS previous
+        WHERE
+            previous.contract_id = history.contract_id
+            AND previous.key = history.key
+            AND previous.block_number <= ?
+        ORDER BY
+            previous.block_number DESC
+        LIMIT
+            1
+    ) AS value
+FROM
+    contract_state_history AS history
+    JOIN contracts ON history.contract_id = contracts.id
+WHERE
+    history.block_number > ?;
\ No newline at end of file
@@ -0,0 +1,12 @@
+SELECT
+    solutions_pool.id,
+    solution
+FROM
+    solutions_pool
+    JOIN solutions ON solutions_pool.content_hash = solutions.content_hash
+WHERE
+    solutions_pool.id > :after_id
+ORDER BY
+    solutions_pool.id
+LIMIT
+    :limit;
\ No newline at end of file
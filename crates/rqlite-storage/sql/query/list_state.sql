@@ -0,0 +1,7 @@
+SELECT
+    contracts.content_hash,
+    contract_state.key,
+    contract_state.value
+FROM
+    contract_state
+    JOIN contracts ON contract_state.contract_id = contracts.id;
\ No newline at end of file
@@ -0,0 +1,18 @@
+SELECT
+    contracts.content_hash,
+    history.key,
+    history.value
+FROM
+    contract_state_history AS history
+    JOIN contracts ON history.contract_id = contracts.id
+WHERE
+    history.block_number = (
+        SELECT
+            MAX(block_number)
+        FROM
+            contract_state_history
+        WHERE
+            contract_id = history.contract_id
+            AND key = history.key
+            AND block_number <= ?
+    );
\ No newline at end of file
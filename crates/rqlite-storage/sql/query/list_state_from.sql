@@ -0,0 +1,6 @@
+SELECT contract_state.key, contract_state.value
+FROM contract_state
+JOIN contracts ON contract_state.contract_id = contracts.id
+WHERE contracts.content_hash = ? AND contract_state.key >= ?
+ORDER BY contract_state.key
+LIMIT ?;
\ No newline at end of file
@@ -0,0 +1,12 @@
+SELECT history.key, history.value
+FROM contract_state_history AS history
+JOIN contracts ON history.contract_id = contracts.id
+WHERE contracts.content_hash = ? AND history.key >= ?
+    AND history.block_number = (
+        SELECT MAX(block_number)
+        FROM contract_state_history
+        WHERE contract_id = history.contract_id AND key = history.key AND block_number <= ?
+    )
+    AND history.value != ?
+ORDER BY history.key
+LIMIT ?;
\ No newline at end of file
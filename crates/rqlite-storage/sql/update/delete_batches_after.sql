@@ -0,0 +1,4 @@
+DELETE FROM
+    batch
+WHERE
+    id > ?;
\ No newline at end of file
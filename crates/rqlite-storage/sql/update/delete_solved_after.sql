@@ -0,0 +1,4 @@
+DELETE FROM
+    solved
+WHERE
+    batch_id > ?;
\ No newline at end of file
@@ -0,0 +1,4 @@
+DELETE FROM
+    contract_state_history
+WHERE
+    block_number > ?;
\ No newline at end of file
@@ -0,0 +1,13 @@
+DELETE FROM
+    failed_solutions
+WHERE
+    id NOT IN (
+        SELECT
+            id
+        FROM
+            failed_solutions
+        ORDER BY
+            id DESC
+        LIMIT
+            ?
+    );
\ No newline at end of file
@@ -0,0 +1,4 @@
+INSERT
+    OR REPLACE INTO schema_version (id, version)
+VALUES
+    (0, ?);
\ No newline at end of file
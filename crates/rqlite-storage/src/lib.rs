@@ -6,23 +6,27 @@
 
 use anyhow::{bail, ensure};
 use essential_hash::hash;
+use essential_server_types::state_root::StateTree;
 use essential_state_read_vm::StateRead;
 use essential_storage::{
-    failed_solution::{FailedSolution, SolutionFailReason, SolutionOutcomes},
-    key_range, CommitData, QueryState, StateStorage, Storage,
+    failed_solution::{CheckOutcome, FailedSolution, SolutionFailReason, SolutionOutcomes},
+    key_range, BlockHeader, CommitData, QueryState, StateChange, StateStorage, Storage,
 };
 use essential_types::{
     contract::{Contract, SignedContract},
     Block, ContentAddress, Hash, Key, Word,
 };
 use futures::{FutureExt, StreamExt};
-use std::{pin::Pin, sync::Arc, time::Duration};
+use std::{collections::BTreeSet, pin::Pin, sync::Arc, time::Duration};
 use thiserror::Error;
 
 use values::{single_value, QueryValues};
 
+pub use migrate::{UnsupportedSchemaVersion, SCHEMA_VERSION};
+
 const CREATE_TABLES_RETRY_DELAY: Duration = Duration::from_secs(1);
 
+mod metrics;
 #[cfg(test)]
 mod test_encode_decode;
 mod values;
@@ -34,7 +38,7 @@ const PAGE_SIZE: usize = 100;
 const RESULTS_KEY: &str = "results";
 
 /// The key to errors in the results of a query.
-const ERROR_KEY: &str = "errors";
+const ERROR_KEY: &str = "error";
 
 const MAX_DB_CONNECTIONS: usize = 400;
 
@@ -45,8 +49,19 @@ pub struct RqliteStorage {
     http: Db,
     server: reqwest::Url,
     streams: essential_storage::streams::Notify,
+    /// The tree of the current state.
+    ///
+    /// Loaded by the first state write and kept in step with the database
+    /// after that. It's dropped if a write fails so it's loaded again.
+    /// This assumes no other server writes state to the same database.
+    state_tree: Arc<tokio::sync::Mutex<Option<StateTree>>>,
+    /// The first block with recorded state if the database
+    /// was migrated from before state was recorded by block.
+    state_history_start: Option<u64>,
 }
 
+type StateTreeGuard<'a> = tokio::sync::MutexGuard<'a, Option<StateTree>>;
+
 #[derive(Clone)]
 struct Db {
     semaphore: Arc<tokio::sync::Semaphore>,
@@ -65,6 +80,17 @@ fn decode<T: serde::de::DeserializeOwned>(value: &str) -> anyhow::Result<T> {
     Ok(postcard::from_bytes(&value)?)
 }
 
+/// Encodes a state key into blob data that sorts in the same order as the key
+/// which is then hex encoded.
+fn encode_key(key: &essential_types::Key) -> String {
+    hex::encode_upper(essential_storage::encode_sortable_key(key))
+}
+
+/// Decodes a hex encoded state key.
+fn decode_key(value: &str) -> anyhow::Result<essential_types::Key> {
+    essential_storage::decode_sortable_key(&hex::decode(value)?)
+}
+
 /// Constructs an SQL statement ready for execution in the form of a list of JSON values,
 /// where the first element is the SQL string at the specified path and the following
 /// elements are its arguments.
@@ -93,13 +119,21 @@ macro_rules! include_sql {
     };
 }
 
+mod migrate;
+
 impl Db {
-    async fn acquire<F, Fut, R>(&self, f: F) -> anyhow::Result<R>
+    /// Run a request to the given rqlite endpoint once a connection is available.
+    async fn acquire<F, Fut, R>(&self, endpoint: &str, f: F) -> anyhow::Result<R>
     where
         F: FnOnce(reqwest::Client) -> Fut,
         Fut: std::future::Future<Output = anyhow::Result<R>>,
     {
+        let waiting = metrics::SEMAPHORE_WAIT.start_timer();
         let permit = self.semaphore.acquire().await?;
+        waiting.observe_duration();
+        let _request = metrics::REQUEST_DURATION
+            .with_label_values(&[endpoint])
+            .start_timer();
         f(self.http.clone()).await
     }
 }
@@ -107,25 +141,35 @@ impl Db {
 impl RqliteStorage {
     /// Create a new rqlite storage from the rqlite server address.
     pub async fn new(server: &str) -> anyhow::Result<Self> {
-        let s = Self {
+        let mut s = Self {
             http: Db {
                 semaphore: Arc::new(tokio::sync::Semaphore::new(MAX_DB_CONNECTIONS)),
                 http: reqwest::Client::new(),
             },
             server: reqwest::Url::parse(server)?,
             streams: essential_storage::streams::Notify::new(),
+            state_tree: Arc::new(tokio::sync::Mutex::new(None)),
+            state_history_start: None,
         };
         while let Err(err) = s.create_tables().await {
+            if err.is::<UnsupportedSchemaVersion>() {
+                return Err(err);
+            }
             #[cfg(feature = "tracing")]
             tracing::warn!("Failed to create tables: {:?}. Retrying...", err);
             tokio::time::sleep(CREATE_TABLES_RETRY_DELAY).await;
         }
+        let sql = &[include_sql!("query/get_state_history_start.sql")];
+        let queries = s.query_values(sql).await?;
+        s.state_history_start = single_value(&queries).and_then(|n| n.as_u64());
         Ok(s)
     }
 
     /// Create all the tables in the rqlite server.
+    /// Tables created by an older version are migrated first.
     /// This is idempotent.
     pub async fn create_tables(&self) -> anyhow::Result<()> {
+        self.migrate().await?;
         let creates = &[
             include_sql!("create/predicates.sql"),
             include_sql!("create/contracts.sql"),
@@ -134,21 +178,38 @@ impl RqliteStorage {
             include_sql!("create/solutions_pool.sql"),
             include_sql!("create/solved.sql"),
             include_sql!("create/contract_state.sql"),
+            include_sql!("create/contract_state_history.sql"),
+            include_sql!("create/state_history_start.sql"),
             include_sql!("create/batch.sql"),
             include_sql!("create/failed_solutions.sql"),
             include_sql!("index/solved_batch_id.sql"),
             include_sql!("index/solved_content_hash.sql"),
             include_sql!("index/failed_solutions_content_hash.sql"),
+            include_sql!("update/set_schema_version.sql", SCHEMA_VERSION),
         ];
         self.execute(&creates[..]).await
     }
 
+    /// Fail if the state at the end of the block wasn't recorded.
+    fn ensure_state_recorded(&self, block_number: u64) -> anyhow::Result<()> {
+        if let Some(start) = self.state_history_start {
+            ensure!(
+                block_number >= start,
+                "The state of block {block_number} is unknown. \
+                 State was first recorded at the end of block {start} when the database was migrated"
+            );
+        }
+        Ok(())
+    }
+
     /// Execute a sql statement on the rqlite server.
     async fn execute(&self, sql: &[&[serde_json::Value]]) -> anyhow::Result<()> {
         let url = self.server.join("/db/execute?transaction")?;
         let r = self
             .http
-            .acquire(|http| async move { Ok(http.post(url).json(&sql).send().await?) })
+            .acquire("execute", |http| async move {
+                Ok(http.post(url).json(&sql).send().await?)
+            })
             .await?;
         ensure!(
             r.status().is_success(),
@@ -165,7 +226,9 @@ impl RqliteStorage {
         let url = self.server.join("/db/request?transaction&level=strong")?;
         let r = self
             .http
-            .acquire(|http| async move { Ok(http.post(url).json(&sql).send().await?) })
+            .acquire("request", |http| async move {
+                Ok(http.post(url).json(&sql).send().await?)
+            })
             .await?;
         ensure!(
             r.status().is_success(),
@@ -185,7 +248,9 @@ impl RqliteStorage {
         let url = self.server.join("/db/request?transaction&level=strong")?;
         let r = self
             .http
-            .acquire(|http| async move { Ok(http.post(url).json(&sql).send().await?) })
+            .acquire("request", |http| async move {
+                Ok(http.post(url).json(&sql).send().await?)
+            })
             .await?;
         ensure!(
             r.status().is_success(),
@@ -205,7 +270,9 @@ impl RqliteStorage {
         let url = self.server.join("/db/query?transaction")?;
         let r = self
             .http
-            .acquire(|http| async move { Ok(http.post(url).json(&sql).send().await?) })
+            .acquire("query", |http| async move {
+                Ok(http.post(url).json(&sql).send().await?)
+            })
             .await?;
         ensure!(
             r.status().is_success(),
@@ -219,6 +286,68 @@ impl RqliteStorage {
     }
 }
 
+impl RqliteStorage {
+    /// Lock the state tree for a write and return a copy to update.
+    ///
+    /// The tree is loaded from the database if it isn't already.
+    /// The lock must be held until the write is executed and the
+    /// copy should only replace the tree if the write succeeds.
+    async fn lock_state_tree(&self) -> anyhow::Result<(StateTreeGuard<'_>, StateTree)> {
+        let guard = self.state_tree.lock().await;
+        let tree = match &*guard {
+            Some(tree) => tree.clone(),
+            None => {
+                let sql = &[include_sql!("query/list_state.sql")];
+                let queries = self.query_values(sql).await?;
+                let state = values::list_state(queries)?;
+                StateTree::from_state(state.iter().map(|(a, k, v)| (a, k, v)))
+            }
+        };
+        Ok((guard, tree))
+    }
+
+    /// Apply the `pending` updates to the tree.
+    ///
+    /// Updates to addresses without a contract are dropped by the database
+    /// so they are left out of the tree.
+    async fn update_state_tree(
+        &self,
+        tree: &mut StateTree,
+        pending: &[(ContentAddress, Key, Vec<Word>)],
+    ) -> anyhow::Result<()> {
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let addresses: Vec<_> = pending
+            .iter()
+            .map(|(address, _, _)| address.clone())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let sql: Vec<_> = addresses
+            .iter()
+            .map(|address| include_sql!(owned "query/get_contract_salt.sql", encode(address)))
+            .collect();
+        let sql: Vec<&[serde_json::Value]> = sql.iter().map(|v| v.as_slice()).collect();
+        let queries = self.query_values(&sql[..]).await?;
+        values::update_state_tree(tree, queries, &addresses, pending)
+    }
+
+    /// Move solutions from the pool to the failed state for the same reason.
+    async fn fail_pool_solutions(
+        &self,
+        hashes: Vec<Hash>,
+        reason: SolutionFailReason,
+    ) -> anyhow::Result<Vec<Hash>> {
+        if hashes.is_empty() {
+            return Ok(hashes);
+        }
+        let failed: Vec<_> = hashes.iter().map(|hash| (*hash, reason.clone())).collect();
+        self.move_solutions_to_failed(&failed).await?;
+        Ok(hashes)
+    }
+}
+
 fn handle_errors(
     result: &serde_json::Map<String, serde_json::Value>,
     sql: &[&[serde_json::Value]],
@@ -240,62 +369,60 @@ impl StateStorage for RqliteStorage {
         key: &essential_types::Key,
         value: Vec<essential_types::Word>,
     ) -> anyhow::Result<Vec<essential_types::Word>> {
+        let (mut tree_lock, mut tree) = self.lock_state_tree().await?;
+        // The write fails if the address has no contract.
+        tree.update(address, key, &value);
         let address = encode(address);
-        let key = encode(key);
+        let key = encode_key(key);
+        let delete = value.is_empty();
         let value = encode(&value);
-        if value.is_empty() {
+        let r = if delete {
             // Delete the value and return the existing value if it exists.
             let inserts = &[
                 include_sql!("query/get_state.sql", address.clone(), key.clone()),
-                include_sql!("update/delete_state.sql", address, key),
+                include_sql!("update/delete_state.sql", address.clone(), key.clone()),
+                include_sql!("insert/state_history.sql", key, value, address),
             ];
             self.execute_query_words(&inserts[..]).await
         } else {
             // Update the value and return the existing value if it exists.
             let inserts = &[
                 include_sql!("query/get_state.sql", address.clone(), key.clone()),
-                include_sql!("update/update_state.sql", key, value, address),
+                include_sql!(
+                    "update/update_state.sql",
+                    key.clone(),
+                    value.clone(),
+                    address.clone()
+                ),
+                include_sql!("insert/state_history.sql", key, value, address),
             ];
             self.execute_query_words(&inserts[..]).await
-        }
+        };
+        *tree_lock = r.is_ok().then_some(tree);
+        r
     }
 
     async fn update_state_batch<U>(&self, updates: U) -> anyhow::Result<Vec<Vec<Word>>>
     where
         U: IntoIterator<Item = (ContentAddress, essential_types::Key, Vec<Word>)> + Send,
     {
-        let sql: Vec<_> = updates
-            .into_iter()
-            .flat_map(|(address, key, value)| {
-                let address = encode(&address);
-                let key = encode(&key);
-                let value = encode(&value);
-                if value.is_empty() {
-                    // Delete the value and return the existing value if it exists.
-                    [
-                        include_sql!(owned "query/get_state.sql", address.clone(), key.clone()),
-                        include_sql!(owned "update/delete_state.sql", address, key),
-                    ]
-                } else {
-                    // Update the value and return the existing value if it exists.
-                    [
-                        include_sql!(owned "query/get_state.sql", address.clone(), key.clone()),
-                        include_sql!(owned "update/update_state.sql", key, value, address),
-                    ]
-                }
-            })
-            .collect();
+        let updates: Vec<_> = updates.into_iter().collect();
 
         // Return early if there are no updates.
-        if sql.is_empty() {
+        if updates.is_empty() {
             return Ok(Vec::new());
         }
 
+        let (mut tree_lock, mut tree) = self.lock_state_tree().await?;
+        self.update_state_tree(&mut tree, &updates).await?;
+        let sql = update_state_batch(updates);
+
         // TODO: Is there a way to avoid this?
         // Maybe create an owned version of execute.
         let sql: Vec<&[serde_json::Value]> = sql.iter().map(|v| &v[..]).collect();
-        let queries = self.execute_query(&sql).await?;
-        values::map_execute_to_multiple_values(queries)
+        let r = self.execute_query(&sql).await;
+        *tree_lock = r.is_ok().then_some(tree);
+        values::map_execute_to_multiple_values(r?)
     }
 }
 
@@ -306,7 +433,7 @@ impl QueryState for RqliteStorage {
         key: &essential_types::Key,
     ) -> anyhow::Result<Vec<essential_types::Word>> {
         let address = encode(address);
-        let key = encode(key);
+        let key = encode_key(key);
         let sql = &[include_sql!("query/get_state.sql", address, key)];
         let queries = self.query_values(sql).await?;
         match single_value(&queries) {
@@ -315,6 +442,61 @@ impl QueryState for RqliteStorage {
             _ => bail!("State stored incorrectly"),
         }
     }
+
+    async fn query_state_batch(
+        &self,
+        address: &ContentAddress,
+        keys: &[essential_types::Key],
+    ) -> anyhow::Result<Vec<Vec<Word>>> {
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+        let address = encode(address);
+        let keys: Vec<_> = keys.iter().map(encode_key).collect();
+        // The keys are passed as a json array so they can all be read in one query.
+        let sql = &[include_sql!(
+            "query/get_state_batch.sql",
+            address,
+            serde_json::to_string(&keys)?
+        )];
+        let queries = self.query_values(sql).await?;
+        values::get_state_batch(queries, &keys)
+    }
+
+    async fn query_state_range(
+        &self,
+        address: &ContentAddress,
+        start_key: &essential_types::Key,
+        end_key: Option<&essential_types::Key>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<(essential_types::Key, Vec<Word>)>> {
+        let address = encode(address);
+        let start_key = encode_key(start_key);
+        let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+        let queries = match end_key {
+            Some(end_key) => {
+                let end_key = encode_key(end_key);
+                let sql = &[include_sql!(
+                    "query/list_state_range.sql",
+                    address,
+                    start_key,
+                    end_key,
+                    limit
+                )];
+                self.query_values(sql).await?
+            }
+            None => {
+                let sql = &[include_sql!(
+                    "query/list_state_from.sql",
+                    address,
+                    start_key,
+                    limit
+                )];
+                self.query_values(sql).await?
+            }
+        };
+        values::list_state_range(queries)
+    }
 }
 
 impl Storage for RqliteStorage {
@@ -365,7 +547,11 @@ impl Storage for RqliteStorage {
         // TODO: Is there a way to avoid this?
         // Maybe create an owned version of execute.
         let inserts: Vec<&[serde_json::Value]> = inserts.iter().map(|v| v.as_slice()).collect();
+        // Contracts decide which state updates are applied so
+        // they can't be inserted while the state tree is updated.
+        let tree_lock = self.state_tree.lock().await;
         let r = self.execute(&inserts[..]).await;
+        drop(tree_lock);
 
         // Notify the streams of the new contract.
         self.streams.notify_new_contracts();
@@ -379,10 +565,16 @@ impl Storage for RqliteStorage {
     ) -> anyhow::Result<()> {
         let hash = encode(&hash(&solution));
         let solution = encode(&solution);
+        let unix_time = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
 
         let inserts = &[
             include_sql!("insert/solutions.sql", hash.clone(), solution),
-            include_sql!("insert/solutions_pool.sql", hash),
+            include_sql!(
+                "insert/solutions_pool.sql",
+                hash,
+                unix_time.as_secs(),
+                unix_time.subsec_nanos()
+            ),
         ];
         self.execute(&inserts[..]).await
     }
@@ -397,15 +589,22 @@ impl Storage for RqliteStorage {
             return Ok(());
         }
 
-        let sql = move_solutions_to_solved(block_number, block_timestamp, solutions)?;
+        // Hold the tree so the state can't change before the block is written.
+        let (tree_lock, tree) = self.lock_state_tree().await?;
+        let sql =
+            move_solutions_to_solved(block_number, block_timestamp, 0, solutions, tree.root())?;
 
         // TODO: Is there a way to avoid this?
         // Maybe create an owned version of execute.
         let sql: Vec<&[serde_json::Value]> = sql.iter().map(|v| v.as_slice()).collect();
         let r = self.execute(&sql[..]).await;
+        drop(tree_lock);
 
-        // Notify the streams of the new blocks.
-        self.streams.notify_new_blocks();
+        if r.is_ok() {
+            // Notify the streams of the new blocks.
+            self.streams.notify_new_blocks();
+            self.streams.notify_new_outcomes(solutions.iter().copied());
+        }
 
         r
     }
@@ -423,7 +622,10 @@ impl Storage for RqliteStorage {
         // TODO: Is there a way to avoid this?
         // Maybe create an owned version of execute.
         let sql: Vec<&[serde_json::Value]> = sql.iter().map(|v| v.as_slice()).collect();
-        self.execute(&sql[..]).await
+        let r = self.execute(&sql[..]).await;
+        self.streams
+            .notify_new_outcomes(solutions.iter().map(|(hash, _)| *hash));
+        r
     }
 
     async fn get_predicate(
@@ -542,6 +744,41 @@ impl Storage for RqliteStorage {
         values::list_solutions_pool(queries)
     }
 
+    fn stream_solutions_pool(
+        self,
+        limit: Option<usize>,
+    ) -> impl futures::Stream<Item = anyhow::Result<essential_types::solution::Solution>> + Send + 'static
+    {
+        essential_storage::streams::page_by_id(limit, PAGE_SIZE, move |after_id, limit| {
+            let storage = self.clone();
+            async move {
+                let sql = &[include_sql!(
+                    named "query/list_solutions_pool_after.sql",
+                    "after_id" => after_id,
+                    "limit" => limit
+                )];
+                let queries = storage.query_values(sql).await?;
+                values::list_solutions_pool_after(queries)
+            }
+        })
+    }
+
+    async fn pool_size(&self) -> anyhow::Result<usize> {
+        let sql = &[include_sql!("query/count_solutions_pool.sql")];
+        let queries = self.query_values(sql).await?;
+        match single_value(&queries).and_then(|size| size.as_u64()) {
+            Some(size) => Ok(usize::try_from(size)?),
+            None => bail!("Failed to count solutions pool"),
+        }
+    }
+
+    async fn is_in_pool(&self, solution_hash: Hash) -> anyhow::Result<bool> {
+        let hash = encode(&solution_hash);
+        let sql = &[include_sql!("query/get_pool_solution.sql", hash)];
+        let queries = self.query_values(sql).await?;
+        Ok(single_value(&queries).is_some())
+    }
+
     async fn list_failed_solutions_pool(
         &self,
         page: Option<usize>,
@@ -585,6 +822,20 @@ impl Storage for RqliteStorage {
         values::list_blocks(queries)
     }
 
+    async fn list_block_headers(
+        &self,
+        block_number: Option<u64>,
+        page: Option<usize>,
+    ) -> anyhow::Result<Vec<BlockHeader>> {
+        let page = page.unwrap_or(0);
+        let block_number = block_number.unwrap_or(0);
+        let sql = &[
+            include_sql!(named "query/list_block_headers.sql", "block_number" => block_number, "page_size" => PAGE_SIZE, "page_number" => page),
+        ];
+        let queries = self.query_values(sql).await?;
+        values::list_block_headers(queries)
+    }
+
     fn subscribe_blocks(
         self,
         start_time: Option<Duration>,
@@ -619,6 +870,22 @@ impl Storage for RqliteStorage {
         .flat_map(futures::stream::iter)
     }
 
+    fn subscribe_solution_outcomes(
+        self,
+        solution_hashes: Vec<Hash>,
+    ) -> impl futures::Stream<Item = anyhow::Result<(Hash, CheckOutcome)>> + Send + 'static {
+        let new_outcomes = self.streams.subscribe_outcomes();
+        essential_storage::streams::solution_outcomes(new_outcomes, solution_hashes, move |hash| {
+            let storage = self.clone();
+            async move {
+                let outcomes = storage.get_solution(hash).await?;
+                Ok(outcomes
+                    .map(|s| s.outcome.into_iter().zip(s.timestamps).collect())
+                    .unwrap_or_default())
+            }
+        })
+    }
+
     async fn get_solution(&self, solution_hash: Hash) -> anyhow::Result<Option<SolutionOutcomes>> {
         let hash = encode(&solution_hash);
         let sql = &[
@@ -637,6 +904,48 @@ impl Storage for RqliteStorage {
         self.execute(&sql[..]).await
     }
 
+    async fn prune_excess_failed_solutions(&self, max_count: usize) -> anyhow::Result<()> {
+        let sql = &[include_sql!("update/prune_excess_failed.sql", max_count)];
+        self.execute(&sql[..]).await
+    }
+
+    async fn list_prunable_failed_solutions(
+        &self,
+        older_than: Duration,
+        max_count: Option<usize>,
+    ) -> anyhow::Result<Vec<FailedSolution>> {
+        // A negative limit is no limit.
+        let max_count = max_count.map_or(-1, |c| i64::try_from(c).unwrap_or(i64::MAX));
+        let sql = &[include_sql!(named "query/list_prunable_failed.sql",
+            "older_than" => older_than.as_secs(),
+            "max_count" => max_count
+        )];
+        let queries = self.query_values(sql).await?;
+        values::list_failed_solutions(queries)
+    }
+
+    async fn expire_solutions(&self, added_before: Duration) -> anyhow::Result<Vec<Hash>> {
+        let sql = &[include_sql!(named "query/list_expired_solutions_pool.sql",
+            "seconds" => added_before.as_secs(),
+            "nanos" => added_before.subsec_nanos()
+        )];
+        let queries = self.query_values(sql).await?;
+        let expired = values::list_pool_hashes(queries)?;
+        self.fail_pool_solutions(expired, SolutionFailReason::Expired)
+            .await
+    }
+
+    async fn evict_solutions(&self, capacity: usize) -> anyhow::Result<Vec<Hash>> {
+        let sql = &[include_sql!(
+            "query/list_evicted_solutions_pool.sql",
+            capacity
+        )];
+        let queries = self.query_values(sql).await?;
+        let evicted = values::list_pool_hashes(queries)?;
+        self.fail_pool_solutions(evicted, SolutionFailReason::Evicted)
+            .await
+    }
+
     fn commit_block(
         &self,
         data: CommitData,
@@ -647,6 +956,7 @@ impl Storage for RqliteStorage {
             state_updates,
             block_number,
             block_timestamp,
+            gas_used,
         } = data;
         let r = if !failed.is_empty() {
             move_solutions_to_failed(failed)
@@ -655,47 +965,184 @@ impl Storage for RqliteStorage {
         };
 
         let new_block = !solved.is_empty();
+        let outcomes: Vec<_> = solved
+            .iter()
+            .copied()
+            .chain(failed.iter().map(|(hash, _)| *hash))
+            .collect();
 
-        let r = r.and_then(|mut sql| {
-            let r = if !solved.is_empty() {
-                move_solutions_to_solved(block_number, block_timestamp, solved)
-            } else {
-                Ok(Vec::new())
-            };
-            match r {
-                Ok(s) => {
-                    sql.extend(s);
-                    sql.extend(update_state_batch(state_updates));
-                    Ok(sql)
-                }
-                Err(e) => Err(e),
-            }
+        // The block commits to the state after the updates
+        // so they are needed again to update the state tree.
+        let state_updates: Vec<_> = state_updates.collect();
+        let r = r.map(|mut sql| {
+            sql.extend(update_state_batch(state_updates.clone()));
+            sql
         });
+        let solved = solved.to_vec();
 
         async move {
-            let sql = r?;
-            if sql.is_empty() {
+            let mut sql = r?;
+            if sql.is_empty() && solved.is_empty() {
                 return Ok(());
             }
+            let (mut tree_lock, mut tree) = self.lock_state_tree().await?;
+            self.update_state_tree(&mut tree, &state_updates).await?;
+            sql.extend(move_solutions_to_solved(
+                block_number,
+                block_timestamp,
+                gas_used,
+                &solved,
+                tree.root(),
+            )?);
             // TODO: Is there a way to avoid this?
             // Maybe create an owned version of execute.
             let sql: Vec<&[serde_json::Value]> = sql.iter().map(|v| v.as_slice()).collect();
             let r = self.execute(&sql[..]).await;
+            *tree_lock = r.is_ok().then_some(tree);
+            drop(tree_lock);
 
             if new_block {
                 // Notify the streams of the new blocks.
                 self.streams.notify_new_blocks();
             }
+            self.streams.notify_new_outcomes(outcomes);
 
             r
         }
     }
 
+    async fn query_state_at(
+        &self,
+        address: &ContentAddress,
+        key: &Key,
+        block_number: u64,
+    ) -> anyhow::Result<Vec<Word>> {
+        self.ensure_state_recorded(block_number)?;
+        let address = encode(address);
+        let key = encode_key(key);
+        let sql = &[include_sql!(
+            "query/get_state_at.sql",
+            address,
+            key,
+            block_number
+        )];
+        let queries = self.query_values(sql).await?;
+        match single_value(&queries) {
+            Some(serde_json::Value::String(v)) => decode(v),
+            None => Ok(Vec::new()),
+            _ => bail!("State stored incorrectly"),
+        }
+    }
+
+    async fn query_state_range_at(
+        &self,
+        address: &ContentAddress,
+        start_key: &Key,
+        end_key: Option<&Key>,
+        limit: usize,
+        block_number: u64,
+    ) -> anyhow::Result<Vec<(Key, Vec<Word>)>> {
+        self.ensure_state_recorded(block_number)?;
+        let address = encode(address);
+        let start_key = encode_key(start_key);
+        let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+        // Cleared keys are skipped in the query so they don't count towards the limit.
+        let empty = encode(&Vec::<Word>::new());
+        let queries = match end_key {
+            Some(end_key) => {
+                let end_key = encode_key(end_key);
+                let sql = &[include_sql!(
+                    "query/list_state_range_at.sql",
+                    address,
+                    start_key,
+                    end_key,
+                    block_number,
+                    empty,
+                    limit
+                )];
+                self.query_values(sql).await?
+            }
+            None => {
+                let sql = &[include_sql!(
+                    "query/list_state_from_at.sql",
+                    address,
+                    start_key,
+                    block_number,
+                    empty,
+                    limit
+                )];
+                self.query_values(sql).await?
+            }
+        };
+        values::list_state_range(queries)
+    }
+
+    async fn list_state_at(
+        &self,
+        block_number: u64,
+    ) -> anyhow::Result<Vec<(ContentAddress, Key, Vec<Word>)>> {
+        self.ensure_state_recorded(block_number)?;
+        let sql = &[include_sql!("query/list_state_at.sql", block_number)];
+        let queries = self.query_values(sql).await?;
+        values::list_state(queries)
+    }
+
+    async fn get_block_state_diff(&self, block_number: u64) -> anyhow::Result<Vec<StateChange>> {
+        // The diff is from the state of the previous block.
+        if let Some(previous) = block_number.checked_sub(1) {
+            self.ensure_state_recorded(previous)?;
+        }
+        let sql = &[include_sql!("query/get_block_state_diff.sql", block_number)];
+        let queries = self.query_values(sql).await?;
+        values::get_block_state_diff(queries)
+    }
+
     async fn get_latest_block(&self) -> anyhow::Result<Option<Block>> {
         let sql = &[include_sql!("query/get_latest_block.sql")];
         let queries = self.query_values(sql).await?;
         values::get_latest_block(queries)
     }
+
+    async fn revert_to_block(&self, block_number: u64) -> anyhow::Result<()> {
+        self.ensure_state_recorded(block_number)?;
+        let unix_time = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+        let (mut tree_lock, mut tree) = self.lock_state_tree().await?;
+        let sql = &[include_sql!(
+            "query/list_reverted_state.sql",
+            block_number,
+            block_number
+        )];
+        let queries = self.query_values(sql).await?;
+        let reverted = values::list_reverted_state(queries)?;
+        // Reverted keys were written so their contracts exist.
+        for (address, key, value) in &reverted {
+            tree.update(address, key, value);
+        }
+
+        let mut sql = revert_state(reverted);
+        // Batch ids start at one.
+        let batch_id = block_number.saturating_add(1);
+        sql.extend([
+            include_sql!(owned "update/delete_state_history_after.sql", block_number),
+            include_sql!(owned "insert/copy_solved_to_pool.sql",
+                unix_time.as_secs(),
+                unix_time.subsec_nanos(),
+                batch_id
+            ),
+            include_sql!(owned "update/delete_solved_after.sql", batch_id),
+            include_sql!(owned "update/delete_batches_after.sql", batch_id),
+        ]);
+        let sql: Vec<&[serde_json::Value]> = sql.iter().map(|v| v.as_slice()).collect();
+        let r = self.execute(&sql[..]).await;
+        *tree_lock = r.is_ok().then_some(tree);
+        drop(tree_lock);
+
+        // Blocks were removed.
+        // Solutions lose outcomes but don't gain any so outcome streams aren't notified.
+        self.streams.notify_new_blocks();
+
+        r
+    }
 }
 
 fn move_solutions_to_failed(
@@ -733,7 +1180,9 @@ fn move_solutions_to_failed(
 fn move_solutions_to_solved(
     _block_number: u64,
     block_timestamp: Duration,
+    gas_used: u64,
     solutions: &[Hash],
+    state_root: Hash,
 ) -> anyhow::Result<Vec<Vec<serde_json::Value>>> {
     if solutions.is_empty() {
         return Ok(Vec::new());
@@ -748,13 +1197,17 @@ fn move_solutions_to_solved(
     let mut sql = vec![include_sql!(
         owned "insert/batch.sql",
         block_timestamp.as_secs(),
-        block_timestamp.subsec_nanos()
+        block_timestamp.subsec_nanos(),
+        gas_used,
+        encode(&state_root)
     )];
     sql.extend(inserts);
     sql.push(include_sql!(owned "update/delete_empty_batch.sql"));
     Ok(sql)
 }
 
+/// Each update reads the existing value, writes the new value
+/// and records the write in the state history as part of the next block.
 fn update_state_batch<U>(updates: U) -> Vec<Vec<serde_json::Value>>
 where
     U: IntoIterator<Item = (ContentAddress, essential_types::Key, Vec<Word>)>,
@@ -763,25 +1216,48 @@ where
         .into_iter()
         .flat_map(|(address, key, value)| {
             let address = encode(&address);
-            let key = encode(&key);
+            let key = encode_key(&key);
+            let delete = value.is_empty();
             let value = encode(&value);
-            if value.is_empty() {
+            let history = include_sql!(owned "insert/state_history.sql", key.clone(), value.clone(), address.clone());
+            if delete {
                 // Delete the value and return the existing value if it exists.
                 [
                     include_sql!(owned "query/get_state.sql", address.clone(), key.clone()),
                     include_sql!(owned "update/delete_state.sql", address, key),
+                    history,
                 ]
             } else {
                 // Update the value and return the existing value if it exists.
                 [
                     include_sql!(owned "query/get_state.sql", address.clone(), key.clone()),
                     include_sql!(owned "update/update_state.sql", key, value, address),
+                    history,
                 ]
             }
         })
         .collect()
 }
 
+/// Restore each key to its value at the end of the block being reverted to
+/// without recording the writes in the state history.
+fn revert_state(
+    reverted: Vec<(ContentAddress, essential_types::Key, Vec<Word>)>,
+) -> Vec<Vec<serde_json::Value>> {
+    reverted
+        .into_iter()
+        .map(|(address, key, value)| {
+            let address = encode(&address);
+            let key = encode_key(&key);
+            if value.is_empty() {
+                include_sql!(owned "update/delete_state.sql", address, key)
+            } else {
+                include_sql!(owned "update/update_state.sql", key, encode(&value), address)
+            }
+        })
+        .collect()
+}
+
 /// Error for rqlite read.
 #[derive(Debug, Error)]
 pub enum RqliteError {
@@ -0,0 +1,25 @@
+//! Metrics for requests to the rqlite server.
+//!
+//! These are registered with the default [`prometheus`] registry.
+
+use prometheus::{register_histogram, register_histogram_vec, Histogram, HistogramVec};
+use std::sync::LazyLock;
+
+/// Time taken by requests to the rqlite server by endpoint.
+pub(crate) static REQUEST_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "essential_rqlite_request_duration_seconds",
+        "Time taken by requests to the rqlite server.",
+        &["endpoint"]
+    )
+    .expect("metric is only registered once")
+});
+
+/// Time spent waiting for one of the limited connections to the rqlite server.
+pub(crate) static SEMAPHORE_WAIT: LazyLock<Histogram> = LazyLock::new(|| {
+    register_histogram!(
+        "essential_rqlite_semaphore_wait_seconds",
+        "Time spent waiting for a connection to the rqlite server."
+    )
+    .expect("metric is only registered once")
+});
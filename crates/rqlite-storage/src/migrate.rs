@@ -0,0 +1,163 @@
+//! Versioned migrations of the database schema.
+//!
+//! Tables are created with `CREATE TABLE IF NOT EXISTS` so a database created
+//! by an older version keeps its old tables. The version of the schema is stored
+//! in the `schema_version` table. A database without a version that already has
+//! tables was created before versioning and is at version zero.
+//!
+//! Each migration brings the schema from the previous version to its own and
+//! is executed in the same transaction as the version update.
+//! Tables added since version zero are created before migrating
+//! so only the tables that already existed need migrating.
+//!
+//! The state of blocks committed before version one wasn't recorded.
+//! Migrating records the current state as the state at the end of the latest block
+//! and gives that block its state root. The state at earlier blocks can't be queried.
+
+use thiserror::Error;
+
+use essential_server_types::state_root::StateTree;
+
+use crate::{encode, encode_key, values, RqliteStorage};
+
+/// The version of the schema created by this version of the storage.
+pub const SCHEMA_VERSION: u64 = 1;
+
+/// The database was created by a newer version of the storage.
+#[derive(Debug, Error)]
+#[error("database schema version {0} is newer than the supported version {SCHEMA_VERSION}")]
+pub struct UnsupportedSchemaVersion(pub u64);
+
+impl RqliteStorage {
+    /// Migrate the schema to [`SCHEMA_VERSION`].
+    pub(crate) async fn migrate(&self) -> anyhow::Result<()> {
+        self.execute(&[
+            include_sql!("create/schema_version.sql"),
+            include_sql!("create/contract_state_history.sql"),
+            include_sql!("create/state_history_start.sql"),
+        ])
+        .await?;
+        let current = self.schema_version().await?;
+        for version in current + 1..=SCHEMA_VERSION {
+            let mut sql = self.migration(version).await?;
+            sql.push(include_sql!(owned "update/set_schema_version.sql", version));
+            let sql: Vec<&[serde_json::Value]> = sql.iter().map(|v| v.as_slice()).collect();
+            self.execute(&sql[..]).await?;
+        }
+        Ok(())
+    }
+
+    /// The version of the schema in the database.
+    ///
+    /// A new database is at [`SCHEMA_VERSION`] as its tables are about to be created.
+    async fn schema_version(&self) -> anyhow::Result<u64> {
+        let sql = &[include_sql!("query/get_schema_version.sql")];
+        let queries = self.query_values(sql).await?;
+        if let Some(version) = values::single_value(&queries) {
+            let Some(version) = version.as_u64() else {
+                anyhow::bail!("invalid schema version {:?}", version);
+            };
+            if version > SCHEMA_VERSION {
+                return Err(UnsupportedSchemaVersion(version).into());
+            }
+            return Ok(version);
+        }
+        // Batches have been stored since the first version.
+        let sql = &[include_sql!("query/get_table.sql", "batch")];
+        let queries = self.query_values(sql).await?;
+        match values::single_value(&queries) {
+            Some(_) => Ok(0),
+            None => Ok(SCHEMA_VERSION),
+        }
+    }
+
+    /// The statements that migrate the schema from `version - 1` to `version`.
+    async fn migration(&self, version: u64) -> anyhow::Result<Vec<Vec<serde_json::Value>>> {
+        let sql = match version {
+            // Blocks record the gas they used and their state root.
+            // The state of older blocks is unknown so they have a zero root.
+            // Solutions in the pool record when they were added. Their time is unknown
+            // so it's the time of the migration, which starts their time to live from now.
+            // State keys are re-encoded so they sort in key order.
+            // State history starts at the latest block.
+            1 => {
+                let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+                let mut sql = vec![
+                    include_sql!(owned "migrate/add_batch_gas_used.sql"),
+                    include_sql!(owned "migrate/add_batch_state_root.sql"),
+                    include_sql!(owned "migrate/add_pool_created_at_seconds.sql"),
+                    include_sql!(owned "migrate/add_pool_created_at_nanos.sql"),
+                    include_sql!(
+                        owned "migrate/set_pool_created_at.sql",
+                        now.as_secs(),
+                        now.subsec_nanos()
+                    ),
+                ];
+                sql.extend(self.encode_sortable_keys().await?);
+                sql.extend(self.start_state_history().await?);
+                sql
+            }
+            _ => anyhow::bail!("no migration to schema version {version}"),
+        };
+        Ok(sql)
+    }
+
+    /// The statements that re-encode the state keys written before keys were sortable.
+    ///
+    /// Every key is first marked as old so a re-encoded key can't
+    /// clash with a key that hasn't been re-encoded yet.
+    async fn encode_sortable_keys(&self) -> anyhow::Result<Vec<Vec<serde_json::Value>>> {
+        let sql = &[
+            include_sql!("migrate/list_state_keys.sql"),
+            include_sql!("migrate/list_state_history_keys.sql"),
+        ];
+        let queries = self.query_values(sql).await?;
+        let [state, history] = &queries.queries[..] else {
+            anyhow::bail!("expected two queries {:?}", queries);
+        };
+        let state = values::list_unsortable_keys(state.as_ref())?;
+        let history = values::list_unsortable_keys(history.as_ref())?;
+
+        let mut sql = vec![
+            include_sql!(owned "migrate/mark_state_keys.sql"),
+            include_sql!(owned "migrate/mark_state_history_keys.sql"),
+        ];
+        sql.extend(state.into_iter().map(
+            |(id, key)| include_sql!(owned "migrate/set_state_key.sql", encode_key(&key), id),
+        ));
+        sql.extend(history.into_iter().map(|(id, key)| {
+            include_sql!(owned "migrate/set_state_history_key.sql", encode_key(&key), id)
+        }));
+        Ok(sql)
+    }
+
+    /// The statements that start the state history at the latest block.
+    ///
+    /// The current state is recorded as the state at the end of the latest block,
+    /// which is given the root of that state. Without blocks the current state is
+    /// part of the next block and the whole history is known.
+    ///
+    /// Run after the keys are re-encoded so the history is copied with the new keys.
+    async fn start_state_history(&self) -> anyhow::Result<Vec<Vec<serde_json::Value>>> {
+        let sql = &[include_sql!("migrate/get_latest_batch_id.sql")];
+        let queries = self.query_values(sql).await?;
+        // Batch ids start at one.
+        let latest = values::single_value(&queries)
+            .and_then(|id| id.as_u64())
+            .and_then(|id| id.checked_sub(1));
+        let Some(block_number) = latest else {
+            return Ok(vec![
+                include_sql!(owned "migrate/seed_state_history.sql", 0),
+            ]);
+        };
+
+        let sql = &[include_sql!("query/list_state.sql")];
+        let state = values::list_unsortable_state(self.query_values(sql).await?)?;
+        let tree = StateTree::from_state(state.iter().map(|(a, k, v)| (a, k, v)));
+        Ok(vec![
+            include_sql!(owned "migrate/seed_state_history.sql", block_number),
+            include_sql!(owned "migrate/set_state_history_start.sql", block_number),
+            include_sql!(owned "migrate/set_latest_batch_state_root.sql", encode(&tree.root())),
+        ])
+    }
+}
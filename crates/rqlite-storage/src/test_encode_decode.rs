@@ -15,3 +15,12 @@ fn test_contract() {
     let r: Contract = decode(&data).unwrap();
     assert_eq!(r, Contract::without_salt(vec![Predicate::empty()]));
 }
+
+#[test]
+fn test_migrated_state_root() {
+    // Batches from before state roots were recorded default to this.
+    let default = include_str!("../sql/migrate/add_batch_state_root.sql");
+    let zero = encode(&[0u8; 32]);
+    assert!(default.contains(&format!("'{zero}'")));
+    assert_eq!(decode::<Hash>(&zero).unwrap(), [0; 32]);
+}
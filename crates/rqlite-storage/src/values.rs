@@ -4,29 +4,49 @@ use std::{
 };
 
 use anyhow::{bail, ensure};
-use essential_storage::failed_solution::{CheckOutcome, FailedSolution, SolutionOutcomes};
+use essential_server_types::state_root::StateTree;
+use essential_storage::{
+    failed_solution::{CheckOutcome, FailedSolution, SolutionOutcomes},
+    BlockHeader, StateChange,
+};
 use essential_types::{
     contract::{Contract, SignedContract},
     predicate::Predicate,
     solution::Solution,
-    Block, Hash, Signature, Word,
+    Block, ContentAddress, Hash, Key, Signature, Word,
 };
 use serde::de::DeserializeOwned;
 use serde_json::Value;
 
-use crate::{decode, RESULTS_KEY};
+use crate::{decode, decode_key, RESULTS_KEY};
 
+#[cfg(test)]
+mod test_get_block_state_diff;
 #[cfg(test)]
 mod test_get_contract;
 #[cfg(test)]
 mod test_get_solution;
 #[cfg(test)]
+mod test_get_state_batch;
+#[cfg(test)]
+mod test_list_block_headers;
+#[cfg(test)]
 mod test_list_contracts;
 #[cfg(test)]
 mod test_list_failed_solutions;
 #[cfg(test)]
+mod test_list_pool_hashes;
+#[cfg(test)]
+mod test_list_reverted_state;
+#[cfg(test)]
 mod test_list_solutions;
 #[cfg(test)]
+mod test_list_solutions_pool_after;
+#[cfg(test)]
+mod test_list_state_range;
+#[cfg(test)]
+mod test_list_unsortable_keys;
+#[cfg(test)]
 mod test_list_winning_blocks;
 #[cfg(test)]
 mod test_map_execute_to_values;
@@ -36,6 +56,8 @@ mod test_map_query_to_query_values;
 mod test_map_solution_to_block;
 #[cfg(test)]
 mod test_single_value;
+#[cfg(test)]
+mod test_update_state_tree;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct QueryValues {
@@ -141,24 +163,38 @@ pub fn get_solution(
         bail!("expected a single column");
     };
 
-    let outcomes = outcomes
+    let (outcomes, timestamps) = outcomes
         .iter()
-        .map(|Columns { columns }| match &columns[..] {
-            [Value::Number(block_number), Value::Null, _, _] => block_number
-                .as_u64()
-                .and_then(|n| n.checked_sub(1))
-                .map(CheckOutcome::Success)
-                .ok_or_else(|| anyhow::anyhow!("failed to parse block_number")),
-            [Value::Null, Value::String(reason), _, _] => decode(reason).map(CheckOutcome::Fail),
-            _ => bail!("unexpected columns: {:?}", columns),
+        .map(|Columns { columns }| {
+            let (outcome, created_at_secs, created_at_nanos) = match &columns[..] {
+                [Value::Number(block_number), Value::Null, secs, nanos] => {
+                    let outcome = block_number
+                        .as_u64()
+                        .and_then(|n| n.checked_sub(1))
+                        .map(CheckOutcome::Success)
+                        .ok_or_else(|| anyhow::anyhow!("failed to parse block_number"))?;
+                    (outcome, secs, nanos)
+                }
+                [Value::Null, Value::String(reason), secs, nanos] => {
+                    (CheckOutcome::Fail(decode(reason)?), secs, nanos)
+                }
+                _ => bail!("unexpected columns: {:?}", columns),
+            };
+            match (created_at_secs.as_u64(), created_at_nanos.as_u64()) {
+                (Some(secs), Some(nanos)) => Ok((outcome, Duration::new(secs, nanos as u32))),
+                _ => bail!("Failed to parse created_at_secs or created_at_nanos"),
+            }
         })
-        .collect::<anyhow::Result<_>>()?;
+        .collect::<anyhow::Result<Vec<_>>>()?
+        .into_iter()
+        .unzip();
 
     let solution = decode(solution)?;
 
     Ok(Some(SolutionOutcomes {
         solution,
         outcome: outcomes,
+        timestamps,
     }))
 }
 
@@ -240,6 +276,43 @@ pub fn list_solutions_pool(queries: QueryValues) -> anyhow::Result<Vec<Solution>
     list_solutions(queries)
 }
 
+/// List solutions in the pool along with their position in the pool.
+pub fn list_solutions_pool_after(
+    QueryValues { queries }: QueryValues,
+) -> anyhow::Result<Vec<(i64, Solution)>> {
+    // Only expecting a single query.
+    let rows = match &queries[..] {
+        [Some(Rows { rows })] => rows,
+        [None] => return Ok(Vec::new()),
+        _ => bail!("expected a single query {:?}", queries),
+    };
+
+    rows.iter()
+        .map(|Columns { columns }| match &columns[..] {
+            [Value::Number(id), Value::String(solution)] => {
+                let Some(id) = id.as_i64() else {
+                    bail!("Failed to parse solutions pool id");
+                };
+                Ok((id, decode(solution)?))
+            }
+            _ => bail!("unexpected columns: {:?}", columns),
+        })
+        .collect()
+}
+
+/// List the hashes of solutions in the pool in the order they were queried.
+pub fn list_pool_hashes(QueryValues { queries }: QueryValues) -> anyhow::Result<Vec<Hash>> {
+    let rows = match &queries[..] {
+        [rows] => rows.iter().flat_map(|rows| &rows.rows),
+        _ => bail!("expected a single query {:?}", queries),
+    };
+    rows.map(|Columns { columns }| match &columns[..] {
+        [Value::String(hash)] => decode(hash),
+        _ => bail!("unexpected columns: {:?}", columns),
+    })
+    .collect()
+}
+
 fn list_solutions<S>(QueryValues { queries }: QueryValues) -> anyhow::Result<Vec<S>>
 where
     S: DeserializeOwned,
@@ -289,7 +362,7 @@ pub fn list_failed_solutions(
     // Decode solution from each row.
     rows.iter()
         .map(|Columns { columns }| match &columns[..] {
-            [solution, reason] => {
+            [solution, reason, Value::Number(created_at_secs), Value::Number(created_at_nanos)] => {
                 let solution = match solution {
                     serde_json::Value::String(solution) => decode(solution)?,
                     _ => bail!("unexpected column type {:?} for solution", solution),
@@ -298,7 +371,16 @@ pub fn list_failed_solutions(
                     serde_json::Value::String(reason) => decode(reason)?,
                     _ => bail!("unexpected column type {:?} for reason", reason),
                 };
-                Ok(FailedSolution { solution, reason })
+                let (Some(created_at_secs), Some(created_at_nanos)) =
+                    (created_at_secs.as_u64(), created_at_nanos.as_u64())
+                else {
+                    bail!("Failed to parse created_at_secs or created_at_nanos");
+                };
+                Ok(FailedSolution {
+                    solution,
+                    reason,
+                    timestamp: Duration::new(created_at_secs, created_at_nanos as u32),
+                })
             }
             _ => Err(anyhow::anyhow!("unexpected columns: {:?}", columns)),
         })
@@ -330,6 +412,219 @@ pub fn list_blocks(QueryValues { queries }: QueryValues) -> anyhow::Result<Vec<B
     Ok(r?.into_values().collect())
 }
 
+pub fn list_block_headers(
+    QueryValues { queries }: QueryValues,
+) -> anyhow::Result<Vec<BlockHeader>> {
+    // Only expecting a single query.
+    let rows = match &queries[..] {
+        [Some(Rows { rows })] => rows,
+        [None] => return Ok(Vec::new()),
+        _ => bail!("expected a single query {:?}", queries),
+    };
+
+    rows.iter()
+        .map(|Columns { columns }| match &columns[..] {
+            [Value::Number(batch_id), Value::Number(created_at_secs), Value::Number(created_at_nanos), Value::Number(gas_used), Value::String(state_root)] => {
+                match (
+                    batch_id.as_u64(),
+                    created_at_secs.as_u64(),
+                    created_at_nanos.as_u64(),
+                    gas_used.as_u64(),
+                ) {
+                    (Some(batch_id), Some(created_at_secs), Some(created_at_nanos), Some(gas_used)) => {
+                        let Some(number) = batch_id.checked_sub(1) else {
+                            bail!("batch_id must be greater than 0");
+                        };
+                        Ok(BlockHeader {
+                            number,
+                            timestamp: Duration::new(created_at_secs, created_at_nanos as u32),
+                            gas_used,
+                            state_root: decode(state_root)?,
+                        })
+                    }
+                    _ => bail!(
+                        "Failed to parse batch_id, created_at_secs, created_at_nanos or gas_used"
+                    ),
+                }
+            }
+            _ => bail!("unexpected columns: {:?}", columns),
+        })
+        .collect()
+}
+
+/// Map a single query of the state to its values.
+pub fn list_state(
+    QueryValues { queries }: QueryValues,
+) -> anyhow::Result<Vec<(ContentAddress, Key, Vec<Word>)>> {
+    match &queries[..] {
+        [state] => state_rows(state.as_ref(), decode_key),
+        _ => bail!("expected a single query {:?}", queries),
+    }
+}
+
+/// Map a single query of the state with keys written before keys were sortable.
+pub fn list_unsortable_state(
+    QueryValues { queries }: QueryValues,
+) -> anyhow::Result<Vec<(ContentAddress, Key, Vec<Word>)>> {
+    match &queries[..] {
+        [state] => state_rows(state.as_ref(), decode),
+        _ => bail!("expected a single query {:?}", queries),
+    }
+}
+
+/// Decode the state changed by a block sorted by address then key.
+///
+/// A missing old value means the key had no value before the block.
+pub fn get_block_state_diff(
+    QueryValues { queries }: QueryValues,
+) -> anyhow::Result<Vec<StateChange>> {
+    let rows = match &queries[..] {
+        [rows] => rows.iter().flat_map(|rows| &rows.rows),
+        _ => bail!("expected a single query {:?}", queries),
+    };
+    let mut diff = rows
+        .map(|Columns { columns }| {
+            let [Value::String(address), Value::String(key), old_value, Value::String(new_value)] =
+                &columns[..]
+            else {
+                bail!("unexpected columns: {:?}", columns);
+            };
+            let old_value = match old_value {
+                Value::String(old_value) => decode(old_value)?,
+                Value::Null => Vec::new(),
+                _ => bail!("unexpected old value: {:?}", old_value),
+            };
+            Ok(StateChange {
+                address: decode(address)?,
+                key: decode_key(key)?,
+                old_value,
+                new_value: decode(new_value)?,
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    diff.sort();
+    Ok(diff)
+}
+
+/// Decode the state written after a block with its value at the end of the block.
+///
+/// A missing value means the key had no value at the end of the block.
+pub fn list_reverted_state(
+    QueryValues { queries }: QueryValues,
+) -> anyhow::Result<Vec<(ContentAddress, Key, Vec<Word>)>> {
+    let rows = match &queries[..] {
+        [rows] => rows.iter().flat_map(|rows| &rows.rows),
+        _ => bail!("expected a single query {:?}", queries),
+    };
+    rows.map(|Columns { columns }| {
+        let [Value::String(address), Value::String(key), value] = &columns[..] else {
+            bail!("unexpected columns: {:?}", columns);
+        };
+        let value = match value {
+            Value::String(value) => decode(value)?,
+            Value::Null => Vec::new(),
+            _ => bail!("unexpected value: {:?}", value),
+        };
+        Ok((decode(address)?, decode_key(key)?, value))
+    })
+    .collect()
+}
+
+/// Decode rows of key and value into the values of the encoded `keys` in order.
+///
+/// Keys without a row have no value.
+pub fn get_state_batch(
+    QueryValues { queries }: QueryValues,
+    keys: &[String],
+) -> anyhow::Result<Vec<Vec<Word>>> {
+    let rows = match &queries[..] {
+        [rows] => rows.iter().flat_map(|rows| &rows.rows),
+        _ => bail!("expected a single query {:?}", queries),
+    };
+    let values = rows
+        .map(|Columns { columns }| {
+            let [Value::String(key), Value::String(value)] = &columns[..] else {
+                bail!("unexpected columns: {:?}", columns);
+            };
+            Ok((key.as_str(), decode(value)?))
+        })
+        .collect::<anyhow::Result<HashMap<_, _>>>()?;
+    Ok(keys
+        .iter()
+        .map(|key| values.get(key.as_str()).cloned().unwrap_or_default())
+        .collect())
+}
+
+/// Decode rows of key and value in key order.
+pub fn list_state_range(
+    QueryValues { queries }: QueryValues,
+) -> anyhow::Result<Vec<(Key, Vec<Word>)>> {
+    let rows = match &queries[..] {
+        [rows] => rows.iter().flat_map(|rows| &rows.rows),
+        _ => bail!("expected a single query {:?}", queries),
+    };
+    rows.map(|Columns { columns }| {
+        let [Value::String(key), Value::String(value)] = &columns[..] else {
+            bail!("unexpected columns: {:?}", columns);
+        };
+        Ok((decode_key(key)?, decode(value)?))
+    })
+    .collect()
+}
+
+/// Decode rows of address, key and value.
+///
+/// Empty values are skipped as they are the same as no value.
+fn state_rows(
+    rows: Option<&Rows>,
+    decode_key: fn(&str) -> anyhow::Result<Key>,
+) -> anyhow::Result<Vec<(ContentAddress, Key, Vec<Word>)>> {
+    rows.iter()
+        .flat_map(|rows| &rows.rows)
+        .map(|Columns { columns }| {
+            let [Value::String(address), Value::String(key), Value::String(value)] = &columns[..]
+            else {
+                bail!("unexpected columns: {:?}", columns);
+            };
+            Ok((
+                decode(address)?,
+                decode_key(key)?,
+                decode::<Vec<Word>>(value)?,
+            ))
+        })
+        .filter(|r| !matches!(r, Ok((_, _, value)) if value.is_empty()))
+        .collect()
+}
+
+/// Apply the `pending` updates to the state tree.
+///
+/// There is a contract salt query for each of the `addresses` in order.
+/// Pending updates to addresses without a contract are ignored.
+pub fn update_state_tree(
+    tree: &mut StateTree,
+    QueryValues { queries }: QueryValues,
+    addresses: &[ContentAddress],
+    pending: &[(ContentAddress, Key, Vec<Word>)],
+) -> anyhow::Result<()> {
+    ensure!(
+        queries.len() == addresses.len(),
+        "expected a contract query for each address {:?}",
+        queries
+    );
+    let deployed: Vec<_> = addresses
+        .iter()
+        .zip(&queries)
+        .filter(|(_, rows)| rows.as_ref().is_some_and(|rows| !rows.rows.is_empty()))
+        .map(|(address, _)| address)
+        .collect();
+    for (address, key, value) in pending {
+        if deployed.contains(&address) {
+            tree.update(address, key, value);
+        }
+    }
+    Ok(())
+}
+
 fn map_solution_to_block(
     mut map: BTreeMap<u64, Block>,
     columns: &[Value],
@@ -376,8 +671,8 @@ pub fn map_execute_to_values(
         bail!("Query results are invalid");
     };
 
-    // Results must have a single object
-    let [serde_json::Value::Object(results), _] = &results[..] else {
+    // The read is the first result and is followed by the writes
+    let [serde_json::Value::Object(results), _, ..] = &results[..] else {
         bail!("invalid amount of results");
     };
 
@@ -411,11 +706,10 @@ pub fn map_execute_to_multiple_values(
 ) -> anyhow::Result<Vec<Vec<Word>>> {
     queries
         .into_iter()
-        .enumerate()
-        // Skip every second result as they are the results of the write queries
-        // and not the read queries that we are interested in.
-        .filter(|(i, _)| i % 2 == 0)
-        .map(|(_, row)| {
+        // Each update is a read followed by two writes.
+        // Skip the results of the writes as only the reads are of interest.
+        .step_by(3)
+        .map(|row| {
             // If the row is None, return an empty vec
             let Some(Rows { rows }) = row else {
                 return Ok(Vec::new());
@@ -482,8 +776,8 @@ pub fn assert_row_changed(
         bail!("Query results are invalid");
     };
 
-    // Results must be a two object
-    let [_, serde_json::Value::Object(results)] = &results[..] else {
+    // The write is the second result
+    let [_, serde_json::Value::Object(results), ..] = &results[..] else {
         bail!("invalid amount of results");
     };
 
@@ -515,3 +809,19 @@ pub fn get_latest_block(QueryValues { queries }: QueryValues) -> anyhow::Result<
         });
     Ok(r?.into_values().next())
 }
+
+/// Decode rows of ids and keys encoded before keys were sortable.
+pub fn list_unsortable_keys(rows: Option<&Rows>) -> anyhow::Result<Vec<(i64, Key)>> {
+    rows.iter()
+        .flat_map(|rows| &rows.rows)
+        .map(|Columns { columns }| match &columns[..] {
+            [Value::Number(id), Value::String(key)] => {
+                let Some(id) = id.as_i64() else {
+                    bail!("Failed to parse state id");
+                };
+                Ok((id, decode(key)?))
+            }
+            _ => bail!("unexpected columns: {:?}", columns),
+        })
+        .collect()
+}
@@ -0,0 +1,74 @@
+use super::*;
+use crate::{encode, encode_key};
+
+#[test]
+fn test_empty_query() {
+    let queries = QueryValues {
+        queries: vec![None],
+    };
+
+    assert!(get_block_state_diff(queries).unwrap().is_empty());
+}
+
+#[test]
+fn test_invalid_query() {
+    let queries = QueryValues { queries: vec![] };
+    get_block_state_diff(queries).unwrap_err();
+
+    let queries = QueryValues {
+        queries: vec![Some(Rows {
+            rows: vec![Columns {
+                columns: vec![
+                    Value::String(encode(&ContentAddress([0; 32]))),
+                    Value::String(encode_key(&vec![1])),
+                    Value::Number(1.into()),
+                    Value::String(encode(&vec![2 as Word])),
+                ],
+            }],
+        })],
+    };
+    get_block_state_diff(queries).unwrap_err();
+}
+
+#[test]
+fn test_valid_query() {
+    let queries = QueryValues {
+        queries: vec![Some(Rows {
+            rows: vec![
+                Columns {
+                    columns: vec![
+                        Value::String(encode(&ContentAddress([1; 32]))),
+                        Value::String(encode_key(&vec![1])),
+                        Value::String(encode(&vec![2 as Word])),
+                        Value::String(encode(&Vec::<Word>::new())),
+                    ],
+                },
+                Columns {
+                    columns: vec![
+                        Value::String(encode(&ContentAddress([0; 32]))),
+                        Value::String(encode_key(&vec![3])),
+                        Value::Null,
+                        Value::String(encode(&vec![4 as Word])),
+                    ],
+                },
+            ],
+        })],
+    };
+
+    let r = get_block_state_diff(queries).unwrap();
+    let expected = vec![
+        StateChange {
+            address: ContentAddress([0; 32]),
+            key: vec![3],
+            old_value: vec![],
+            new_value: vec![4],
+        },
+        StateChange {
+            address: ContentAddress([1; 32]),
+            key: vec![1],
+            old_value: vec![2],
+            new_value: vec![],
+        },
+    ];
+    assert_eq!(r, expected);
+}
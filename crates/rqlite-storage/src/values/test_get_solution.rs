@@ -42,8 +42,8 @@ fn test_valid_solution() {
                     columns: vec![
                         Value::Number(1.into()),
                         Value::Null,
-                        Value::Null,
-                        Value::Null,
+                        Value::Number(2.into()),
+                        Value::Number(3.into()),
                     ],
                 }],
             }),
@@ -54,6 +54,7 @@ fn test_valid_solution() {
     let expected = SolutionOutcomes {
         solution: solution.clone(),
         outcome: vec![CheckOutcome::Success(0)],
+        timestamps: vec![Duration::new(2, 3)],
     };
     assert_eq!(r, expected);
 
@@ -69,8 +70,8 @@ fn test_valid_solution() {
                     columns: vec![
                         Value::Null,
                         Value::String(encode(&reason)),
-                        Value::Null,
-                        Value::Null,
+                        Value::Number(4.into()),
+                        Value::Number(5.into()),
                     ],
                 }],
             }),
@@ -81,6 +82,7 @@ fn test_valid_solution() {
     let expected = SolutionOutcomes {
         solution,
         outcome: vec![CheckOutcome::Fail(reason)],
+        timestamps: vec![Duration::new(4, 5)],
     };
     assert_eq!(r, expected);
 }
@@ -304,4 +306,27 @@ fn test_wrong_num_rows() {
     };
 
     get_solution(queries).unwrap_err();
+
+    // Missing timestamps.
+    let queries = QueryValues {
+        queries: vec![
+            Some(Rows {
+                rows: vec![Columns {
+                    columns: vec![Value::String(encode(&solution))],
+                }],
+            }),
+            Some(Rows {
+                rows: vec![Columns {
+                    columns: vec![
+                        Value::Number(1.into()),
+                        Value::Null,
+                        Value::Null,
+                        Value::Null,
+                    ],
+                }],
+            }),
+        ],
+    };
+
+    get_solution(queries).unwrap_err();
 }
@@ -0,0 +1,59 @@
+use super::*;
+use crate::{encode, encode_key};
+
+#[test]
+fn test_empty_query() {
+    let queries = QueryValues {
+        queries: vec![None],
+    };
+    let keys = vec![encode_key(&vec![1])];
+
+    assert_eq!(
+        get_state_batch(queries, &keys).unwrap(),
+        vec![Vec::<Word>::new()]
+    );
+}
+
+#[test]
+fn test_invalid_query() {
+    let queries = QueryValues { queries: vec![] };
+    get_state_batch(queries, &[]).unwrap_err();
+
+    let queries = QueryValues {
+        queries: vec![Some(Rows {
+            rows: vec![Columns {
+                columns: vec![Value::String(encode_key(&vec![1]))],
+            }],
+        })],
+    };
+    get_state_batch(queries, &[]).unwrap_err();
+}
+
+#[test]
+fn test_valid_query() {
+    let queries = QueryValues {
+        queries: vec![Some(Rows {
+            rows: vec![
+                Columns {
+                    columns: vec![
+                        Value::String(encode_key(&vec![3])),
+                        Value::String(encode(&vec![30 as Word])),
+                    ],
+                },
+                Columns {
+                    columns: vec![
+                        Value::String(encode_key(&vec![1])),
+                        Value::String(encode(&vec![10 as Word, 11])),
+                    ],
+                },
+            ],
+        })],
+    };
+    let keys: Vec<_> = [vec![1], vec![2], vec![3], vec![1]]
+        .iter()
+        .map(encode_key)
+        .collect();
+
+    let r = get_state_batch(queries, &keys).unwrap();
+    assert_eq!(r, vec![vec![10, 11], vec![], vec![30], vec![10, 11]]);
+}
@@ -0,0 +1,96 @@
+use super::*;
+use crate::encode;
+
+#[test]
+fn test_empty_query() {
+    let queries = QueryValues {
+        queries: vec![None],
+    };
+
+    assert!(list_block_headers(queries).unwrap().is_empty());
+}
+
+#[test]
+fn test_invalid_query() {
+    let queries = QueryValues { queries: vec![] };
+
+    list_block_headers(queries).unwrap_err();
+
+    let queries = QueryValues {
+        queries: vec![None, None],
+    };
+
+    list_block_headers(queries).unwrap_err();
+}
+
+#[test]
+fn test_valid_query() {
+    let queries = QueryValues {
+        queries: vec![Some(Rows {
+            rows: vec![
+                Columns {
+                    columns: vec![
+                        Value::Number(1.into()),
+                        Value::Number(2.into()),
+                        Value::Number(3.into()),
+                        Value::Number(4.into()),
+                        Value::String(encode(&[1u8; 32])),
+                    ],
+                },
+                Columns {
+                    columns: vec![
+                        Value::Number(2.into()),
+                        Value::Number(5.into()),
+                        Value::Number(6.into()),
+                        Value::Number(7.into()),
+                        Value::String(encode(&[2u8; 32])),
+                    ],
+                },
+            ],
+        })],
+    };
+
+    let r = list_block_headers(queries).unwrap();
+    let expected = vec![
+        BlockHeader {
+            number: 0,
+            timestamp: Duration::new(2, 3),
+            gas_used: 4,
+            state_root: [1; 32],
+        },
+        BlockHeader {
+            number: 1,
+            timestamp: Duration::new(5, 6),
+            gas_used: 7,
+            state_root: [2; 32],
+        },
+    ];
+    assert_eq!(r, expected);
+}
+
+#[test]
+fn test_invalid_columns() {
+    let queries = QueryValues {
+        queries: vec![Some(Rows {
+            rows: vec![Columns {
+                columns: vec![
+                    Value::Number(0.into()),
+                    Value::Number(2.into()),
+                    Value::Number(3.into()),
+                    Value::Number(4.into()),
+                    Value::String(encode(&[1u8; 32])),
+                ],
+            }],
+        })],
+    };
+    list_block_headers(queries).unwrap_err();
+
+    let queries = QueryValues {
+        queries: vec![Some(Rows {
+            rows: vec![Columns {
+                columns: vec![Value::Number(1.into()), Value::Number(2.into())],
+            }],
+        })],
+    };
+    list_block_headers(queries).unwrap_err();
+}
@@ -35,6 +35,8 @@ fn test_valid_query() {
                 columns: vec![
                     Value::String(encode(&solution)),
                     Value::String(encode(&reason)),
+                    Value::Number(1.into()),
+                    Value::Number(2.into()),
                 ],
             }],
         })],
@@ -44,6 +46,7 @@ fn test_valid_query() {
     let expected = vec![FailedSolution {
         solution: solution.clone(),
         reason: reason.clone(),
+        timestamp: Duration::new(1, 2),
     }];
     assert_eq!(r, expected);
 
@@ -54,12 +57,16 @@ fn test_valid_query() {
                     columns: vec![
                         Value::String(encode(&solution)),
                         Value::String(encode(&reason)),
+                        Value::Number(1.into()),
+                        Value::Number(2.into()),
                     ],
                 },
                 Columns {
                     columns: vec![
                         Value::String(encode(&solution)),
                         Value::String(encode(&reason)),
+                        Value::Number(1.into()),
+                        Value::Number(2.into()),
                     ],
                 },
             ],
@@ -71,8 +78,13 @@ fn test_valid_query() {
         FailedSolution {
             solution: solution.clone(),
             reason: reason.clone(),
+            timestamp: Duration::new(1, 2),
+        },
+        FailedSolution {
+            solution,
+            reason,
+            timestamp: Duration::new(1, 2),
         },
-        FailedSolution { solution, reason },
     ];
     assert_eq!(r, expected);
 }
@@ -90,6 +102,8 @@ fn test_invalid_data() {
                     Value::String(invalid.clone()),
                     Value::String(encode(&solution)),
                     Value::String(encode(&reason)),
+                    Value::Number(1.into()),
+                    Value::Number(2.into()),
                 ],
             }],
         })],
@@ -127,6 +141,8 @@ fn test_invalid_data() {
                     Value::Bool(true),
                     Value::String(encode(&solution)),
                     Value::String(encode(&reason)),
+                    Value::Number(1.into()),
+                    Value::Number(2.into()),
                 ],
             }],
         })],
@@ -0,0 +1,45 @@
+use super::*;
+use crate::encode;
+
+#[test]
+fn test_empty_query() {
+    let queries = QueryValues {
+        queries: vec![None],
+    };
+
+    assert!(list_pool_hashes(queries).unwrap().is_empty());
+}
+
+#[test]
+fn test_invalid_query() {
+    let queries = QueryValues { queries: vec![] };
+    list_pool_hashes(queries).unwrap_err();
+
+    let queries = QueryValues {
+        queries: vec![Some(Rows {
+            rows: vec![Columns {
+                columns: vec![Value::Number(1.into())],
+            }],
+        })],
+    };
+    list_pool_hashes(queries).unwrap_err();
+}
+
+#[test]
+fn test_valid_query() {
+    let queries = QueryValues {
+        queries: vec![Some(Rows {
+            rows: vec![
+                Columns {
+                    columns: vec![Value::String(encode(&[2u8; 32]))],
+                },
+                Columns {
+                    columns: vec![Value::String(encode(&[1u8; 32]))],
+                },
+            ],
+        })],
+    };
+
+    let r = list_pool_hashes(queries).unwrap();
+    assert_eq!(r, vec![[2; 32], [1; 32]]);
+}
@@ -0,0 +1,61 @@
+use super::*;
+use crate::{encode, encode_key};
+
+#[test]
+fn test_empty_query() {
+    let queries = QueryValues {
+        queries: vec![None],
+    };
+
+    assert!(list_reverted_state(queries).unwrap().is_empty());
+}
+
+#[test]
+fn test_invalid_query() {
+    let queries = QueryValues { queries: vec![] };
+    list_reverted_state(queries).unwrap_err();
+
+    let queries = QueryValues {
+        queries: vec![Some(Rows {
+            rows: vec![Columns {
+                columns: vec![
+                    Value::String(encode(&ContentAddress([0; 32]))),
+                    Value::String(encode_key(&vec![1])),
+                    Value::Number(1.into()),
+                ],
+            }],
+        })],
+    };
+    list_reverted_state(queries).unwrap_err();
+}
+
+#[test]
+fn test_valid_query() {
+    let queries = QueryValues {
+        queries: vec![Some(Rows {
+            rows: vec![
+                Columns {
+                    columns: vec![
+                        Value::String(encode(&ContentAddress([1; 32]))),
+                        Value::String(encode_key(&vec![1])),
+                        Value::String(encode(&vec![2 as Word])),
+                    ],
+                },
+                Columns {
+                    columns: vec![
+                        Value::String(encode(&ContentAddress([0; 32]))),
+                        Value::String(encode_key(&vec![3])),
+                        Value::Null,
+                    ],
+                },
+            ],
+        })],
+    };
+
+    let r = list_reverted_state(queries).unwrap();
+    let expected = vec![
+        (ContentAddress([1; 32]), vec![1], vec![2]),
+        (ContentAddress([0; 32]), vec![3], vec![]),
+    ];
+    assert_eq!(r, expected);
+}
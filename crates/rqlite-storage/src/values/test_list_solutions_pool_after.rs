@@ -0,0 +1,68 @@
+use super::*;
+use crate::encode;
+use test_utils::{empty::Empty, solution_with_decision_variables};
+
+#[test]
+fn test_empty_query() {
+    let queries = QueryValues {
+        queries: vec![None],
+    };
+
+    assert!(list_solutions_pool_after(queries).unwrap().is_empty());
+}
+
+#[test]
+fn test_invalid_query() {
+    let queries = QueryValues { queries: vec![] };
+
+    list_solutions_pool_after(queries).unwrap_err();
+
+    let queries = QueryValues {
+        queries: vec![None, None],
+    };
+
+    list_solutions_pool_after(queries).unwrap_err();
+}
+
+#[test]
+fn test_valid_query() {
+    let solution = Solution::empty();
+    let solution2 = solution_with_decision_variables(1);
+    let queries = QueryValues {
+        queries: vec![Some(Rows {
+            rows: vec![
+                Columns {
+                    columns: vec![Value::Number(1.into()), Value::String(encode(&solution))],
+                },
+                Columns {
+                    columns: vec![Value::Number(3.into()), Value::String(encode(&solution2))],
+                },
+            ],
+        })],
+    };
+
+    let r = list_solutions_pool_after(queries).unwrap();
+    assert_eq!(r, vec![(1, solution), (3, solution2)]);
+}
+
+#[test]
+fn test_invalid_columns() {
+    let solution = Solution::empty();
+    let queries = QueryValues {
+        queries: vec![Some(Rows {
+            rows: vec![Columns {
+                columns: vec![Value::String(encode(&solution))],
+            }],
+        })],
+    };
+    list_solutions_pool_after(queries).unwrap_err();
+
+    let queries = QueryValues {
+        queries: vec![Some(Rows {
+            rows: vec![Columns {
+                columns: vec![Value::Number(1.into()), Value::Number(2.into())],
+            }],
+        })],
+    };
+    list_solutions_pool_after(queries).unwrap_err();
+}
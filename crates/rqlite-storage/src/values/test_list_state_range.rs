@@ -0,0 +1,51 @@
+use super::*;
+use crate::{encode, encode_key};
+
+#[test]
+fn test_empty_query() {
+    let queries = QueryValues {
+        queries: vec![None],
+    };
+
+    assert!(list_state_range(queries).unwrap().is_empty());
+}
+
+#[test]
+fn test_invalid_query() {
+    let queries = QueryValues { queries: vec![] };
+    list_state_range(queries).unwrap_err();
+
+    let queries = QueryValues {
+        queries: vec![Some(Rows {
+            rows: vec![Columns {
+                columns: vec![Value::String(encode_key(&vec![1]))],
+            }],
+        })],
+    };
+    list_state_range(queries).unwrap_err();
+}
+
+#[test]
+fn test_valid_query() {
+    let queries = QueryValues {
+        queries: vec![Some(Rows {
+            rows: vec![
+                Columns {
+                    columns: vec![
+                        Value::String(encode_key(&vec![-1])),
+                        Value::String(encode(&vec![1 as Word])),
+                    ],
+                },
+                Columns {
+                    columns: vec![
+                        Value::String(encode_key(&vec![1, 2])),
+                        Value::String(encode(&vec![2 as Word, 3])),
+                    ],
+                },
+            ],
+        })],
+    };
+
+    let r = list_state_range(queries).unwrap();
+    assert_eq!(r, vec![(vec![-1], vec![1]), (vec![1, 2], vec![2, 3])]);
+}
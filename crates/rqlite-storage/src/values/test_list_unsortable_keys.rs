@@ -0,0 +1,49 @@
+use super::*;
+use crate::encode;
+
+#[test]
+fn test_empty_query() {
+    assert!(list_unsortable_keys(None).unwrap().is_empty());
+}
+
+#[test]
+fn test_invalid_query() {
+    let rows = Rows {
+        rows: vec![Columns {
+            columns: vec![Value::String(encode(&vec![1 as Word]))],
+        }],
+    };
+    list_unsortable_keys(Some(&rows)).unwrap_err();
+
+    let rows = Rows {
+        rows: vec![Columns {
+            columns: vec![
+                Value::Number(1.into()),
+                Value::String("not hex".to_string()),
+            ],
+        }],
+    };
+    list_unsortable_keys(Some(&rows)).unwrap_err();
+}
+
+#[test]
+fn test_valid_query() {
+    let keys: Vec<Key> = vec![vec![1], vec![-1, 2], vec![]];
+    let rows = Rows {
+        rows: keys
+            .iter()
+            .enumerate()
+            .map(|(id, key)| Columns {
+                columns: vec![Value::Number(id.into()), Value::String(encode(key))],
+            })
+            .collect(),
+    };
+
+    let r = list_unsortable_keys(Some(&rows)).unwrap();
+    let expected: Vec<_> = keys
+        .into_iter()
+        .enumerate()
+        .map(|(id, key)| (id as i64, key))
+        .collect();
+    assert_eq!(r, expected);
+}
@@ -0,0 +1,110 @@
+use super::*;
+use crate::{encode, encode_key};
+use essential_server_types::state_root::{state_root as root, EMPTY};
+
+fn state_row(address: &ContentAddress, key: &Key, value: &Vec<Word>) -> Columns {
+    Columns {
+        columns: vec![
+            Value::String(encode(address)),
+            Value::String(encode_key(key)),
+            Value::String(encode(value)),
+        ],
+    }
+}
+
+fn salt_row() -> Option<Rows> {
+    Some(Rows {
+        rows: vec![Columns {
+            columns: vec![Value::String(encode(&[0u8; 32]))],
+        }],
+    })
+}
+
+#[test]
+fn test_no_updates() {
+    let mut tree = StateTree::new();
+    let queries = QueryValues { queries: vec![] };
+    update_state_tree(&mut tree, queries, &[], &[]).unwrap();
+    assert_eq!(tree.root(), EMPTY);
+}
+
+#[test]
+fn test_invalid_query() {
+    let a = ContentAddress([1; 32]);
+    let mut tree = StateTree::new();
+    let queries = QueryValues { queries: vec![] };
+    update_state_tree(&mut tree, queries, std::slice::from_ref(&a), &[]).unwrap_err();
+
+    let queries = QueryValues {
+        queries: vec![None, None],
+    };
+    update_state_tree(&mut tree, queries, &[a], &[]).unwrap_err();
+}
+
+#[test]
+fn test_pending_updates() {
+    let a = ContentAddress([1; 32]);
+    let b = ContentAddress([2; 32]);
+    let missing = ContentAddress([3; 32]);
+    let state = [
+        (a.clone(), vec![0], vec![1]),
+        (a.clone(), vec![1], vec![2]),
+        (b.clone(), vec![0], vec![3]),
+    ];
+    let mut tree = StateTree::from_state(state.iter().map(|(a, k, v)| (a, k, v)));
+    let queries = QueryValues {
+        queries: vec![salt_row(), salt_row(), None],
+    };
+    let pending = vec![
+        // Delete.
+        (a.clone(), vec![1], vec![]),
+        // Update.
+        (b.clone(), vec![0], vec![4]),
+        // Insert.
+        (b.clone(), vec![1], vec![5]),
+        // No contract so ignored.
+        (missing.clone(), vec![0], vec![6]),
+    ];
+
+    update_state_tree(
+        &mut tree,
+        queries,
+        &[a.clone(), b.clone(), missing],
+        &pending,
+    )
+    .unwrap();
+    let expected = [
+        (a, vec![0], vec![1]),
+        (b.clone(), vec![0], vec![4]),
+        (b, vec![1], vec![5]),
+    ];
+    assert_eq!(
+        tree.root(),
+        root(expected.iter().map(|(a, k, v)| (a, k, v)))
+    );
+}
+
+#[test]
+fn test_list_state() {
+    let a = ContentAddress([1; 32]);
+    let queries = QueryValues {
+        queries: vec![Some(Rows {
+            rows: vec![
+                state_row(&a, &vec![0], &vec![1]),
+                // Empty values are the same as no value.
+                state_row(&a, &vec![1], &vec![]),
+            ],
+        })],
+    };
+    assert_eq!(list_state(queries).unwrap(), vec![(a, vec![0], vec![1])]);
+
+    let queries = QueryValues {
+        queries: vec![None],
+    };
+    assert!(list_state(queries).unwrap().is_empty());
+
+    let queries = QueryValues {
+        queries: vec![None, None],
+    };
+    list_state(queries).unwrap_err();
+}
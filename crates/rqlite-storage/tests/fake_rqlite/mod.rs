@@ -0,0 +1,128 @@
+//! A fake rqlite server backed by SQLite so the storage can be
+//! tested against a database without running rqlite.
+//!
+//! Only the parts of the rqlite HTTP API the storage uses are implemented.
+//! Every request runs its statements in a transaction that is rolled back
+//! at the first error, which is reported in the `error` field of its result.
+
+use std::sync::{Arc, Mutex};
+
+use axum::{extract::State, routing::post, Json, Router};
+use rusqlite::{types::Value as SqlValue, Connection};
+use serde_json::{json, Value};
+
+/// The fake rqlite server.
+pub struct FakeRqlite {
+    /// The url to connect the storage to.
+    pub url: String,
+    /// The database behind the server.
+    pub conn: Arc<Mutex<Connection>>,
+}
+
+impl FakeRqlite {
+    /// Serve the database until the runtime shuts down.
+    pub async fn start(conn: Connection) -> Self {
+        conn.execute_batch("PRAGMA foreign_keys = ON;").unwrap();
+        let conn = Arc::new(Mutex::new(conn));
+        let app = Router::new()
+            .route("/db/execute", post(request))
+            .route("/db/query", post(request))
+            .route("/db/request", post(request))
+            .with_state(conn.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        Self { url, conn }
+    }
+}
+
+async fn request(
+    State(conn): State<Arc<Mutex<Connection>>>,
+    Json(statements): Json<Vec<Value>>,
+) -> Json<Value> {
+    let mut conn = conn.lock().unwrap();
+    let tx = conn.transaction().unwrap();
+    let mut results = Vec::new();
+    for statement in &statements {
+        match run(&tx, statement) {
+            Ok(result) => results.push(result),
+            Err(e) => {
+                results.push(json!({ "error": e.to_string() }));
+                return Json(json!({ "results": results }));
+            }
+        }
+    }
+    tx.commit().unwrap();
+    Json(json!({ "results": results }))
+}
+
+/// Run a statement given as the sql followed by its positional
+/// parameters or a map of its named parameters.
+fn run(conn: &Connection, statement: &Value) -> rusqlite::Result<Value> {
+    let (sql, params) = match statement {
+        Value::String(sql) => (sql.as_str(), &[][..]),
+        Value::Array(statement) => match &statement[..] {
+            [Value::String(sql), params @ ..] => (sql.as_str(), params),
+            _ => panic!("unexpected statement {statement:?}"),
+        },
+        _ => panic!("unexpected statement {statement:?}"),
+    };
+    let mut stmt = conn.prepare(sql)?;
+    match params {
+        [Value::Object(named)] => {
+            for (name, value) in named {
+                if let Some(i) = stmt.parameter_index(&format!(":{name}"))? {
+                    stmt.raw_bind_parameter(i, to_sql(value))?;
+                }
+            }
+        }
+        params => {
+            for (i, value) in params.iter().enumerate() {
+                stmt.raw_bind_parameter(i + 1, to_sql(value))?;
+            }
+        }
+    }
+
+    if stmt.column_count() == 0 {
+        let rows_affected = stmt.raw_execute()?;
+        return Ok(json!({ "rows_affected": rows_affected }));
+    }
+    let columns: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+    let mut rows = stmt.raw_query();
+    let mut values = Vec::new();
+    while let Some(row) = rows.next()? {
+        let row = (0..columns.len())
+            .map(|i| row.get::<_, SqlValue>(i).map(from_sql))
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        values.push(Value::Array(row));
+    }
+    // Rqlite leaves out the values of a query without rows.
+    if values.is_empty() {
+        Ok(json!({ "columns": columns }))
+    } else {
+        Ok(json!({ "columns": columns, "values": values }))
+    }
+}
+
+fn to_sql(value: &Value) -> SqlValue {
+    match value {
+        Value::Null => SqlValue::Null,
+        Value::Bool(b) => SqlValue::Integer(*b as i64),
+        Value::Number(n) => match n.as_i64() {
+            Some(n) => SqlValue::Integer(n),
+            None => SqlValue::Real(n.as_f64().unwrap()),
+        },
+        Value::String(s) => SqlValue::Text(s.clone()),
+        _ => panic!("unexpected parameter {value:?}"),
+    }
+}
+
+fn from_sql(value: SqlValue) -> Value {
+    match value {
+        SqlValue::Null => Value::Null,
+        SqlValue::Integer(n) => n.into(),
+        SqlValue::Real(n) => n.into(),
+        SqlValue::Text(s) => s.into(),
+        SqlValue::Blob(b) => hex::encode_upper(b).into(),
+    }
+}
@@ -0,0 +1,431 @@
+use std::time::Duration;
+
+use essential_rqlite_storage::{RqliteStorage, SCHEMA_VERSION};
+use essential_server_types::state_root::StateTree;
+use essential_storage::{QueryState, Storage};
+use essential_types::{ContentAddress, Key, Word};
+use rusqlite::{named_params, params, Connection};
+
+use common::*;
+use fake_rqlite::FakeRqlite;
+
+mod common;
+mod fake_rqlite;
+
+#[test]
+fn test_schema_version() {
+    let conn = Connection::open_in_memory().unwrap();
+    let get_table = include_sql!("query", "get_table");
+    let get_version = include_sql!("query", "get_schema_version");
+
+    // A new database has no tables.
+    assert!(query(&conn, get_table, ["batch"], |row| row.get::<_, String>(0)).is_empty());
+
+    create_tables(&conn);
+    let tables = query(&conn, get_table, ["batch"], |row| {
+        row.get::<_, String>(0).unwrap()
+    });
+    assert_eq!(tables, vec!["batch".to_string()]);
+    assert!(query(&conn, get_version, [], |row| row.get::<_, u64>(0)).is_empty());
+
+    let set_version = include_sql!("update", "set_schema_version");
+    conn.execute(set_version, [1]).unwrap();
+    conn.execute(set_version, [2]).unwrap();
+    let versions = query(&conn, get_version, [], |row| row.get::<_, u64>(0).unwrap());
+    assert_eq!(versions, vec![2]);
+}
+
+#[test]
+fn test_migrate_batch() {
+    let conn = Connection::open_in_memory().unwrap();
+
+    // The batch table before blocks recorded their gas and state root.
+    conn.execute(
+        "CREATE TABLE batch (
+            id INTEGER PRIMARY KEY,
+            created_at_seconds INTEGER NOT NULL,
+            created_at_nanos INTEGER NOT NULL
+        );",
+        [],
+    )
+    .unwrap();
+    conn.execute(
+        "INSERT INTO batch (created_at_seconds, created_at_nanos) VALUES (1, 0);",
+        [],
+    )
+    .unwrap();
+
+    conn.execute(include_sql!("migrate", "add_batch_gas_used"), [])
+        .unwrap();
+    conn.execute(include_sql!("migrate", "add_batch_state_root"), [])
+        .unwrap();
+
+    // Creating the tables again leaves the migrated table alone.
+    create_tables(&conn);
+
+    conn.execute(include_sql!("insert", "batch"), (2, 0, 10, "root"))
+        .unwrap();
+
+    let result = query(
+        &conn,
+        include_sql!("query", "list_block_headers"),
+        named_params! {
+            ":block_number": 0,
+            ":page_size": 10,
+            ":page_number": 0,
+        },
+        |row| {
+            (
+                row.get::<_, usize>(0).unwrap(),
+                row.get::<_, u64>(3).unwrap(),
+                row.get::<_, String>(4).unwrap(),
+            )
+        },
+    );
+    assert_eq!(
+        result,
+        vec![(1, 0, "0".repeat(64)), (2, 10, "root".to_string()),]
+    );
+}
+
+#[test]
+fn test_migrate_state_keys() {
+    let conn = Connection::open_in_memory().unwrap();
+    create_tables(&conn);
+    insert_contract(&conn, 1, Duration::from_secs(1), 0..2);
+
+    // The new encoding of one key is the old encoding of another.
+    for (key, value) in [("A", 1), ("B", 2)] {
+        conn.execute(
+            include_sql!("update", "update_state"),
+            params![key, value, "hash1"],
+        )
+        .unwrap();
+        conn.execute(
+            include_sql!("insert", "state_history"),
+            params![key, value, "hash1"],
+        )
+        .unwrap();
+    }
+
+    let list_keys = |sql| {
+        let mut keys = query(&conn, sql, [], |row| {
+            (
+                row.get::<_, i64>(0).unwrap(),
+                row.get::<_, String>(1).unwrap(),
+            )
+        });
+        keys.sort();
+        keys
+    };
+    let state = list_keys(include_sql!("migrate", "list_state_keys"));
+    let history = list_keys(include_sql!("migrate", "list_state_history_keys"));
+    assert_eq!(state.len(), 2);
+    assert_eq!(history.len(), 2);
+
+    conn.execute(include_sql!("migrate", "mark_state_keys"), [])
+        .unwrap();
+    conn.execute(include_sql!("migrate", "mark_state_history_keys"), [])
+        .unwrap();
+    let new_key = |key: &str| if key == "A" { "B" } else { "C" };
+    for (id, key) in &state {
+        conn.execute(
+            include_sql!("migrate", "set_state_key"),
+            params![new_key(key), id],
+        )
+        .unwrap();
+    }
+    for (id, key) in &history {
+        conn.execute(
+            include_sql!("migrate", "set_state_history_key"),
+            params![new_key(key), id],
+        )
+        .unwrap();
+    }
+
+    let get = |key| {
+        query(
+            &conn,
+            include_sql!("query", "get_state"),
+            ["hash1", key],
+            |row| row.get::<_, usize>(0).unwrap(),
+        )
+    };
+    assert_eq!(get("A"), Vec::<usize>::new());
+    assert_eq!(get("B"), vec![1]);
+    assert_eq!(get("C"), vec![2]);
+    let get_at = |key| {
+        query(
+            &conn,
+            include_sql!("query", "get_state_at"),
+            params!["hash1", key, 0],
+            |row| row.get::<_, usize>(0).unwrap(),
+        )
+    };
+    assert_eq!(get_at("B"), vec![1]);
+    assert_eq!(get_at("C"), vec![2]);
+}
+
+#[test]
+fn test_migrate_solutions_pool() {
+    let conn = Connection::open_in_memory().unwrap();
+    conn.execute(include_sql!("create", "solutions"), [])
+        .unwrap();
+
+    // The pool before solutions recorded when they were added.
+    conn.execute(
+        "CREATE TABLE solutions_pool (
+            id INTEGER PRIMARY KEY,
+            content_hash BLOB NOT NULL UNIQUE,
+            FOREIGN KEY (content_hash) REFERENCES solutions (content_hash)
+        );",
+        [],
+    )
+    .unwrap();
+    conn.execute(include_sql!("insert", "solutions"), ["hash0", "solution0"])
+        .unwrap();
+    conn.execute(
+        "INSERT INTO solutions_pool (content_hash) VALUES ('hash0');",
+        [],
+    )
+    .unwrap();
+
+    conn.execute(include_sql!("migrate", "add_pool_created_at_seconds"), [])
+        .unwrap();
+    conn.execute(include_sql!("migrate", "add_pool_created_at_nanos"), [])
+        .unwrap();
+    conn.execute(include_sql!("migrate", "set_pool_created_at"), [10, 5])
+        .unwrap();
+
+    // Creating the tables again leaves the migrated table alone.
+    create_tables(&conn);
+
+    // Solutions from before the migration expire relative to the migration time.
+    let expired = |seconds: u64| {
+        query(
+            &conn,
+            include_sql!("query", "list_expired_solutions_pool"),
+            named_params! { ":seconds": seconds, ":nanos": 0 },
+            |row| row.get::<_, String>(0).unwrap(),
+        )
+    };
+    assert!(expired(10).is_empty());
+    assert_eq!(expired(11), vec!["hash0".to_string()]);
+}
+
+#[test]
+fn test_start_state_history() {
+    let conn = Connection::open_in_memory().unwrap();
+    create_tables(&conn);
+    insert_contract(&conn, 1, Duration::from_secs(1), 0..2);
+    for i in 0..2 {
+        conn.execute(include_sql!("insert", "batch"), (i, 0, 0, "0"))
+            .unwrap();
+    }
+
+    // State written before it was recorded by block.
+    for (key, value) in [("A", 1), ("B", 2)] {
+        conn.execute(
+            include_sql!("update", "update_state"),
+            params![key, value, "hash1"],
+        )
+        .unwrap();
+    }
+
+    let latest = query(
+        &conn,
+        include_sql!("migrate", "get_latest_batch_id"),
+        [],
+        |row| row.get::<_, u64>(0).unwrap(),
+    );
+    assert_eq!(latest, vec![2]);
+    conn.execute(include_sql!("migrate", "seed_state_history"), [1])
+        .unwrap();
+    conn.execute(include_sql!("migrate", "set_state_history_start"), [1])
+        .unwrap();
+    conn.execute(
+        include_sql!("migrate", "set_latest_batch_state_root"),
+        ["root"],
+    )
+    .unwrap();
+
+    let start = query(
+        &conn,
+        include_sql!("query", "get_state_history_start"),
+        [],
+        |row| row.get::<_, u64>(0).unwrap(),
+    );
+    assert_eq!(start, vec![1]);
+
+    // The current state is the state at the end of the latest block.
+    let get_at = |key, block: u64| {
+        query(
+            &conn,
+            include_sql!("query", "get_state_at"),
+            params!["hash1", key, block],
+            |row| row.get::<_, usize>(0).unwrap(),
+        )
+    };
+    assert_eq!(get_at("A", 1), vec![1]);
+    assert_eq!(get_at("B", 2), vec![2]);
+
+    let roots = query(
+        &conn,
+        "SELECT state_root FROM batch ORDER BY id;",
+        [],
+        |row| row.get::<_, String>(0).unwrap(),
+    );
+    assert_eq!(roots, vec!["0".to_string(), "root".to_string()]);
+}
+
+/// The schema before it was versioned.
+const UNVERSIONED_SCHEMA: &str = "
+CREATE TABLE predicates (
+    id INTEGER PRIMARY KEY,
+    predicate BLOB NOT NULL,
+    content_hash BLOB NOT NULL UNIQUE
+);
+CREATE TABLE contracts (
+    id INTEGER PRIMARY KEY,
+    content_hash BLOB NOT NULL UNIQUE,
+    salt BLOB NOT NULL,
+    signature BLOB NOT NULL,
+    created_at_seconds INTEGER NOT NULL,
+    created_at_nanos INTEGER NOT NULL
+);
+CREATE TABLE contract_pairing (
+    id INTEGER PRIMARY KEY,
+    contract_id INTEGER NOT NULL,
+    predicate_id INTEGER NOT NULL,
+    FOREIGN KEY (contract_id) REFERENCES contracts (id),
+    FOREIGN KEY (predicate_id) REFERENCES predicates (id),
+    UNIQUE(contract_id, predicate_id)
+);
+CREATE TABLE solutions (
+    id INTEGER PRIMARY KEY,
+    content_hash BLOB NOT NULL UNIQUE,
+    solution BLOB NOT NULL
+);
+CREATE TABLE solutions_pool (
+    id INTEGER PRIMARY KEY,
+    content_hash BLOB NOT NULL UNIQUE,
+    FOREIGN KEY (content_hash) REFERENCES solutions (content_hash)
+);
+CREATE TABLE batch (
+    id INTEGER PRIMARY KEY,
+    created_at_seconds INTEGER NOT NULL,
+    created_at_nanos INTEGER NOT NULL
+);
+CREATE TABLE solved (
+    id INTEGER PRIMARY KEY,
+    content_hash BLOB NOT NULL,
+    batch_id INTEGER NOT NULL,
+    FOREIGN KEY (batch_id) REFERENCES batch (id),
+    FOREIGN KEY (content_hash) REFERENCES solutions (content_hash)
+);
+CREATE TABLE contract_state (
+    id INTEGER PRIMARY KEY,
+    contract_id INTEGER NOT NULL,
+    key BLOB NOT NULL,
+    value BLOB NOT NULL,
+    FOREIGN KEY (contract_id) REFERENCES contracts (id),
+    UNIQUE(contract_id, key)
+);
+CREATE TABLE failed_solutions (
+    id INTEGER PRIMARY KEY,
+    content_hash BLOB NOT NULL,
+    reason BLOB NOT NULL,
+    created_at_seconds INTEGER NOT NULL,
+    created_at_nanos INTEGER NOT NULL,
+    FOREIGN KEY (content_hash) REFERENCES solutions (content_hash)
+);
+";
+
+/// Values were encoded as hex of their postcard bytes, keys included.
+fn encode<T: serde::Serialize>(value: &T) -> String {
+    hex::encode_upper(postcard::to_allocvec(value).unwrap())
+}
+
+#[tokio::test]
+async fn test_migrate_unversioned_database() {
+    let conn = Connection::open_in_memory().unwrap();
+    conn.execute_batch(UNVERSIONED_SCHEMA).unwrap();
+
+    let address = ContentAddress([1; 32]);
+    let state: Vec<(Key, Vec<Word>)> = vec![(vec![1], vec![10]), (vec![-1], vec![20])];
+    conn.execute(
+        include_sql!("insert", "contracts"),
+        params![encode(&address), "salt", "signature", 1, 0],
+    )
+    .unwrap();
+    for (key, value) in &state {
+        conn.execute(
+            "INSERT INTO contract_state (contract_id, key, value) VALUES (1, ?, ?);",
+            [encode(key), encode(value)],
+        )
+        .unwrap();
+    }
+    for i in 0..2 {
+        conn.execute(
+            "INSERT INTO batch (created_at_seconds, created_at_nanos) VALUES (?, 0);",
+            [i],
+        )
+        .unwrap();
+    }
+    conn.execute(include_sql!("insert", "solutions"), ["hash0", "solution0"])
+        .unwrap();
+    conn.execute(
+        "INSERT INTO solutions_pool (content_hash) VALUES ('hash0');",
+        [],
+    )
+    .unwrap();
+
+    let rqlite = FakeRqlite::start(conn).await;
+    let storage = tokio::time::timeout(Duration::from_secs(10), RqliteStorage::new(&rqlite.url))
+        .await
+        .expect("creating the tables keeps failing");
+    let storage = storage.unwrap();
+    // Creating the tables again doesn't migrate again.
+    storage.create_tables().await.unwrap();
+
+    let version = {
+        let conn = rqlite.conn.lock().unwrap();
+        query(
+            &conn,
+            include_sql!("query", "get_schema_version"),
+            [],
+            |row| row.get::<_, u64>(0).unwrap(),
+        )
+    };
+    assert_eq!(version, vec![SCHEMA_VERSION]);
+
+    // The keys were re-encoded.
+    for (key, value) in &state {
+        assert_eq!(&storage.query_state(&address, key).await.unwrap(), value);
+        assert_eq!(
+            &storage.query_state_at(&address, key, 1).await.unwrap(),
+            value
+        );
+    }
+    let range = storage
+        .query_state_range(&address, &vec![Word::MIN], None, 10)
+        .await
+        .unwrap();
+    let mut sorted = state.clone();
+    sorted.sort();
+    assert_eq!(range, sorted);
+
+    // The state is known from the end of the latest block, which has its root.
+    assert!(storage
+        .query_state_at(&address, &state[0].0, 0)
+        .await
+        .is_err());
+    assert!(storage.list_state_at(0).await.is_err());
+    let tree = StateTree::from_state(state.iter().map(|(k, v)| (&address, k, v)));
+    let headers = storage.list_block_headers(None, None).await.unwrap();
+    let roots: Vec<_> = headers.iter().map(|h| h.state_root).collect();
+    assert_eq!(roots, vec![[0; 32], tree.root()]);
+    assert!(headers.iter().all(|h| h.gas_used == 0));
+
+    assert_eq!(storage.pool_size().await.unwrap(), 1);
+}
@@ -14,13 +14,19 @@ fn test_insert_solutions() {
     // Double insert is a noop
     conn.execute(include_sql!("insert", "solutions"), ["hash1", "solution1"])
         .unwrap();
-    conn.execute(include_sql!("insert", "solutions_pool"), ["hash1"])
-        .unwrap();
+    conn.execute(
+        include_sql!("insert", "solutions_pool"),
+        params!["hash1", 0, 0],
+    )
+    .unwrap();
 
     conn.execute(include_sql!("insert", "solutions"), ["hash1", "solution1"])
         .unwrap();
-    conn.execute(include_sql!("insert", "solutions_pool"), ["hash1"])
-        .unwrap();
+    conn.execute(
+        include_sql!("insert", "solutions_pool"),
+        params!["hash1", 0, 0],
+    )
+    .unwrap();
 
     let result = query(&conn, "select * from solutions_pool", [], |row| {
         (
@@ -44,8 +50,11 @@ fn test_insert_solutions() {
     // Can insert a second solution
     conn.execute(include_sql!("insert", "solutions"), ["hash2", "solution2"])
         .unwrap();
-    conn.execute(include_sql!("insert", "solutions_pool"), ["hash2"])
-        .unwrap();
+    conn.execute(
+        include_sql!("insert", "solutions_pool"),
+        params!["hash2", 0, 0],
+    )
+    .unwrap();
 
     let result = query(&conn, "select * from solutions_pool", [], |row| {
         (
@@ -87,8 +96,17 @@ fn test_insert_solutions() {
         vec![("solution1".to_string(),), ("solution2".to_string(),),]
     );
 
+    // Count solutions pool
+    let result = query(
+        &conn,
+        include_sql!("query", "count_solutions_pool"),
+        [],
+        |row| row.get::<_, usize>(0).unwrap(),
+    );
+    assert_eq!(result, vec![2]);
+
     // Move solutions to solved
-    conn.execute(include_sql!("insert", "batch"), params![0, 0])
+    conn.execute(include_sql!("insert", "batch"), params![0, 0, 0, "root"])
         .unwrap();
     conn.execute(include_sql!("insert", "copy_to_solved"), ["hash1"])
         .unwrap();
@@ -186,7 +204,7 @@ fn test_list_solutions_pool_page() {
         .unwrap();
         conn.execute(
             include_sql!("insert", "solutions_pool"),
-            [format!("hash{}", i)],
+            params![format!("hash{}", i), 0, 0],
         )
         .unwrap();
     }
@@ -254,11 +272,17 @@ fn test_move_solutions_to_failed() {
     conn.execute(include_sql!("insert", "solutions"), ["hash2", "solution2"])
         .unwrap();
 
-    conn.execute(include_sql!("insert", "solutions_pool"), ["hash1"])
-        .unwrap();
+    conn.execute(
+        include_sql!("insert", "solutions_pool"),
+        params!["hash1", 0, 0],
+    )
+    .unwrap();
 
-    conn.execute(include_sql!("insert", "solutions_pool"), ["hash2"])
-        .unwrap();
+    conn.execute(
+        include_sql!("insert", "solutions_pool"),
+        params!["hash2", 0, 0],
+    )
+    .unwrap();
 
     move_solutions_to_failed(&conn, &[("hash1", "reason1", 10), ("hash2", "reason2", 20)]);
 
@@ -362,8 +386,11 @@ fn test_batch_paging() {
                 [hash.to_string(), format!("solution{}", i)],
             )
             .unwrap();
-            conn.execute(include_sql!("insert", "solutions_pool"), [hash.to_string()])
-                .unwrap();
+            conn.execute(
+                include_sql!("insert", "solutions_pool"),
+                params![hash.to_string(), 0, 0],
+            )
+            .unwrap();
         }
 
         move_solutions_to_solved(
@@ -617,6 +644,53 @@ fn test_batch_paging() {
     );
 }
 
+#[test]
+fn test_block_headers() {
+    let conn = Connection::open_in_memory().unwrap();
+    create_tables(&conn);
+
+    for i in 0..4 {
+        let hash = format!("hash{}", i);
+        conn.execute(
+            include_sql!("insert", "solutions"),
+            [&hash, &format!("solution{}", i)],
+        )
+        .unwrap();
+        conn.execute(
+            include_sql!("insert", "solutions_pool"),
+            params![&hash, 0, 0],
+        )
+        .unwrap();
+        move_solutions_to_solved(&conn, &[hash], Duration::new(i, 0));
+    }
+
+    let result = query(
+        &conn,
+        include_sql!("query", "list_block_headers"),
+        named_params! {
+            ":block_number": 1,
+            ":page_size": 2,
+            ":page_number": 0,
+        },
+        |row| {
+            (
+                row.get::<_, usize>(0).unwrap(),
+                row.get::<_, u64>(1).unwrap(),
+                row.get::<_, u32>(2).unwrap(),
+                row.get::<_, u64>(3).unwrap(),
+                row.get::<_, String>(4).unwrap(),
+            )
+        },
+    );
+    assert_eq!(
+        result,
+        vec![
+            (2, 1, 0, 10, "root1".to_string()),
+            (3, 2, 0, 20, "root2".to_string())
+        ]
+    );
+}
+
 #[test]
 fn test_empty_batch() {
     let conn = Connection::open_in_memory().unwrap();
@@ -631,7 +705,7 @@ fn test_empty_batch() {
 
         conn.execute(
             include_sql!("insert", "solutions_pool"),
-            [&format!("hash{}", i)],
+            params![&format!("hash{}", i), 0, 0],
         )
         .unwrap();
     }
@@ -648,10 +722,118 @@ fn test_empty_batch() {
     assert_eq!(result, vec![1, 2]);
 }
 
+#[test]
+fn test_revert_batches() {
+    let conn = Connection::open_in_memory().unwrap();
+    create_tables(&conn);
+
+    for i in 0..5 {
+        conn.execute(
+            include_sql!("insert", "solutions"),
+            [&format!("hash{}", i), "solution1"],
+        )
+        .unwrap();
+
+        conn.execute(
+            include_sql!("insert", "solutions_pool"),
+            params![&format!("hash{}", i), 0, 0],
+        )
+        .unwrap();
+    }
+
+    move_solutions_to_solved(&conn, &["hash0".to_string()], Duration::new(0, 0));
+    move_solutions_to_solved(
+        &conn,
+        &["hash2".to_string(), "hash1".to_string()],
+        Duration::new(1, 1),
+    );
+    move_solutions_to_solved(&conn, &["hash3".to_string()], Duration::new(2, 2));
+
+    // Revert to the end of the first batch.
+    conn.execute(include_sql!("insert", "copy_solved_to_pool"), [3, 0, 1])
+        .unwrap();
+    for sql in [
+        include_sql!("update", "delete_solved_after"),
+        include_sql!("update", "delete_batches_after"),
+    ] {
+        conn.execute(sql, [1]).unwrap();
+    }
+
+    let result = query(&conn, "select id from batch", [], |row| {
+        row.get::<_, usize>(0).unwrap()
+    });
+    assert_eq!(result, vec![1]);
+    let result = query(&conn, "select content_hash from solved", [], |row| {
+        row.get::<_, String>(0).unwrap()
+    });
+    assert_eq!(result, vec!["hash0"]);
+
+    // Reverted solutions are behind the solutions already in the pool in block order.
+    let result = query(
+        &conn,
+        "select content_hash from solutions_pool order by id",
+        [],
+        |row| row.get::<_, String>(0).unwrap(),
+    );
+    assert_eq!(result, vec!["hash4", "hash2", "hash1", "hash3"]);
+}
+
+#[test]
+fn test_expired_and_evicted_pool() {
+    let conn = Connection::open_in_memory().unwrap();
+    create_tables(&conn);
+
+    let created_at = [(2, 0), (1, 5), (1, 2), (3, 0)];
+    for (i, (secs, nanos)) in created_at.into_iter().enumerate() {
+        conn.execute(
+            include_sql!("insert", "solutions"),
+            [&format!("hash{}", i), "solution1"],
+        )
+        .unwrap();
+        conn.execute(
+            include_sql!("insert", "solutions_pool"),
+            params![&format!("hash{}", i), secs, nanos],
+        )
+        .unwrap();
+    }
+
+    let expired = |secs: u64, nanos: u32| {
+        query(
+            &conn,
+            include_sql!("query", "list_expired_solutions_pool"),
+            named_params! { ":seconds": secs, ":nanos": nanos },
+            |row| row.get::<_, String>(0).unwrap(),
+        )
+    };
+    assert!(expired(1, 2).is_empty());
+    assert_eq!(expired(1, 3), vec!["hash2"]);
+    assert_eq!(expired(2, 0), vec!["hash1", "hash2"]);
+    assert_eq!(expired(2, 1), vec!["hash0", "hash1", "hash2"]);
+
+    // Eviction is by position in the pool rather than time.
+    let evicted = |capacity: usize| {
+        query(
+            &conn,
+            include_sql!("query", "list_evicted_solutions_pool"),
+            [capacity],
+            |row| row.get::<_, String>(0).unwrap(),
+        )
+    };
+    assert!(evicted(4).is_empty());
+    assert!(evicted(5).is_empty());
+    assert_eq!(evicted(2), vec!["hash0", "hash1"]);
+    assert_eq!(evicted(0), vec!["hash0", "hash1", "hash2", "hash3"]);
+}
+
 fn move_solutions_to_solved(conn: &Connection, hashes: &[String], time: Duration) {
     conn.execute(
         include_sql!("insert", "batch"),
-        params![time.as_secs(), time.subsec_nanos()],
+        params![
+            time.as_secs(),
+            time.subsec_nanos(),
+            time.as_secs() * 10,
+            format!("root{}", time.as_secs())
+        ],
     )
     .unwrap();
     for hash in hashes {
@@ -664,6 +846,64 @@ fn move_solutions_to_solved(conn: &Connection, hashes: &[String], time: Duration
         .unwrap();
 }
 
+#[test]
+fn test_prune_excess_failed() {
+    let conn = Connection::open_in_memory().unwrap();
+    create_tables(&conn);
+
+    for i in 1..=4 {
+        conn.execute(
+            include_sql!("insert", "solutions"),
+            [format!("hash{i}"), format!("solution{i}")],
+        )
+        .unwrap();
+        conn.execute(
+            include_sql!("insert", "solutions_pool"),
+            params![format!("hash{i}"), 0, 0],
+        )
+        .unwrap();
+    }
+    move_solutions_to_failed(
+        &conn,
+        &[
+            ("hash1", "reason1", 10),
+            ("hash2", "reason2", 20),
+            ("hash3", "reason3", 30),
+            ("hash4", "reason4", 40),
+        ],
+    );
+
+    let prunable = |older_than: u64, max_count: i64| {
+        query(
+            &conn,
+            include_sql!("query", "list_prunable_failed"),
+            named_params! {
+                ":older_than": older_than,
+                ":max_count": max_count,
+            },
+            |row| row.get::<_, String>(0).unwrap(),
+        )
+    };
+    assert!(prunable(10, -1).is_empty());
+    assert_eq!(prunable(25, -1), vec!["solution1", "solution2"]);
+    assert_eq!(prunable(25, 3), vec!["solution1", "solution2"]);
+    assert_eq!(prunable(0, 1), vec!["solution1", "solution2", "solution3"]);
+    assert_eq!(prunable(35, 3), vec!["solution1", "solution2", "solution3"]);
+
+    conn.execute(include_sql!("update", "prune_excess_failed"), [2])
+        .unwrap();
+    let result = query(
+        &conn,
+        include_sql!("query", "list_failed_solutions"),
+        named_params! {
+            ":page_size": 10,
+            ":page_number": 0,
+        },
+        |row| row.get::<_, String>(0).unwrap(),
+    );
+    assert_eq!(result, vec!["solution3", "solution4"]);
+}
+
 fn move_solutions_to_failed(conn: &Connection, hashes_reasons: &[(&str, &str, u64)]) {
     for (hash, reason, secs) in hashes_reasons {
         conn.execute(
@@ -78,3 +78,360 @@ fn test_update_state() {
     let result = get_state(&conn, 1, 149..150);
     assert_eq!(result, vec![149]);
 }
+
+#[test]
+fn test_list_state() {
+    let conn = Connection::open_in_memory().unwrap();
+    create_tables(&conn);
+
+    insert_contract(&conn, 1, Duration::from_secs(1), 0..2);
+    insert_contract(&conn, 2, Duration::from_secs(2), 2..4);
+
+    insert_state(&conn, 1, 0..2);
+    insert_state(&conn, 2, 5..6);
+    // No contract so nothing is inserted.
+    insert_state(&conn, 3, 0..1);
+
+    let mut result = query(&conn, include_sql!("query", "list_state"), [], |row| {
+        (
+            row.get::<_, String>(0).unwrap(),
+            row.get::<_, String>(1).unwrap(),
+            row.get::<_, usize>(2).unwrap(),
+        )
+    });
+    result.sort();
+    assert_eq!(
+        result,
+        vec![
+            ("hash1".to_string(), "key0".to_string(), 0),
+            ("hash1".to_string(), "key1".to_string(), 1),
+            ("hash2".to_string(), "key5".to_string(), 5),
+        ]
+    );
+}
+
+#[test]
+fn test_list_state_range() {
+    let conn = Connection::open_in_memory().unwrap();
+    create_tables(&conn);
+
+    insert_contract(&conn, 1, Duration::from_secs(1), 0..2);
+    insert_contract(&conn, 2, Duration::from_secs(2), 2..4);
+
+    insert_state(&conn, 1, 0..8);
+    insert_state(&conn, 2, 0..8);
+
+    let range = |start: usize, end: usize, limit: usize| {
+        query(
+            &conn,
+            include_sql!("query", "list_state_range"),
+            params![
+                "hash1",
+                format!("key{}", start),
+                format!("key{}", end),
+                limit
+            ],
+            |row| {
+                (
+                    row.get::<_, String>(0).unwrap(),
+                    row.get::<_, usize>(1).unwrap(),
+                )
+            },
+        )
+    };
+    let keys = |r: Range<usize>| r.map(|i| (format!("key{}", i), i)).collect::<Vec<_>>();
+
+    // Results are in key order and the end is excluded.
+    assert_eq!(range(2, 5, 10), keys(2..5));
+    assert_eq!(range(2, 5, 2), keys(2..4));
+    assert!(range(5, 5, 10).is_empty());
+    assert!(range(5, 2, 10).is_empty());
+
+    let from = |start: usize, limit: usize| {
+        query(
+            &conn,
+            include_sql!("query", "list_state_from"),
+            params!["hash1", format!("key{}", start), limit],
+            |row| {
+                (
+                    row.get::<_, String>(0).unwrap(),
+                    row.get::<_, usize>(1).unwrap(),
+                )
+            },
+        )
+    };
+    assert_eq!(from(5, 10), keys(5..8));
+    assert_eq!(from(0, 3), keys(0..3));
+}
+
+#[test]
+fn test_get_state_batch() {
+    let conn = Connection::open_in_memory().unwrap();
+    create_tables(&conn);
+
+    insert_contract(&conn, 1, Duration::from_secs(1), 0..2);
+    insert_contract(&conn, 2, Duration::from_secs(2), 2..4);
+
+    insert_state(&conn, 1, 0..4);
+    insert_state(&conn, 2, 0..4);
+
+    let keys = r#"["key1", "key3", "key9"]"#;
+    let mut result = query(
+        &conn,
+        include_sql!("query", "get_state_batch"),
+        params!["hash1", keys],
+        |row| {
+            (
+                row.get::<_, String>(0).unwrap(),
+                row.get::<_, usize>(1).unwrap(),
+            )
+        },
+    );
+    result.sort();
+    assert_eq!(
+        result,
+        vec![("key1".to_string(), 1), ("key3".to_string(), 3)]
+    );
+}
+
+fn insert_history(conn: &Connection, content_hash: usize, key: usize, value: usize) {
+    conn.execute(
+        include_sql!("insert", "state_history"),
+        params![
+            format!("key{}", key),
+            value,
+            format!("hash{}", content_hash)
+        ],
+    )
+    .unwrap();
+}
+
+fn new_batch(conn: &Connection, i: u64) {
+    conn.execute(include_sql!("insert", "batch"), params![i, 0, 0, "root"])
+        .unwrap();
+}
+
+fn get_state_at(conn: &Connection, content_hash: usize, key: usize, block: u64) -> Vec<usize> {
+    query(
+        conn,
+        include_sql!("query", "get_state_at"),
+        params![
+            format!("hash{}", content_hash),
+            format!("key{}", key),
+            block
+        ],
+        |row| row.get::<_, usize>(0).unwrap(),
+    )
+}
+
+fn list_state_at(conn: &Connection, block: u64) -> Vec<(String, String, usize)> {
+    let mut result = query(
+        conn,
+        include_sql!("query", "list_state_at"),
+        [block],
+        |row| {
+            (
+                row.get::<_, String>(0).unwrap(),
+                row.get::<_, String>(1).unwrap(),
+                row.get::<_, usize>(2).unwrap(),
+            )
+        },
+    );
+    result.sort();
+    result
+}
+
+fn get_block_state_diff(conn: &Connection, block: u64) -> Vec<(String, Option<usize>, usize)> {
+    let mut result = query(
+        conn,
+        include_sql!("query", "get_block_state_diff"),
+        [block],
+        |row| {
+            assert_eq!(row.get::<_, String>(0).unwrap(), "hash1");
+            (
+                row.get::<_, String>(1).unwrap(),
+                row.get::<_, Option<usize>>(2).unwrap(),
+                row.get::<_, usize>(3).unwrap(),
+            )
+        },
+    );
+    result.sort();
+    result
+}
+
+#[test]
+fn test_state_history() {
+    let conn = Connection::open_in_memory().unwrap();
+    create_tables(&conn);
+
+    insert_contract(&conn, 1, Duration::from_secs(1), 0..2);
+
+    // Written before any batch so part of block 0.
+    insert_history(&conn, 1, 0, 10);
+    insert_history(&conn, 1, 1, 11);
+    new_batch(&conn, 0);
+
+    // Part of block 1. The second write to a key in a block replaces the first.
+    insert_history(&conn, 1, 0, 20);
+    insert_history(&conn, 1, 0, 21);
+    new_batch(&conn, 1);
+
+    // Part of block 2 which isn't created yet.
+    insert_history(&conn, 1, 1, 31);
+
+    // No contract so nothing is recorded.
+    insert_history(&conn, 2, 0, 1);
+
+    assert_eq!(get_state_at(&conn, 1, 0, 0), vec![10]);
+    assert_eq!(get_state_at(&conn, 1, 0, 1), vec![21]);
+    assert_eq!(get_state_at(&conn, 1, 0, 5), vec![21]);
+    assert_eq!(get_state_at(&conn, 1, 1, 1), vec![11]);
+    assert_eq!(get_state_at(&conn, 1, 1, 2), vec![31]);
+    assert_eq!(get_state_at(&conn, 1, 2, 2), Vec::<usize>::new());
+    assert_eq!(get_state_at(&conn, 2, 0, 2), Vec::<usize>::new());
+
+    let s = |k: &str, v| ("hash1".to_string(), k.to_string(), v);
+    assert_eq!(list_state_at(&conn, 0), vec![s("key0", 10), s("key1", 11)]);
+    assert_eq!(list_state_at(&conn, 1), vec![s("key0", 21), s("key1", 11)]);
+    assert_eq!(list_state_at(&conn, 2), vec![s("key0", 21), s("key1", 31)]);
+
+    assert_eq!(
+        get_block_state_diff(&conn, 0),
+        vec![
+            ("key0".to_string(), None, 10),
+            ("key1".to_string(), None, 11)
+        ]
+    );
+    assert_eq!(
+        get_block_state_diff(&conn, 1),
+        vec![("key0".to_string(), Some(10), 21)]
+    );
+    assert_eq!(
+        get_block_state_diff(&conn, 2),
+        vec![("key1".to_string(), Some(11), 31)]
+    );
+    assert!(get_block_state_diff(&conn, 3).is_empty());
+}
+
+fn list_state_range_at(
+    conn: &Connection,
+    start: usize,
+    end: Option<usize>,
+    limit: usize,
+    block: u64,
+) -> Vec<(String, usize)> {
+    // A value of zero stands in for a cleared value.
+    let row = |row: &rusqlite::Row| {
+        (
+            row.get::<_, String>(0).unwrap(),
+            row.get::<_, usize>(1).unwrap(),
+        )
+    };
+    let start = format!("key{}", start);
+    match end {
+        Some(end) => query(
+            conn,
+            include_sql!("query", "list_state_range_at"),
+            params!["hash1", start, format!("key{}", end), block, 0, limit],
+            row,
+        ),
+        None => query(
+            conn,
+            include_sql!("query", "list_state_from_at"),
+            params!["hash1", start, block, 0, limit],
+            row,
+        ),
+    }
+}
+
+#[test]
+fn test_list_state_range_at() {
+    let conn = Connection::open_in_memory().unwrap();
+    create_tables(&conn);
+
+    insert_contract(&conn, 1, Duration::from_secs(1), 0..2);
+
+    for key in 0..4 {
+        insert_history(&conn, 1, key, key + 10);
+    }
+    new_batch(&conn, 0);
+
+    // Block 1 clears key1 and updates key2.
+    insert_history(&conn, 1, 1, 0);
+    insert_history(&conn, 1, 2, 22);
+    new_batch(&conn, 1);
+
+    let s = |k: usize, v| (format!("key{}", k), v);
+    assert_eq!(
+        list_state_range_at(&conn, 0, None, 10, 0),
+        vec![s(0, 10), s(1, 11), s(2, 12), s(3, 13)]
+    );
+    assert_eq!(
+        list_state_range_at(&conn, 0, None, 10, 1),
+        vec![s(0, 10), s(2, 22), s(3, 13)]
+    );
+    assert_eq!(
+        list_state_range_at(&conn, 1, Some(3), 10, 0),
+        vec![s(1, 11), s(2, 12)]
+    );
+    assert_eq!(
+        list_state_range_at(&conn, 1, Some(3), 10, 1),
+        vec![s(2, 22)]
+    );
+
+    // Cleared keys don't count towards the limit.
+    assert_eq!(list_state_range_at(&conn, 1, None, 1, 1), vec![s(2, 22)]);
+    assert_eq!(
+        list_state_range_at(&conn, 1, None, 2, 0),
+        vec![s(1, 11), s(2, 12)]
+    );
+}
+
+fn list_reverted_state(conn: &Connection, block: u64) -> Vec<(String, Option<usize>)> {
+    let mut result = query(
+        conn,
+        include_sql!("query", "list_reverted_state"),
+        [block, block],
+        |row| {
+            assert_eq!(row.get::<_, String>(0).unwrap(), "hash1");
+            (
+                row.get::<_, String>(1).unwrap(),
+                row.get::<_, Option<usize>>(2).unwrap(),
+            )
+        },
+    );
+    result.sort();
+    result
+}
+
+#[test]
+fn test_revert_state() {
+    let conn = Connection::open_in_memory().unwrap();
+    create_tables(&conn);
+
+    insert_contract(&conn, 1, Duration::from_secs(1), 0..2);
+
+    insert_history(&conn, 1, 0, 10);
+    new_batch(&conn, 0);
+    insert_history(&conn, 1, 0, 20);
+    insert_history(&conn, 1, 1, 21);
+    new_batch(&conn, 1);
+    insert_history(&conn, 1, 1, 31);
+
+    // Each key written after the block is listed once with its value at the end of the block.
+    assert_eq!(
+        list_reverted_state(&conn, 0),
+        vec![("key0".to_string(), Some(10)), ("key1".to_string(), None)]
+    );
+    assert_eq!(
+        list_reverted_state(&conn, 1),
+        vec![("key1".to_string(), Some(21))]
+    );
+    assert!(list_reverted_state(&conn, 2).is_empty());
+
+    conn.execute(include_sql!("update", "delete_state_history_after"), [0])
+        .unwrap();
+    assert!(list_reverted_state(&conn, 0).is_empty());
+    let s = |k: &str, v| ("hash1".to_string(), k.to_string(), v);
+    assert_eq!(list_state_at(&conn, 2), vec![s("key0", 10)]);
+}
@@ -0,0 +1,448 @@
+//! Strategies for choosing which solutions from the pool go into a block
+//! and in what order.
+//!
+//! The run loop hands a [`BlockBuilder`] the solutions pool and a [`BlockContext`].
+//! The builder decides the order that solutions are pushed into the context.
+//! The context checks each solution against the state of the block so far,
+//! enforces the gas limits and collects the ordered valid and failed solutions.
+
+use crate::{run::Solutions, solution::read::read_contract_from_storage, Config, Gas};
+use essential_state_read_vm::StateRead;
+use essential_storage::{failed_solution::SolutionFailReason, Storage};
+use essential_transaction_storage::{Transaction, TransactionStorage};
+use essential_types::{predicate::Predicate, solution::Solution, Hash, PredicateAddress};
+use std::{cmp::Reverse, collections::HashMap, future::Future, sync::Arc};
+
+#[cfg(test)]
+mod tests;
+
+/// A strategy for building a block from the solutions pool.
+pub trait BlockBuilder: Send + Sync {
+    /// Push solutions from the `pool` into the `block` in the order they should be applied.
+    ///
+    /// The pool is in FIFO order.
+    /// Solutions that are never pushed are left in the pool for a later block.
+    fn build<S>(
+        &self,
+        pool: Vec<Arc<Solution>>,
+        block: &mut BlockContext<S>,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send
+    where
+        S: Storage + StateRead + Clone + Send + Sync + 'static;
+}
+
+/// Applies solutions in the order they were submitted.
+///
+/// Once a solution doesn't fit in the block the block is complete,
+/// so later solutions can't jump ahead of an earlier one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Fifo;
+
+/// Applies the solutions that use the most gas first.
+///
+/// Solutions that don't fit in the block are skipped so
+/// smaller solutions may fill the remaining space.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HighestGasFirst;
+
+/// Applies the solutions that use the least gas first,
+/// fitting as many solutions into the block as possible.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GreedyMaxSolutions;
+
+/// One of the built-in block builders, chosen at runtime.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BlockBuilderKind {
+    /// See [`Fifo`].
+    #[default]
+    Fifo,
+    /// See [`HighestGasFirst`].
+    HighestGasFirst,
+    /// See [`GreedyMaxSolutions`].
+    GreedyMaxSolutions,
+}
+
+/// The block being built.
+pub struct BlockContext<S>
+where
+    S: Storage + StateRead + Clone + Send + Sync + 'static,
+{
+    storage: S,
+    transaction: TransactionStorage<S>,
+    /// The state before any solution subject to the gas limits is applied.
+    ///
+    /// Includes the exempt solutions, such as the block state solution,
+    /// so checking against it sees the block's number and time.
+    pre_block: TransactionStorage<S>,
+    block_gas_limit: Gas,
+    solution_gas_limit: Gas,
+    retry_not_composable: bool,
+    max_not_composable_retries: usize,
+    retries: Retries,
+    gas_used: Gas,
+    /// The number of valid solutions that are exempt from the gas limits.
+    exempt: usize,
+    valid_solutions: Vec<Arc<Solution>>,
+    failed_solutions: Vec<(Arc<Solution>, SolutionFailReason)>,
+}
+
+/// The number of blocks in a row each solution has been left in the pool as not composable.
+///
+/// Kept by the run loop between blocks so a solution that never
+/// composes with the rest of its block is eventually failed.
+#[derive(Debug, Default)]
+pub(crate) struct Retries(HashMap<Hash, usize>);
+
+/// The result of pushing a solution into a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pushed {
+    /// The solution was applied to the block using this much gas.
+    Valid(Gas),
+    /// The solution is invalid or exceeds the solution gas limit
+    /// and will be moved to the failed pool.
+    Failed,
+    /// The solution would take the block over its gas limit.
+    /// It is left in the pool.
+    BlockFull,
+    /// The solution is valid against the state before this block but not
+    /// once the solutions already in the block have been applied.
+    /// It is moved to the failed pool unless retrying is enabled,
+    /// in which case it is left in the pool for the next block
+    /// until it has been retried `max_not_composable_retries` times.
+    NotComposable,
+}
+
+impl BlockBuilder for Fifo {
+    async fn build<S>(
+        &self,
+        pool: Vec<Arc<Solution>>,
+        block: &mut BlockContext<S>,
+    ) -> anyhow::Result<()>
+    where
+        S: Storage + StateRead + Clone + Send + Sync + 'static,
+    {
+        for solution in pool {
+            if let Pushed::BlockFull = block.push(solution).await? {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl BlockBuilder for HighestGasFirst {
+    async fn build<S>(
+        &self,
+        pool: Vec<Arc<Solution>>,
+        block: &mut BlockContext<S>,
+    ) -> anyhow::Result<()>
+    where
+        S: Storage + StateRead + Clone + Send + Sync + 'static,
+    {
+        let ranked = rank_by_gas(pool, block, Reverse).await?;
+        push_all(ranked, block).await
+    }
+}
+
+impl BlockBuilder for GreedyMaxSolutions {
+    async fn build<S>(
+        &self,
+        pool: Vec<Arc<Solution>>,
+        block: &mut BlockContext<S>,
+    ) -> anyhow::Result<()>
+    where
+        S: Storage + StateRead + Clone + Send + Sync + 'static,
+    {
+        let ranked = rank_by_gas(pool, block, |gas| gas).await?;
+        push_all(ranked, block).await
+    }
+}
+
+impl BlockBuilder for BlockBuilderKind {
+    async fn build<S>(
+        &self,
+        pool: Vec<Arc<Solution>>,
+        block: &mut BlockContext<S>,
+    ) -> anyhow::Result<()>
+    where
+        S: Storage + StateRead + Clone + Send + Sync + 'static,
+    {
+        match self {
+            BlockBuilderKind::Fifo => Fifo.build(pool, block).await,
+            BlockBuilderKind::HighestGasFirst => HighestGasFirst.build(pool, block).await,
+            BlockBuilderKind::GreedyMaxSolutions => GreedyMaxSolutions.build(pool, block).await,
+        }
+    }
+}
+
+/// Sort the pool by the gas each solution uses against the current block state.
+///
+/// The sort is stable so solutions using the same gas stay in FIFO order.
+/// Solutions that fail this check are put last in FIFO order as they may
+/// become valid once other solutions have been applied.
+async fn rank_by_gas<S, K, F>(
+    pool: Vec<Arc<Solution>>,
+    block: &BlockContext<S>,
+    key: F,
+) -> anyhow::Result<Vec<Arc<Solution>>>
+where
+    S: Storage + StateRead + Clone + Send + Sync + 'static,
+    K: Ord,
+    F: Fn(Gas) -> K,
+{
+    let mut ranked = Vec::with_capacity(pool.len());
+    let mut unranked = vec![];
+    for solution in pool {
+        match block.check(&solution).await? {
+            Some(gas) => ranked.push((gas, solution)),
+            None => unranked.push(solution),
+        }
+    }
+    ranked.sort_by_key(|(gas, _)| key(*gas));
+    Ok(ranked
+        .into_iter()
+        .map(|(_, solution)| solution)
+        .chain(unranked)
+        .collect())
+}
+
+/// Push every solution, skipping those that don't fit.
+async fn push_all<S>(pool: Vec<Arc<Solution>>, block: &mut BlockContext<S>) -> anyhow::Result<()>
+where
+    S: Storage + StateRead + Clone + Send + Sync + 'static,
+{
+    for solution in pool {
+        block.push(solution).await?;
+    }
+    Ok(())
+}
+
+impl Retries {
+    /// Forget the solutions that are no longer in the pool.
+    pub(crate) fn retain_pool(&mut self, pool: &[Arc<Solution>]) {
+        if self.0.is_empty() {
+            return;
+        }
+        let pool: std::collections::HashSet<Hash> = pool
+            .iter()
+            .map(|solution| essential_hash::hash(solution.as_ref()))
+            .collect();
+        self.0.retain(|hash, _| pool.contains(hash));
+    }
+}
+
+impl<S> BlockContext<S>
+where
+    S: Storage + StateRead + Clone + Send + Sync + 'static,
+{
+    pub(crate) fn new(storage: S, config: &Config) -> Self {
+        let transaction = storage.clone().transaction();
+        Self {
+            pre_block: transaction.clone(),
+            transaction,
+            storage,
+            block_gas_limit: config.block_gas_limit,
+            solution_gas_limit: config.solution_gas_limit,
+            retry_not_composable: config.retry_not_composable,
+            max_not_composable_retries: config.max_not_composable_retries,
+            retries: Retries::default(),
+            gas_used: 0,
+            exempt: 0,
+            valid_solutions: vec![],
+            failed_solutions: vec![],
+        }
+    }
+
+    /// Continue counting the retries of not composable solutions from previous blocks.
+    pub(crate) fn with_retries(mut self, retries: Retries) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// The state of the block with all valid solutions so far applied.
+    pub fn transaction(&self) -> &TransactionStorage<S> {
+        &self.transaction
+    }
+
+    /// Total gas used by the valid solutions so far.
+    pub fn gas_used(&self) -> Gas {
+        self.gas_used
+    }
+
+    /// Gas remaining before the block reaches its gas limit.
+    pub fn gas_remaining(&self) -> Gas {
+        self.block_gas_limit.saturating_sub(self.gas_used)
+    }
+
+    /// The valid solutions so far in the order they were applied.
+    pub fn valid_solutions(&self) -> &[Arc<Solution>] {
+        &self.valid_solutions
+    }
+
+    /// The failed solutions so far with the reason they failed.
+    pub fn failed_solutions(&self) -> &[(Arc<Solution>, SolutionFailReason)] {
+        &self.failed_solutions
+    }
+
+    /// Check the solution against the current block state without applying it.
+    ///
+    /// Returns the gas the solution would use or `None` if the solution is invalid.
+    /// Errors are only returned if the solution's contract couldn't be read.
+    pub async fn check(&self, solution: &Arc<Solution>) -> anyhow::Result<Option<Gas>> {
+        let contract = read_contract_from_storage(solution, &self.storage).await?;
+        let result = crate::checked_state_transition(
+            &self.transaction,
+            solution.clone(),
+            &contract,
+            Default::default(),
+        )
+        .await;
+        Ok(result.ok().map(|(_, gas)| gas))
+    }
+
+    /// Check the solution against the current block state and, if
+    /// it is valid and fits within the gas limits, apply it.
+    ///
+    /// Errors are only returned if the solution's contract couldn't be read.
+    pub async fn push(&mut self, solution: Arc<Solution>) -> anyhow::Result<Pushed> {
+        self.push_inner(solution, false).await
+    }
+
+    /// Push a solution that is not subject to the gas limits.
+    ///
+    /// Exempt solutions must be pushed before any other solution.
+    pub(crate) async fn push_exempt(&mut self, solution: Arc<Solution>) -> anyhow::Result<Pushed> {
+        self.push_inner(solution, true).await
+    }
+
+    async fn push_inner(
+        &mut self,
+        solution: Arc<Solution>,
+        exempt: bool,
+    ) -> anyhow::Result<Pushed> {
+        #[cfg(feature = "tracing")]
+        let solution_hash = essential_hash::content_addr(solution.as_ref());
+
+        // Get the contract for this solution.
+        let contract = read_contract_from_storage(&solution, &self.storage).await?;
+
+        // Apply the proposed mutations, check the contract and return the result.
+        let check_config = Default::default();
+
+        match crate::checked_state_transition(
+            &self.transaction,
+            solution.clone(),
+            &contract,
+            check_config,
+        )
+        .await
+        {
+            // A solution that can't fit in any block is failed
+            // rather than left to block the pool.
+            Ok((_, gas)) if !exempt && gas > self.solution_gas_limit.min(self.block_gas_limit) => {
+                // Collect the failed solution with the reason.
+                self.failed_solutions
+                    .push((solution, SolutionFailReason::GasLimitExceeded(gas)));
+                #[cfg(feature = "tracing")]
+                tracing::debug!(failed_solution = %solution_hash, gas, "solution gas limit exceeded");
+                Ok(Pushed::Failed)
+            }
+            Ok((_, gas)) if !exempt && gas > self.gas_remaining() => {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(block_full = %solution_hash, gas, gas_used = self.gas_used);
+                Ok(Pushed::BlockFull)
+            }
+            Ok((post_state, gas)) => {
+                self.gas_used = self.gas_used.saturating_add(gas);
+                if exempt {
+                    self.exempt += 1;
+                    self.pre_block = post_state.clone();
+                }
+                // Update the transaction to the post state.
+                self.transaction = post_state;
+                // Collect the valid solution.
+                self.valid_solutions.push(solution);
+                #[cfg(feature = "tracing")]
+                tracing::debug!(valid_solution = %solution_hash);
+                Ok(Pushed::Valid(gas))
+            }
+            Err(_) if self.valid_on_pre_block_state(&solution, &contract).await => {
+                // Only fails in combination with the solutions already in the block.
+                let retry = self.retry_not_composable && self.retry(&solution);
+                if !retry {
+                    self.failed_solutions
+                        .push((solution, SolutionFailReason::NotComposable));
+                }
+                #[cfg(feature = "tracing")]
+                tracing::debug!(not_composable = %solution_hash, retry);
+                Ok(Pushed::NotComposable)
+            }
+            Err(err) => {
+                // Collect the failed solution with the reason.
+                self.failed_solutions.push((
+                    solution,
+                    SolutionFailReason::ConstraintsFailed(err.to_string()),
+                ));
+                #[cfg(feature = "tracing")]
+                tracing::debug!(failed_solution = %solution_hash, %err);
+                Ok(Pushed::Failed)
+            }
+        }
+    }
+
+    /// Count another retry of a not composable solution,
+    /// returning whether it may be retried in the next block.
+    fn retry(&mut self, solution: &Solution) -> bool {
+        let hash = essential_hash::hash(solution);
+        let retries = self.retries.0.entry(hash).or_default();
+        *retries += 1;
+        if *retries > self.max_not_composable_retries {
+            self.retries.0.remove(&hash);
+            return false;
+        }
+        true
+    }
+
+    /// Check if the solution is valid on its own against the state before this block.
+    ///
+    /// The exempt solutions are applied first so the check sees the block's number and time.
+    async fn valid_on_pre_block_state(
+        &self,
+        solution: &Arc<Solution>,
+        contract: &HashMap<PredicateAddress, Arc<Predicate>>,
+    ) -> bool {
+        // Nothing else has been applied so the result would be the same.
+        if self.valid_solutions.len() == self.exempt {
+            return false;
+        }
+        crate::checked_state_transition(
+            &self.pre_block,
+            solution.clone(),
+            contract,
+            Default::default(),
+        )
+        .await
+        .is_ok()
+    }
+
+    /// Split the block into its solutions, state transaction and the
+    /// retries of the not composable solutions left in the pool.
+    pub(crate) fn into_parts(self) -> (Solutions, TransactionStorage<S>, Retries) {
+        let mut retries = self.retries;
+        if !retries.0.is_empty() {
+            for solution in self
+                .valid_solutions
+                .iter()
+                .chain(self.failed_solutions.iter().map(|(solution, _)| solution))
+            {
+                retries.0.remove(&essential_hash::hash(solution.as_ref()));
+            }
+        }
+        let solutions = Solutions {
+            valid_solutions: self.valid_solutions,
+            failed_solutions: self.failed_solutions,
+            gas_used: self.gas_used,
+        };
+        (solutions, self.transaction, retries)
+    }
+}
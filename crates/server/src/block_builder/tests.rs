@@ -0,0 +1,239 @@
+use super::*;
+use crate::test_utils::deploy_predicate_to_storage;
+use essential_memory_storage::MemoryStorage;
+use essential_types::{predicate::Predicate, Word};
+use test_utils::{empty::Empty, solution_with_predicate};
+
+/// A predicate with a state read program that runs `n` pushes and pops
+/// and a constraint that pushes `value`.
+///
+/// The larger `n` the more gas the solution uses.
+fn predicate(n: usize, value: Word) -> Predicate {
+    let mut predicate = Predicate::empty();
+    let mut ops: Vec<essential_state_read_vm::asm::Op> = vec![];
+    for _ in 0..n {
+        ops.push(essential_state_read_vm::asm::Stack::Push(1).into());
+        ops.push(essential_state_read_vm::asm::Stack::Pop.into());
+    }
+    ops.push(essential_state_read_vm::asm::TotalControlFlow::Halt.into());
+    predicate.state_read = vec![essential_state_read_vm::asm::to_bytes(ops).collect()];
+    predicate.constraints = vec![essential_constraint_vm::asm::to_bytes(vec![
+        essential_constraint_vm::asm::Stack::Push(value).into(),
+    ])
+    .collect()];
+    predicate
+}
+
+/// Deploy a predicate using `n` to scale its gas and return a solution for it.
+async fn solution(storage: &MemoryStorage, n: usize, value: Word) -> Arc<Solution> {
+    let (address, _) = deploy_predicate_to_storage(storage.clone(), predicate(n, value)).await;
+    Arc::new(solution_with_predicate(address))
+}
+
+struct Pool {
+    storage: MemoryStorage,
+    small: Arc<Solution>,
+    large: Arc<Solution>,
+    medium: Arc<Solution>,
+    small_gas: Gas,
+    medium_gas: Gas,
+    large_gas: Gas,
+}
+
+impl Pool {
+    async fn new() -> Self {
+        let storage = MemoryStorage::new();
+        let small = solution(&storage, 1, 1).await;
+        let large = solution(&storage, 3, 1).await;
+        let medium = solution(&storage, 2, 1).await;
+
+        let block = BlockContext::new(storage.clone(), &Default::default());
+        let small_gas = block.check(&small).await.unwrap().unwrap();
+        let medium_gas = block.check(&medium).await.unwrap().unwrap();
+        let large_gas = block.check(&large).await.unwrap().unwrap();
+        assert!(small_gas < medium_gas && medium_gas < large_gas);
+
+        Self {
+            storage,
+            small,
+            large,
+            medium,
+            small_gas,
+            medium_gas,
+            large_gas,
+        }
+    }
+
+    /// The solutions in FIFO order.
+    fn solutions(&self) -> Vec<Arc<Solution>> {
+        vec![self.small.clone(), self.large.clone(), self.medium.clone()]
+    }
+
+    /// Build a block that fits the large and small solutions.
+    async fn build(&self, builder: impl BlockBuilder) -> BlockContext<MemoryStorage> {
+        let config = Config {
+            block_gas_limit: self.large_gas + self.small_gas,
+            ..Default::default()
+        };
+        let mut block = BlockContext::new(self.storage.clone(), &config);
+        builder.build(self.solutions(), &mut block).await.unwrap();
+        block
+    }
+}
+
+#[tokio::test]
+async fn test_fifo() {
+    let pool = Pool::new().await;
+    let block = pool.build(Fifo).await;
+
+    // The medium solution doesn't fit so the block is complete.
+    assert_eq!(
+        block.valid_solutions(),
+        [pool.small.clone(), pool.large.clone()]
+    );
+    assert!(block.failed_solutions().is_empty());
+    assert_eq!(block.gas_used(), pool.small_gas + pool.large_gas);
+}
+
+#[tokio::test]
+async fn test_highest_gas_first() {
+    let pool = Pool::new().await;
+    let block = pool.build(HighestGasFirst).await;
+
+    // The medium solution is skipped so the small solution fills the block.
+    assert_eq!(
+        block.valid_solutions(),
+        [pool.large.clone(), pool.small.clone()]
+    );
+    assert!(block.failed_solutions().is_empty());
+    assert_eq!(block.gas_remaining(), 0);
+}
+
+#[tokio::test]
+async fn test_greedy_max_solutions() {
+    let pool = Pool::new().await;
+    let block = pool.build(GreedyMaxSolutions).await;
+
+    assert_eq!(
+        block.valid_solutions(),
+        [pool.small.clone(), pool.medium.clone()]
+    );
+    assert!(block.failed_solutions().is_empty());
+    assert_eq!(block.gas_used(), pool.small_gas + pool.medium_gas);
+}
+
+#[tokio::test]
+async fn test_block_builder_kind() {
+    let pool = Pool::new().await;
+    for (kind, expected) in [
+        (BlockBuilderKind::Fifo, pool.build(Fifo).await),
+        (
+            BlockBuilderKind::HighestGasFirst,
+            pool.build(HighestGasFirst).await,
+        ),
+        (
+            BlockBuilderKind::GreedyMaxSolutions,
+            pool.build(GreedyMaxSolutions).await,
+        ),
+    ] {
+        let block = pool.build(kind).await;
+        assert_eq!(block.valid_solutions(), expected.valid_solutions());
+    }
+}
+
+#[tokio::test]
+async fn test_invalid_solutions_fail() {
+    let pool = Pool::new().await;
+    let invalid = solution(&pool.storage, 1, 0).await;
+
+    let mut solutions = pool.solutions();
+    solutions.insert(0, invalid.clone());
+
+    for kind in [
+        BlockBuilderKind::Fifo,
+        BlockBuilderKind::HighestGasFirst,
+        BlockBuilderKind::GreedyMaxSolutions,
+    ] {
+        let config = Config {
+            solution_gas_limit: pool.medium_gas,
+            ..Default::default()
+        };
+        let mut block = BlockContext::new(pool.storage.clone(), &config);
+        kind.build(solutions.clone(), &mut block).await.unwrap();
+
+        assert_eq!(block.valid_solutions().len(), 2);
+        let failed: Vec<_> = block.failed_solutions().iter().collect();
+        assert_eq!(failed.len(), 2);
+        assert!(failed.iter().any(|(s, reason)| *s == invalid
+            && matches!(reason, SolutionFailReason::ConstraintsFailed(_))));
+        assert!(failed.iter().any(|(s, reason)| *s == pool.large
+            && *reason == SolutionFailReason::GasLimitExceeded(pool.large_gas)));
+    }
+}
+
+/// Two counter solutions that are each valid on their own but not together.
+async fn conflicting() -> (Arc<Solution>, Arc<Solution>, MemoryStorage) {
+    let (address, storage) =
+        crate::test_utils::deploy_predicate(crate::test_utils::counter_predicate(1)).await;
+    let solution = crate::test_utils::counter_solution(address, 1).await;
+    let mut conflicting = solution.clone();
+    conflicting.data[0].decision_variables[0].push(0);
+    (Arc::new(solution), Arc::new(conflicting), storage)
+}
+
+#[tokio::test]
+async fn test_not_composable_after_exempt() {
+    let (solution, conflicting, storage) = conflicting().await;
+
+    // A solution that only fails in combination with others is not composable.
+    let mut block = BlockContext::new(storage.clone(), &Default::default());
+    block.push(solution.clone()).await.unwrap();
+    assert_eq!(
+        block.push(conflicting.clone()).await.unwrap(),
+        Pushed::NotComposable
+    );
+
+    // But the exempt solutions are part of the state before the block,
+    // so a solution that fails against them has failed on its own.
+    let mut block = BlockContext::new(storage.clone(), &Default::default());
+    block.push_exempt(solution).await.unwrap();
+    assert_eq!(block.push(conflicting).await.unwrap(), Pushed::Failed);
+    assert!(matches!(
+        block.failed_solutions()[0].1,
+        SolutionFailReason::ConstraintsFailed(_)
+    ));
+}
+
+#[tokio::test]
+async fn test_max_not_composable_retries() {
+    let (solution, conflicting, storage) = conflicting().await;
+    let config = Config {
+        retry_not_composable: true,
+        max_not_composable_retries: 2,
+        ..Default::default()
+    };
+
+    let mut retries = Retries::default();
+    for retry in 0..3 {
+        let mut block = BlockContext::new(storage.clone(), &config).with_retries(retries);
+        block.push(solution.clone()).await.unwrap();
+        assert_eq!(
+            block.push(conflicting.clone()).await.unwrap(),
+            Pushed::NotComposable
+        );
+        let (solutions, _, block_retries) = block.into_parts();
+        retries = block_retries;
+
+        if retry < 2 {
+            // Left in the pool to retry.
+            assert!(solutions.failed_solutions.is_empty());
+        } else {
+            // Retried too many times.
+            assert_eq!(
+                solutions.failed_solutions,
+                vec![(conflicting.clone(), SolutionFailReason::NotComposable)]
+            );
+            assert!(retries.0.is_empty());
+        }
+    }
+}
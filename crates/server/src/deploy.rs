@@ -1,3 +1,4 @@
+use crate::Error;
 use essential_check as check;
 use essential_storage::Storage;
 use essential_types::{contract::SignedContract, ContentAddress};
@@ -11,11 +12,14 @@ pub async fn deploy<S>(storage: &S, contract: SignedContract) -> anyhow::Result<
 where
     S: Storage,
 {
-    check::predicate::check_signed_contract(&contract)?;
+    check::predicate::check_signed_contract(&contract).map_err(Error::invalid)?;
     let contract_addr = essential_hash::contract_addr::from_contract(&contract.contract);
 
     match storage.insert_contract(contract).await {
         Ok(()) => Ok(contract_addr),
-        Err(err) => anyhow::bail!("Failed to deploy contract: {}", err),
+        Err(err) => Err(Error::storage(anyhow::anyhow!(
+            "Failed to deploy contract: {}",
+            err
+        ))),
     }
 }
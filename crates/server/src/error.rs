@@ -0,0 +1,37 @@
+use essential_server_types::SolutionRejection;
+
+/// An error that callers of the server may want to handle differently to other failures.
+///
+/// These are returned wrapped in an [`anyhow::Error`] so can be
+/// found with [`anyhow::Error::downcast_ref`].
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The input failed validation, for example a solution that fails its
+    /// constraints or a contract with an invalid signature.
+    #[error(transparent)]
+    Invalid(anyhow::Error),
+    /// Something the input refers to doesn't exist.
+    #[error("{0}")]
+    NotFound(String),
+    /// The input conflicts with the current state of the server.
+    #[error("{0}")]
+    Conflict(String),
+    /// A submitted solution was rejected by the admission check.
+    #[error("{0}")]
+    Rejected(SolutionRejection),
+    /// The storage layer failed.
+    #[error("storage error")]
+    Storage(#[source] anyhow::Error),
+}
+
+impl Error {
+    /// Wrap an error as [`Error::Invalid`].
+    pub(crate) fn invalid(err: impl Into<anyhow::Error>) -> anyhow::Error {
+        Error::Invalid(err.into()).into()
+    }
+
+    /// Wrap an error as [`Error::Storage`].
+    pub(crate) fn storage(err: impl Into<anyhow::Error>) -> anyhow::Error {
+        Error::Storage(err.into()).into()
+    }
+}
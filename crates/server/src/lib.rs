@@ -4,11 +4,20 @@
 //! For an executable implementation of the Essential server, see the
 //! `essential-rest-server` crate.
 
+pub use block_builder::{
+    BlockBuilder, BlockBuilderKind, BlockContext, Fifo, GreedyMaxSolutions, HighestGasFirst, Pushed,
+};
+pub use error::Error;
 use essential_check::{self as check, solution::CheckPredicateConfig};
-pub use essential_server_types::{CheckSolutionOutput, SolutionOutcome};
+pub use essential_server_types::{
+    CheckSolutionOutput, MainLoopStatus, Reorg, SolutionOutcome, SolutionRejection,
+};
 pub use essential_state_read_vm::{Gas, StateRead};
-use essential_storage::failed_solution::CheckOutcome;
-pub use essential_storage::Storage;
+pub use essential_storage::{
+    failed_solution::{CheckOutcome, FailedSolution, SolutionFailReason, SolutionOutcomes},
+    streams::MAX_SUBSCRIBED_SOLUTIONS,
+    BlockHeader, StateChange, Storage,
+};
 use essential_transaction_storage::{Transaction, TransactionStorage};
 use essential_types::{
     contract::{Contract, SignedContract},
@@ -16,18 +25,30 @@ use essential_types::{
     solution::Solution,
     Block, ContentAddress, Hash, Key, PredicateAddress, Word,
 };
-use run::{Handle, Shutdown};
+use futures::TryStreamExt;
+pub use reorg::BlockUpdate;
+use run::{Commands, Shutdown};
+pub use run::{Controller, Handle};
 use solution::read::read_contract_from_storage;
-use std::{collections::HashMap, ops::Range, sync::Arc, time::Duration};
+use std::{collections::HashMap, ops::Range, path::PathBuf, sync::Arc, time::Duration};
 
+mod block_builder;
 mod deploy;
+mod error;
+mod metrics;
 mod protocol;
 mod query_state_reads;
+mod reorg;
 mod run;
 mod solution;
+mod state_at;
+mod state_proof;
 #[cfg(test)]
 mod test_utils;
 
+/// Maximum number of keys returned by a single state range query.
+pub const MAX_STATE_RANGE: usize = 1000;
+
 #[derive(Clone)]
 pub struct Essential<S>
 where
@@ -38,13 +59,100 @@ where
     // top-level `Config` type for other kinds of configuration (e.g. gas costs).
     config: Arc<CheckPredicateConfig>,
     time_config: Arc<TimeConfig>,
+    /// Held while a block is built and committed so the chain can't change underneath it.
+    block_lock: Arc<tokio::sync::Mutex<()>>,
+    reorgs: tokio::sync::broadcast::Sender<Reorg>,
+    /// Check submitted solutions against the state and the pool before admitting them.
+    admission_check: bool,
+    /// The keys mutated by the solutions in the pool, used by the admission check.
+    pending: solution::admission::PendingPool,
+    /// The number of solutions submitted, watched by the main loop.
+    submissions: Arc<tokio::sync::watch::Sender<u64>>,
+    /// State trees of recent blocks to prove state against.
+    state_trees: state_proof::StateTrees,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize)]
 /// Server configuration.
 pub struct Config {
     /// Interval at which to run the main loop.
     pub run_loop_interval: Duration,
+    /// When blocks are built other than on the interval.
+    /// Default is only on the interval.
+    pub block_production: BlockProduction,
+    /// Maximum total gas the solutions in a single block may use.
+    /// Solutions that would exceed this are left in the pool for a later block.
+    pub block_gas_limit: Gas,
+    /// Maximum gas a single solution may use.
+    /// Solutions that exceed this are moved to the failed pool.
+    pub solution_gas_limit: Gas,
+    /// Maximum number of solutions read from the pool when building a block.
+    /// If `None` the whole pool is read.
+    pub max_pool_solutions: Option<usize>,
+    /// Leave solutions that are not composable with the rest of a block
+    /// in the pool to retry in the next block rather than failing them.
+    pub retry_not_composable: bool,
+    /// How many blocks in a row a solution that is not composable is retried in
+    /// before it is moved to the failed pool as not composable anyway.
+    /// Only used if `retry_not_composable` is set.
+    pub max_not_composable_retries: usize,
+    /// Maximum number of solutions kept in the pool.
+    /// The oldest solutions beyond this are moved to the failed pool as evicted.
+    /// If `None` the pool is unbounded.
+    ///
+    /// Checked before each block is built.
+    pub pool_capacity: Option<usize>,
+    /// How long a solution may wait in the pool before it
+    /// is moved to the failed pool as expired.
+    /// If `None` solutions never expire.
+    ///
+    /// Checked before each block is built.
+    pub solution_ttl: Option<Duration>,
+    /// Failed solutions that failed longer ago than this are pruned.
+    /// If `None` failed solutions are never pruned by age.
+    /// Default is one week.
+    pub failed_solution_max_age: Option<Duration>,
+    /// Maximum number of failed solutions kept.
+    /// The oldest failed solutions beyond this are pruned.
+    /// If `None` failed solutions are never pruned by count.
+    pub failed_solution_max_count: Option<usize>,
+    /// File that pruned failed solutions are appended to as JSON lines
+    /// before they are deleted.
+    /// If `None` pruned failed solutions are not archived.
+    pub failed_solution_archive: Option<PathBuf>,
+    /// How long the main loop waits before trying again after building a block fails.
+    /// Doubles with each failure in a row up to `max_failure_backoff`.
+    pub failure_backoff: Duration,
+    /// The longest the main loop waits before trying again after building a block fails.
+    pub max_failure_backoff: Duration,
+    /// Stop the main loop after building a block fails this many times in a row
+    /// so whatever supervises the server can restart it.
+    /// If `None` the main loop keeps trying.
+    pub max_consecutive_failures: Option<usize>,
+}
+
+/// When the main loop builds blocks.
+///
+/// Except for [`BlockProduction::Manual`], a block is also built on every interval
+/// so the pool is still limited and solutions don't wait forever.
+/// Blocks can always be built on demand with [`Controller::build_block`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockProduction {
+    /// Only build blocks on the interval.
+    #[default]
+    Interval,
+    /// Only build blocks on demand.
+    Manual,
+    /// Build a block as soon as a submitted solution
+    /// brings the pool up to this many solutions.
+    PoolSize(usize),
+    /// Build a block once no solution has been submitted
+    /// for the debounce duration since the last one was.
+    OnSubmit {
+        /// How long to wait for more solutions.
+        debounce: Duration,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -58,6 +166,20 @@ impl Default for Config {
     fn default() -> Self {
         Self {
             run_loop_interval: run::RUN_LOOP_FREQUENCY,
+            block_production: BlockProduction::Interval,
+            block_gas_limit: Gas::MAX,
+            solution_gas_limit: Gas::MAX,
+            max_pool_solutions: None,
+            retry_not_composable: false,
+            max_not_composable_retries: run::MAX_NOT_COMPOSABLE_RETRIES,
+            pool_capacity: None,
+            solution_ttl: None,
+            failed_solution_max_age: Some(run::FAILED_SOLUTION_MAX_AGE),
+            failed_solution_max_count: None,
+            failed_solution_archive: None,
+            failure_backoff: run::FAILURE_BACKOFF,
+            max_failure_backoff: run::MAX_FAILURE_BACKOFF,
+            max_consecutive_failures: None,
         }
     }
 }
@@ -71,8 +193,6 @@ impl Default for TimeConfig {
     }
 }
 
-const PRUNE_FAILED_STORAGE_OLDER_THAN: Duration = Duration::from_secs(604800); // one week
-
 impl<S> Essential<S>
 where
     S: Storage + StateRead + Clone + Send + Sync + 'static,
@@ -84,31 +204,55 @@ where
         config: Arc<CheckPredicateConfig>,
         time_config: Arc<TimeConfig>,
     ) -> Self {
+        let (reorgs, _) = tokio::sync::broadcast::channel(reorg::REORG_CAPACITY);
         Self {
             storage,
             config,
             time_config,
+            block_lock: Default::default(),
+            reorgs,
+            admission_check: false,
+            pending: Default::default(),
+            submissions: Arc::new(tokio::sync::watch::channel(0).0),
+            state_trees: Default::default(),
         }
     }
 
-    pub fn spawn(self, config: Config) -> anyhow::Result<Handle>
+    /// Check each submitted solution before it is added to the pool.
+    ///
+    /// The solution is rejected with a [`SolutionRejection`] if it is already in the pool,
+    /// mutates a key that a solution in the pool also mutates or fails when checked
+    /// against the current state with the mutations of the pool applied.
+    pub fn with_admission_check(mut self, admission_check: bool) -> Self {
+        self.admission_check = admission_check;
+        self
+    }
+
+    /// Spawn the main loop, building blocks with the given block builder.
+    pub fn spawn<B>(self, config: Config, builder: B) -> anyhow::Result<Handle>
     where
         S: 'static + Send + Sync,
+        B: BlockBuilder + 'static,
     {
-        let (mut handle, shutdown) = Handle::new();
-        let jh = tokio::spawn(async move { self.run(shutdown, config.run_loop_interval).await });
+        let config = Arc::new(config);
+        let (mut handle, shutdown, commands) =
+            Handle::new(config.clone(), self.submissions.subscribe());
+        let jh = tokio::spawn(async move { self.run(shutdown, commands, &config, builder).await });
         handle.contract_jh(jh);
         Ok(handle)
     }
 
-    pub async fn run(&self, shutdown: Shutdown, run_loop_interval: Duration) -> anyhow::Result<()> {
-        run::run(
-            &self.storage,
-            shutdown,
-            run_loop_interval,
-            &self.time_config,
-        )
-        .await
+    pub async fn run<B>(
+        &self,
+        shutdown: Shutdown,
+        commands: Commands,
+        config: &Config,
+        builder: B,
+    ) -> anyhow::Result<()>
+    where
+        B: BlockBuilder,
+    {
+        run::run(self, shutdown, commands, config, &builder).await
     }
 
     pub async fn deploy_contract(
@@ -119,13 +263,15 @@ where
     }
 
     pub async fn check_solution(&self, solution: Solution) -> anyhow::Result<CheckSolutionOutput> {
-        check::solution::check(&solution)?;
+        check::solution::check(&solution).map_err(Error::invalid)?;
         let contract = read_contract_from_storage(&solution, &self.storage).await?;
         let transaction = self.storage.clone().transaction();
         let solution = Arc::new(solution);
         let config = self.config.clone();
         let (_post_state, gas) =
-            checked_state_transition(&transaction, solution, &contract, config).await?;
+            checked_state_transition(&transaction, solution, &contract, config)
+                .await
+                .map_err(Error::invalid)?;
         Ok(CheckSolutionOutput { gas })
     }
 
@@ -153,19 +299,29 @@ where
             })
             .collect();
 
-        check::solution::check(&solution)?;
+        check::solution::check(&solution).map_err(Error::invalid)?;
 
         let transaction = self.storage.clone().transaction();
         let config = self.config.clone();
         let solution = Arc::new(solution);
         let (_post_state, gas) =
-            checked_state_transition(&transaction, solution, &predicates, config).await?;
+            checked_state_transition(&transaction, solution, &predicates, config)
+                .await
+                .map_err(Error::invalid)?;
         Ok(CheckSolutionOutput { gas })
     }
 
     pub async fn submit_solution(&self, solution: Solution) -> anyhow::Result<ContentAddress> {
         solution::filter_solution(&self.time_config, &solution)?;
-        solution::submit_solution(&self.storage, solution).await
+        let hash = if self.admission_check {
+            let config = self.config.clone();
+            solution::submit_admitted_solution(&self.storage, &self.pending, solution, config)
+                .await?
+        } else {
+            solution::submit_solution(&self.storage, solution).await?
+        };
+        self.submissions.send_modify(|n| *n = n.wrapping_add(1));
+        Ok(hash)
     }
 
     pub async fn solution_outcome(
@@ -175,34 +331,55 @@ where
         Ok(self
             .storage
             .get_solution(*solution_hash)
-            .await?
-            .map(|outcome| {
-                outcome
-                    .outcome
-                    .into_iter()
-                    .map(|outcome| match outcome {
-                        CheckOutcome::Success(block_number) => {
-                            SolutionOutcome::Success(block_number)
-                        }
-                        CheckOutcome::Fail(fail) => SolutionOutcome::Fail(fail.to_string()),
-                    })
-                    .collect()
-            })
+            .await
+            .map_err(Error::storage)?
+            .map(|outcome| outcome.outcome.into_iter().map(solution_outcome).collect())
             .unwrap_or_default())
     }
 
+    /// Subscribe to the outcomes of solutions.
+    ///
+    /// Outcomes that were already recorded are returned first
+    /// followed by each new outcome as blocks are committed.
+    pub fn subscribe_solution_outcomes(
+        &self,
+        solution_hashes: Vec<Hash>,
+    ) -> impl futures::stream::Stream<Item = anyhow::Result<(Hash, SolutionOutcome)>> + Send + 'static
+    {
+        self.storage
+            .clone()
+            .subscribe_solution_outcomes(solution_hashes)
+            .map_ok(|(hash, outcome)| (hash, solution_outcome(outcome)))
+    }
+
+    pub async fn get_solution(
+        &self,
+        solution_hash: &Hash,
+    ) -> anyhow::Result<Option<SolutionOutcomes>> {
+        self.storage
+            .get_solution(*solution_hash)
+            .await
+            .map_err(Error::storage)
+    }
+
     pub async fn get_predicate(
         &self,
         address: &PredicateAddress,
     ) -> anyhow::Result<Option<Predicate>> {
-        self.storage.get_predicate(address).await
+        self.storage
+            .get_predicate(address)
+            .await
+            .map_err(Error::storage)
     }
 
     pub async fn get_contract(
         &self,
         address: &ContentAddress,
     ) -> anyhow::Result<Option<SignedContract>> {
-        self.storage.get_contract(address).await
+        self.storage
+            .get_contract(address)
+            .await
+            .map_err(Error::storage)
     }
 
     pub async fn list_contracts(
@@ -210,7 +387,10 @@ where
         time_range: Option<Range<Duration>>,
         page: Option<usize>,
     ) -> anyhow::Result<Vec<Contract>> {
-        self.storage.list_contracts(time_range, page).await
+        self.storage
+            .list_contracts(time_range, page)
+            .await
+            .map_err(Error::storage)
     }
 
     pub fn subscribe_contracts(
@@ -224,7 +404,20 @@ where
     }
 
     pub async fn list_solutions_pool(&self, page: Option<usize>) -> anyhow::Result<Vec<Solution>> {
-        self.storage.list_solutions_pool(page).await
+        self.storage
+            .list_solutions_pool(page)
+            .await
+            .map_err(Error::storage)
+    }
+
+    pub async fn list_failed_solutions_pool(
+        &self,
+        page: Option<usize>,
+    ) -> anyhow::Result<Vec<FailedSolution>> {
+        self.storage
+            .list_failed_solutions_pool(page)
+            .await
+            .map_err(Error::storage)
     }
 
     pub async fn list_blocks(
@@ -236,17 +429,105 @@ where
         self.storage
             .list_blocks(time_range, block_number, page)
             .await
+            .map_err(Error::storage)
+    }
+
+    pub async fn list_block_headers(
+        &self,
+        block_number: Option<u64>,
+        page: Option<usize>,
+    ) -> anyhow::Result<Vec<BlockHeader>> {
+        self.storage
+            .list_block_headers(block_number, page)
+            .await
+            .map_err(Error::storage)
     }
 
+    /// Get the state changed by a block.
+    pub async fn get_block_state_diff(
+        &self,
+        block_number: u64,
+    ) -> anyhow::Result<Vec<StateChange>> {
+        state_at::block_header(&self.storage, block_number).await?;
+        self.storage
+            .get_block_state_diff(block_number)
+            .await
+            .map_err(Error::storage)
+    }
+
+    /// Subscribe to new blocks.
+    ///
+    /// If blocks that were already sent are reverted a [`BlockUpdate::Reorg`]
+    /// is sent and the subscription continues from the block after the reorg.
     pub fn subscribe_blocks(
         &self,
         start_time: Option<Duration>,
         start_number: Option<u64>,
         start_page: Option<usize>,
-    ) -> impl futures::stream::Stream<Item = anyhow::Result<Block>> + Send + 'static {
+    ) -> impl futures::stream::Stream<Item = anyhow::Result<BlockUpdate>> + Send + 'static {
+        reorg::subscribe_blocks(
+            self.storage.clone(),
+            self.reorgs.subscribe(),
+            start_time,
+            start_number,
+            start_page,
+        )
+    }
+
+    /// The latest block, if there are any.
+    pub async fn get_latest_block(&self) -> anyhow::Result<Option<Block>> {
         self.storage
-            .clone()
-            .subscribe_blocks(start_time, start_number, start_page)
+            .get_latest_block()
+            .await
+            .map_err(Error::storage)
+    }
+
+    /// The number of solutions in the pool.
+    pub async fn pool_size(&self) -> anyhow::Result<usize> {
+        self.storage.pool_size().await.map_err(Error::storage)
+    }
+
+    /// Revert the chain to the end of a block.
+    ///
+    /// The state is restored to the state at the end of the block and
+    /// the solutions of later blocks are moved back to the pool.
+    /// Waits for any block that is being built to be committed first.
+    pub async fn revert_to_block(&self, block_number: u64) -> anyhow::Result<()> {
+        let _building = self.block_lock.lock().await;
+        state_at::block_header(&self.storage, block_number).await?;
+        self.storage
+            .revert_to_block(block_number)
+            .await
+            .map_err(Error::storage)?;
+        // Solutions of the reverted blocks are back in the pool.
+        self.pending.invalidate().await;
+
+        // It's fine if there are no subscribers.
+        let _ = self.reorgs.send(Reorg { block_number });
+        Ok(())
+    }
+
+    /// Move a solution from the pool to the failed pool as evicted.
+    ///
+    /// Waits for any block that is being built to be committed first.
+    pub async fn evict_solution(&self, solution: Hash) -> anyhow::Result<()> {
+        let _building = self.block_lock.lock().await;
+        let in_pool = self
+            .storage
+            .is_in_pool(solution)
+            .await
+            .map_err(Error::storage)?;
+        if !in_pool {
+            let solution = ContentAddress(solution);
+            return Err(Error::NotFound(format!("Solution {solution} is not in the pool")).into());
+        }
+        self.storage
+            .move_solutions_to_failed(&[(solution, SolutionFailReason::Evicted)])
+            .await
+            .map_err(Error::storage)?;
+        self.pending.remove([&solution]).await;
+        metrics::failed(&SolutionFailReason::Evicted, 1);
+        Ok(())
     }
 
     pub async fn query_state(
@@ -254,7 +535,51 @@ where
         address: &ContentAddress,
         key: &Key,
     ) -> anyhow::Result<Vec<Word>> {
-        self.storage.query_state(address, key).await
+        self.storage
+            .query_state(address, key)
+            .await
+            .map_err(Error::storage)
+    }
+
+    /// Query the keys of a contract from `start_key` up to but not including `end_key`.
+    ///
+    /// At most [`MAX_STATE_RANGE`] keys are returned.
+    pub async fn query_state_range(
+        &self,
+        address: &ContentAddress,
+        start_key: &Key,
+        end_key: Option<&Key>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<(Key, Vec<Word>)>> {
+        self.storage
+            .query_state_range(address, start_key, end_key, limit.min(MAX_STATE_RANGE))
+            .await
+            .map_err(Error::storage)
+    }
+
+    /// Query the keys of a contract that start with `prefix`.
+    ///
+    /// At most [`MAX_STATE_RANGE`] keys are returned.
+    pub async fn query_state_prefix(
+        &self,
+        address: &ContentAddress,
+        prefix: &Key,
+        limit: usize,
+    ) -> anyhow::Result<Vec<(Key, Vec<Word>)>> {
+        self.storage
+            .query_state_prefix(address, prefix, limit.min(MAX_STATE_RANGE))
+            .await
+            .map_err(Error::storage)
+    }
+
+    pub async fn query_state_proof(
+        &self,
+        address: &ContentAddress,
+        key: &Key,
+        block_number: Option<u64>,
+    ) -> anyhow::Result<essential_server_types::QueryStateProof> {
+        state_proof::query_state_proof(&self.storage, &self.state_trees, address, key, block_number)
+            .await
     }
 
     pub async fn query_state_reads(
@@ -264,6 +589,39 @@ where
         let storage = self.storage.clone().transaction();
         query_state_reads::query_state_reads(storage, query).await
     }
+
+    /// Query the value of a key as it was at the end of a block.
+    pub async fn query_state_at(
+        &self,
+        address: &ContentAddress,
+        key: &Key,
+        block_number: u64,
+    ) -> anyhow::Result<Vec<Word>> {
+        state_at::block_header(&self.storage, block_number).await?;
+        self.storage
+            .query_state_at(address, key, block_number)
+            .await
+            .map_err(Error::storage)
+    }
+
+    /// Run a state read query against the state as it was at the end of a block.
+    pub async fn query_state_reads_at(
+        &self,
+        query: essential_server_types::QueryStateReads,
+        block_number: u64,
+    ) -> anyhow::Result<essential_server_types::QueryStateReadsOutput> {
+        state_at::block_header(&self.storage, block_number).await?;
+        let storage = state_at::StateAt::new(self.storage.clone(), block_number).transaction();
+        query_state_reads::query_state_reads(storage, query).await
+    }
+}
+
+/// Convert a stored outcome to the outcome returned to users.
+fn solution_outcome(outcome: CheckOutcome) -> SolutionOutcome {
+    match outcome {
+        CheckOutcome::Success(block_number) => SolutionOutcome::Success(block_number),
+        CheckOutcome::Fail(fail) => SolutionOutcome::Fail(fail.to_string()),
+    }
 }
 
 /// Performs the three main steps of producing a state transition.
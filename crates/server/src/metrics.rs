@@ -0,0 +1,72 @@
+//! Metrics for building blocks.
+//!
+//! These are registered with the default [`prometheus`] registry.
+
+use essential_storage::failed_solution::SolutionFailReason;
+use prometheus::{
+    exponential_buckets, register_histogram, register_int_counter_vec, register_int_gauge,
+    Histogram, IntCounterVec, IntGauge,
+};
+use std::sync::LazyLock;
+
+/// Number of solutions in the pool when the last block was built.
+pub(crate) static POOL_SIZE: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "essential_pool_solutions",
+        "Number of solutions in the pool when the last block was built."
+    )
+    .expect("metric is only registered once")
+});
+
+/// Time taken to build and commit a block.
+pub(crate) static BLOCK_BUILD_DURATION: LazyLock<Histogram> = LazyLock::new(|| {
+    register_histogram!(
+        "essential_block_build_duration_seconds",
+        "Time taken to build and commit a block."
+    )
+    .expect("metric is only registered once")
+});
+
+/// Number of solutions in each block.
+pub(crate) static BLOCK_SOLUTIONS: LazyLock<Histogram> = LazyLock::new(|| {
+    register_histogram!(
+        "essential_block_solutions",
+        "Number of solutions in each block.",
+        exponential_buckets(1.0, 2.0, 16).expect("buckets are valid")
+    )
+    .expect("metric is only registered once")
+});
+
+/// Gas used by each block.
+pub(crate) static BLOCK_GAS: LazyLock<Histogram> = LazyLock::new(|| {
+    register_histogram!(
+        "essential_block_gas",
+        "Gas used by the solutions in each block.",
+        exponential_buckets(1000.0, 4.0, 16).expect("buckets are valid")
+    )
+    .expect("metric is only registered once")
+});
+
+/// Number of solutions moved to the failed pool by reason.
+pub(crate) static FAILED_SOLUTIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "essential_failed_solutions_total",
+        "Number of solutions moved to the failed pool.",
+        &["reason"]
+    )
+    .expect("metric is only registered once")
+});
+
+/// Count solutions that failed for the given reason.
+pub(crate) fn failed(reason: &SolutionFailReason, count: usize) {
+    let reason = match reason {
+        SolutionFailReason::ConstraintsFailed(_) => "constraints_failed",
+        SolutionFailReason::NotComposable => "not_composable",
+        SolutionFailReason::GasLimitExceeded(_) => "gas_limit_exceeded",
+        SolutionFailReason::Expired => "expired",
+        SolutionFailReason::Evicted => "evicted",
+    };
+    FAILED_SOLUTIONS
+        .with_label_values(&[reason])
+        .inc_by(count as u64);
+}
@@ -6,7 +6,7 @@ use essential_server_types::{
     QueryStateReads, QueryStateReadsOutput, Slots, SlotsRequest, StateReadRequestType,
 };
 use essential_state_read_vm::{asm::Op, GasLimit, StateRead};
-use essential_storage::{next_key, QueryState, StateStorage};
+use essential_storage::{consecutive_keys, QueryState, StateStorage};
 use essential_transaction_storage::TransactionStorage;
 use essential_types::{ContentAddress, Key, Value};
 use futures::FutureExt;
@@ -217,17 +217,13 @@ where
 async fn key_range<S>(
     storage: &TransactionStorage<S>,
     contract_addr: ContentAddress,
-    mut key: Key,
+    key: Key,
     num_words: usize,
 ) -> anyhow::Result<Vec<(Key, Value)>>
 where
-    S: QueryState + Send,
+    S: QueryState + Send + Sync,
 {
-    let mut words = Vec::with_capacity(num_words);
-    for _ in 0..num_words {
-        let slot = storage.query_state(&contract_addr, &key).await?;
-        words.push((key.clone(), slot));
-        key = next_key(key).ok_or_else(|| anyhow::anyhow!("Failed to find next key"))?
-    }
-    Ok(words)
+    let keys = consecutive_keys(key, num_words)?;
+    let values = storage.query_state_batch(&contract_addr, &keys).await?;
+    Ok(keys.into_iter().zip(values).collect())
 }
@@ -0,0 +1,137 @@
+use essential_server_types::Reorg;
+use essential_storage::Storage;
+use essential_types::Block;
+use futures::{stream::BoxStream, StreamExt};
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+#[cfg(test)]
+mod tests;
+
+/// Number of reorgs a slow subscriber can fall behind by before its stream ends.
+pub(crate) const REORG_CAPACITY: usize = 16;
+
+/// An update on a block subscription.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlockUpdate {
+    /// The next block.
+    Block(Block),
+    /// The chain was reverted to an earlier block.
+    ///
+    /// The subscription continues from the block after it.
+    Reorg(Reorg),
+}
+
+/// Where a block subscription is up to.
+struct Subscription<S> {
+    storage: S,
+    reorgs: Option<broadcast::Receiver<Reorg>>,
+    blocks: BoxStream<'static, anyhow::Result<Block>>,
+    /// The number of the last block sent.
+    last: Option<u64>,
+    start_time: Option<Duration>,
+    start_number: Option<u64>,
+    start_page: Option<usize>,
+    done: bool,
+}
+
+/// Subscribe to blocks from storage, restarting after each reorg.
+///
+/// A [`BlockUpdate::Reorg`] is only sent if blocks after the
+/// block that was reverted to have already been sent.
+pub(crate) fn subscribe_blocks<S>(
+    storage: S,
+    reorgs: broadcast::Receiver<Reorg>,
+    start_time: Option<Duration>,
+    start_number: Option<u64>,
+    start_page: Option<usize>,
+) -> impl futures::Stream<Item = anyhow::Result<BlockUpdate>> + Send + 'static
+where
+    S: Storage + Clone + Send + Sync + 'static,
+{
+    let blocks = storage
+        .clone()
+        .subscribe_blocks(start_time, start_number, start_page)
+        .boxed();
+    let init = Subscription {
+        storage,
+        reorgs: Some(reorgs),
+        blocks,
+        last: None,
+        start_time,
+        start_number,
+        start_page,
+        done: false,
+    };
+    futures::stream::unfold(init, |mut sub| async move {
+        if sub.done {
+            return None;
+        }
+        loop {
+            tokio::select! {
+                biased;
+                reorg = recv(&mut sub.reorgs) => match reorg {
+                    Ok(reorg) => {
+                        if let Some(update) = sub.restart(reorg) {
+                            return Some((Ok(update), sub));
+                        }
+                    }
+                    Err(RecvError::Lagged(_)) => {
+                        sub.done = true;
+                        let err = anyhow::anyhow!("Fell behind the chain reorganisations");
+                        return Some((Err(err), sub));
+                    }
+                    // Without reorgs this is a plain block subscription.
+                    Err(RecvError::Closed) => sub.reorgs = None,
+                },
+                block = sub.blocks.next() => {
+                    let block = block?;
+                    if let Ok(block) = &block {
+                        sub.last = Some(block.number as u64);
+                    }
+                    return Some((block.map(BlockUpdate::Block), sub));
+                }
+            }
+        }
+    })
+}
+
+/// Receive the next reorg or wait forever if there are no more.
+async fn recv(reorgs: &mut Option<broadcast::Receiver<Reorg>>) -> Result<Reorg, RecvError> {
+    match reorgs {
+        Some(reorgs) => reorgs.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+impl<S> Subscription<S>
+where
+    S: Storage + Clone + Send + Sync + 'static,
+{
+    /// Restart the storage subscription after a reorg.
+    ///
+    /// Blocks are paged by their position so the old subscription
+    /// could skip the blocks that replace the reverted ones.
+    fn restart(&mut self, reorg: Reorg) -> Option<BlockUpdate> {
+        let (update, last) = match self.last {
+            Some(last) if last > reorg.block_number => {
+                (Some(BlockUpdate::Reorg(reorg)), Some(reorg.block_number))
+            }
+            last => (None, last),
+        };
+        self.last = last;
+        self.blocks = match last {
+            Some(last) => self
+                .storage
+                .clone()
+                .subscribe_blocks(None, Some(last.saturating_add(1)), None)
+                .boxed(),
+            None => self
+                .storage
+                .clone()
+                .subscribe_blocks(self.start_time, self.start_number, self.start_page)
+                .boxed(),
+        };
+        update
+    }
+}
@@ -0,0 +1,82 @@
+use super::*;
+use crate::{Error, Essential};
+use essential_memory_storage::MemoryStorage;
+use essential_storage::CommitData;
+use futures::Stream;
+use test_utils::solution_with_all_inputs;
+
+async fn commit(storage: &MemoryStorage, i: u64, number: u64) {
+    let solution = solution_with_all_inputs(i as usize);
+    let hash = essential_hash::hash(&solution);
+    storage.insert_solution_into_pool(solution).await.unwrap();
+    let data = CommitData {
+        failed: &[],
+        solved: &[hash],
+        state_updates: Box::new(std::iter::empty()),
+        block_number: number,
+        block_timestamp: Duration::from_secs(i + 1),
+        gas_used: 0,
+    };
+    storage.commit_block(data).await.unwrap();
+}
+
+async fn next<S>(stream: &mut S) -> BlockUpdate
+where
+    S: Stream<Item = anyhow::Result<BlockUpdate>> + Unpin,
+{
+    tokio::time::timeout(Duration::from_secs(1), stream.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap()
+}
+
+fn number(update: BlockUpdate) -> u64 {
+    match update {
+        BlockUpdate::Block(block) => block.number as u64,
+        BlockUpdate::Reorg(reorg) => panic!("unexpected reorg {reorg:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_revert_to_block() {
+    let storage = MemoryStorage::new();
+    for i in 0..3 {
+        commit(&storage, i, i).await;
+    }
+    let essential = Essential::new(storage.clone(), Default::default(), Default::default());
+
+    let mut all = Box::pin(essential.subscribe_blocks(None, None, None));
+    for i in 0..3 {
+        assert_eq!(number(next(&mut all).await), i);
+    }
+    let mut first = Box::pin(essential.subscribe_blocks(None, None, None));
+    assert_eq!(number(next(&mut first).await), 0);
+
+    let err = essential.revert_to_block(3).await.unwrap_err();
+    assert!(matches!(err.downcast_ref(), Some(Error::NotFound(_))));
+
+    essential.revert_to_block(0).await.unwrap();
+    assert_eq!(storage.list_solutions_pool(None).await.unwrap().len(), 2);
+
+    // Only the subscriber that was sent reverted blocks is told about the reorg.
+    assert_eq!(
+        next(&mut all).await,
+        BlockUpdate::Reorg(Reorg { block_number: 0 })
+    );
+
+    // Both continue from the block after the reorg.
+    commit(&storage, 3, 1).await;
+    for stream in [&mut all, &mut first] {
+        let BlockUpdate::Block(block) = next(stream).await else {
+            panic!("expected a block");
+        };
+        assert_eq!(block.number, 1);
+        assert_eq!(block.solutions, vec![solution_with_all_inputs(3)]);
+    }
+
+    // A reorg that doesn't remove any sent blocks isn't sent.
+    essential.revert_to_block(1).await.unwrap();
+    commit(&storage, 4, 2).await;
+    assert_eq!(number(next(&mut all).await), 2);
+}
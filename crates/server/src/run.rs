@@ -1,44 +1,106 @@
 use crate::{
-    solution::read::read_contract_from_storage, TimeConfig, PRUNE_FAILED_STORAGE_OLDER_THAN,
+    block_builder::{BlockBuilder, BlockContext, Retries},
+    metrics,
+    solution::admission::PendingPool,
+    BlockProduction, Config, Essential, Gas, MainLoopStatus, TimeConfig,
 };
 use anyhow::Context;
 use essential_hash::hash;
 use essential_state_read_vm::StateRead;
-use essential_storage::{failed_solution::SolutionFailReason, CommitData, Storage};
-use essential_transaction_storage::{Transaction, TransactionStorage};
-use essential_types::{contract::SignedContract, solution::Solution, Hash, Signature};
-use std::{sync::Arc, time::Duration};
-use tokio::sync::oneshot;
+use essential_storage::{
+    failed_solution::{FailedSolution, SolutionFailReason},
+    CommitData, Storage,
+};
+use essential_transaction_storage::TransactionStorage;
+use essential_types::{contract::SignedContract, solution::Solution, Block, Hash, Signature};
+use futures::TryStreamExt;
+use std::{path::Path, sync::Arc, time::Duration};
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{mpsc, oneshot, watch},
+    time::Instant,
+};
 
 pub(crate) const RUN_LOOP_FREQUENCY: std::time::Duration = std::time::Duration::from_secs(10);
+pub(crate) const FAILED_SOLUTION_MAX_AGE: Duration = Duration::from_secs(604800); // one week
+pub(crate) const MAX_NOT_COMPOSABLE_RETRIES: usize = 10;
+pub(crate) const FAILURE_BACKOFF: Duration = Duration::from_secs(1);
+pub(crate) const MAX_FAILURE_BACKOFF: Duration = Duration::from_secs(300);
+
+use trigger::Trigger;
 
 #[cfg(test)]
 pub mod tests;
+mod trigger;
 
+/// Controls the main loop spawned by [`crate::Essential::spawn`].
+///
+/// Derefs to the [`Controller`] for the running loop.
 pub struct Handle {
     tx: oneshot::Sender<()>,
+    controller: Controller,
     jh: Option<tokio::task::JoinHandle<anyhow::Result<()>>>,
 }
 
+/// Controls the main loop while it runs.
+///
+/// Cheap to clone so it can be shared with whatever needs to control the loop.
+#[derive(Clone)]
+pub struct Controller {
+    commands: mpsc::Sender<Command>,
+    paused: Arc<watch::Sender<bool>>,
+    status: watch::Receiver<MainLoopStatus>,
+    config: Arc<Config>,
+}
+
 pub struct Shutdown(oneshot::Receiver<()>);
 
-struct Solutions {
-    valid_solutions: Vec<Arc<Solution>>,
-    failed_solutions: Vec<(Arc<Solution>, SolutionFailReason)>,
+/// The receiving side of the commands sent through a [`Handle`]
+/// along with the submitted solutions that may trigger a block.
+pub struct Commands {
+    rx: mpsc::Receiver<Command>,
+    paused: watch::Receiver<bool>,
+    trigger: Trigger,
+    status: watch::Sender<MainLoopStatus>,
+}
+
+/// A command for the main loop to run between blocks.
+enum Command {
+    /// Build a block now, replying with the block if one was committed.
+    BuildBlock(oneshot::Sender<anyhow::Result<Option<Block>>>),
+    /// Limit the pool and prune failed solutions now.
+    Prune(oneshot::Sender<anyhow::Result<()>>),
+}
+
+pub(crate) struct Solutions {
+    pub(crate) valid_solutions: Vec<Arc<Solution>>,
+    pub(crate) failed_solutions: Vec<(Arc<Solution>, SolutionFailReason)>,
+    /// Total gas used by the valid solutions.
+    pub(crate) gas_used: Gas,
 }
 
 /// The main loop that builds blocks.
-pub async fn run<S>(
-    storage: &S,
+pub async fn run<S, B>(
+    essential: &Essential<S>,
     mut shutdown: Shutdown,
-    run_loop_interval: Duration,
-    time_config: &TimeConfig,
+    mut commands: Commands,
+    config: &Config,
+    builder: &B,
 ) -> anyhow::Result<()>
 where
     S: Storage + StateRead + Clone + Send + Sync + 'static,
+    B: BlockBuilder,
     <S as StateRead>::Future: Send,
     <S as StateRead>::Error: Send,
 {
+    let Essential {
+        storage,
+        time_config,
+        block_lock,
+        pending,
+        ..
+    } = essential;
+
     if time_config.enable_time {
         // Deploy the block state contract.
         deploy_protocol_contracts(storage).await?;
@@ -46,18 +108,105 @@ where
 
     // Run the main loop on a fixed interval.
     // The interval is immediately ready the first time.
-    let mut interval = tokio::time::interval(run_loop_interval);
+    let mut interval = tokio::time::interval(config.run_loop_interval);
+
+    let manual = config.block_production == BlockProduction::Manual;
+
+    // When to try again after building a block failed.
+    let mut retry_at: Option<Instant> = None;
+
+    // How many blocks each not composable solution has been retried in.
+    let mut retries = Retries::default();
 
     loop {
-        // Either wait for the interval to tick or the shutdown signal.
+        // Either wait for the interval to tick, a block to be triggered,
+        // a command or the shutdown signal.
+        // While backing off after a failure only the retry builds a block.
         tokio::select! {
-            _ = interval.tick() => {},
+            _ = interval.tick(), if !manual && retry_at.is_none() => {},
+            _ = commands.trigger.wait(storage), if retry_at.is_none() => {},
+            _ = tokio::time::sleep_until(retry_at.unwrap_or_else(Instant::now)), if !manual && retry_at.is_some() => {
+                retry_at = None;
+                // Don't make up for the ticks missed while backing off.
+                interval.reset();
+            },
+            Some(command) = commands.rx.recv() => {
+                let _building = block_lock.lock().await;
+                match command {
+                    Command::BuildBlock(reply) => {
+                        let result = run_loop(storage, config, time_config, builder, &mut retries, pending).await;
+                        let recorded = record(&commands.status, &result, config);
+                        let _ = reply.send(result);
+                        retry_at = recorded?;
+                    }
+                    Command::Prune(reply) => {
+                        let _ = reply.send(prune(storage, config, pending).await);
+                    }
+                }
+                continue;
+            },
             _ = &mut shutdown.0 => return Ok(()),
         }
+        if *commands.paused.borrow() {
+            continue;
+        }
+
+        let _building = block_lock.lock().await;
+        let result = run_loop(storage, config, time_config, builder, &mut retries, pending).await;
+        retry_at = record(&commands.status, &result, config)?;
+    }
+}
+
+/// Publish the result of building a block to the status of the main loop.
+///
+/// Returns when to try again if building the block failed or an error
+/// if it has failed `max_consecutive_failures` times in a row.
+fn record<T>(
+    status: &watch::Sender<MainLoopStatus>,
+    result: &anyhow::Result<T>,
+    config: &Config,
+) -> anyhow::Result<Option<Instant>> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok();
+    let err = match result {
+        Ok(_) => {
+            status.send_modify(|status| {
+                status.consecutive_failures = 0;
+                status.last_success = now;
+                status.backoff = None;
+            });
+            return Ok(None);
+        }
+        Err(err) => err,
+    };
 
-        // Errors are emitted via `tracing`.
-        let _ = run_loop(storage, time_config).await;
+    let mut failures = 0;
+    let mut backoff = Duration::ZERO;
+    status.send_modify(|status| {
+        status.consecutive_failures = status.consecutive_failures.saturating_add(1);
+        failures = status.consecutive_failures;
+        backoff = failure_backoff(config, failures);
+        status.last_error = Some(format!("{err:#}"));
+        status.last_failure = now;
+        status.backoff = Some(backoff);
+    });
+    if let Some(max) = config.max_consecutive_failures {
+        anyhow::ensure!(
+            failures < max,
+            "Building a block failed {failures} times in a row: {err:#}"
+        );
     }
+    Ok(Some(Instant::now() + backoff))
+}
+
+/// How long to wait after the given number of failures in a row.
+fn failure_backoff(config: &Config, failures: usize) -> Duration {
+    let doublings = failures.saturating_sub(1).min(31) as u32;
+    config
+        .failure_backoff
+        .saturating_mul(1 << doublings)
+        .min(config.max_failure_backoff)
 }
 
 /// Deploy the protocol contracts.
@@ -78,17 +227,39 @@ where
     Ok(())
 }
 
+/// Build and commit a block, returning it if it was committed.
 #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, err))]
-async fn run_loop<S>(storage: &S, time_config: &TimeConfig) -> anyhow::Result<()>
+async fn run_loop<S, B>(
+    storage: &S,
+    config: &Config,
+    time_config: &TimeConfig,
+    builder: &B,
+    retries: &mut Retries,
+    pending: &PendingPool,
+) -> anyhow::Result<Option<Block>>
 where
     S: Storage + StateRead + Clone + Send + Sync + 'static,
+    B: BlockBuilder,
     <S as StateRead>::Future: Send,
     <S as StateRead>::Error: Send,
 {
-    // Build a block.
-    let (block_number, block_timestamp, solutions, transaction) = build_block(storage, time_config)
+    // Remove expired solutions and make room in the pool before building.
+    limit_pool(storage, config, pending)
+        .await
+        .context("error limiting solutions pool")?;
+
+    let pool_size = storage
+        .pool_size()
         .await
-        .context("error building block")?;
+        .context("error counting solutions pool")?;
+    metrics::POOL_SIZE.set(pool_size.try_into().unwrap_or(i64::MAX));
+    let started = std::time::Instant::now();
+
+    // Build a block.
+    let (block_number, block_timestamp, solutions, transaction) =
+        build_block(storage, config, time_config, builder, retries)
+            .await
+            .context("error building block")?;
 
     // Move failed solutions.
     let failed_solutions: Vec<(Hash, SolutionFailReason)> = solutions
@@ -107,6 +278,7 @@ where
     let data = CommitData {
         block_number,
         block_timestamp,
+        gas_used: solutions.gas_used,
         failed: &failed_solutions,
         solved: &solved_solutions,
         state_updates: Box::new(transaction.into_updates()),
@@ -117,35 +289,158 @@ where
         .commit_block(data)
         .await
         .context("error committing block")?;
+    pending
+        .remove(
+            solved_solutions
+                .iter()
+                .chain(failed_solutions.iter().map(|(hash, _)| hash)),
+        )
+        .await;
+
+    for (_, reason) in &failed_solutions {
+        metrics::failed(reason, 1);
+    }
 
-    storage
-        .prune_failed_solutions(PRUNE_FAILED_STORAGE_OLDER_THAN)
+    prune_failed(storage, config)
         .await
         .context("error pruning failed solutions")?;
 
+    // A block without any solutions isn't committed.
+    let block = storage
+        .get_latest_block()
+        .await
+        .context("error reading committed block")?
+        .filter(|block| block.number as u64 == block_number);
+    if let Some(block) = &block {
+        metrics::BLOCK_BUILD_DURATION.observe(started.elapsed().as_secs_f64());
+        metrics::BLOCK_SOLUTIONS.observe(block.solutions.len() as f64);
+        metrics::BLOCK_GAS.observe(solutions.gas_used as f64);
+    }
+    Ok(block)
+}
+
+/// Limit the pool and prune failed solutions outside of building a block.
+async fn prune<S>(storage: &S, config: &Config, pending: &PendingPool) -> anyhow::Result<()>
+where
+    S: Storage,
+{
+    limit_pool(storage, config, pending)
+        .await
+        .context("error limiting solutions pool")?;
+    prune_failed(storage, config)
+        .await
+        .context("error pruning failed solutions")
+}
+
+/// Prune the failed solutions that are past the configured retention,
+/// archiving them first if there is an archive.
+async fn prune_failed<S>(storage: &S, config: &Config) -> anyhow::Result<()>
+where
+    S: Storage,
+{
+    let older_than = match config.failed_solution_max_age {
+        Some(max_age) => {
+            let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+            now.saturating_sub(max_age)
+        }
+        None => Duration::ZERO,
+    };
+    if let Some(archive) = &config.failed_solution_archive {
+        let pruned = storage
+            .list_prunable_failed_solutions(older_than, config.failed_solution_max_count)
+            .await?;
+        archive_failed(archive, &pruned)
+            .await
+            .context("error archiving failed solutions")?;
+    }
+    if config.failed_solution_max_age.is_some() {
+        storage.prune_failed_solutions(older_than).await?;
+    }
+    if let Some(max_count) = config.failed_solution_max_count {
+        storage.prune_excess_failed_solutions(max_count).await?;
+    }
+    Ok(())
+}
+
+/// Append failed solutions to the archive as JSON lines.
+///
+/// The archive is synced to disk so the failed solutions can be deleted.
+async fn archive_failed(archive: &Path, failed: &[FailedSolution]) -> anyhow::Result<()> {
+    if failed.is_empty() {
+        return Ok(());
+    }
+    let mut lines = Vec::new();
+    for failed in failed {
+        serde_json::to_writer(&mut lines, failed)?;
+        lines.push(b'\n');
+    }
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(archive)
+        .await?;
+    file.write_all(&lines).await?;
+    file.sync_data().await?;
+    Ok(())
+}
+
+/// Move expired solutions then the oldest solutions beyond
+/// the pool capacity to the failed pool.
+async fn limit_pool<S>(storage: &S, config: &Config, pending: &PendingPool) -> anyhow::Result<()>
+where
+    S: Storage,
+{
+    if let Some(ttl) = config.solution_ttl {
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+        let expired = storage.expire_solutions(now.saturating_sub(ttl)).await?;
+        metrics::failed(&SolutionFailReason::Expired, expired.len());
+        pending.remove(&expired).await;
+    }
+    if let Some(capacity) = config.pool_capacity {
+        let evicted = storage.evict_solutions(capacity).await?;
+        metrics::failed(&SolutionFailReason::Evicted, evicted.len());
+        pending.remove(&evicted).await;
+    }
     Ok(())
 }
 
 /// Build a block from the solutions pool.
 ///
-/// The current implementation is very simple and just builds the
-/// block in FIFO order. If a solution becomes invalid, it is moved to failed.
-async fn build_block<S>(
+/// The block builder decides the order solutions are applied in.
+/// If a solution is invalid, it is moved to failed.
+///
+/// A solution that uses more than the solution gas limit (or more than
+/// an entire block may use) is moved to failed.
+/// A solution that would take the block over its gas limit is left in the pool.
+/// The block state solution is not subject to either limit.
+async fn build_block<S, B>(
     storage: &S,
+    config: &Config,
     time_config: &TimeConfig,
+    builder: &B,
+    retries: &mut Retries,
 ) -> anyhow::Result<(u64, Duration, Solutions, TransactionStorage<S>)>
 where
     S: Storage + StateRead + Clone + Send + Sync + 'static,
+    B: BlockBuilder,
 {
-    // Get all solutions from the pool.
+    // Get the solutions from the pool, up to the configured limit.
     // This returns the solutions in FIFO order.
-    let solutions = storage.list_solutions_pool(Some(0)).await?;
-
-    // Create a state db transaction.
-    let mut transaction = storage.clone().transaction();
+    let solutions: Vec<_> = storage
+        .clone()
+        .stream_solutions_pool(config.max_pool_solutions)
+        .map_ok(Arc::new)
+        .try_collect()
+        .await?;
+
+    // Solutions that left the pool some other way no longer need their retries counted.
+    if config.max_pool_solutions.is_none() {
+        retries.retain_pool(&solutions);
+    }
 
-    let mut valid_solutions: Vec<_> = vec![];
-    let mut failed_solutions: Vec<_> = vec![];
+    // Create the block with a state db transaction.
+    let mut block =
+        BlockContext::new(storage.clone(), config).with_retries(std::mem::take(retries));
 
     let latest_block = storage.get_latest_block().await?;
     let number = latest_block
@@ -165,86 +460,150 @@ where
             .unwrap_or_default(),
     };
 
-    let solutions = if time_config.enable_time {
+    if time_config.enable_time {
         // Add the block state solution at the begging of the block.
         let block_state_solution =
             crate::protocol::block_state_solution(number as u64, timestamp.as_secs());
-        let mut s = vec![block_state_solution];
-        s.extend(solutions);
-        s
-    } else {
-        solutions
-    };
-
-    for solution in solutions {
-        #[cfg(feature = "tracing")]
-        let solution_hash = essential_hash::content_addr(&solution);
-        // Put the solution into an Arc so it's cheap to clone.
-        let solution = Arc::new(solution);
+        block.push_exempt(Arc::new(block_state_solution)).await?;
+    }
 
-        // Get the contract for this solution.
-        let contract = read_contract_from_storage(&solution, storage).await?;
+    builder.build(solutions, &mut block).await?;
 
-        // Apply the proposed mutations, check the contract and return the result.
-        let config = Default::default();
-
-        match crate::checked_state_transition(&transaction, solution.clone(), &contract, config)
-            .await
-        {
-            Ok((post_state, _gas)) => {
-                // Update the transaction to the post state.
-                transaction = post_state;
-                // Collect the valid solution.
-                valid_solutions.push(solution);
-                #[cfg(feature = "tracing")]
-                tracing::debug!(valid_solution = %solution_hash);
-            }
-            Err(err) => {
-                // Collect the failed solution with the reason.
-                failed_solutions.push((
-                    solution,
-                    SolutionFailReason::ConstraintsFailed(err.to_string()),
-                ));
-                #[cfg(feature = "tracing")]
-                tracing::debug!(failed_solution = %solution_hash, %err);
-            }
-        }
-    }
+    let (mut solutions, transaction, block_retries) = block.into_parts();
+    *retries = block_retries;
 
     // If there is only one valid solution then
     // it's only the block state solution.
-    if valid_solutions.len() == 1 && time_config.enable_time {
-        valid_solutions.clear();
+    if solutions.valid_solutions.len() == 1 && time_config.enable_time {
+        solutions.valid_solutions.clear();
+        solutions.gas_used = 0;
     }
 
-    Ok((
-        number as u64,
-        timestamp,
-        Solutions {
-            valid_solutions,
-            failed_solutions,
-        },
-        transaction,
-    ))
+    Ok((number as u64, timestamp, solutions, transaction))
 }
 
 impl Handle {
-    pub fn new() -> (Self, Shutdown) {
+    /// Create a handle for a main loop that watches the given count of submitted solutions.
+    pub fn new(
+        config: Arc<Config>,
+        submissions: watch::Receiver<u64>,
+    ) -> (Self, Shutdown, Commands) {
         let (tx, rx) = oneshot::channel();
-        (Self { tx, jh: None }, Shutdown(rx))
+        let (commands_tx, commands_rx) = mpsc::channel(1);
+        let (paused_tx, paused_rx) = watch::channel(false);
+        let (status_tx, status_rx) = watch::channel(MainLoopStatus::default());
+        let trigger = Trigger::new(config.block_production, submissions);
+        let controller = Controller {
+            commands: commands_tx,
+            paused: Arc::new(paused_tx),
+            status: status_rx,
+            config,
+        };
+        let handle = Self {
+            tx,
+            controller,
+            jh: None,
+        };
+        let commands = Commands {
+            rx: commands_rx,
+            paused: paused_rx,
+            trigger,
+            status: status_tx,
+        };
+        (handle, Shutdown(rx), commands)
     }
 
     pub fn contract_jh(&mut self, jh: tokio::task::JoinHandle<anyhow::Result<()>>) {
         self.jh = Some(jh);
     }
 
+    /// A controller for the running loop that can be shared.
+    pub fn controller(&self) -> Controller {
+        self.controller.clone()
+    }
+
+    /// Shut down the main loop and wait for it to stop.
+    ///
+    /// Returns the error the main loop stopped with if it stopped on its own.
     pub async fn shutdown(self) -> anyhow::Result<()> {
-        self.tx
-            .send(())
-            .map_err(|_| anyhow::anyhow!("Failed to send shutdown signal"))?;
-        if let Some(jh) = self.jh {
-            jh.await??;
+        let sent = self.tx.send(());
+        match self.jh {
+            Some(jh) => jh.await?,
+            None => sent.map_err(|_| anyhow::anyhow!("Failed to send shutdown signal")),
         }
-        Ok(())
     }
 }
+
+impl std::ops::Deref for Handle {
+    type Target = Controller;
+
+    fn deref(&self) -> &Self::Target {
+        &self.controller
+    }
+}
+
+impl Controller {
+    /// The configuration the main loop is running with.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Whether the main loop is still running.
+    ///
+    /// The main loop only stops when it's shut down or fails.
+    pub fn is_running(&self) -> bool {
+        !self.commands.is_closed()
+    }
+
+    /// Wait until the main loop stops.
+    pub async fn wait_until_stopped(&self) {
+        self.commands.closed().await
+    }
+
+    /// How the main loop is doing.
+    pub fn status(&self) -> MainLoopStatus {
+        self.status.borrow().clone()
+    }
+
+    /// Stop building blocks on the interval or when triggered by submitted solutions.
+    ///
+    /// Blocks can still be built and the pool pruned on demand while paused.
+    pub fn pause(&self) {
+        self.paused.send_replace(true);
+    }
+
+    /// Resume building blocks on the interval or when triggered by submitted solutions.
+    pub fn resume(&self) {
+        self.paused.send_replace(false);
+    }
+
+    /// Whether building blocks is paused.
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// Build a block now rather than waiting for the interval.
+    ///
+    /// Returns the block or `None` if there were no solutions to build it from.
+    pub async fn build_block(&self) -> anyhow::Result<Option<Block>> {
+        let (reply, rx) = oneshot::channel();
+        self.send(Command::BuildBlock(reply)).await?;
+        rx.await.map_err(|_| stopped())?
+    }
+
+    /// Expire and evict solutions from the pool and prune
+    /// failed solutions now rather than waiting for the next block.
+    pub async fn prune(&self) -> anyhow::Result<()> {
+        let (reply, rx) = oneshot::channel();
+        self.send(Command::Prune(reply)).await?;
+        rx.await.map_err(|_| stopped())?
+    }
+
+    async fn send(&self, command: Command) -> anyhow::Result<()> {
+        self.commands.send(command).await.map_err(|_| stopped())
+    }
+}
+
+fn stopped() -> anyhow::Error {
+    anyhow::anyhow!("The main loop has stopped")
+}
@@ -2,30 +2,49 @@ use crate::{
     deploy::deploy,
     solution::submit_solution,
     test_utils::{counter_predicate, counter_solution, deploy_predicate, test_solution},
+    BlockProduction, Config, Essential, TimeConfig,
 };
 use essential_memory_storage::MemoryStorage;
 use essential_state_read_vm::StateRead;
-use essential_storage::{QueryState, Storage};
-use essential_types::{predicate::Predicate, PredicateAddress, Word};
-use std::time::Duration;
+use essential_storage::{
+    failed_solution::{FailedSolution, SolutionFailReason},
+    QueryState, Storage,
+};
+use essential_types::{
+    predicate::Predicate,
+    solution::{Solution, SolutionData},
+    PredicateAddress, Word,
+};
+use std::{sync::Arc, time::Duration};
 use test_utils::{empty::Empty, sign_contract_with_random_keypair};
 
-use super::RUN_LOOP_FREQUENCY;
-
 async fn run<S>(storage: &S) -> anyhow::Result<()>
 where
     S: Storage + StateRead + Clone + Send + Sync + 'static,
     <S as StateRead>::Future: Send,
     <S as StateRead>::Error: Send,
 {
-    let (tx, rx) = tokio::sync::oneshot::channel();
-    let shutdown = super::Shutdown(rx);
-    let s = storage.clone();
+    run_with_config(storage, Default::default(), Default::default()).await
+}
+
+async fn run_with_config<S>(
+    storage: &S,
+    config: Config,
+    time_config: TimeConfig,
+) -> anyhow::Result<()>
+where
+    S: Storage + StateRead + Clone + Send + Sync + 'static,
+    <S as StateRead>::Future: Send,
+    <S as StateRead>::Error: Send,
+{
+    let (_, submissions) = tokio::sync::watch::channel(0);
+    let (handle, shutdown, commands) = super::Handle::new(Arc::new(config.clone()), submissions);
+    let essential = Essential::new(storage.clone(), Default::default(), Arc::new(time_config));
     let jh = tokio::spawn(async move {
-        super::run(&s, shutdown, RUN_LOOP_FREQUENCY, &Default::default()).await
+        super::run(&essential, shutdown, commands, &config, &crate::Fifo).await
     });
     tokio::time::sleep(Duration::from_millis(100)).await;
-    tx.send(()).unwrap();
+    handle.tx.send(()).unwrap();
     jh.await?
 }
 
@@ -198,3 +217,691 @@ async fn test_tracing() {
     submit_solution(&storage, solution.clone()).await.unwrap();
     run(&storage).await.unwrap();
 }
+
+fn no_time() -> TimeConfig {
+    TimeConfig {
+        enable_time: false,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_solution_gas_limit() {
+    let (solution, storage) = test_solution(None, 1).await;
+    submit_solution(&storage, solution.clone()).await.unwrap();
+
+    let config = Config {
+        solution_gas_limit: 1,
+        ..Default::default()
+    };
+    run_with_config(&storage, config, no_time()).await.unwrap();
+
+    assert!(storage
+        .list_blocks(None, None, None)
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(storage.list_solutions_pool(None).await.unwrap().is_empty());
+    let failed = storage.list_failed_solutions_pool(None).await.unwrap();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].solution, solution);
+    assert!(matches!(
+        failed[0].reason,
+        SolutionFailReason::GasLimitExceeded(gas) if gas > 1
+    ));
+}
+
+#[tokio::test]
+async fn test_block_gas_limit() {
+    let (solution, storage) = test_solution(None, 1).await;
+    let (solution2, _) = test_solution(Some(storage.clone()), 2).await;
+
+    let essential = Essential::new(storage.clone(), Default::default(), Default::default());
+    let gas = essential
+        .check_solution(solution.clone())
+        .await
+        .unwrap()
+        .gas;
+    let gas2 = essential
+        .check_solution(solution2.clone())
+        .await
+        .unwrap()
+        .gas;
+
+    submit_solution(&storage, solution.clone()).await.unwrap();
+    submit_solution(&storage, solution2.clone()).await.unwrap();
+
+    // Only one of the solutions fits in a block.
+    let config = Config {
+        block_gas_limit: gas + gas2 - 1,
+        ..Default::default()
+    };
+    run_with_config(&storage, config.clone(), no_time())
+        .await
+        .unwrap();
+
+    let blocks = storage.list_blocks(None, None, None).await.unwrap();
+    assert_eq!(blocks.len(), 1);
+    assert_eq!(blocks[0].solutions, vec![solution]);
+    assert_eq!(
+        storage.list_solutions_pool(None).await.unwrap(),
+        vec![solution2.clone()]
+    );
+    assert!(storage
+        .list_failed_solutions_pool(None)
+        .await
+        .unwrap()
+        .is_empty());
+
+    let headers = storage.list_block_headers(None, None).await.unwrap();
+    assert_eq!(headers[0].gas_used, gas);
+
+    // The remaining solution goes in the next block.
+    run_with_config(&storage, config, no_time()).await.unwrap();
+
+    let blocks = storage.list_blocks(None, None, None).await.unwrap();
+    assert_eq!(blocks.len(), 2);
+    assert_eq!(blocks[1].solutions, vec![solution2]);
+    assert!(storage.list_solutions_pool(None).await.unwrap().is_empty());
+}
+
+/// Solutions for an empty predicate that differ by their decision variables.
+async fn pool_solutions(n: Word) -> (Vec<Solution>, MemoryStorage) {
+    let (predicate_address, storage) = deploy_predicate(Predicate::empty()).await;
+    let solutions = (0..n)
+        .map(|i| Solution {
+            data: vec![SolutionData {
+                predicate_to_solve: predicate_address.clone(),
+                decision_variables: vec![vec![i]],
+                transient_data: Default::default(),
+                state_mutations: Default::default(),
+            }],
+        })
+        .collect();
+    (solutions, storage)
+}
+
+#[tokio::test]
+async fn test_full_pool() {
+    // More than a single page of the pool.
+    let (solutions, storage) = pool_solutions(250).await;
+    for solution in &solutions {
+        submit_solution(&storage, solution.clone()).await.unwrap();
+    }
+
+    run_with_config(&storage, Default::default(), no_time())
+        .await
+        .unwrap();
+
+    let blocks = storage.list_blocks(None, None, None).await.unwrap();
+    assert_eq!(blocks.len(), 1);
+    assert_eq!(blocks[0].solutions, solutions);
+    assert!(storage.list_solutions_pool(None).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_max_pool_solutions() {
+    let (solutions, storage) = pool_solutions(10).await;
+    for solution in &solutions {
+        submit_solution(&storage, solution.clone()).await.unwrap();
+    }
+
+    let config = Config {
+        max_pool_solutions: Some(4),
+        ..Default::default()
+    };
+    run_with_config(&storage, config, no_time()).await.unwrap();
+
+    let blocks = storage.list_blocks(None, None, None).await.unwrap();
+    assert_eq!(blocks.len(), 1);
+    assert_eq!(blocks[0].solutions, solutions[..4]);
+    assert_eq!(
+        storage.list_solutions_pool(None).await.unwrap(),
+        solutions[4..]
+    );
+}
+
+#[tokio::test]
+async fn test_pool_capacity() {
+    let (solutions, storage) = pool_solutions(10).await;
+    for solution in &solutions {
+        submit_solution(&storage, solution.clone()).await.unwrap();
+    }
+
+    let config = Config {
+        pool_capacity: Some(4),
+        ..Default::default()
+    };
+    run_with_config(&storage, config, no_time()).await.unwrap();
+
+    // The oldest solutions are evicted before the block is built.
+    let blocks = storage.list_blocks(None, None, None).await.unwrap();
+    assert_eq!(blocks.len(), 1);
+    assert_eq!(blocks[0].solutions, solutions[6..]);
+    let failed = storage.list_failed_solutions_pool(None).await.unwrap();
+    assert_eq!(failed.len(), 6);
+    assert!(failed
+        .iter()
+        .all(|f| f.reason == SolutionFailReason::Evicted));
+}
+
+#[tokio::test]
+async fn test_solution_ttl() {
+    let (solutions, storage) = pool_solutions(2).await;
+    submit_solution(&storage, solutions[0].clone())
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    submit_solution(&storage, solutions[1].clone())
+        .await
+        .unwrap();
+
+    let config = Config {
+        solution_ttl: Some(Duration::from_millis(150)),
+        ..Default::default()
+    };
+    run_with_config(&storage, config, no_time()).await.unwrap();
+
+    let blocks = storage.list_blocks(None, None, None).await.unwrap();
+    assert_eq!(blocks.len(), 1);
+    assert_eq!(blocks[0].solutions, solutions[1..]);
+    let failed = storage.list_failed_solutions_pool(None).await.unwrap();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].solution, solutions[0]);
+    assert_eq!(failed[0].reason, SolutionFailReason::Expired);
+}
+
+/// Solutions that have all failed, oldest first.
+async fn failed_solutions(n: Word) -> (Vec<Solution>, MemoryStorage) {
+    let (solutions, storage) = pool_solutions(n).await;
+    for solution in &solutions {
+        submit_solution(&storage, solution.clone()).await.unwrap();
+        let failed = [(
+            essential_hash::hash(solution),
+            SolutionFailReason::NotComposable,
+        )];
+        storage.move_solutions_to_failed(&failed).await.unwrap();
+    }
+    (solutions, storage)
+}
+
+#[tokio::test]
+async fn test_failed_solution_max_age() {
+    let (_, storage) = failed_solutions(3).await;
+
+    // The default only prunes failed solutions older than a week.
+    run_with_config(&storage, Default::default(), no_time())
+        .await
+        .unwrap();
+    let failed = storage.list_failed_solutions_pool(None).await.unwrap();
+    assert_eq!(failed.len(), 3);
+
+    let config = Config {
+        failed_solution_max_age: Some(Duration::ZERO),
+        ..Default::default()
+    };
+    run_with_config(&storage, config, no_time()).await.unwrap();
+    let failed = storage.list_failed_solutions_pool(None).await.unwrap();
+    assert!(failed.is_empty());
+}
+
+#[tokio::test]
+async fn test_failed_solution_max_count_archive() {
+    let (solutions, storage) = failed_solutions(3).await;
+    let dir = tempfile::tempdir().unwrap();
+    let archive = dir.path().join("failed.jsonl");
+
+    let config = Config {
+        failed_solution_max_count: Some(1),
+        failed_solution_archive: Some(archive.clone()),
+        ..Default::default()
+    };
+    run_with_config(&storage, config, no_time()).await.unwrap();
+
+    let failed = storage.list_failed_solutions_pool(None).await.unwrap();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].solution, solutions[2]);
+
+    // The pruned failed solutions are archived oldest first.
+    let archived: Vec<FailedSolution> = std::fs::read_to_string(&archive)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(archived.len(), 2);
+    assert_eq!(archived[0].solution, solutions[0]);
+    assert_eq!(archived[1].solution, solutions[1]);
+    assert!(archived
+        .iter()
+        .all(|f| f.reason == SolutionFailReason::NotComposable));
+}
+
+/// Two counter solutions that are each valid on their own but not together.
+async fn conflicting_counter_solutions() -> (Solution, Solution, PredicateAddress, MemoryStorage) {
+    let (predicate_address, storage) = deploy_predicate(counter_predicate(1)).await;
+    let solution = counter_solution(predicate_address.clone(), 1).await;
+    let mut conflicting = solution.clone();
+    conflicting.data[0].decision_variables[0].push(0);
+    (solution, conflicting, predicate_address, storage)
+}
+
+#[tokio::test]
+async fn test_not_composable() {
+    let (solution, conflicting, predicate_address, storage) = conflicting_counter_solutions().await;
+    let invalid = counter_solution(predicate_address, 3).await;
+
+    submit_solution(&storage, solution.clone()).await.unwrap();
+    submit_solution(&storage, conflicting.clone())
+        .await
+        .unwrap();
+    submit_solution(&storage, invalid.clone()).await.unwrap();
+
+    run_with_config(&storage, Default::default(), no_time())
+        .await
+        .unwrap();
+
+    let blocks = storage.list_blocks(None, None, None).await.unwrap();
+    assert_eq!(blocks.len(), 1);
+    assert_eq!(blocks[0].solutions, vec![solution]);
+
+    let failed = storage.list_failed_solutions_pool(None).await.unwrap();
+    assert_eq!(failed.len(), 2);
+    assert_eq!(failed[0].solution, conflicting);
+    assert_eq!(failed[0].reason, SolutionFailReason::NotComposable);
+    assert_eq!(failed[1].solution, invalid);
+    assert!(matches!(
+        failed[1].reason,
+        SolutionFailReason::ConstraintsFailed(_)
+    ));
+}
+
+#[tokio::test]
+async fn test_retry_not_composable() {
+    let (solution, conflicting, _, storage) = conflicting_counter_solutions().await;
+
+    submit_solution(&storage, solution.clone()).await.unwrap();
+    submit_solution(&storage, conflicting.clone())
+        .await
+        .unwrap();
+
+    let config = Config {
+        retry_not_composable: true,
+        ..Default::default()
+    };
+    run_with_config(&storage, config.clone(), no_time())
+        .await
+        .unwrap();
+
+    let blocks = storage.list_blocks(None, None, None).await.unwrap();
+    assert_eq!(blocks.len(), 1);
+    assert_eq!(blocks[0].solutions, vec![solution]);
+    assert!(storage
+        .list_failed_solutions_pool(None)
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        storage.list_solutions_pool(None).await.unwrap(),
+        vec![conflicting.clone()]
+    );
+
+    // On its own against the new state the solution is now invalid.
+    run_with_config(&storage, config, no_time()).await.unwrap();
+
+    assert!(storage.list_solutions_pool(None).await.unwrap().is_empty());
+    let failed = storage.list_failed_solutions_pool(None).await.unwrap();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].solution, conflicting);
+    assert!(matches!(
+        failed[0].reason,
+        SolutionFailReason::ConstraintsFailed(_)
+    ));
+}
+
+#[tokio::test]
+async fn test_handle_build_block() {
+    let (solutions, storage) = pool_solutions(2).await;
+    let essential = Essential::new(storage.clone(), Default::default(), Arc::new(no_time()));
+    let config = Config {
+        run_loop_interval: Duration::from_millis(10),
+        ..Default::default()
+    };
+    let handle = essential.spawn(config, crate::Fifo).unwrap();
+    handle.pause();
+    assert!(handle.is_paused());
+
+    // Nothing is built on the interval while paused.
+    submit_solution(&storage, solutions[0].clone())
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(storage
+        .list_blocks(None, None, None)
+        .await
+        .unwrap()
+        .is_empty());
+
+    // But a block can still be built on demand.
+    let block = handle.build_block().await.unwrap().unwrap();
+    assert_eq!(block.number, 0);
+    assert_eq!(block.solutions, solutions[..1]);
+    assert!(handle.build_block().await.unwrap().is_none());
+
+    handle.resume();
+    submit_solution(&storage, solutions[1].clone())
+        .await
+        .unwrap();
+    let blocks = loop {
+        let blocks = storage.list_blocks(None, None, None).await.unwrap();
+        if blocks.len() > 1 {
+            break blocks;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    };
+    assert_eq!(blocks[1].solutions, solutions[1..]);
+
+    handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_handle_prune() {
+    let (solutions, storage) = pool_solutions(3).await;
+    for solution in &solutions {
+        submit_solution(&storage, solution.clone()).await.unwrap();
+    }
+    let essential = Essential::new(storage.clone(), Default::default(), Arc::new(no_time()));
+    let config = Config {
+        pool_capacity: Some(1),
+        ..Default::default()
+    };
+    let handle = essential
+        .clone()
+        .spawn(config.clone(), crate::Fifo)
+        .unwrap();
+    handle.pause();
+    assert_eq!(handle.config().pool_capacity, config.pool_capacity);
+
+    // Evicting a solution that isn't in the pool is an error.
+    let missing = essential_hash::hash(&Solution { data: vec![] });
+    let err = essential.evict_solution(missing).await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(crate::Error::NotFound(_))
+    ));
+
+    let hash = essential_hash::hash(&solutions[2]);
+    essential.evict_solution(hash).await.unwrap();
+    assert_eq!(
+        storage.list_solutions_pool(None).await.unwrap(),
+        solutions[..2]
+    );
+
+    handle.prune().await.unwrap();
+    assert_eq!(
+        storage.list_solutions_pool(None).await.unwrap(),
+        solutions[1..2]
+    );
+    let failed = storage.list_failed_solutions_pool(None).await.unwrap();
+    assert_eq!(failed.len(), 2);
+    assert!(failed
+        .iter()
+        .all(|f| f.reason == SolutionFailReason::Evicted));
+
+    let controller = handle.controller();
+    assert!(controller.is_running());
+    handle.shutdown().await.unwrap();
+    assert!(!controller.is_running());
+}
+
+/// Wait for the storage to have `n` blocks.
+async fn wait_for_blocks(storage: &MemoryStorage, n: usize) -> Vec<essential_types::Block> {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let blocks = storage.list_blocks(None, None, None).await.unwrap();
+            if blocks.len() >= n {
+                break blocks;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap()
+}
+
+/// Spawn the main loop with a long interval so blocks are only built by the production mode.
+async fn spawn_production(
+    storage: &MemoryStorage,
+    block_production: BlockProduction,
+) -> (Essential<MemoryStorage>, super::Handle) {
+    let essential = Essential::new(storage.clone(), Default::default(), Arc::new(no_time()));
+    let config = Config {
+        run_loop_interval: Duration::from_secs(3600),
+        block_production,
+        ..Default::default()
+    };
+    let handle = essential.clone().spawn(config, crate::Fifo).unwrap();
+    // Let the first interval tick pass while the pool is empty.
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    (essential, handle)
+}
+
+#[tokio::test]
+async fn test_manual_production() {
+    let (solutions, storage) = pool_solutions(1).await;
+    let essential = Essential::new(storage.clone(), Default::default(), Arc::new(no_time()));
+    let config = Config {
+        run_loop_interval: Duration::from_millis(10),
+        block_production: BlockProduction::Manual,
+        ..Default::default()
+    };
+    let handle = essential.clone().spawn(config, crate::Fifo).unwrap();
+
+    essential
+        .submit_solution(solutions[0].clone())
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(storage
+        .list_blocks(None, None, None)
+        .await
+        .unwrap()
+        .is_empty());
+
+    let block = handle.build_block().await.unwrap().unwrap();
+    assert_eq!(block.solutions, solutions);
+
+    handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_pool_size_production() {
+    let (solutions, storage) = pool_solutions(3).await;
+    let (essential, handle) = spawn_production(&storage, BlockProduction::PoolSize(3)).await;
+
+    for solution in &solutions[..2] {
+        essential.submit_solution(solution.clone()).await.unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(storage
+        .list_blocks(None, None, None)
+        .await
+        .unwrap()
+        .is_empty());
+
+    essential
+        .submit_solution(solutions[2].clone())
+        .await
+        .unwrap();
+    let blocks = wait_for_blocks(&storage, 1).await;
+    assert_eq!(blocks[0].solutions, solutions);
+
+    handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_on_submit_production() {
+    let (solutions, storage) = pool_solutions(2).await;
+    let debounce = Duration::from_millis(200);
+    let (essential, handle) =
+        spawn_production(&storage, BlockProduction::OnSubmit { debounce }).await;
+
+    // Solutions submitted within the debounce go in the same block.
+    for solution in &solutions {
+        essential.submit_solution(solution.clone()).await.unwrap();
+    }
+    assert!(storage
+        .list_blocks(None, None, None)
+        .await
+        .unwrap()
+        .is_empty());
+    let blocks = wait_for_blocks(&storage, 1).await;
+    assert_eq!(blocks[0].solutions, solutions);
+
+    handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_metrics() {
+    let (solutions, storage) = pool_solutions(3).await;
+    for solution in &solutions {
+        submit_solution(&storage, solution.clone()).await.unwrap();
+    }
+
+    // Other tests record metrics too so only check they increase.
+    let evicted = || {
+        crate::metrics::FAILED_SOLUTIONS
+            .with_label_values(&["evicted"])
+            .get()
+    };
+    let blocks = crate::metrics::BLOCK_SOLUTIONS.get_sample_count();
+    let solution_count = crate::metrics::BLOCK_SOLUTIONS.get_sample_sum();
+    let failed = evicted();
+
+    let config = Config {
+        pool_capacity: Some(2),
+        ..Default::default()
+    };
+    run_with_config(&storage, config, no_time()).await.unwrap();
+
+    assert!(crate::metrics::BLOCK_SOLUTIONS.get_sample_count() > blocks);
+    assert!(crate::metrics::BLOCK_SOLUTIONS.get_sample_sum() >= solution_count + 2.0);
+    assert!(crate::metrics::BLOCK_BUILD_DURATION.get_sample_count() > 0);
+    assert!(evicted() > failed);
+}
+
+/// Builds blocks first in first out unless it's broken.
+struct Breakable(Arc<std::sync::atomic::AtomicBool>);
+
+impl crate::BlockBuilder for Breakable {
+    async fn build<S>(
+        &self,
+        pool: Vec<Arc<Solution>>,
+        block: &mut crate::BlockContext<S>,
+    ) -> anyhow::Result<()>
+    where
+        S: Storage + StateRead + Clone + Send + Sync + 'static,
+    {
+        anyhow::ensure!(
+            !self.0.load(std::sync::atomic::Ordering::SeqCst),
+            "builder is broken"
+        );
+        crate::Fifo.build(pool, block).await
+    }
+}
+
+#[tokio::test]
+async fn test_failure_backoff() {
+    let (solutions, storage) = pool_solutions(1).await;
+    submit_solution(&storage, solutions[0].clone())
+        .await
+        .unwrap();
+    let essential = Essential::new(storage.clone(), Default::default(), Arc::new(no_time()));
+    let config = Config {
+        run_loop_interval: Duration::from_millis(10),
+        failure_backoff: Duration::from_millis(50),
+        max_failure_backoff: Duration::from_millis(100),
+        ..Default::default()
+    };
+    let broken = Arc::new(std::sync::atomic::AtomicBool::new(true));
+    let handle = essential.spawn(config, Breakable(broken.clone())).unwrap();
+
+    let status = wait_for_status(&handle, |status| status.consecutive_failures > 0).await;
+    assert_eq!(
+        status.last_error.as_deref(),
+        Some("error building block: builder is broken")
+    );
+    assert!(status.last_failure.is_some());
+    assert!(status.last_success.is_none());
+    assert_eq!(status.backoff, Some(Duration::from_millis(50)));
+
+    // Without backing off it would fail on every interval.
+    tokio::time::sleep(Duration::from_millis(120)).await;
+    assert!(handle.status().consecutive_failures <= 3);
+
+    // The backoff doubles up to the maximum.
+    let status = wait_for_status(&handle, |status| status.consecutive_failures >= 3).await;
+    assert_eq!(status.backoff, Some(Duration::from_millis(100)));
+
+    broken.store(false, std::sync::atomic::Ordering::SeqCst);
+    let blocks = wait_for_blocks(&storage, 1).await;
+    assert_eq!(blocks[0].solutions, solutions);
+    let status = wait_for_status(&handle, |status| status.last_success.is_some()).await;
+    assert_eq!(status.consecutive_failures, 0);
+    assert!(status.backoff.is_none());
+    assert!(status.last_error.is_some());
+
+    handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_max_consecutive_failures() {
+    let storage = MemoryStorage::new();
+    let essential = Essential::new(storage.clone(), Default::default(), Arc::new(no_time()));
+    let config = Config {
+        block_production: BlockProduction::Manual,
+        max_consecutive_failures: Some(2),
+        ..Default::default()
+    };
+    let broken = Arc::new(std::sync::atomic::AtomicBool::new(true));
+    let handle = essential.spawn(config, Breakable(broken.clone())).unwrap();
+
+    handle.build_block().await.unwrap_err();
+    assert_eq!(handle.status().consecutive_failures, 1);
+
+    // A success resets the count.
+    broken.store(false, std::sync::atomic::Ordering::SeqCst);
+    assert!(handle.build_block().await.unwrap().is_none());
+    assert_eq!(handle.status().consecutive_failures, 0);
+
+    broken.store(true, std::sync::atomic::Ordering::SeqCst);
+    handle.build_block().await.unwrap_err();
+    assert!(handle.is_running());
+
+    // The last failure is still replied to before the main loop stops.
+    let err = handle.build_block().await.unwrap_err();
+    assert!(format!("{err:#}").contains("builder is broken"));
+    handle.wait_until_stopped().await;
+    assert!(!handle.is_running());
+    assert_eq!(handle.status().consecutive_failures, 2);
+
+    let err = handle.shutdown().await.unwrap_err();
+    assert!(err.to_string().contains("failed 2 times in a row"));
+}
+
+/// Wait for the status of the main loop to match.
+async fn wait_for_status(
+    handle: &super::Handle,
+    f: impl Fn(&crate::MainLoopStatus) -> bool,
+) -> crate::MainLoopStatus {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let status = handle.status();
+            if f(&status) {
+                break status;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    })
+    .await
+    .unwrap()
+}
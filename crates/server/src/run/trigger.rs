@@ -0,0 +1,113 @@
+use crate::BlockProduction;
+use essential_storage::Storage;
+use tokio::{sync::watch, time::Instant};
+
+#[cfg(test)]
+mod tests;
+
+/// Decides when a block is built because solutions were submitted.
+pub(super) struct Trigger {
+    production: BlockProduction,
+    /// The number of solutions submitted so far.
+    submissions: watch::Receiver<u64>,
+    /// When the debounced block is due.
+    deadline: Option<Instant>,
+    /// The size of the pool when it was last counted.
+    pool_len: usize,
+    /// The number of solutions submitted when the pool was last counted.
+    counted_at: u64,
+    /// Whether a submission was seen that the pool hasn't been counted since.
+    ///
+    /// Waiting is cancelled when another branch of the main loop wins
+    /// so this keeps a submission that was seen before the count finished.
+    recount: bool,
+}
+
+impl Trigger {
+    pub(super) fn new(production: BlockProduction, submissions: watch::Receiver<u64>) -> Self {
+        let counted_at = *submissions.borrow();
+        Self {
+            production,
+            submissions,
+            deadline: None,
+            // Unknown until it's counted.
+            pool_len: usize::MAX,
+            counted_at,
+            recount: false,
+        }
+    }
+
+    /// Wait until a block should be built.
+    ///
+    /// Waits forever if blocks are not built on submission.
+    pub(super) async fn wait<S>(&mut self, storage: &S)
+    where
+        S: Storage + Clone + Send + Sync + 'static,
+    {
+        loop {
+            match self.production {
+                BlockProduction::Interval | BlockProduction::Manual => std::future::pending().await,
+                BlockProduction::PoolSize(size) => {
+                    if !self.recount {
+                        self.submitted().await;
+                        self.recount = true;
+                    }
+                    if self.pool_full(storage, size).await {
+                        return;
+                    }
+                }
+                BlockProduction::OnSubmit { debounce } => match self.deadline {
+                    Some(deadline) => tokio::select! {
+                        _ = tokio::time::sleep_until(deadline) => {
+                            self.deadline = None;
+                            return;
+                        }
+                        _ = self.submitted() => {
+                            self.deadline = Some(Instant::now() + debounce);
+                        }
+                    },
+                    None => {
+                        self.submitted().await;
+                        self.deadline = Some(Instant::now() + debounce);
+                    }
+                },
+            }
+        }
+    }
+
+    /// Wait for a solution to be submitted.
+    async fn submitted(&mut self) {
+        if self.submissions.changed().await.is_err() {
+            // Nothing can be submitted anymore.
+            std::future::pending().await
+        }
+    }
+
+    /// Whether the pool has at least `size` solutions.
+    ///
+    /// The pool is only counted when enough solutions have been
+    /// submitted since it was last counted that it could be full.
+    async fn pool_full<S>(&mut self, storage: &S, size: usize) -> bool
+    where
+        S: Storage,
+    {
+        let submitted = *self.submissions.borrow();
+        let since =
+            usize::try_from(submitted.saturating_sub(self.counted_at)).unwrap_or(usize::MAX);
+        if self.pool_len.saturating_add(since) < size {
+            self.recount = false;
+            return false;
+        }
+        let len = storage.pool_size().await;
+        self.recount = false;
+        match len {
+            Ok(len) => {
+                self.pool_len = len;
+                self.counted_at = submitted;
+                len >= size
+            }
+            // Errors are left for building the block to report.
+            Err(_) => true,
+        }
+    }
+}
@@ -0,0 +1,27 @@
+use super::*;
+use crate::test_utils::sanity_solution;
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_recount_after_cancelled_wait() {
+    let (solution, storage) = sanity_solution().await;
+    storage.insert_solution_into_pool(solution).await.unwrap();
+
+    let (submissions, rx) = watch::channel(0);
+    let mut trigger = Trigger::new(BlockProduction::PoolSize(1), rx);
+
+    // The submission is seen but waiting is cancelled before the pool is counted.
+    submissions.send_modify(|n| *n += 1);
+    trigger.submitted().await;
+    trigger.recount = true;
+
+    // Waiting again counts the pool without another submission.
+    tokio::time::timeout(Duration::from_secs(1), trigger.wait(&storage))
+        .await
+        .unwrap();
+
+    // Once counted, waiting needs another submission.
+    tokio::time::timeout(Duration::from_millis(100), trigger.wait(&storage))
+        .await
+        .unwrap_err();
+}
@@ -1,11 +1,13 @@
-use essential_check as check;
+use essential_check::{self as check, solution::CheckPredicateConfig};
+use essential_state_read_vm::StateRead;
 use essential_storage::{StateStorage, Storage};
 use essential_transaction_storage::TransactionStorage;
 use essential_types::{predicate::Predicate, solution::Solution, ContentAddress, PredicateAddress};
 use std::{collections::HashMap, sync::Arc};
 
-use crate::TimeConfig;
+use crate::{Error, TimeConfig};
 
+pub(crate) mod admission;
 pub(crate) mod read;
 #[cfg(test)]
 mod tests;
@@ -16,18 +18,57 @@ pub async fn submit_solution<S>(storage: &S, solution: Solution) -> anyhow::Resu
 where
     S: Storage,
 {
-    check::solution::check(&solution)?;
+    validate_solution(storage, &solution).await?;
+    insert_solution(storage, solution).await
+}
+
+/// Validates a signed solution, checks it against the state and
+/// the pool with [`admission::admit_solution`] and submits it to storage.
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all, err(level=tracing::Level::DEBUG), ret(Display)))]
+pub(crate) async fn submit_admitted_solution<S>(
+    storage: &S,
+    pending: &admission::PendingPool,
+    solution: Solution,
+    config: Arc<CheckPredicateConfig>,
+) -> anyhow::Result<ContentAddress>
+where
+    S: Storage + StateRead + Clone + Send + Sync + 'static,
+    <S as StateRead>::Future: Send,
+    <S as StateRead>::Error: Send,
+{
+    let contract = validate_solution(storage, &solution).await?;
+    admission::admit_solution(storage, pending, solution, &contract, config).await
+}
+
+/// Validates a signed solution and reads the predicates it solves.
+async fn validate_solution<S>(
+    storage: &S,
+    solution: &Solution,
+) -> anyhow::Result<HashMap<PredicateAddress, Arc<Predicate>>>
+where
+    S: Storage,
+{
+    check::solution::check(solution).map_err(Error::invalid)?;
 
     // Validation of contract being read from storage.
     let contract: HashMap<PredicateAddress, Arc<Predicate>> =
-        read::read_contract_from_storage(&solution, storage).await?;
-    validate_contract(&solution, &contract)?;
+        read::read_contract_from_storage(solution, storage).await?;
+    validate_contract(solution, &contract).map_err(Error::invalid)?;
+    Ok(contract)
+}
 
-    // Insert the solution into the pool.
+/// Insert the solution into the pool.
+async fn insert_solution<S>(storage: &S, solution: Solution) -> anyhow::Result<ContentAddress>
+where
+    S: Storage,
+{
     let solution_hash = essential_hash::content_addr(&solution);
     match storage.insert_solution_into_pool(solution).await {
         Ok(()) => Ok(solution_hash),
-        Err(err) => anyhow::bail!("Failed to submit solution: {}", err),
+        Err(err) => Err(Error::storage(anyhow::anyhow!(
+            "Failed to submit solution: {}",
+            err
+        ))),
     }
 }
 
@@ -96,7 +137,7 @@ pub(crate) fn filter_solution(time_config: &TimeConfig, solution: &Solution) ->
             .iter()
             .any(|data| data.predicate_to_solve.contract == block_state_address)
         {
-            anyhow::bail!("Block state solutions are blocked");
+            return Err(Error::Conflict("Block state solutions are blocked".to_string()).into());
         }
     }
     Ok(())
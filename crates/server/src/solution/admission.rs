@@ -0,0 +1,162 @@
+use crate::{checked_state_transition, Error};
+use essential_check::solution::CheckPredicateConfig;
+use essential_server_types::SolutionRejection;
+use essential_state_read_vm::StateRead;
+use essential_storage::Storage;
+use essential_transaction_storage::{Transaction, TransactionStorage};
+use essential_types::{
+    predicate::Predicate, solution::Solution, ContentAddress, Hash, Key, PredicateAddress, Word,
+};
+use futures::TryStreamExt;
+use std::{collections::HashMap, sync::Arc};
+
+/// The keys mutated by the solutions in the pool.
+///
+/// Submissions hold the lock from the admission check until the solution is
+/// in the pool so two conflicting solutions can't both be admitted.
+/// The index is built from the pool the first time it is used and kept up to date
+/// as solutions leave the pool. It is rebuilt after solutions return to the pool.
+#[derive(Clone, Default)]
+pub(crate) struct PendingPool(Arc<tokio::sync::Mutex<Pending>>);
+
+#[derive(Default)]
+struct Pending {
+    /// Whether the index matches the pool.
+    synced: bool,
+    /// The keys each solution in the pool mutates.
+    solutions: HashMap<Hash, Vec<(ContentAddress, Key)>>,
+    /// The solution that mutates each key and the value it mutates it to.
+    mutations: HashMap<(ContentAddress, Key), (Hash, Vec<Word>)>,
+}
+
+impl PendingPool {
+    /// Forget solutions that have left the pool.
+    pub(crate) async fn remove(&self, hashes: impl IntoIterator<Item = &Hash>) {
+        let mut pending = self.0.lock().await;
+        if pending.synced {
+            for hash in hashes {
+                pending.remove(hash);
+            }
+        }
+    }
+
+    /// Rebuild the index from the pool the next time it is used.
+    ///
+    /// Used when solutions return to the pool.
+    pub(crate) async fn invalidate(&self) {
+        *self.0.lock().await = Pending::default();
+    }
+}
+
+impl Pending {
+    /// Build the index from the solutions in the pool.
+    async fn sync<S>(&mut self, storage: &S) -> anyhow::Result<()>
+    where
+        S: Storage + Clone + Send + Sync + 'static,
+    {
+        *self = Pending::default();
+        let mut pool = Box::pin(storage.clone().stream_solutions_pool(None));
+        while let Some(solution) = pool.try_next().await.map_err(Error::storage)? {
+            self.insert(essential_hash::hash(&solution), &solution);
+        }
+        self.synced = true;
+        Ok(())
+    }
+
+    fn insert(&mut self, hash: Hash, solution: &Solution) {
+        let mut keys = Vec::new();
+        for data in &solution.data {
+            let contract = &data.predicate_to_solve.contract;
+            for mutation in &data.state_mutations {
+                let key = (contract.clone(), mutation.key.clone());
+                self.mutations
+                    .insert(key.clone(), (hash, mutation.value.clone()));
+                keys.push(key);
+            }
+        }
+        self.solutions.insert(hash, keys);
+    }
+
+    fn remove(&mut self, hash: &Hash) {
+        for key in self.solutions.remove(hash).into_iter().flatten() {
+            if self.mutations.get(&key).is_some_and(|(h, _)| h == hash) {
+                self.mutations.remove(&key);
+            }
+        }
+    }
+}
+
+/// Check a validated solution against the pool and the state and add it to the pool.
+///
+/// The solution is rejected if it is already in the pool, if it mutates a key
+/// that a solution in the pool also mutates or if it fails its state transition
+/// on top of the current state with the mutations of the pool applied.
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all, err(level=tracing::Level::DEBUG)))]
+pub(crate) async fn admit_solution<S>(
+    storage: &S,
+    pending: &PendingPool,
+    solution: Solution,
+    contract: &HashMap<PredicateAddress, Arc<Predicate>>,
+    config: Arc<CheckPredicateConfig>,
+) -> anyhow::Result<ContentAddress>
+where
+    S: Storage + StateRead + Clone + Send + Sync + 'static,
+    <S as StateRead>::Future: Send,
+    <S as StateRead>::Error: Send,
+{
+    let mut pending = pending.0.lock().await;
+    if !pending.synced {
+        pending.sync(storage).await?;
+    }
+
+    let hash = essential_hash::hash(&solution);
+    if pending.solutions.contains_key(&hash) {
+        return Err(Error::Rejected(SolutionRejection::Duplicate).into());
+    }
+    for (contract, key) in mutated_keys(&solution) {
+        if let Some((pending_hash, _)) = pending.mutations.get(&(contract.clone(), key.clone())) {
+            let rejection = SolutionRejection::ConflictingMutation {
+                solution: ContentAddress(*pending_hash),
+                contract: contract.clone(),
+                key: key.clone(),
+            };
+            return Err(Error::Rejected(rejection).into());
+        }
+    }
+
+    let mut pre_state = storage.clone().transaction();
+    for ((contract, key), (_, value)) in &pending.mutations {
+        pre_state.apply_state(contract, key.clone(), value.clone());
+    }
+    check_transition(&pre_state, &solution, contract, config).await?;
+
+    let address = super::insert_solution(storage, solution.clone()).await?;
+    pending.insert(hash, &solution);
+    Ok(address)
+}
+
+/// Dry run the state transition of the solution on top of the pre state.
+async fn check_transition<S>(
+    pre_state: &TransactionStorage<S>,
+    solution: &Solution,
+    contract: &HashMap<PredicateAddress, Arc<Predicate>>,
+    config: Arc<CheckPredicateConfig>,
+) -> anyhow::Result<()>
+where
+    S: Storage + StateRead + Clone + Send + Sync + 'static,
+{
+    let solution = Arc::new(solution.clone());
+    match checked_state_transition(pre_state, solution, contract, config).await {
+        Ok(_) => Ok(()),
+        Err(err) => Err(Error::Rejected(SolutionRejection::Failed(err.to_string())).into()),
+    }
+}
+
+/// The contract and key of every mutation in the solution.
+fn mutated_keys(solution: &Solution) -> impl Iterator<Item = (&ContentAddress, &Key)> {
+    solution.data.iter().flat_map(|data| {
+        data.state_mutations
+            .iter()
+            .map(|mutation| (&data.predicate_to_solve.contract, &mutation.key))
+    })
+}
@@ -1,3 +1,4 @@
+use crate::Error;
 use essential_storage::Storage;
 use essential_types::{predicate::Predicate, solution::Solution, PredicateAddress};
 use std::{collections::HashMap, sync::Arc};
@@ -18,19 +19,19 @@ where
                 contract.insert(address, Arc::new(predicate));
             }
             Ok(None) => {
-                anyhow::bail!(
+                return Err(Error::NotFound(format!(
                     "Failed to retrieve contract from storage. contract: {}, predicate: {}",
-                    address.contract,
-                    address.predicate
-                );
+                    address.contract, address.predicate
+                ))
+                .into());
             }
             Err(err) => {
-                anyhow::bail!(
+                return Err(Error::storage(anyhow::anyhow!(
                     "Failed to retrieve contract from storage. contract: {}, predicate: {}. Error {}",
                     address.contract,
                     address.predicate,
                     err
-                );
+                )));
             }
         }
     }
@@ -1,2 +1,3 @@
+mod admission;
 mod read;
 mod submit;
@@ -0,0 +1,136 @@
+use crate::{
+    solution::{admission::PendingPool, submit_admitted_solution},
+    test_utils::{counter_predicate, counter_solution, deploy_predicate, sanity_solution},
+    Error,
+};
+use essential_server_types::SolutionRejection;
+use essential_storage::{failed_solution::SolutionFailReason, Storage};
+use essential_types::ContentAddress;
+
+fn rejection(err: anyhow::Error) -> SolutionRejection {
+    match err.downcast_ref::<Error>() {
+        Some(Error::Rejected(rejection)) => rejection.clone(),
+        _ => panic!("expected a rejection, got {err:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_admit_solution() {
+    let (solution, storage) = sanity_solution().await;
+    let pending = PendingPool::default();
+    submit_admitted_solution(&storage, &pending, solution.clone(), Default::default())
+        .await
+        .unwrap();
+    let result = storage.list_solutions_pool(None).await.unwrap();
+    assert_eq!(result, vec![solution.clone()]);
+
+    let err = submit_admitted_solution(&storage, &pending, solution, Default::default())
+        .await
+        .unwrap_err();
+    assert_eq!(rejection(err), SolutionRejection::Duplicate);
+}
+
+#[tokio::test]
+async fn test_reject_conflicting_mutation() {
+    let (predicate_address, storage) = deploy_predicate(counter_predicate(1)).await;
+    let pending = PendingPool::default();
+    let solution = counter_solution(predicate_address.clone(), 1).await;
+    submit_admitted_solution(&storage, &pending, solution.clone(), Default::default())
+        .await
+        .unwrap();
+
+    let mut conflicting = solution.clone();
+    conflicting.data[0].decision_variables[0].push(0);
+    let err = submit_admitted_solution(&storage, &pending, conflicting, Default::default())
+        .await
+        .unwrap_err();
+    assert_eq!(
+        rejection(err),
+        SolutionRejection::ConflictingMutation {
+            solution: ContentAddress(essential_hash::hash(&solution)),
+            contract: predicate_address.contract,
+            key: vec![0, 0, 0, 0],
+        }
+    );
+    assert_eq!(storage.list_solutions_pool(None).await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_reject_failed_solution() {
+    let (predicate_address, storage) = deploy_predicate(counter_predicate(1)).await;
+    let pending = PendingPool::default();
+
+    // The counter starts at zero so can't be set to three.
+    let invalid = counter_solution(predicate_address, 3).await;
+    let err = submit_admitted_solution(&storage, &pending, invalid, Default::default())
+        .await
+        .unwrap_err();
+    assert!(matches!(rejection(err), SolutionRejection::Failed(_)));
+    assert!(storage.list_solutions_pool(None).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_admit_after_solution_leaves_pool() {
+    let (predicate_address, storage) = deploy_predicate(counter_predicate(1)).await;
+    let solution = counter_solution(predicate_address.clone(), 1).await;
+
+    // Solutions already in the pool are indexed on first use.
+    storage
+        .insert_solution_into_pool(solution.clone())
+        .await
+        .unwrap();
+    let pending = PendingPool::default();
+    let mut conflicting = solution.clone();
+    conflicting.data[0].decision_variables[0].push(0);
+    let err = submit_admitted_solution(&storage, &pending, conflicting.clone(), Default::default())
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        rejection(err),
+        SolutionRejection::ConflictingMutation { .. }
+    ));
+
+    // Once the solution leaves the pool its key is free again.
+    let hash = essential_hash::hash(&solution);
+    storage
+        .move_solutions_to_failed(&[(hash, SolutionFailReason::Evicted)])
+        .await
+        .unwrap();
+    pending.remove([&hash]).await;
+    submit_admitted_solution(&storage, &pending, conflicting.clone(), Default::default())
+        .await
+        .unwrap();
+    assert_eq!(
+        storage.list_solutions_pool(None).await.unwrap(),
+        vec![conflicting]
+    );
+}
+
+#[tokio::test]
+async fn test_admit_conflicting_solutions_at_once() {
+    let (predicate_address, storage) = deploy_predicate(counter_predicate(1)).await;
+    let pending = PendingPool::default();
+    let solutions: Vec<_> = futures::future::join_all((0..4).map(|i| {
+        let predicate_address = predicate_address.clone();
+        async move {
+            let mut solution = counter_solution(predicate_address, 1).await;
+            solution.data[0].decision_variables[0].push(i);
+            solution
+        }
+    }))
+    .await;
+
+    let results =
+        futures::future::join_all(solutions.into_iter().map(|solution| {
+            let storage = storage.clone();
+            let pending = pending.clone();
+            async move {
+                submit_admitted_solution(&storage, &pending, solution, Default::default()).await
+            }
+        }))
+        .await;
+
+    // Only one of the solutions that mutate the same key is admitted.
+    assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+    assert_eq!(storage.list_solutions_pool(None).await.unwrap().len(), 1);
+}
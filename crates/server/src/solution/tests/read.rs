@@ -25,3 +25,16 @@ async fn test_fail_to_retrieve_contract() {
         .unwrap();
     validate_contract(&solution, &contract).unwrap();
 }
+
+#[tokio::test]
+async fn test_missing_contract_is_not_found() {
+    let (mut solution, storage) = sanity_solution().await;
+    solution.data[0].predicate_to_solve = PredicateAddress::empty();
+    let err = read_contract_from_storage(&solution, &storage)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<crate::Error>(),
+        Some(crate::Error::NotFound(_))
+    ));
+}
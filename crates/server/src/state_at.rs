@@ -0,0 +1,82 @@
+use crate::Error;
+use essential_storage::{BlockHeader, QueryState, StateStorage, Storage};
+use essential_types::{ContentAddress, Key, Word};
+
+#[cfg(test)]
+mod tests;
+
+/// A read only view of the state as it was at the end of a block.
+#[derive(Clone)]
+pub struct StateAt<S> {
+    storage: S,
+    block_number: u64,
+}
+
+impl<S> StateAt<S> {
+    /// View the state at the end of the given block.
+    pub fn new(storage: S, block_number: u64) -> Self {
+        Self {
+            storage,
+            block_number,
+        }
+    }
+}
+
+/// Get the header of a block that must exist.
+pub async fn block_header<S>(storage: &S, block_number: u64) -> anyhow::Result<BlockHeader>
+where
+    S: Storage,
+{
+    storage
+        .list_block_headers(Some(block_number), None)
+        .await
+        .map_err(Error::storage)?
+        .into_iter()
+        .next()
+        .filter(|header| header.number == block_number)
+        .ok_or_else(|| Error::NotFound(format!("Block {block_number} not found")).into())
+}
+
+impl<S> QueryState for StateAt<S>
+where
+    S: Storage + Sync,
+{
+    async fn query_state(&self, address: &ContentAddress, key: &Key) -> anyhow::Result<Vec<Word>> {
+        self.storage
+            .query_state_at(address, key, self.block_number)
+            .await
+    }
+
+    async fn query_state_range(
+        &self,
+        address: &ContentAddress,
+        start_key: &Key,
+        end_key: Option<&Key>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<(Key, Vec<Word>)>> {
+        self.storage
+            .query_state_range_at(address, start_key, end_key, limit, self.block_number)
+            .await
+    }
+}
+
+impl<S> StateStorage for StateAt<S>
+where
+    S: Storage + Sync,
+{
+    async fn update_state(
+        &self,
+        _address: &ContentAddress,
+        _key: &Key,
+        _value: Vec<Word>,
+    ) -> anyhow::Result<Vec<Word>> {
+        anyhow::bail!("The state at a past block can't be updated")
+    }
+
+    async fn update_state_batch<U>(&self, _updates: U) -> anyhow::Result<Vec<Vec<Word>>>
+    where
+        U: IntoIterator<Item = (ContentAddress, Key, Vec<Word>)> + Send,
+    {
+        anyhow::bail!("The state at a past block can't be updated")
+    }
+}
@@ -0,0 +1,86 @@
+use super::*;
+use crate::test_utils::deploy_predicate;
+use essential_storage::CommitData;
+use essential_types::{predicate::Predicate, solution::Solution};
+use std::time::Duration;
+use test_utils::{empty::Empty, solution_with_all_inputs};
+
+async fn commit<S: Storage>(
+    storage: &S,
+    solution: Solution,
+    number: u64,
+    state_updates: Vec<(ContentAddress, Key, Vec<Word>)>,
+) {
+    let hash = essential_hash::hash(&solution);
+    storage.insert_solution_into_pool(solution).await.unwrap();
+    let data = CommitData {
+        failed: &[],
+        solved: &[hash],
+        state_updates: Box::new(state_updates.into_iter()),
+        block_number: number,
+        block_timestamp: Duration::from_secs(number + 1),
+        gas_used: 0,
+    };
+    storage.commit_block(data).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_block_header() {
+    let (address, storage) = deploy_predicate(Predicate::empty()).await;
+    let err = block_header(&storage, 0).await.unwrap_err();
+    assert!(matches!(err.downcast_ref(), Some(Error::NotFound(_))));
+
+    let updates = vec![(address.contract, vec![0], vec![1])];
+    commit(&storage, solution_with_all_inputs(0), 0, updates).await;
+    let header = block_header(&storage, 0).await.unwrap();
+    assert_eq!(header.number, 0);
+
+    let err = block_header(&storage, 1).await.unwrap_err();
+    assert!(matches!(err.downcast_ref(), Some(Error::NotFound(_))));
+}
+
+#[tokio::test]
+async fn test_state_at() {
+    let (address, storage) = deploy_predicate(Predicate::empty()).await;
+    let address = address.contract;
+    let key = vec![0];
+
+    commit(
+        &storage,
+        solution_with_all_inputs(0),
+        0,
+        vec![(address.clone(), key.clone(), vec![1])],
+    )
+    .await;
+    commit(
+        &storage,
+        solution_with_all_inputs(1),
+        1,
+        vec![(address.clone(), key.clone(), vec![2])],
+    )
+    .await;
+
+    for (block, expected) in [(0, vec![1]), (1, vec![2])] {
+        let state = StateAt::new(storage.clone(), block);
+        assert_eq!(state.query_state(&address, &key).await.unwrap(), expected);
+    }
+
+    let state = StateAt::new(storage.clone(), 0);
+    let r = state
+        .query_state_range(&address, &vec![], None, 10)
+        .await
+        .unwrap();
+    assert_eq!(r, vec![(key.clone(), vec![1])]);
+
+    // Past state is read only.
+    let state = StateAt::new(storage.clone(), 0);
+    state
+        .update_state(&address, &key, vec![3])
+        .await
+        .unwrap_err();
+    state
+        .update_state_batch(vec![(address.clone(), key.clone(), vec![3])])
+        .await
+        .unwrap_err();
+    assert_eq!(storage.query_state(&address, &key).await.unwrap(), vec![2]);
+}
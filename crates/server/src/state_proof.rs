@@ -0,0 +1,150 @@
+use crate::{state_at::block_header, Error};
+use essential_lock::StdLock;
+use essential_server_types::{state_root::StateTree, QueryStateProof};
+use essential_storage::{BlockHeader, Storage};
+use essential_types::{ContentAddress, Key};
+use std::{collections::VecDeque, sync::Arc};
+
+#[cfg(test)]
+mod tests;
+
+/// The number of block state trees kept for proofs.
+///
+/// Trees share the nodes that didn't change between blocks
+/// so a tree costs little more than the state its block changed.
+const CACHED_TREES: usize = 16;
+
+/// The number of trees that can be built from the full state of a block at once.
+const MAX_TREE_BUILDS: usize = 1;
+
+/// State trees of recent blocks to prove state against.
+#[derive(Clone)]
+pub(crate) struct StateTrees {
+    /// Trees by block number, most recently used last.
+    trees: Arc<StdLock<VecDeque<(u64, StateTree)>>>,
+    /// Limits building trees from the full state of a block.
+    builds: Arc<tokio::sync::Semaphore>,
+}
+
+impl Default for StateTrees {
+    fn default() -> Self {
+        Self {
+            trees: Arc::new(StdLock::new(VecDeque::new())),
+            builds: Arc::new(tokio::sync::Semaphore::new(MAX_TREE_BUILDS)),
+        }
+    }
+}
+
+/// Query the value of a key along with a proof against the state root of a block.
+///
+/// The latest block is used if no block number is given.
+pub async fn query_state_proof<S>(
+    storage: &S,
+    trees: &StateTrees,
+    address: &ContentAddress,
+    key: &Key,
+    block_number: Option<u64>,
+) -> anyhow::Result<QueryStateProof>
+where
+    S: Storage,
+{
+    let block_number = match block_number {
+        Some(block_number) => block_number,
+        None => match storage.get_latest_block().await.map_err(Error::storage)? {
+            Some(block) => block.number as u64,
+            None => return Err(Error::NotFound("There are no blocks yet".to_string()).into()),
+        },
+    };
+    let header = block_header(storage, block_number).await?;
+    let tree = trees.tree(storage, &header).await?;
+
+    let value = storage
+        .query_state_at(address, key, block_number)
+        .await
+        .map_err(Error::storage)?;
+    let proof = tree.proof(address, key);
+    Ok(QueryStateProof {
+        block_number,
+        state_root: header.state_root,
+        value,
+        proof,
+    })
+}
+
+impl StateTrees {
+    /// The state tree of the block with the given header.
+    ///
+    /// A cached tree is only used if its root matches the header so
+    /// trees of blocks that were reverted are never used.
+    /// The tree of the block after a cached tree is built by applying the
+    /// block's state changes. Otherwise the tree is built from the block's full state.
+    async fn tree<S>(&self, storage: &S, header: &BlockHeader) -> anyhow::Result<StateTree>
+    where
+        S: Storage,
+    {
+        if let Some(tree) = self.get(header) {
+            return Ok(tree);
+        }
+
+        if let Some(mut tree) = header.number.checked_sub(1).and_then(|n| self.get_any(n)) {
+            let diff = storage
+                .get_block_state_diff(header.number)
+                .await
+                .map_err(Error::storage)?;
+            for change in diff {
+                tree.update(&change.address, &change.key, &change.new_value);
+            }
+            if tree.root() == header.state_root {
+                self.insert(header.number, tree.clone());
+                return Ok(tree);
+            }
+        }
+
+        // Only one request builds a tree from the full state at a time
+        // and later requests for the same block use the cached tree.
+        let _permit = self.builds.acquire().await?;
+        if let Some(tree) = self.get(header) {
+            return Ok(tree);
+        }
+        let state = storage
+            .list_state_at(header.number)
+            .await
+            .map_err(Error::storage)?;
+        let tree = StateTree::from_state(state.iter().map(|(a, k, v)| (a, k, v)));
+        self.insert(header.number, tree.clone());
+        Ok(tree)
+    }
+
+    /// The cached tree of the block if its root matches the header.
+    fn get(&self, header: &BlockHeader) -> Option<StateTree> {
+        self.get_any(header.number)
+            .filter(|tree| tree.root() == header.state_root)
+    }
+
+    /// The cached tree of the block, which may be from a reverted block.
+    fn get_any(&self, block_number: u64) -> Option<StateTree> {
+        self.trees.apply(|trees| {
+            let i = trees.iter().position(|(n, _)| *n == block_number)?;
+            let entry = trees.remove(i)?;
+            let tree = entry.1.clone();
+            trees.push_back(entry);
+            Some(tree)
+        })
+    }
+
+    fn insert(&self, block_number: u64, tree: StateTree) {
+        self.trees.apply(|trees| {
+            trees.retain(|(n, _)| *n != block_number);
+            if trees.len() >= CACHED_TREES {
+                trees.pop_front();
+            }
+            trees.push_back((block_number, tree));
+        })
+    }
+
+    #[cfg(test)]
+    fn cached(&self) -> Vec<u64> {
+        self.trees
+            .apply(|trees| trees.iter().map(|(n, _)| *n).collect())
+    }
+}
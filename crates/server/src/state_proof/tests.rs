@@ -0,0 +1,188 @@
+use super::*;
+use crate::test_utils::deploy_predicate;
+use essential_server_types::verify_state_proof;
+use essential_storage::{CommitData, StateStorage};
+use essential_types::{predicate::Predicate, solution::Solution, Word};
+use std::time::Duration;
+use test_utils::{empty::Empty, solution_with_all_inputs};
+
+async fn commit<S: Storage>(
+    storage: &S,
+    solution: Solution,
+    number: u64,
+    state_updates: Vec<(ContentAddress, Key, Vec<Word>)>,
+) {
+    let hash = essential_hash::hash(&solution);
+    storage.insert_solution_into_pool(solution).await.unwrap();
+    let data = CommitData {
+        failed: &[],
+        solved: &[hash],
+        state_updates: Box::new(state_updates.into_iter()),
+        block_number: number,
+        block_timestamp: Duration::from_secs(number + 1),
+        gas_used: 0,
+    };
+    storage.commit_block(data).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_query_state_proof() {
+    let (address, storage) = deploy_predicate(Predicate::empty()).await;
+    let address = address.contract;
+    let trees = StateTrees::default();
+
+    let err = query_state_proof(&storage, &trees, &address, &vec![0], None)
+        .await
+        .unwrap_err();
+    assert!(matches!(err.downcast_ref(), Some(Error::NotFound(_))));
+
+    let updates = (0..10)
+        .map(|i| (address.clone(), vec![i], vec![i + 1]))
+        .collect();
+    commit(&storage, solution_with_all_inputs(0), 0, updates).await;
+
+    // Present and missing keys at the latest block.
+    for key in [vec![3], vec![20]] {
+        let r = query_state_proof(&storage, &trees, &address, &key, None)
+            .await
+            .unwrap();
+        assert_eq!(r.block_number, 0);
+        let expected: Vec<Word> = if key[0] < 10 {
+            vec![key[0] + 1]
+        } else {
+            vec![]
+        };
+        assert_eq!(r.value, expected);
+        assert!(verify_state_proof(
+            &r.state_root,
+            &address,
+            &key,
+            &r.value,
+            &r.proof
+        ));
+    }
+
+    // The block number can be given.
+    let r = query_state_proof(&storage, &trees, &address, &vec![3], Some(0))
+        .await
+        .unwrap();
+    assert_eq!(r.value, vec![4]);
+
+    let err = query_state_proof(&storage, &trees, &address, &vec![3], Some(1))
+        .await
+        .unwrap_err();
+    assert!(matches!(err.downcast_ref(), Some(Error::NotFound(_))));
+
+    // Old blocks can still be proven once the state moves on.
+    commit(
+        &storage,
+        solution_with_all_inputs(1),
+        1,
+        vec![(address.clone(), vec![3], vec![100])],
+    )
+    .await;
+    for (block, expected) in [(0, vec![4]), (1, vec![100])] {
+        let r = query_state_proof(&storage, &trees, &address, &vec![3], Some(block))
+            .await
+            .unwrap();
+        assert_eq!(r.block_number, block);
+        assert_eq!(r.value, expected);
+        assert!(verify_state_proof(
+            &r.state_root,
+            &address,
+            &vec![3],
+            &r.value,
+            &r.proof
+        ));
+    }
+
+    // State updated outside of a block belongs to the next block.
+    storage
+        .update_state(&address, &vec![3], vec![])
+        .await
+        .unwrap();
+    let r = query_state_proof(&storage, &trees, &address, &vec![3], None)
+        .await
+        .unwrap();
+    assert_eq!(r.value, vec![100]);
+    assert!(verify_state_proof(
+        &r.state_root,
+        &address,
+        &vec![3],
+        &r.value,
+        &r.proof
+    ));
+}
+
+#[tokio::test]
+async fn test_state_trees() {
+    let (address, storage) = deploy_predicate(Predicate::empty()).await;
+    let address = address.contract;
+    let trees = StateTrees::default();
+    let prove = |block| {
+        let (storage, trees, address) = (&storage, &trees, &address);
+        async move {
+            let r = query_state_proof(storage, trees, address, &vec![0], Some(block))
+                .await
+                .unwrap();
+            assert!(verify_state_proof(
+                &r.state_root,
+                address,
+                &vec![0],
+                &r.value,
+                &r.proof
+            ));
+            r.value
+        }
+    };
+
+    commit(
+        &storage,
+        solution_with_all_inputs(0),
+        0,
+        vec![(address.clone(), vec![0], vec![1])],
+    )
+    .await;
+    assert_eq!(prove(0).await, vec![1]);
+    assert_eq!(trees.cached(), vec![0]);
+
+    // The next block's tree is built from the cached tree and is cached too.
+    commit(
+        &storage,
+        solution_with_all_inputs(1),
+        1,
+        vec![(address.clone(), vec![0], vec![2])],
+    )
+    .await;
+    assert_eq!(prove(1).await, vec![2]);
+    assert_eq!(trees.cached(), vec![0, 1]);
+
+    // A reverted block's tree isn't used for the block that replaces it.
+    storage.revert_to_block(0).await.unwrap();
+    commit(
+        &storage,
+        solution_with_all_inputs(2),
+        1,
+        vec![(address.clone(), vec![0], vec![3])],
+    )
+    .await;
+    assert_eq!(prove(1).await, vec![3]);
+    assert_eq!(trees.cached(), vec![0, 1]);
+    assert_eq!(prove(0).await, vec![1]);
+    assert_eq!(trees.cached(), vec![1, 0]);
+
+    // Only the most recently used trees are kept.
+    for block in 2..CACHED_TREES as u64 + 2 {
+        commit(
+            &storage,
+            solution_with_all_inputs(block as usize + 1),
+            block,
+            vec![(address.clone(), vec![0], vec![block as Word + 2])],
+        )
+        .await;
+        assert_eq!(prove(block).await, vec![block as Word + 2]);
+    }
+    let cached = trees.cached();
+    assert_eq!(cached.len(), CACHED_TREES);
+    assert!(!cached.contains(&1));
+}
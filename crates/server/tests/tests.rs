@@ -1,6 +1,6 @@
 use std::time::Duration;
 
-use essential_server::{SolutionOutcome, StateRead, Storage};
+use essential_server::{Fifo, SolutionOutcome, StateRead, Storage};
 use essential_types::{predicate::Predicate, PredicateAddress};
 use test_dbs::create_test;
 use test_utils::{empty::Empty, sign_contract_with_random_keypair, solution_with_predicate};
@@ -24,8 +24,9 @@ where
     let server = essential_server::Essential::new(s, Default::default(), Default::default());
     let config = essential_server::Config {
         run_loop_interval: Duration::from_millis(100),
+        ..Default::default()
     };
-    let handle = server.clone().spawn(config).unwrap();
+    let handle = server.clone().spawn(config, Fifo).unwrap();
 
     let solution = solution_with_predicate(predicate_address);
     let solution_hash = essential_hash::hash(&solution);
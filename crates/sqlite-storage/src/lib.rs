@@ -0,0 +1,748 @@
+#![deny(missing_docs)]
+//! # SQLite storage
+//! This uses an embedded SQLite database file to store data.
+//!
+//! The schema and queries are shared with the rqlite storage.
+
+use essential_lock::StdLock;
+use essential_server_types::state_root::StateTree;
+use essential_state_read_vm::StateRead;
+use essential_storage::{
+    failed_solution::{CheckOutcome, FailedSolution, SolutionFailReason, SolutionOutcomes},
+    key_range, BlockHeader, CommitData, QueryState, StateChange, StateStorage, Storage,
+};
+use essential_types::{
+    contract::{Contract, SignedContract},
+    predicate::Predicate,
+    solution::Solution,
+    Block, ContentAddress, Hash, Key, PredicateAddress, Word,
+};
+use futures::{FutureExt, StreamExt};
+use rusqlite::Connection;
+use std::{path::Path, pin::Pin, sync::Arc, time::Duration};
+use thiserror::Error;
+
+mod values;
+
+/// Amount of values returned in a single page.
+const PAGE_SIZE: usize = 100;
+
+/// Includes an SQL statement from the shared `sql` directory.
+macro_rules! include_sql {
+    ($name:expr) => {
+        include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/sql/", $name))
+    };
+}
+
+pub(crate) use include_sql;
+
+#[derive(Clone)]
+/// SQLite storage
+/// Safe to clone as all clones share the same database connection.
+pub struct SqliteStorage {
+    db: Arc<StdLock<Database>>,
+    streams: essential_storage::streams::Notify,
+}
+
+struct Database {
+    conn: Connection,
+    /// The tree of the current state.
+    ///
+    /// Built when the database is opened and updated by each state write.
+    state_tree: StateTree,
+}
+
+/// Encodes a type into blob data.
+fn encode<T: serde::Serialize>(value: &T) -> Vec<u8> {
+    postcard::to_allocvec(value).expect("How can this fail?")
+}
+
+/// Decodes a blob into a type.
+fn decode<T: serde::de::DeserializeOwned>(value: &[u8]) -> anyhow::Result<T> {
+    Ok(postcard::from_bytes(value)?)
+}
+
+/// Encodes a state key into blob data that sorts in the same order as the key.
+fn encode_key(key: &Key) -> Vec<u8> {
+    essential_storage::encode_sortable_key(key)
+}
+
+/// Decodes a state key blob.
+fn decode_key(value: &[u8]) -> anyhow::Result<Key> {
+    essential_storage::decode_sortable_key(value)
+}
+
+impl SqliteStorage {
+    /// Open the SQLite database at the given path.
+    /// The database file and tables are created if they don't exist.
+    pub fn new(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let conn = Connection::open(path)?;
+        // Write ahead logging makes commits cheaper and keeps
+        // the database consistent if the process crashes.
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+        Self::with_connection(conn)
+    }
+
+    /// Create a SQLite database that only lives in memory.
+    /// This is useful for testing.
+    pub fn in_memory() -> anyhow::Result<Self> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(conn: Connection) -> anyhow::Result<Self> {
+        conn.pragma_update(None, "foreign_keys", true)?;
+        create_tables(&conn)?;
+        let state_tree = values::state_tree(&conn)?;
+        Ok(Self {
+            db: Arc::new(StdLock::new(Database { conn, state_tree })),
+            streams: essential_storage::streams::Notify::new(),
+        })
+    }
+
+    /// Run a function with the database connection.
+    ///
+    /// SQLite calls block so they are run on the blocking thread pool.
+    async fn apply<F, R>(&self, f: F) -> anyhow::Result<R>
+    where
+        F: FnOnce(&mut Connection) -> anyhow::Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || db.apply(|db| f(&mut db.conn))).await?
+    }
+
+    /// Run a function inside a database transaction.
+    ///
+    /// The transaction is only committed if the function succeeds.
+    async fn transaction<F, R>(&self, f: F) -> anyhow::Result<R>
+    where
+        F: FnOnce(&rusqlite::Transaction) -> anyhow::Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        self.apply(|conn| {
+            let tx = conn.transaction()?;
+            let r = f(&tx)?;
+            tx.commit()?;
+            Ok(r)
+        })
+        .await
+    }
+
+    /// Run a function that writes state inside a database transaction.
+    ///
+    /// The function updates a copy of the state tree which only replaces
+    /// the tree if the transaction is committed.
+    async fn state_transaction<F, R>(&self, f: F) -> anyhow::Result<R>
+    where
+        F: FnOnce(&rusqlite::Transaction, &mut StateTree) -> anyhow::Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || {
+            db.apply(|db| {
+                let mut tree = db.state_tree.clone();
+                let tx = db.conn.transaction()?;
+                let r = f(&tx, &mut tree)?;
+                tx.commit()?;
+                db.state_tree = tree;
+                Ok(r)
+            })
+        })
+        .await?
+    }
+}
+
+/// Create all the tables.
+/// This is idempotent.
+fn create_tables(conn: &Connection) -> anyhow::Result<()> {
+    let creates = [
+        include_sql!("create/predicates.sql"),
+        include_sql!("create/contracts.sql"),
+        include_sql!("create/contract_pairing.sql"),
+        include_sql!("create/solutions.sql"),
+        include_sql!("create/solutions_pool.sql"),
+        include_sql!("create/solved.sql"),
+        include_sql!("create/contract_state.sql"),
+        include_sql!("create/contract_state_history.sql"),
+        include_sql!("create/batch.sql"),
+        include_sql!("create/failed_solutions.sql"),
+        include_sql!("index/solved_batch_id.sql"),
+        include_sql!("index/solved_content_hash.sql"),
+        include_sql!("index/failed_solutions_content_hash.sql"),
+    ];
+    for sql in creates {
+        conn.execute(sql, [])?;
+    }
+    Ok(())
+}
+
+impl StateStorage for SqliteStorage {
+    async fn update_state(
+        &self,
+        address: &ContentAddress,
+        key: &Key,
+        value: Vec<Word>,
+    ) -> anyhow::Result<Vec<Word>> {
+        let address = encode(address);
+        let key = encode_key(key);
+        self.state_transaction(move |tx, tree| {
+            let (existing, changed) = values::update_state(tx, tree, &address, &key, &value)?;
+            anyhow::ensure!(changed || value.is_empty(), "No state for address");
+            Ok(existing)
+        })
+        .await
+    }
+
+    async fn update_state_batch<U>(&self, updates: U) -> anyhow::Result<Vec<Vec<Word>>>
+    where
+        U: IntoIterator<Item = (ContentAddress, Key, Vec<Word>)> + Send,
+    {
+        let updates = encode_updates(updates);
+
+        // Return early if there are no updates.
+        if updates.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        self.state_transaction(move |tx, tree| values::update_state_batch(tx, tree, &updates))
+            .await
+    }
+}
+
+impl QueryState for SqliteStorage {
+    async fn query_state(&self, address: &ContentAddress, key: &Key) -> anyhow::Result<Vec<Word>> {
+        let address = encode(address);
+        let key = encode_key(key);
+        self.apply(move |conn| values::get_state(conn, &address, &key))
+            .await
+    }
+
+    async fn query_state_batch(
+        &self,
+        address: &ContentAddress,
+        keys: &[Key],
+    ) -> anyhow::Result<Vec<Vec<Word>>> {
+        let address = encode(address);
+        let keys: Vec<_> = keys.iter().map(encode_key).collect();
+        self.apply(move |conn| {
+            keys.iter()
+                .map(|key| values::get_state(conn, &address, key))
+                .collect()
+        })
+        .await
+    }
+
+    async fn query_state_range(
+        &self,
+        address: &ContentAddress,
+        start_key: &Key,
+        end_key: Option<&Key>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<(Key, Vec<Word>)>> {
+        let address = encode(address);
+        let start_key = encode_key(start_key);
+        let end_key = end_key.map(encode_key);
+        self.apply(move |conn| {
+            values::list_state_range(conn, &address, &start_key, end_key.as_deref(), limit)
+        })
+        .await
+    }
+}
+
+impl Storage for SqliteStorage {
+    async fn insert_contract(&self, mut contract: SignedContract) -> anyhow::Result<()> {
+        // Get the time this contract was created at.
+        let created_at = std::time::SystemTime::now();
+        let unix_time = created_at.duration_since(std::time::UNIX_EPOCH)?;
+
+        contract.contract.sort_by_key(essential_hash::content_addr);
+
+        // Encode the data into blobs.
+        let contract_addr = essential_hash::contract_addr::from_contract(&contract.contract);
+        let address = encode(&contract_addr);
+        let signature = encode(&contract.signature);
+        let salt = encode(&contract.contract.salt);
+        let predicates: Vec<_> = contract
+            .contract
+            .iter()
+            .map(|predicate| {
+                (
+                    encode(predicate),
+                    encode(&essential_hash::content_addr(predicate)),
+                )
+            })
+            .collect();
+
+        let r = self
+            .transaction(move |tx| {
+                values::insert_contract(tx, &address, &salt, &signature, unix_time, &predicates)
+            })
+            .await;
+
+        // Notify the streams of the new contract.
+        self.streams.notify_new_contracts();
+
+        r
+    }
+
+    async fn insert_solution_into_pool(&self, solution: Solution) -> anyhow::Result<()> {
+        let hash = encode(&essential_hash::hash(&solution));
+        let solution = encode(&solution);
+        let unix_time = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+        self.transaction(move |tx| {
+            tx.execute(include_sql!("insert/solutions.sql"), (&hash, &solution))?;
+            tx.execute(
+                include_sql!("insert/solutions_pool.sql"),
+                (&hash, unix_time.as_secs(), unix_time.subsec_nanos()),
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn move_solutions_to_solved(
+        &self,
+        _block_number: u64,
+        block_timestamp: Duration,
+        solutions: &[Hash],
+    ) -> anyhow::Result<()> {
+        if solutions.is_empty() {
+            return Ok(());
+        }
+
+        let hashes: Vec<_> = solutions.iter().map(encode).collect();
+        let r = self
+            .state_transaction(move |tx, tree| {
+                values::move_solutions_to_solved(tx, tree, block_timestamp, 0, &hashes)
+            })
+            .await;
+
+        if r.is_ok() {
+            // Notify the streams of the new blocks.
+            self.streams.notify_new_blocks();
+            self.streams.notify_new_outcomes(solutions.iter().copied());
+        }
+
+        r
+    }
+
+    async fn move_solutions_to_failed(
+        &self,
+        solutions: &[(Hash, SolutionFailReason)],
+    ) -> anyhow::Result<()> {
+        if solutions.is_empty() {
+            return Ok(());
+        }
+
+        let failed = encode_failed(solutions);
+        let unix_time = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+        let r = self
+            .transaction(move |tx| values::move_solutions_to_failed(tx, unix_time, &failed))
+            .await;
+        self.streams
+            .notify_new_outcomes(solutions.iter().map(|(hash, _)| *hash));
+        r
+    }
+
+    async fn get_predicate(&self, address: &PredicateAddress) -> anyhow::Result<Option<Predicate>> {
+        let contract = encode(&address.contract);
+        let predicate = encode(&address.predicate);
+        self.apply(move |conn| values::get_predicate(conn, &contract, &predicate))
+            .await
+    }
+
+    async fn get_contract(
+        &self,
+        address: &ContentAddress,
+    ) -> anyhow::Result<Option<SignedContract>> {
+        let address = encode(address);
+        self.apply(move |conn| values::get_contract(conn, &address))
+            .await
+    }
+
+    async fn list_contracts(
+        &self,
+        time_range: Option<std::ops::Range<Duration>>,
+        page: Option<usize>,
+    ) -> anyhow::Result<Vec<Contract>> {
+        let page = page.unwrap_or(0);
+        self.apply(move |conn| values::list_contracts(conn, time_range, page, PAGE_SIZE))
+            .await
+    }
+
+    fn subscribe_contracts(
+        self,
+        start_time: Option<Duration>,
+        start_page: Option<usize>,
+    ) -> impl futures::Stream<Item = anyhow::Result<Contract>> + Send + 'static {
+        let new_contracts = self.streams.subscribe_contracts();
+        let init = essential_storage::streams::StreamState::new(start_page, start_time, None);
+        futures::stream::unfold(init, move |state| {
+            let storage = self.clone();
+            essential_storage::streams::next_data(
+                new_contracts.clone(),
+                state,
+                PAGE_SIZE,
+                // List contracts expects a Range not a RangeFrom so we give it a range from
+                // start till the end of time.
+                move |get| {
+                    let storage = storage.clone();
+                    async move {
+                        storage
+                            .list_contracts(get.time.map(|s| s..Duration::MAX), Some(get.page))
+                            .await
+                    }
+                },
+            )
+        })
+        .flat_map(futures::stream::iter)
+    }
+
+    async fn list_solutions_pool(&self, page: Option<usize>) -> anyhow::Result<Vec<Solution>> {
+        let page = page.unwrap_or(0);
+        self.apply(move |conn| values::list_solutions_pool(conn, page, PAGE_SIZE))
+            .await
+    }
+
+    fn stream_solutions_pool(
+        self,
+        limit: Option<usize>,
+    ) -> impl futures::Stream<Item = anyhow::Result<Solution>> + Send + 'static {
+        essential_storage::streams::page_by_id(limit, PAGE_SIZE, move |after_id, limit| {
+            let storage = self.clone();
+            async move {
+                storage
+                    .apply(move |conn| values::list_solutions_pool_after(conn, after_id, limit))
+                    .await
+            }
+        })
+    }
+
+    async fn pool_size(&self) -> anyhow::Result<usize> {
+        self.apply(|conn| values::pool_size(conn)).await
+    }
+
+    async fn is_in_pool(&self, solution_hash: Hash) -> anyhow::Result<bool> {
+        let hash = encode(&solution_hash);
+        self.apply(move |conn| values::is_in_pool(conn, &hash))
+            .await
+    }
+
+    async fn list_failed_solutions_pool(
+        &self,
+        page: Option<usize>,
+    ) -> anyhow::Result<Vec<FailedSolution>> {
+        let page = page.unwrap_or(0);
+        self.apply(move |conn| values::list_failed_solutions(conn, page, PAGE_SIZE))
+            .await
+    }
+
+    async fn list_blocks(
+        &self,
+        time_range: Option<std::ops::Range<Duration>>,
+        block_number: Option<u64>,
+        page: Option<usize>,
+    ) -> anyhow::Result<Vec<Block>> {
+        let page = page.unwrap_or(0);
+        let block_number = block_number.unwrap_or(0);
+        self.apply(move |conn| values::list_blocks(conn, time_range, block_number, page, PAGE_SIZE))
+            .await
+    }
+
+    async fn list_block_headers(
+        &self,
+        block_number: Option<u64>,
+        page: Option<usize>,
+    ) -> anyhow::Result<Vec<BlockHeader>> {
+        let page = page.unwrap_or(0);
+        let block_number = block_number.unwrap_or(0);
+        self.apply(move |conn| values::list_block_headers(conn, block_number, page, PAGE_SIZE))
+            .await
+    }
+
+    fn subscribe_blocks(
+        self,
+        start_time: Option<Duration>,
+        block_number: Option<u64>,
+        start_page: Option<usize>,
+    ) -> impl futures::Stream<Item = anyhow::Result<Block>> + Send + 'static {
+        let new_blocks = self.streams.subscribe_blocks();
+        let init =
+            essential_storage::streams::StreamState::new(start_page, start_time, block_number);
+        futures::stream::unfold(init, move |state| {
+            let storage = self.clone();
+            essential_storage::streams::next_data(
+                new_blocks.clone(),
+                state,
+                PAGE_SIZE,
+                // List blocks expects a Range not a RangeFrom so we give it a range from
+                // start till the end of time.
+                move |get| {
+                    let storage = storage.clone();
+                    async move {
+                        storage
+                            .list_blocks(
+                                get.time.map(|s| s..Duration::MAX),
+                                get.number,
+                                Some(get.page),
+                            )
+                            .await
+                    }
+                },
+            )
+        })
+        .flat_map(futures::stream::iter)
+    }
+
+    fn subscribe_solution_outcomes(
+        self,
+        solution_hashes: Vec<Hash>,
+    ) -> impl futures::Stream<Item = anyhow::Result<(Hash, CheckOutcome)>> + Send + 'static {
+        let new_outcomes = self.streams.subscribe_outcomes();
+        essential_storage::streams::solution_outcomes(new_outcomes, solution_hashes, move |hash| {
+            let storage = self.clone();
+            async move {
+                let outcomes = storage.get_solution(hash).await?;
+                Ok(outcomes
+                    .map(|s| s.outcome.into_iter().zip(s.timestamps).collect())
+                    .unwrap_or_default())
+            }
+        })
+    }
+
+    async fn get_solution(&self, solution_hash: Hash) -> anyhow::Result<Option<SolutionOutcomes>> {
+        let hash = encode(&solution_hash);
+        self.apply(move |conn| values::get_solution(conn, &hash))
+            .await
+    }
+
+    async fn query_state_at(
+        &self,
+        address: &ContentAddress,
+        key: &Key,
+        block_number: u64,
+    ) -> anyhow::Result<Vec<Word>> {
+        let address = encode(address);
+        let key = encode_key(key);
+        self.apply(move |conn| values::get_state_at(conn, &address, &key, block_number))
+            .await
+    }
+
+    async fn query_state_range_at(
+        &self,
+        address: &ContentAddress,
+        start_key: &Key,
+        end_key: Option<&Key>,
+        limit: usize,
+        block_number: u64,
+    ) -> anyhow::Result<Vec<(Key, Vec<Word>)>> {
+        let address = encode(address);
+        let start_key = encode_key(start_key);
+        let end_key = end_key.map(encode_key);
+        self.apply(move |conn| {
+            values::list_state_range_at(
+                conn,
+                &address,
+                &start_key,
+                end_key.as_deref(),
+                limit,
+                block_number,
+            )
+        })
+        .await
+    }
+
+    async fn list_state_at(
+        &self,
+        block_number: u64,
+    ) -> anyhow::Result<Vec<(ContentAddress, Key, Vec<Word>)>> {
+        self.apply(move |conn| values::list_state_at(conn, block_number))
+            .await
+    }
+
+    async fn get_block_state_diff(&self, block_number: u64) -> anyhow::Result<Vec<StateChange>> {
+        self.apply(move |conn| values::get_block_state_diff(conn, block_number))
+            .await
+    }
+
+    async fn get_latest_block(&self) -> anyhow::Result<Option<Block>> {
+        self.apply(|conn| values::get_latest_block(conn)).await
+    }
+
+    async fn prune_failed_solutions(&self, older_than: Duration) -> anyhow::Result<()> {
+        self.apply(move |conn| {
+            conn.execute(
+                include_sql!("update/prune_failed.sql"),
+                [older_than.as_secs()],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn prune_excess_failed_solutions(&self, max_count: usize) -> anyhow::Result<()> {
+        self.apply(move |conn| {
+            conn.execute(include_sql!("update/prune_excess_failed.sql"), [max_count])?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list_prunable_failed_solutions(
+        &self,
+        older_than: Duration,
+        max_count: Option<usize>,
+    ) -> anyhow::Result<Vec<FailedSolution>> {
+        self.apply(move |conn| values::list_prunable_failed_solutions(conn, older_than, max_count))
+            .await
+    }
+
+    async fn expire_solutions(&self, added_before: Duration) -> anyhow::Result<Vec<Hash>> {
+        let unix_time = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+        let r = self
+            .transaction(move |tx| {
+                let hashes = values::list_expired_solutions_pool(tx, added_before)?;
+                fail_pool_solutions(tx, unix_time, hashes, SolutionFailReason::Expired)
+            })
+            .await;
+        if let Ok(expired) = &r {
+            self.streams.notify_new_outcomes(expired.iter().copied());
+        }
+        r
+    }
+
+    async fn evict_solutions(&self, capacity: usize) -> anyhow::Result<Vec<Hash>> {
+        let unix_time = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+        let r = self
+            .transaction(move |tx| {
+                let hashes = values::list_evicted_solutions_pool(tx, capacity)?;
+                fail_pool_solutions(tx, unix_time, hashes, SolutionFailReason::Evicted)
+            })
+            .await;
+        if let Ok(evicted) = &r {
+            self.streams.notify_new_outcomes(evicted.iter().copied());
+        }
+        r
+    }
+
+    fn commit_block(
+        &self,
+        data: CommitData,
+    ) -> impl std::future::Future<Output = anyhow::Result<()>> + Send {
+        let CommitData {
+            failed,
+            solved,
+            state_updates,
+            block_number: _,
+            block_timestamp,
+            gas_used,
+        } = data;
+
+        let outcomes: Vec<_> = solved
+            .iter()
+            .copied()
+            .chain(failed.iter().map(|(hash, _)| *hash))
+            .collect();
+
+        // The commit data borrows so encode everything up front.
+        let failed = encode_failed(failed);
+        let solved: Vec<_> = solved.iter().map(encode).collect();
+        let updates = encode_updates(state_updates);
+        let new_block = !solved.is_empty();
+        let unix_time = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH);
+
+        async move {
+            let unix_time = unix_time?;
+            let r = self
+                .state_transaction(move |tx, tree| {
+                    values::move_solutions_to_failed(tx, unix_time, &failed)?;
+                    values::update_state_batch(tx, tree, &updates)?;
+                    values::move_solutions_to_solved(tx, tree, block_timestamp, gas_used, &solved)?;
+                    Ok(())
+                })
+                .await;
+
+            if new_block {
+                // Notify the streams of the new blocks.
+                self.streams.notify_new_blocks();
+            }
+            self.streams.notify_new_outcomes(outcomes);
+
+            r
+        }
+    }
+
+    async fn revert_to_block(&self, block_number: u64) -> anyhow::Result<()> {
+        let unix_time = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+        let r = self
+            .state_transaction(move |tx, tree| {
+                values::revert_to_block(tx, tree, block_number, unix_time)
+            })
+            .await;
+
+        // Blocks were removed.
+        // Solutions lose outcomes but don't gain any so outcome streams aren't notified.
+        self.streams.notify_new_blocks();
+
+        r
+    }
+}
+
+/// Move solutions from the pool to the failed state for the same reason.
+///
+/// Returns the decoded hashes in the same order.
+fn fail_pool_solutions(
+    conn: &Connection,
+    failed_at: Duration,
+    hashes: Vec<Vec<u8>>,
+    reason: SolutionFailReason,
+) -> anyhow::Result<Vec<Hash>> {
+    let reason = encode(&reason);
+    let failed: Vec<_> = hashes
+        .into_iter()
+        .map(|hash| (hash, reason.clone()))
+        .collect();
+    values::move_solutions_to_failed(conn, failed_at, &failed)?;
+    failed.iter().map(|(hash, _)| decode(hash)).collect()
+}
+
+/// Encode the hashes and reasons of failed solutions.
+fn encode_failed(solutions: &[(Hash, SolutionFailReason)]) -> Vec<(Vec<u8>, Vec<u8>)> {
+    solutions
+        .iter()
+        .map(|(hash, reason)| (encode(hash), encode(reason)))
+        .collect()
+}
+
+/// Encode the address and key of state updates.
+///
+/// Values are left as words so deletes can be detected.
+fn encode_updates<U>(updates: U) -> Vec<(Vec<u8>, Vec<u8>, Vec<Word>)>
+where
+    U: IntoIterator<Item = (ContentAddress, Key, Vec<Word>)>,
+{
+    updates
+        .into_iter()
+        .map(|(address, key, value)| (encode(&address), encode_key(&key), value))
+        .collect()
+}
+
+/// Error for SQLite read.
+#[derive(Debug, Error)]
+pub enum SqliteError {
+    /// Error during read
+    #[error("failed to read")]
+    ReadError(#[from] anyhow::Error),
+}
+
+impl StateRead for SqliteStorage {
+    type Error = SqliteError;
+
+    type Future =
+        Pin<Box<dyn std::future::Future<Output = Result<Vec<Vec<Word>>, Self::Error>> + Send>>;
+
+    fn key_range(&self, contract_addr: ContentAddress, key: Key, num_words: usize) -> Self::Future {
+        let storage = self.clone();
+        async move { key_range(&storage, contract_addr, key, num_words).await }.boxed()
+    }
+}
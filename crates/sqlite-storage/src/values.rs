@@ -0,0 +1,801 @@
+//! Queries against the SQLite connection and the mapping of their rows to values.
+
+use std::{collections::BTreeMap, ops::Range, time::Duration};
+
+use anyhow::bail;
+use essential_server_types::state_root::StateTree;
+use essential_storage::{
+    failed_solution::{CheckOutcome, FailedSolution, SolutionOutcomes},
+    BlockHeader, StateChange,
+};
+use essential_types::{
+    contract::{Contract, SignedContract},
+    predicate::Predicate,
+    solution::Solution,
+    Block, ContentAddress, Key, Word,
+};
+use rusqlite::{named_params, Connection, OptionalExtension, Params, Row};
+
+use crate::{decode, decode_key, encode, include_sql};
+
+/// SQLite integers are signed so clamp any larger values.
+fn int(value: u64) -> i64 {
+    i64::try_from(value).unwrap_or(i64::MAX)
+}
+
+/// Run a query and map each row.
+fn rows<P, T, F>(conn: &Connection, sql: &str, params: P, f: F) -> anyhow::Result<Vec<T>>
+where
+    P: Params,
+    F: FnMut(&Row) -> rusqlite::Result<T>,
+{
+    let mut stmt = conn.prepare_cached(sql)?;
+    let rows = stmt.query_map(params, f)?.collect::<Result<_, _>>()?;
+    Ok(rows)
+}
+
+/// Run a query that returns at most a single blob.
+fn single_blob<P>(conn: &Connection, sql: &str, params: P) -> anyhow::Result<Option<Vec<u8>>>
+where
+    P: Params,
+{
+    let mut stmt = conn.prepare_cached(sql)?;
+    Ok(stmt.query_row(params, |row| row.get(0)).optional()?)
+}
+
+pub fn get_state(conn: &Connection, address: &[u8], key: &[u8]) -> anyhow::Result<Vec<Word>> {
+    match single_blob(conn, include_sql!("query/get_state.sql"), (address, key))? {
+        Some(value) => decode(&value),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Get the value of a key at the end of a block.
+pub fn get_state_at(
+    conn: &Connection,
+    address: &[u8],
+    key: &[u8],
+    block_number: u64,
+) -> anyhow::Result<Vec<Word>> {
+    let sql = include_sql!("query/get_state_at.sql");
+    match single_blob(conn, sql, (address, key, int(block_number)))? {
+        Some(value) => decode(&value),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// The values of a contract's keys from `start_key` up to but not including `end_key` in key order.
+pub fn list_state_range(
+    conn: &Connection,
+    address: &[u8],
+    start_key: &[u8],
+    end_key: Option<&[u8]>,
+    limit: usize,
+) -> anyhow::Result<Vec<(Key, Vec<Word>)>> {
+    let limit = int(limit as u64);
+    let row = |row: &Row| Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?));
+    let rows = match end_key {
+        Some(end_key) => rows(
+            conn,
+            include_sql!("query/list_state_range.sql"),
+            (address, start_key, end_key, limit),
+            row,
+        )?,
+        None => rows(
+            conn,
+            include_sql!("query/list_state_from.sql"),
+            (address, start_key, limit),
+            row,
+        )?,
+    };
+    rows.into_iter()
+        .map(|(key, value)| Ok((decode_key(&key)?, decode(&value)?)))
+        .collect()
+}
+
+/// A range of the state of a contract as it was at the end of a block.
+///
+/// Keys cleared by the block are skipped so they don't count towards the limit.
+pub fn list_state_range_at(
+    conn: &Connection,
+    address: &[u8],
+    start_key: &[u8],
+    end_key: Option<&[u8]>,
+    limit: usize,
+    block_number: u64,
+) -> anyhow::Result<Vec<(Key, Vec<Word>)>> {
+    let limit = int(limit as u64);
+    let block_number = int(block_number);
+    let empty = encode(&Vec::<Word>::new());
+    let row = |row: &Row| Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?));
+    let rows = match end_key {
+        Some(end_key) => rows(
+            conn,
+            include_sql!("query/list_state_range_at.sql"),
+            (address, start_key, end_key, block_number, empty, limit),
+            row,
+        )?,
+        None => rows(
+            conn,
+            include_sql!("query/list_state_from_at.sql"),
+            (address, start_key, block_number, empty, limit),
+            row,
+        )?,
+    };
+    rows.into_iter()
+        .map(|(key, value)| Ok((decode_key(&key)?, decode(&value)?)))
+        .collect()
+}
+
+/// Update or delete a single value.
+///
+/// The write is recorded in the state history as part of the next block
+/// and applied to the state tree.
+///
+/// Returns the existing value and whether a row was changed.
+pub fn update_state(
+    conn: &Connection,
+    tree: &mut StateTree,
+    address: &[u8],
+    key: &[u8],
+    value: &[Word],
+) -> anyhow::Result<(Vec<Word>, bool)> {
+    let existing = get_state(conn, address, key)?;
+    let changed = if value.is_empty() {
+        conn.prepare_cached(include_sql!("update/delete_state.sql"))?
+            .execute((address, key))?
+    } else {
+        conn.prepare_cached(include_sql!("update/update_state.sql"))?
+            .execute((key, encode(&value), address))?
+    };
+    conn.prepare_cached(include_sql!("insert/state_history.sql"))?
+        .execute((key, encode(&value), address))?;
+    if changed == 1 {
+        tree.update(&decode(address)?, &decode_key(key)?, &value.to_vec());
+    }
+    Ok((existing, changed == 1))
+}
+
+/// Apply a batch of state updates returning the existing values.
+pub fn update_state_batch(
+    conn: &Connection,
+    tree: &mut StateTree,
+    updates: &[(Vec<u8>, Vec<u8>, Vec<Word>)],
+) -> anyhow::Result<Vec<Vec<Word>>> {
+    updates
+        .iter()
+        .map(|(address, key, value)| Ok(update_state(conn, tree, address, key, value)?.0))
+        .collect()
+}
+
+pub fn insert_contract(
+    conn: &Connection,
+    address: &[u8],
+    salt: &[u8],
+    signature: &[u8],
+    created_at: Duration,
+    predicates: &[(Vec<u8>, Vec<u8>)],
+) -> anyhow::Result<()> {
+    conn.execute(
+        include_sql!("insert/contracts.sql"),
+        (
+            address,
+            salt,
+            signature,
+            int(created_at.as_secs()),
+            created_at.subsec_nanos(),
+        ),
+    )?;
+    for (predicate, hash) in predicates {
+        conn.prepare_cached(include_sql!("insert/predicates.sql"))?
+            .execute((predicate, hash))?;
+        conn.prepare_cached(include_sql!("insert/contract_pairing.sql"))?
+            .execute((address, hash))?;
+    }
+    Ok(())
+}
+
+/// All the state currently in the database.
+pub fn list_state(conn: &Connection) -> anyhow::Result<Vec<(ContentAddress, Key, Vec<Word>)>> {
+    decode_state(rows(
+        conn,
+        include_sql!("query/list_state.sql"),
+        [],
+        state_row,
+    )?)
+}
+
+/// All the state at the end of a block.
+///
+/// Keys that were cleared by then aren't included.
+pub fn list_state_at(
+    conn: &Connection,
+    block_number: u64,
+) -> anyhow::Result<Vec<(ContentAddress, Key, Vec<Word>)>> {
+    let sql = include_sql!("query/list_state_at.sql");
+    let mut state = decode_state(rows(conn, sql, [int(block_number)], state_row)?)?;
+    state.retain(|(_, _, value)| !value.is_empty());
+    Ok(state)
+}
+
+/// The state changed by a block sorted by address then key.
+pub fn get_block_state_diff(
+    conn: &Connection,
+    block_number: u64,
+) -> anyhow::Result<Vec<StateChange>> {
+    let sql = include_sql!("query/get_block_state_diff.sql");
+    let rows = rows(conn, sql, [int(block_number)], |row| {
+        Ok((
+            row.get::<_, Vec<u8>>(0)?,
+            row.get::<_, Vec<u8>>(1)?,
+            row.get::<_, Option<Vec<u8>>>(2)?,
+            row.get::<_, Vec<u8>>(3)?,
+        ))
+    })?;
+    let mut diff = rows
+        .into_iter()
+        .map(|(address, key, old_value, new_value)| {
+            Ok(StateChange {
+                address: decode(&address)?,
+                key: decode_key(&key)?,
+                old_value: old_value
+                    .map(|v| decode(&v))
+                    .transpose()?
+                    .unwrap_or_default(),
+                new_value: decode(&new_value)?,
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    diff.sort();
+    Ok(diff)
+}
+
+fn state_row(row: &Row) -> rusqlite::Result<(Vec<u8>, Vec<u8>, Vec<u8>)> {
+    Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+}
+
+fn decode_state(
+    rows: Vec<(Vec<u8>, Vec<u8>, Vec<u8>)>,
+) -> anyhow::Result<Vec<(ContentAddress, Key, Vec<Word>)>> {
+    rows.into_iter()
+        .map(|(address, key, value)| Ok((decode(&address)?, decode_key(&key)?, decode(&value)?)))
+        .collect()
+}
+
+/// Build the tree of all the state currently in the database.
+pub fn state_tree(conn: &Connection) -> anyhow::Result<StateTree> {
+    let state = list_state(conn)?;
+    Ok(StateTree::from_state(
+        state
+            .iter()
+            .map(|(address, key, value)| (address, key, value)),
+    ))
+}
+
+/// Create a new batch and move the solutions from the pool into it.
+///
+/// The batch commits to the root of the state tree so any state updates
+/// for the block must be applied first.
+///
+/// The batch is removed again if none of the solutions were in the pool.
+pub fn move_solutions_to_solved(
+    conn: &Connection,
+    tree: &StateTree,
+    block_timestamp: Duration,
+    gas_used: u64,
+    hashes: &[Vec<u8>],
+) -> anyhow::Result<()> {
+    if hashes.is_empty() {
+        return Ok(());
+    }
+    conn.execute(
+        include_sql!("insert/batch.sql"),
+        (
+            int(block_timestamp.as_secs()),
+            block_timestamp.subsec_nanos(),
+            int(gas_used),
+            encode(&tree.root()),
+        ),
+    )?;
+    for hash in hashes {
+        conn.prepare_cached(include_sql!("insert/copy_to_solved.sql"))?
+            .execute([hash])?;
+        conn.prepare_cached(include_sql!("update/delete_from_solutions_pool.sql"))?
+            .execute([hash])?;
+    }
+    conn.execute(include_sql!("update/delete_empty_batch.sql"), [])?;
+    Ok(())
+}
+
+pub fn move_solutions_to_failed(
+    conn: &Connection,
+    failed_at: Duration,
+    failed: &[(Vec<u8>, Vec<u8>)],
+) -> anyhow::Result<()> {
+    for (hash, reason) in failed {
+        conn.prepare_cached(include_sql!("insert/copy_to_failed.sql"))?
+            .execute((
+                reason,
+                int(failed_at.as_secs()),
+                failed_at.subsec_nanos(),
+                hash,
+            ))?;
+        conn.prepare_cached(include_sql!("update/delete_from_solutions_pool.sql"))?
+            .execute([hash])?;
+    }
+    Ok(())
+}
+
+/// Revert to the end of a block.
+///
+/// Restores the state written after the block, moves the solutions
+/// of later batches back to the pool and deletes the later batches.
+/// The reverted solutions are added to the pool at `now`.
+pub fn revert_to_block(
+    conn: &Connection,
+    tree: &mut StateTree,
+    block_number: u64,
+    now: Duration,
+) -> anyhow::Result<()> {
+    let block_number = int(block_number);
+    let sql = include_sql!("query/list_reverted_state.sql");
+    let reverted = rows(conn, sql, [block_number, block_number], |row| {
+        Ok((
+            row.get::<_, Vec<u8>>(0)?,
+            row.get::<_, Vec<u8>>(1)?,
+            row.get::<_, Option<Vec<u8>>>(2)?,
+        ))
+    })?;
+    for (address, key, value) in reverted {
+        let value: Vec<Word> = value.map(|v| decode(&v)).transpose()?.unwrap_or_default();
+        tree.update(&decode(&address)?, &decode_key(&key)?, &value);
+        if value.is_empty() {
+            conn.prepare_cached(include_sql!("update/delete_state.sql"))?
+                .execute((&address, &key))?;
+        } else {
+            conn.prepare_cached(include_sql!("update/update_state.sql"))?
+                .execute((&key, encode(&value), &address))?;
+        }
+    }
+    conn.execute(
+        include_sql!("update/delete_state_history_after.sql"),
+        [block_number],
+    )?;
+
+    // Batch ids start at one.
+    let batch_id = block_number.saturating_add(1);
+    conn.execute(
+        include_sql!("insert/copy_solved_to_pool.sql"),
+        (int(now.as_secs()), now.subsec_nanos(), batch_id),
+    )?;
+    conn.execute(include_sql!("update/delete_solved_after.sql"), [batch_id])?;
+    conn.execute(include_sql!("update/delete_batches_after.sql"), [batch_id])?;
+    Ok(())
+}
+
+pub fn get_predicate(
+    conn: &Connection,
+    contract: &[u8],
+    predicate: &[u8],
+) -> anyhow::Result<Option<Predicate>> {
+    single_blob(
+        conn,
+        include_sql!("query/get_predicate.sql"),
+        (contract, predicate),
+    )?
+    .map(|predicate| decode(&predicate))
+    .transpose()
+}
+
+pub fn get_contract(conn: &Connection, address: &[u8]) -> anyhow::Result<Option<SignedContract>> {
+    let Some(signature) = single_blob(
+        conn,
+        include_sql!("query/get_contract_signature.sql"),
+        [address],
+    )?
+    else {
+        return Ok(None);
+    };
+    let Some(salt) = single_blob(conn, include_sql!("query/get_contract_salt.sql"), [address])?
+    else {
+        bail!("missing salt for contract");
+    };
+    let predicates = rows(
+        conn,
+        include_sql!("query/get_contract.sql"),
+        [address],
+        |row| row.get::<_, Vec<u8>>(0),
+    )?
+    .iter()
+    .map(|predicate| decode(predicate))
+    .collect::<anyhow::Result<_>>()?;
+
+    Ok(Some(SignedContract {
+        contract: Contract {
+            predicates,
+            salt: decode(&salt)?,
+        },
+        signature: decode(&signature)?,
+    }))
+}
+
+pub fn list_contracts(
+    conn: &Connection,
+    time_range: Option<Range<Duration>>,
+    page: usize,
+    page_size: usize,
+) -> anyhow::Result<Vec<Contract>> {
+    let id_and_blob = |row: &Row| Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?));
+    let (salts, predicates) = match time_range {
+        Some(range) => {
+            let params = named_params! {
+                ":start_seconds": int(range.start.as_secs()),
+                ":start_nanos": range.start.subsec_nanos(),
+                ":end_seconds": int(range.end.as_secs()),
+                ":end_nanos": range.end.subsec_nanos(),
+                ":page_size": page_size,
+                ":page_number": page,
+            };
+            (
+                rows(
+                    conn,
+                    include_sql!("query/list_contract_salts_by_time.sql"),
+                    params,
+                    id_and_blob,
+                )?,
+                rows(
+                    conn,
+                    include_sql!("query/list_contracts_by_time.sql"),
+                    params,
+                    id_and_blob,
+                )?,
+            )
+        }
+        None => {
+            let params = named_params! {
+                ":page_size": page_size,
+                ":page_number": page,
+            };
+            (
+                rows(
+                    conn,
+                    include_sql!("query/list_contract_salts.sql"),
+                    params,
+                    id_and_blob,
+                )?,
+                rows(
+                    conn,
+                    include_sql!("query/list_contracts.sql"),
+                    params,
+                    id_and_blob,
+                )?,
+            )
+        }
+    };
+
+    let salts = salts.into_iter().collect::<BTreeMap<_, _>>();
+
+    // The predicates are ordered by contract_id then by predicate id.
+    // Group them into their respective contracts.
+    let mut contracts = BTreeMap::<_, Vec<Predicate>>::new();
+    for (contract_id, predicate) in predicates {
+        contracts
+            .entry(contract_id)
+            .or_default()
+            .push(decode(&predicate)?);
+    }
+
+    contracts
+        .into_iter()
+        .map(|(contract_id, predicates)| {
+            let Some(salt) = salts.get(&contract_id) else {
+                bail!("missing salt for contract_id");
+            };
+            Ok(Contract {
+                salt: decode(salt)?,
+                predicates,
+            })
+        })
+        .collect()
+}
+
+pub fn list_solutions_pool(
+    conn: &Connection,
+    page: usize,
+    page_size: usize,
+) -> anyhow::Result<Vec<Solution>> {
+    rows(
+        conn,
+        include_sql!("query/list_solutions_pool.sql"),
+        named_params! {
+            ":page_size": page_size,
+            ":page_number": page,
+        },
+        |row| row.get::<_, Vec<u8>>(0),
+    )?
+    .iter()
+    .map(|solution| decode(solution))
+    .collect()
+}
+
+pub fn list_solutions_pool_after(
+    conn: &Connection,
+    after_id: i64,
+    limit: usize,
+) -> anyhow::Result<Vec<(i64, Solution)>> {
+    rows(
+        conn,
+        include_sql!("query/list_solutions_pool_after.sql"),
+        named_params! {
+            ":after_id": after_id,
+            ":limit": limit,
+        },
+        |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?)),
+    )?
+    .into_iter()
+    .map(|(id, solution)| Ok((id, decode(&solution)?)))
+    .collect()
+}
+
+/// The number of solutions in the pool.
+pub fn pool_size(conn: &Connection) -> anyhow::Result<usize> {
+    let size: i64 = conn.query_row(include_sql!("query/count_solutions_pool.sql"), [], |row| {
+        row.get(0)
+    })?;
+    Ok(usize::try_from(size)?)
+}
+
+/// Whether the solution with the hash is in the pool.
+pub fn is_in_pool(conn: &Connection, hash: &[u8]) -> anyhow::Result<bool> {
+    let mut stmt = conn.prepare_cached(include_sql!("query/get_pool_solution.sql"))?;
+    Ok(stmt.exists([hash])?)
+}
+
+/// List the hashes of the solutions added to the pool before `added_before`, oldest first.
+pub fn list_expired_solutions_pool(
+    conn: &Connection,
+    added_before: Duration,
+) -> anyhow::Result<Vec<Vec<u8>>> {
+    rows(
+        conn,
+        include_sql!("query/list_expired_solutions_pool.sql"),
+        named_params! {
+            ":seconds": int(added_before.as_secs()),
+            ":nanos": added_before.subsec_nanos(),
+        },
+        |row| row.get::<_, Vec<u8>>(0),
+    )
+}
+
+/// List the hashes of the oldest solutions in the pool beyond `capacity`, oldest first.
+pub fn list_evicted_solutions_pool(
+    conn: &Connection,
+    capacity: usize,
+) -> anyhow::Result<Vec<Vec<u8>>> {
+    rows(
+        conn,
+        include_sql!("query/list_evicted_solutions_pool.sql"),
+        [int(capacity as u64)],
+        |row| row.get::<_, Vec<u8>>(0),
+    )
+}
+
+pub fn list_failed_solutions(
+    conn: &Connection,
+    page: usize,
+    page_size: usize,
+) -> anyhow::Result<Vec<FailedSolution>> {
+    failed_solutions(
+        conn,
+        include_sql!("query/list_failed_solutions.sql"),
+        named_params! {
+            ":page_size": page_size,
+            ":page_number": page,
+        },
+    )
+}
+
+/// List the failed solutions that failed before `older_than`
+/// or are older than the newest `max_count`.
+pub fn list_prunable_failed_solutions(
+    conn: &Connection,
+    older_than: Duration,
+    max_count: Option<usize>,
+) -> anyhow::Result<Vec<FailedSolution>> {
+    failed_solutions(
+        conn,
+        include_sql!("query/list_prunable_failed.sql"),
+        named_params! {
+            ":older_than": int(older_than.as_secs()),
+            // A negative limit is no limit.
+            ":max_count": max_count.map_or(-1, |c| int(c as u64)),
+        },
+    )
+}
+
+/// Run a query for rows of failed solutions.
+fn failed_solutions<P>(
+    conn: &Connection,
+    sql: &str,
+    params: P,
+) -> anyhow::Result<Vec<FailedSolution>>
+where
+    P: Params,
+{
+    rows(conn, sql, params, |row| {
+        Ok((
+            row.get::<_, Vec<u8>>(0)?,
+            row.get::<_, Vec<u8>>(1)?,
+            row.get::<_, u64>(2)?,
+            row.get::<_, u32>(3)?,
+        ))
+    })?
+    .iter()
+    .map(|(solution, reason, secs, nanos)| {
+        Ok(FailedSolution {
+            solution: decode(solution)?,
+            reason: decode(reason)?,
+            timestamp: Duration::new(*secs, *nanos),
+        })
+    })
+    .collect()
+}
+
+pub fn list_blocks(
+    conn: &Connection,
+    time_range: Option<Range<Duration>>,
+    block_number: u64,
+    page: usize,
+    page_size: usize,
+) -> anyhow::Result<Vec<Block>> {
+    let rows = match time_range {
+        Some(range) => rows(
+            conn,
+            include_sql!("query/list_winning_batches_by_time.sql"),
+            named_params! {
+                ":block_number": int(block_number),
+                ":page_size": page_size,
+                ":page_number": page,
+                ":start_seconds": int(range.start.as_secs()),
+                ":start_nanos": range.start.subsec_nanos(),
+                ":end_seconds": int(range.end.as_secs()),
+                ":end_nanos": range.end.subsec_nanos(),
+            },
+            block_row,
+        )?,
+        None => rows(
+            conn,
+            include_sql!("query/list_winning_batches.sql"),
+            named_params! {
+                ":block_number": int(block_number),
+                ":page_size": page_size,
+                ":page_number": page,
+            },
+            block_row,
+        )?,
+    };
+    map_rows_to_blocks(rows)
+}
+
+pub fn list_block_headers(
+    conn: &Connection,
+    block_number: u64,
+    page: usize,
+    page_size: usize,
+) -> anyhow::Result<Vec<BlockHeader>> {
+    rows(
+        conn,
+        include_sql!("query/list_block_headers.sql"),
+        named_params! {
+            ":block_number": int(block_number),
+            ":page_size": page_size,
+            ":page_number": page,
+        },
+        |row| {
+            Ok((
+                row.get::<_, u64>(0)?,
+                row.get::<_, u64>(1)?,
+                row.get::<_, u32>(2)?,
+                row.get::<_, u64>(3)?,
+                row.get::<_, Vec<u8>>(4)?,
+            ))
+        },
+    )?
+    .into_iter()
+    .map(
+        |(batch_id, created_at_secs, created_at_nanos, gas_used, state_root)| {
+            let Some(number) = batch_id.checked_sub(1) else {
+                bail!("batch_id must be greater than 0");
+            };
+            Ok(BlockHeader {
+                number,
+                timestamp: Duration::new(created_at_secs, created_at_nanos),
+                gas_used,
+                state_root: decode(&state_root)?,
+            })
+        },
+    )
+    .collect()
+}
+
+pub fn get_latest_block(conn: &Connection) -> anyhow::Result<Option<Block>> {
+    let rows = rows(
+        conn,
+        include_sql!("query/get_latest_block.sql"),
+        [],
+        block_row,
+    )?;
+    Ok(map_rows_to_blocks(rows)?.into_iter().next())
+}
+
+pub fn get_solution(conn: &Connection, hash: &[u8]) -> anyhow::Result<Option<SolutionOutcomes>> {
+    let Some(solution) = single_blob(conn, include_sql!("query/get_solution.sql"), [hash])? else {
+        return Ok(None);
+    };
+
+    let (outcome, timestamps) = rows(
+        conn,
+        include_sql!("query/get_solution_outcomes.sql"),
+        (hash, hash),
+        |row| {
+            Ok((
+                row.get::<_, Option<u64>>(0)?,
+                row.get::<_, Option<Vec<u8>>>(1)?,
+                row.get::<_, u64>(2)?,
+                row.get::<_, u32>(3)?,
+            ))
+        },
+    )?
+    .into_iter()
+    .map(|(batch_id, reason, secs, nanos)| {
+        let outcome = match (batch_id, reason) {
+            (Some(batch_id), None) => batch_id
+                .checked_sub(1)
+                .map(CheckOutcome::Success)
+                .ok_or_else(|| anyhow::anyhow!("batch_id must be greater than 0"))?,
+            (None, Some(reason)) => CheckOutcome::Fail(decode(&reason)?),
+            _ => bail!("unexpected columns for solution outcome"),
+        };
+        Ok((outcome, Duration::new(secs, nanos)))
+    })
+    .collect::<anyhow::Result<Vec<_>>>()?
+    .into_iter()
+    .unzip();
+
+    Ok(Some(SolutionOutcomes {
+        solution: decode(&solution)?,
+        outcome,
+        timestamps,
+    }))
+}
+
+/// A row of a block query.
+/// Batch id, solution, created at seconds and created at nanos.
+type BlockRow = (u64, Vec<u8>, u64, u32);
+
+fn block_row(row: &Row) -> rusqlite::Result<BlockRow> {
+    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+}
+
+/// Group the solutions of each batch into blocks.
+///
+/// The rows are ordered by batch id.
+fn map_rows_to_blocks(rows: Vec<BlockRow>) -> anyhow::Result<Vec<Block>> {
+    let mut blocks = BTreeMap::<u64, Block>::new();
+    for (batch_id, solution, created_at_secs, created_at_nanos) in rows {
+        let Some(number) = batch_id.checked_sub(1) else {
+            bail!("batch_id must be greater than 0");
+        };
+        let solution = decode(&solution)?;
+        blocks
+            .entry(batch_id)
+            .or_insert_with(|| Block {
+                number: number as Word,
+                timestamp: Duration::new(created_at_secs, created_at_nanos),
+                solutions: Vec::new(),
+            })
+            .solutions
+            .push(solution);
+    }
+    Ok(blocks.into_values().collect())
+}
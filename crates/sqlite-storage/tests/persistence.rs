@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use essential_sqlite_storage::SqliteStorage;
+use essential_storage::{CommitData, QueryState, StateStorage, Storage};
+use essential_types::{contract::Contract, ContentAddress, PredicateAddress};
+use test_utils::{
+    predicate_with_salt, sign_contract_with_random_keypair, solution_with_all_inputs,
+    solution_with_predicate,
+};
+
+#[tokio::test]
+async fn test_reopen_database() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("essential.db");
+
+    let contract = sign_contract_with_random_keypair(vec![predicate_with_salt(0)]);
+    let address = essential_hash::contract_addr::from_contract(&contract.contract);
+    let predicate = PredicateAddress {
+        contract: address.clone(),
+        predicate: ContentAddress(essential_hash::hash(&contract.contract.predicates[0])),
+    };
+    let solution = solution_with_predicate(predicate);
+    let solution_hash = essential_hash::hash(&solution);
+
+    {
+        let storage = SqliteStorage::new(&path).unwrap();
+        storage.insert_contract(contract.clone()).await.unwrap();
+        storage
+            .update_state(&address, &vec![0], vec![42])
+            .await
+            .unwrap();
+        storage
+            .insert_solution_into_pool(solution.clone())
+            .await
+            .unwrap();
+        storage
+            .commit_block(CommitData {
+                block_number: 0,
+                block_timestamp: Duration::from_secs(1),
+                gas_used: 0,
+                failed: &[],
+                solved: &[solution_hash],
+                state_updates: Box::new(std::iter::empty()),
+            })
+            .await
+            .unwrap();
+    }
+
+    let storage = SqliteStorage::new(&path).unwrap();
+    let contracts: Vec<Contract> = storage.list_contracts(None, None).await.unwrap();
+    assert_eq!(contracts, vec![contract.contract]);
+    assert_eq!(
+        storage.query_state(&address, &vec![0]).await.unwrap(),
+        vec![42]
+    );
+    assert!(storage.list_solutions_pool(None).await.unwrap().is_empty());
+
+    let blocks = storage.list_blocks(None, None, None).await.unwrap();
+    assert_eq!(blocks.len(), 1);
+    assert_eq!(blocks[0].number, 0);
+    assert_eq!(blocks[0].solutions, vec![solution]);
+    assert_eq!(blocks[0].timestamp, Duration::from_secs(1));
+
+    // The state tree is rebuilt so a block without state updates has the same root.
+    let solution = solution_with_all_inputs(1);
+    storage
+        .insert_solution_into_pool(solution.clone())
+        .await
+        .unwrap();
+    storage
+        .commit_block(CommitData {
+            block_number: 1,
+            block_timestamp: Duration::from_secs(2),
+            gas_used: 0,
+            failed: &[],
+            solved: &[essential_hash::hash(&solution)],
+            state_updates: Box::new(std::iter::empty()),
+        })
+        .await
+        .unwrap();
+    let headers = storage.list_block_headers(None, None).await.unwrap();
+    assert_eq!(headers.len(), 2);
+    assert_eq!(headers[0].state_root, headers[1].state_root);
+    assert_ne!(headers[0].state_root, [0; 32]);
+}
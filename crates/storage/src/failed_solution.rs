@@ -1,4 +1,4 @@
-use std::fmt::Display;
+use std::{fmt::Display, time::Duration};
 
 use essential_types::solution::Solution;
 use serde::{Deserialize, Serialize};
@@ -10,18 +10,26 @@ pub enum SolutionFailReason {
     ConstraintsFailed(String),
     /// Not composable with other solutions to build a batch.
     NotComposable,
+    /// Used more gas than a single solution is allowed to use.
+    GasLimitExceeded(u64),
+    /// Stayed in the pool for longer than solutions are allowed to.
+    Expired,
+    /// Removed from the pool to make room for newer solutions.
+    Evicted,
 }
 
 /// A failed solution.
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct FailedSolution {
     /// The failed solution.
     pub solution: Solution,
     /// Reason why the solution failed.
     pub reason: SolutionFailReason,
+    /// Time the solution failed.
+    pub timestamp: Duration,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 /// Outcome of a solution check.
 pub enum CheckOutcome {
     /// The solution was successful in this block.
@@ -30,12 +38,17 @@ pub enum CheckOutcome {
     Fail(SolutionFailReason),
 }
 /// A solution with its outcome.
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct SolutionOutcomes {
     /// The solution.
     pub solution: Solution,
-    /// The outcomes of the solution.
+    /// The outcomes of the solution, oldest first.
     pub outcome: Vec<CheckOutcome>,
+    /// The time of each outcome.
+    /// For a success this is the block's timestamp.
+    ///
+    /// Matches `outcome` by index.
+    pub timestamps: Vec<Duration>,
 }
 
 impl Display for SolutionFailReason {
@@ -45,6 +58,11 @@ impl Display for SolutionFailReason {
                 write!(f, "ConstraintsFailed: {}", reason)
             }
             SolutionFailReason::NotComposable => write!(f, "NotComposable"),
+            SolutionFailReason::GasLimitExceeded(gas) => {
+                write!(f, "GasLimitExceeded: used {} gas", gas)
+            }
+            SolutionFailReason::Expired => write!(f, "Expired"),
+            SolutionFailReason::Evicted => write!(f, "Evicted"),
         }
     }
 }
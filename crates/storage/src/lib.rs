@@ -11,13 +11,17 @@ use essential_types::{
     solution::Solution,
     Block, ContentAddress, Hash, Key, PredicateAddress, Word,
 };
-use failed_solution::{FailedSolution, SolutionFailReason, SolutionOutcomes};
+use failed_solution::{CheckOutcome, FailedSolution, SolutionFailReason, SolutionOutcomes};
+use serde::{Deserialize, Serialize};
 
 /// Module for failed solution struct.
 pub mod failed_solution;
 /// Module for streams.
 pub mod streams;
 
+#[cfg(test)]
+mod tests;
+
 /// Data to commit after a block has been built.
 /// This data should all be committed atomically.
 pub struct CommitData<'a> {
@@ -25,6 +29,8 @@ pub struct CommitData<'a> {
     pub block_number: u64,
     /// Block timestamp
     pub block_timestamp: Duration,
+    /// Total gas used by the solved solutions
+    pub gas_used: u64,
     /// Failed solutions
     pub failed: &'a [(Hash, SolutionFailReason)],
     /// Solved solutions
@@ -33,6 +39,34 @@ pub struct CommitData<'a> {
     pub state_updates: Box<dyn Iterator<Item = (ContentAddress, Key, Vec<Word>)> + 'a>,
 }
 
+/// Data recorded about a block in addition to its solutions.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct BlockHeader {
+    /// Block number
+    pub number: u64,
+    /// Block timestamp
+    pub timestamp: Duration,
+    /// Total gas used by the solutions in the block
+    pub gas_used: u64,
+    /// Root of the state after the block is applied.
+    ///
+    /// See `essential_server_types::state_root` for how it is computed.
+    pub state_root: Hash,
+}
+
+/// A change to a single key made by a block.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct StateChange {
+    /// Content address of the contract the state belongs to
+    pub address: ContentAddress,
+    /// The key that changed
+    pub key: Key,
+    /// The value before the block. Empty if the key had no value.
+    pub old_value: Vec<Word>,
+    /// The value after the block. Empty if the key was cleared.
+    pub new_value: Vec<Word>,
+}
+
 /// Storage trait for the Essential platform.
 /// All inserts and updates are idempotent.
 pub trait Storage: StateStorage {
@@ -50,6 +84,9 @@ pub trait Storage: StateStorage {
     ) -> impl Future<Output = anyhow::Result<()>> + Send;
 
     /// Move these solutions from the pool to the solved state.
+    ///
+    /// The block records no gas used as the storage can't know the gas
+    /// the solutions used. Use [`Storage::commit_block`] to record it.
     fn move_solutions_to_solved(
         &self,
         block_number: u64,
@@ -101,6 +138,20 @@ pub trait Storage: StateStorage {
         page: Option<usize>,
     ) -> impl Future<Output = anyhow::Result<Vec<Solution>>> + Send;
 
+    /// Stream the solutions in the pool in the order they were inserted.
+    /// At most `limit` solutions are returned, or the whole pool if `limit` is `None`.
+    /// Unlike [`Storage::subscribe_blocks`] the stream ends once the pool has been read.
+    fn stream_solutions_pool(
+        self,
+        limit: Option<usize>,
+    ) -> impl futures::Stream<Item = anyhow::Result<Solution>> + Send + 'static;
+
+    /// The number of solutions in the pool.
+    fn pool_size(&self) -> impl Future<Output = anyhow::Result<usize>> + Send;
+
+    /// Whether the solution is in the pool.
+    fn is_in_pool(&self, solution_hash: Hash) -> impl Future<Output = anyhow::Result<bool>> + Send;
+
     /// List all failed solutions in the pool.
     fn list_failed_solutions_pool(
         &self,
@@ -115,6 +166,14 @@ pub trait Storage: StateStorage {
         page: Option<usize>,
     ) -> impl Future<Output = anyhow::Result<Vec<Block>>> + Send;
 
+    /// List the headers of all blocks that have been solved.
+    /// This will paginate the results. The page is 0-indexed.
+    fn list_block_headers(
+        &self,
+        block_number: Option<u64>,
+        page: Option<usize>,
+    ) -> impl Future<Output = anyhow::Result<Vec<BlockHeader>>> + Send;
+
     /// Subscribe to new blocks from a given block number or start page or start time.
     /// This will return all the blocks from that point then continue to stream
     /// as new blocks are added.
@@ -125,12 +184,68 @@ pub trait Storage: StateStorage {
         start_page: Option<usize>,
     ) -> impl futures::Stream<Item = anyhow::Result<Block>> + Send + 'static;
 
+    /// Subscribe to the outcomes of the given solutions.
+    ///
+    /// Outcomes that were already recorded are returned first
+    /// followed by each new outcome as it is recorded.
+    fn subscribe_solution_outcomes(
+        self,
+        solution_hashes: Vec<Hash>,
+    ) -> impl futures::Stream<Item = anyhow::Result<(Hash, CheckOutcome)>> + Send + 'static;
+
     /// Get failed solution and its failing reason.
     fn get_solution(
         &self,
         solution_hash: Hash,
     ) -> impl std::future::Future<Output = anyhow::Result<Option<SolutionOutcomes>>> + Send;
 
+    /// Query the value of a key as it was at the end of the given block.
+    ///
+    /// Every state write is versioned by the block it is part of.
+    /// Writes made outside of [`Storage::commit_block`] are part of the next block.
+    ///
+    /// Fails if the storage didn't record the state of the block, such as a block
+    /// committed before the storage was migrated to record state by block.
+    /// The same goes for the other queries of state at a block and for reverts.
+    fn query_state_at(
+        &self,
+        address: &ContentAddress,
+        key: &Key,
+        block_number: u64,
+    ) -> impl std::future::Future<Output = anyhow::Result<Vec<Word>>> + Send;
+
+    /// Query the keys of a content address from `start_key` up to but not including `end_key`
+    /// as they were at the end of the given block.
+    ///
+    /// Keys are ordered as in [`QueryState::query_state_range`].
+    /// Returns at most `limit` keys with their values in key order.
+    /// Keys without a value at the block aren't included.
+    fn query_state_range_at(
+        &self,
+        address: &ContentAddress,
+        start_key: &Key,
+        end_key: Option<&Key>,
+        limit: usize,
+        block_number: u64,
+    ) -> impl std::future::Future<Output = anyhow::Result<Vec<(Key, Vec<Word>)>>> + Send;
+
+    /// List all of the state of every contract as it was at the end of the given block.
+    ///
+    /// Used to build state proofs so isn't paginated.
+    fn list_state_at(
+        &self,
+        block_number: u64,
+    ) -> impl std::future::Future<Output = anyhow::Result<Vec<(ContentAddress, Key, Vec<Word>)>>> + Send;
+
+    /// Get the state changed by a block, sorted by address then key.
+    ///
+    /// Keys written more than once in the block are only listed once
+    /// with their value from before the block and their final value.
+    fn get_block_state_diff(
+        &self,
+        block_number: u64,
+    ) -> impl std::future::Future<Output = anyhow::Result<Vec<StateChange>>> + Send;
+
     /// Get latest block.
     fn get_latest_block(
         &self,
@@ -142,11 +257,54 @@ pub trait Storage: StateStorage {
         older_than: Duration,
     ) -> impl std::future::Future<Output = anyhow::Result<()>> + Send;
 
+    /// Prune the oldest failed solutions so at most `max_count` remain.
+    fn prune_excess_failed_solutions(
+        &self,
+        max_count: usize,
+    ) -> impl std::future::Future<Output = anyhow::Result<()>> + Send;
+
+    /// List the failed solutions that [`Storage::prune_failed_solutions`] with `older_than`
+    /// and [`Storage::prune_excess_failed_solutions`] with `max_count` would prune, oldest first.
+    fn list_prunable_failed_solutions(
+        &self,
+        older_than: Duration,
+        max_count: Option<usize>,
+    ) -> impl std::future::Future<Output = anyhow::Result<Vec<FailedSolution>>> + Send;
+
+    /// Move the solutions that were added to the pool before `added_before`
+    /// to the failed state as [`SolutionFailReason::Expired`].
+    ///
+    /// Returns the hashes of the expired solutions, oldest first.
+    fn expire_solutions(
+        &self,
+        added_before: Duration,
+    ) -> impl std::future::Future<Output = anyhow::Result<Vec<Hash>>> + Send;
+
+    /// Move the oldest solutions in the pool to the failed state as
+    /// [`SolutionFailReason::Evicted`] until at most `capacity` remain.
+    ///
+    /// Returns the hashes of the evicted solutions, oldest first.
+    fn evict_solutions(
+        &self,
+        capacity: usize,
+    ) -> impl std::future::Future<Output = anyhow::Result<Vec<Hash>>> + Send;
+
     /// Commit block data atomically.
     fn commit_block(
         &self,
         data: CommitData,
     ) -> impl std::future::Future<Output = anyhow::Result<()>> + Send;
+
+    /// Revert the chain to the end of the given block atomically.
+    ///
+    /// Every state write after the block is undone, including writes
+    /// made outside of [`Storage::commit_block`] since the block.
+    /// The solutions of later blocks are moved back to the pool
+    /// behind any solutions already in it and the later blocks are deleted.
+    fn revert_to_block(
+        &self,
+        block_number: u64,
+    ) -> impl std::future::Future<Output = anyhow::Result<()>> + Send;
 }
 
 /// Storage trait just for state reads and writes.
@@ -176,26 +334,90 @@ pub trait QueryState {
         address: &ContentAddress,
         key: &Key,
     ) -> impl std::future::Future<Output = anyhow::Result<Vec<Word>>> + Send;
+
+    /// Query the state of a content address at each of the keys.
+    ///
+    /// Returns the values in the same order as the keys.
+    /// By default each key is queried in turn so implementations
+    /// should override this if they can read many keys at once.
+    fn query_state_batch(
+        &self,
+        address: &ContentAddress,
+        keys: &[Key],
+    ) -> impl std::future::Future<Output = anyhow::Result<Vec<Vec<Word>>>> + Send
+    where
+        Self: Sync,
+    {
+        async move {
+            let mut values = Vec::with_capacity(keys.len());
+            for key in keys {
+                values.push(self.query_state(address, key).await?);
+            }
+            Ok(values)
+        }
+    }
+
+    /// Query the keys of a content address from `start_key` up to but not including `end_key`.
+    ///
+    /// Keys are ordered word by word and a key comes before any longer key it is a prefix of.
+    /// No `end_key` means there is no upper bound.
+    ///
+    /// Returns at most `limit` keys with their values in key order.
+    /// Keys without a value aren't included.
+    fn query_state_range(
+        &self,
+        address: &ContentAddress,
+        start_key: &Key,
+        end_key: Option<&Key>,
+        limit: usize,
+    ) -> impl std::future::Future<Output = anyhow::Result<Vec<(Key, Vec<Word>)>>> + Send;
+
+    /// Query the keys of a content address that start with `prefix`.
+    ///
+    /// Returns at most `limit` keys with their values in key order.
+    fn query_state_prefix(
+        &self,
+        address: &ContentAddress,
+        prefix: &Key,
+        limit: usize,
+    ) -> impl std::future::Future<Output = anyhow::Result<Vec<(Key, Vec<Word>)>>> + Send
+    where
+        Self: Sync,
+    {
+        async move {
+            let end_key = prefix_end(prefix);
+            self.query_state_range(address, prefix, end_key.as_ref(), limit)
+                .await
+        }
+    }
 }
 
 /// Get a range of words from the state.
+///
+/// All of the keys are read with a single [`QueryState::query_state_batch`].
 pub async fn key_range<S, E>(
     storage: &S,
     contract_addr: ContentAddress,
-    mut key: Key,
+    key: Key,
     num_words: usize,
 ) -> Result<Vec<Vec<Word>>, E>
 where
-    S: QueryState + Send,
+    S: QueryState + Sync,
     E: From<anyhow::Error>,
 {
-    let mut words = vec![];
-    for _ in 0..num_words {
-        let slot = storage.query_state(&contract_addr, &key).await?;
-        words.push(slot);
-        key = next_key(key).ok_or_else(|| anyhow::anyhow!("Failed to find next key"))?
+    let keys = consecutive_keys(key, num_words)?;
+    Ok(storage.query_state_batch(&contract_addr, &keys).await?)
+}
+
+/// The `num_keys` consecutive keys starting at `key`.
+pub fn consecutive_keys(mut key: Key, num_keys: usize) -> anyhow::Result<Vec<Key>> {
+    let mut keys = Vec::with_capacity(num_keys);
+    for _ in 0..num_keys {
+        let next = next_key(key.clone()).ok_or_else(|| anyhow::anyhow!("Failed to find next key"));
+        keys.push(key);
+        key = next?;
     }
-    Ok(words)
+    Ok(keys)
 }
 
 /// Calculate the next key.
@@ -211,3 +433,47 @@ pub fn next_key(mut key: Key) -> Option<Key> {
     }
     None
 }
+
+/// The first key after every key that starts with `prefix`.
+///
+/// Returns `None` if no key comes after them.
+pub fn prefix_end(prefix: &Key) -> Option<Key> {
+    let mut end = prefix.clone();
+    while let Some(w) = end.pop() {
+        if w != Word::MAX {
+            end.push(w + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+/// Whether `key` is in the range from `start_key` up to but not including `end_key`.
+pub fn key_in_range(key: &Key, start_key: &Key, end_key: Option<&Key>) -> bool {
+    key >= start_key && end_key.is_none_or(|end_key| key < end_key)
+}
+
+/// Encode a key into bytes that sort in the same order as the key.
+///
+/// Each word is big endian with its sign bit flipped so negative words sort first.
+pub fn encode_sortable_key(key: &Key) -> Vec<u8> {
+    key.iter()
+        .flat_map(|w| ((*w as u64) ^ (1 << 63)).to_be_bytes())
+        .collect()
+}
+
+/// Decode a key encoded with [`encode_sortable_key`].
+pub fn decode_sortable_key(bytes: &[u8]) -> anyhow::Result<Key> {
+    anyhow::ensure!(
+        bytes.len().is_multiple_of(8),
+        "invalid key length {}",
+        bytes.len()
+    );
+    Ok(bytes
+        .chunks_exact(8)
+        .map(|chunk| {
+            let word = u64::from_be_bytes(chunk.try_into().expect("chunk is 8 bytes"));
+            (word ^ (1 << 63)) as Word
+        })
+        .collect())
+}
@@ -1,19 +1,34 @@
-use std::time::Duration;
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use essential_types::Hash;
+use tokio::sync::broadcast;
+
+use crate::failed_solution::CheckOutcome;
 
 #[cfg(test)]
 mod tests;
 
-/// Notify that there are new contracts or blocks.
+/// The most solutions a single [`solution_outcomes`] stream can follow.
+pub const MAX_SUBSCRIBED_SOLUTIONS: usize = 128;
+
+/// The number of outcome notifications kept for subscribers that fall behind.
+const OUTCOMES_CAPACITY: usize = 1024;
+
+/// Notify that there are new contracts, blocks or solution outcomes.
 #[derive(Clone)]
 pub struct Notify {
     contracts: tokio::sync::watch::Sender<()>,
     blocks: tokio::sync::watch::Sender<()>,
+    outcomes: broadcast::Sender<Arc<[Hash]>>,
 }
 
 /// Wait for new data.
 #[derive(Clone)]
 pub struct NewData(tokio::sync::watch::Receiver<()>);
 
+/// Wait for solutions to have new outcomes.
+pub struct NewOutcomes(broadcast::Receiver<Arc<[Hash]>>);
+
 /// State of the stream.
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct StreamState {
@@ -126,6 +141,127 @@ where
     }
 }
 
+/// Stream data that is ordered by an increasing id, one page at a time.
+///
+/// `get_page` is given the id to start after and the maximum number of items to return.
+/// Paging by id rather than page number means data removed while
+/// streaming doesn't cause later data to be skipped.
+/// The stream ends after `limit` items, when a page isn't full or after an error.
+pub fn page_by_id<F, Fut, D>(
+    limit: Option<usize>,
+    page_size: usize,
+    get_page: F,
+) -> impl futures::Stream<Item = anyhow::Result<D>>
+where
+    F: Fn(i64, usize) -> Fut,
+    Fut: std::future::Future<Output = anyhow::Result<Vec<(i64, D)>>>,
+{
+    use futures::StreamExt;
+    futures::stream::unfold(Some((0, limit)), move |state| {
+        let (after_id, remaining) = match state {
+            Some(state) => state,
+            None => return futures::future::Either::Left(futures::future::ready(None)),
+        };
+        let page_size = remaining.map_or(page_size, |r| r.min(page_size));
+        if page_size == 0 {
+            return futures::future::Either::Left(futures::future::ready(None));
+        }
+        let page = get_page(after_id, page_size);
+        futures::future::Either::Right(async move {
+            match page.await {
+                Ok(page) => {
+                    // A full page means there may be more data.
+                    let next = match page.last() {
+                        Some((id, _)) if page.len() == page_size => {
+                            Some((*id, remaining.map(|r| r - page_size)))
+                        }
+                        _ => None,
+                    };
+                    let page: Vec<_> = page.into_iter().map(|(_, d)| Ok(d)).collect();
+                    Some((page, next))
+                }
+                Err(e) => Some((vec![Err(e)], None)),
+            }
+        })
+    })
+    .flat_map(futures::stream::iter)
+}
+
+/// Stream the outcomes of the given solutions as they are recorded.
+///
+/// `get_outcomes` returns all the outcomes of a solution with their timestamps, oldest first.
+/// Outcomes that were recorded before the stream started are returned first.
+/// After that only the solutions that are notified as having new outcomes are queried again.
+/// An outcome is identified by its timestamp so one that is recorded while older
+/// outcomes are pruned is still returned.
+///
+/// At most [`MAX_SUBSCRIBED_SOLUTIONS`] solutions can be followed.
+/// The stream ends after an error or when the new outcomes channel is closed.
+pub fn solution_outcomes<F, Fut>(
+    new_outcomes: NewOutcomes,
+    mut solution_hashes: Vec<Hash>,
+    get_outcomes: F,
+) -> impl futures::Stream<Item = anyhow::Result<(Hash, CheckOutcome)>>
+where
+    F: Fn(Hash) -> Fut,
+    Fut: std::future::Future<Output = anyhow::Result<Vec<(CheckOutcome, Duration)>>>,
+{
+    use futures::StreamExt;
+
+    let mut unique = HashSet::new();
+    solution_hashes.retain(|hash| unique.insert(*hash));
+    let init = if solution_hashes.len() > MAX_SUBSCRIBED_SOLUTIONS {
+        Err(anyhow::anyhow!(
+            "can't follow more than {MAX_SUBSCRIBED_SOLUTIONS} solutions at once"
+        ))
+    } else {
+        // The outcomes already returned for each solution.
+        let seen = vec![HashSet::new(); solution_hashes.len()];
+        Ok((new_outcomes, solution_hashes, seen, true, get_outcomes))
+    };
+    futures::stream::unfold(Some(init), |state| async move {
+        let (mut new_outcomes, solution_hashes, mut seen, mut first, get_outcomes) = match state? {
+            Ok(state) => state,
+            Err(e) => return Some((vec![Err(e)], None)),
+        };
+        loop {
+            // Every solution is queried the first time.
+            // After that wait for new outcomes or end the stream if the channel is closed.
+            let touched = if first {
+                None
+            } else {
+                new_outcomes.wait().await.ok()?
+            };
+            first = false;
+            let mut out = vec![];
+            for (hash, seen) in solution_hashes.iter().zip(seen.iter_mut()) {
+                if touched
+                    .as_ref()
+                    .is_some_and(|touched: &Arc<[Hash]>| !touched.contains(hash))
+                {
+                    continue;
+                }
+                match get_outcomes(*hash).await {
+                    Ok(outcomes) => {
+                        let outcomes: HashSet<_> = outcomes.into_iter().collect();
+                        let mut new: Vec<_> = outcomes.difference(seen).cloned().collect();
+                        new.sort_by_key(|(_, time)| *time);
+                        out.extend(new.into_iter().map(|(o, _)| Ok((*hash, o))));
+                        // Pruned outcomes are forgotten as they won't be returned again.
+                        *seen = outcomes;
+                    }
+                    Err(e) => return Some((vec![Err(e)], None)),
+                }
+            }
+            if !out.is_empty() {
+                let state = (new_outcomes, solution_hashes, seen, first, get_outcomes);
+                return Some((out, Some(Ok(state))));
+            }
+        }
+    })
+    .flat_map(futures::stream::iter)
+}
+
 impl StreamState {
     /// Create a new stream state from a page.
     pub fn new(page: Option<usize>, time: Option<Duration>, number: Option<u64>) -> Self {
@@ -142,7 +278,12 @@ impl Notify {
     pub fn new() -> Self {
         let (contracts, _) = tokio::sync::watch::channel(());
         let (blocks, _) = tokio::sync::watch::channel(());
-        Self { contracts, blocks }
+        let (outcomes, _) = broadcast::channel(OUTCOMES_CAPACITY);
+        Self {
+            contracts,
+            blocks,
+            outcomes,
+        }
     }
 
     /// Notify that there are new contracts.
@@ -159,6 +300,17 @@ impl Notify {
         let _ = self.blocks.send(());
     }
 
+    /// Notify that the given solutions have new outcomes.
+    pub fn notify_new_outcomes(&self, solution_hashes: impl IntoIterator<Item = Hash>) {
+        let hashes: Arc<[Hash]> = solution_hashes.into_iter().collect();
+        if hashes.is_empty() {
+            return;
+        }
+        // There might not be any subscribers so we
+        // need to ignore the error.
+        let _ = self.outcomes.send(hashes);
+    }
+
     /// Subscribe to new contracts.
     pub fn subscribe_contracts(&self) -> NewData {
         NewData(self.contracts.subscribe())
@@ -168,6 +320,11 @@ impl Notify {
     pub fn subscribe_blocks(&self) -> NewData {
         NewData(self.blocks.subscribe())
     }
+
+    /// Subscribe to new solution outcomes.
+    pub fn subscribe_outcomes(&self) -> NewOutcomes {
+        NewOutcomes(self.outcomes.subscribe())
+    }
 }
 
 impl NewData {
@@ -181,6 +338,20 @@ impl NewData {
     }
 }
 
+impl NewOutcomes {
+    /// Wait for solutions to have new outcomes.
+    /// Returns the hashes of the solutions or `None` if notifications were
+    /// missed and all solutions need to be checked.
+    /// Returns an error if the channel is closed.
+    pub async fn wait(&mut self) -> anyhow::Result<Option<Arc<[Hash]>>> {
+        match self.0.recv().await {
+            Ok(hashes) => Ok(Some(hashes)),
+            Err(broadcast::error::RecvError::Lagged(_)) => Ok(None),
+            Err(broadcast::error::RecvError::Closed) => Err(anyhow::anyhow!("channel closed")),
+        }
+    }
+}
+
 impl Default for Notify {
     fn default() -> Self {
         Self::new()
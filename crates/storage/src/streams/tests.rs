@@ -192,3 +192,160 @@ async fn ordering() {
     assert_eq!(*result[0].as_ref().unwrap(), 2);
     assert_eq!(*result[1].as_ref().unwrap(), 3);
 }
+
+#[tokio::test]
+async fn test_page_by_id() {
+    use futures::StreamExt;
+
+    let data: Vec<(i64, usize)> = (0..10).map(|i| (i * 2 + 1, i as usize)).collect();
+    let pages = Arc::new(AtomicUsize::new(0));
+    let get_page = |after_id: i64, limit: usize| {
+        let pages = pages.clone();
+        let page: Vec<_> = data
+            .iter()
+            .filter(|(id, _)| *id > after_id)
+            .take(limit)
+            .cloned()
+            .collect();
+        async move {
+            pages.fetch_add(1, Ordering::Relaxed);
+            Ok(page)
+        }
+    };
+
+    let result: Vec<_> = page_by_id(None, 3, get_page)
+        .map(Result::unwrap)
+        .collect()
+        .await;
+    assert_eq!(result, (0..10).collect::<Vec<_>>());
+    assert_eq!(pages.swap(0, Ordering::Relaxed), 4);
+
+    let result: Vec<_> = page_by_id(Some(4), 3, get_page)
+        .map(Result::unwrap)
+        .collect()
+        .await;
+    assert_eq!(result, (0..4).collect::<Vec<_>>());
+    assert_eq!(pages.swap(0, Ordering::Relaxed), 2);
+
+    let result: Vec<_> = page_by_id(Some(0), 3, get_page)
+        .map(Result::unwrap)
+        .collect()
+        .await;
+    assert!(result.is_empty());
+    assert_eq!(pages.swap(0, Ordering::Relaxed), 0);
+
+    // An error ends the stream.
+    let result: Vec<anyhow::Result<()>> =
+        page_by_id(None, 3, |_, _| async { Err(anyhow::anyhow!("error")) })
+            .collect()
+            .await;
+    assert_eq!(result.len(), 1);
+    assert!(result[0].is_err());
+}
+
+#[tokio::test]
+async fn test_solution_outcomes() {
+    use futures::StreamExt;
+    use std::sync::Mutex;
+
+    let notify = Notify::new();
+    let outcomes = Arc::new(Mutex::new(vec![(
+        CheckOutcome::Success(0),
+        Duration::from_secs(0),
+    )]));
+    let get_outcomes = |_| {
+        let outcomes = outcomes.lock().unwrap().clone();
+        async move { Ok(outcomes) }
+    };
+    let stream = solution_outcomes(notify.subscribe_outcomes(), vec![[0; 32]], get_outcomes);
+    futures::pin_mut!(stream);
+
+    // Existing outcomes are returned.
+    let result = stream.next().await.unwrap().unwrap();
+    assert_eq!(result, ([0; 32], CheckOutcome::Success(0)));
+
+    // Only new outcomes are returned after a notification.
+    outcomes
+        .lock()
+        .unwrap()
+        .push((CheckOutcome::Success(1), Duration::from_secs(1)));
+    notify.notify_new_outcomes([[0; 32]]);
+    let result = stream.next().await.unwrap().unwrap();
+    assert_eq!(result, ([0; 32], CheckOutcome::Success(1)));
+
+    // A new outcome is returned even if an old one was pruned at the same time.
+    {
+        let mut outcomes = outcomes.lock().unwrap();
+        outcomes.remove(0);
+        outcomes.push((CheckOutcome::Success(2), Duration::from_secs(2)));
+    }
+    notify.notify_new_outcomes([[0; 32]]);
+    let result = stream.next().await.unwrap().unwrap();
+    assert_eq!(result, ([0; 32], CheckOutcome::Success(2)));
+
+    // The stream ends when the notify is dropped.
+    drop(notify);
+    assert!(stream.next().await.is_none());
+
+    // An error ends the stream.
+    let notify = Notify::new();
+    let result: Vec<_> = solution_outcomes(notify.subscribe_outcomes(), vec![[0; 32]], |_| async {
+        Err(anyhow::anyhow!("error"))
+    })
+    .collect()
+    .await;
+    assert_eq!(result.len(), 1);
+    assert!(result[0].is_err());
+}
+
+#[tokio::test]
+async fn test_solution_outcomes_touched() {
+    use futures::StreamExt;
+    use std::sync::Mutex;
+
+    let notify = Notify::new();
+    let queried = Arc::new(Mutex::new(vec![]));
+    let get_outcomes = |hash: Hash| {
+        queried.lock().unwrap().push(hash);
+        let time = Duration::from_secs(queried.lock().unwrap().len() as u64);
+        async move { Ok(vec![(CheckOutcome::Success(0), time)]) }
+    };
+    let stream = solution_outcomes(
+        notify.subscribe_outcomes(),
+        vec![[0; 32], [1; 32], [0; 32]],
+        get_outcomes,
+    );
+    futures::pin_mut!(stream);
+
+    // Duplicate hashes are only queried once.
+    stream.next().await.unwrap().unwrap();
+    stream.next().await.unwrap().unwrap();
+    assert_eq!(*queried.lock().unwrap(), vec![[0; 32], [1; 32]]);
+
+    // Only the notified solutions are queried again.
+    notify.notify_new_outcomes([[1; 32], [2; 32]]);
+    let result = stream.next().await.unwrap().unwrap();
+    assert_eq!(result.0, [1; 32]);
+    assert_eq!(*queried.lock().unwrap(), vec![[0; 32], [1; 32], [1; 32]]);
+}
+
+#[tokio::test]
+async fn test_solution_outcomes_max() {
+    use futures::StreamExt;
+
+    let notify = Notify::new();
+    let hashes = (0..=MAX_SUBSCRIBED_SOLUTIONS)
+        .map(|i| {
+            let mut hash = [0; 32];
+            hash[..8].copy_from_slice(&(i as u64).to_be_bytes());
+            hash
+        })
+        .collect();
+    let result: Vec<_> = solution_outcomes(notify.subscribe_outcomes(), hashes, |_| async {
+        Ok(vec![])
+    })
+    .collect()
+    .await;
+    assert_eq!(result.len(), 1);
+    assert!(result[0].is_err());
+}
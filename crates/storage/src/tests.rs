@@ -0,0 +1,61 @@
+use super::*;
+
+#[test]
+fn test_prefix_end() {
+    assert_eq!(prefix_end(&vec![]), None);
+    assert_eq!(prefix_end(&vec![1]), Some(vec![2]));
+    assert_eq!(prefix_end(&vec![1, 2]), Some(vec![1, 3]));
+    assert_eq!(prefix_end(&vec![-1]), Some(vec![0]));
+    assert_eq!(prefix_end(&vec![1, Word::MAX]), Some(vec![2]));
+    assert_eq!(prefix_end(&vec![Word::MAX, Word::MAX]), None);
+
+    // Every key with the prefix is before the end and every key after isn't.
+    let prefix = vec![1, Word::MAX];
+    let end = prefix_end(&prefix);
+    for key in [vec![1, Word::MAX], vec![1, Word::MAX, Word::MAX]] {
+        assert!(key_in_range(&key, &prefix, end.as_ref()));
+    }
+    for key in [vec![1], vec![2], vec![2, Word::MIN], vec![1, 0]] {
+        assert!(!key_in_range(&key, &prefix, end.as_ref()));
+    }
+}
+
+#[test]
+fn test_sortable_key() {
+    let mut keys = vec![
+        vec![],
+        vec![Word::MIN],
+        vec![-1],
+        vec![-1, 5],
+        vec![0],
+        vec![0, Word::MIN],
+        vec![0, 0],
+        vec![1],
+        vec![Word::MAX],
+        vec![Word::MAX, Word::MAX],
+    ];
+    let mut encoded: Vec<_> = keys.iter().map(encode_sortable_key).collect();
+    keys.sort();
+    encoded.sort();
+    let decoded: Vec<_> = encoded
+        .iter()
+        .map(|bytes| decode_sortable_key(bytes).unwrap())
+        .collect();
+    assert_eq!(decoded, keys);
+
+    decode_sortable_key(&[0; 7]).unwrap_err();
+}
+
+#[test]
+fn test_consecutive_keys() {
+    assert!(consecutive_keys(vec![0], 0).unwrap().is_empty());
+    assert_eq!(
+        consecutive_keys(vec![1, Word::MAX - 1], 3).unwrap(),
+        vec![
+            vec![1, Word::MAX - 1],
+            vec![1, Word::MAX],
+            vec![2, Word::MIN]
+        ]
+    );
+    consecutive_keys(vec![Word::MAX - 1], 2).unwrap_err();
+}
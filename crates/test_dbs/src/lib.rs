@@ -14,6 +14,7 @@ macro_rules! create_test {
                 #[cfg(feature = "rqlite")]
                 $func($crate::TestRqlite::new().await.rqlite).await;
                 $func(essential_memory_storage::MemoryStorage::new()).await;
+                $func(essential_sqlite_storage::SqliteStorage::in_memory().unwrap()).await;
             }
         }
     };
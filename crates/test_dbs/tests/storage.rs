@@ -1,14 +1,17 @@
 use std::time::Duration;
 
+use essential_server_types::state_root::{state_root as root_of, EMPTY};
 use essential_storage::{
     failed_solution::{CheckOutcome, FailedSolution, SolutionFailReason},
-    CommitData, Storage,
+    BlockHeader, CommitData, StateChange, Storage,
 };
 use essential_types::{contract::Contract, ContentAddress, PredicateAddress, Word};
+use futures::TryStreamExt;
 use test_dbs::create_test;
 use test_utils::{
     empty::Empty, predicate_with_salt, predicate_with_salt_and_state,
     sign_contract_with_random_keypair, solution_with_all_inputs,
+    solution_with_all_inputs_fixed_size,
 };
 
 create_test!(insert_contract);
@@ -214,6 +217,11 @@ async fn move_solutions_to_solved<S: Storage>(storage: S) {
     assert_eq!(result[0].number, 0);
     assert_eq!(result[1].number, 1);
     assert_eq!(result[2].number, 2);
+
+    // The gas the solutions used isn't known so the blocks record none.
+    let headers = storage.list_block_headers(None, None).await.unwrap();
+    assert_eq!(headers.len(), 3);
+    assert!(headers.iter().all(|header| header.gas_used == 0));
 }
 
 create_test!(get_predicate);
@@ -416,6 +424,96 @@ async fn list_solutions_pool<S: Storage>(storage: S) {
     assert!(result.is_empty());
 }
 
+create_test!(pool_size);
+
+async fn pool_size<S: Storage>(storage: S) {
+    assert_eq!(storage.pool_size().await.unwrap(), 0);
+
+    let solutions: Vec<_> = (0..3).map(solution_with_all_inputs).collect();
+    for solution in &solutions {
+        storage
+            .insert_solution_into_pool(solution.clone())
+            .await
+            .unwrap();
+    }
+    for solution in &solutions {
+        let hash = essential_hash::hash(solution);
+        assert!(storage.is_in_pool(hash).await.unwrap());
+    }
+
+    // Solutions already in the pool aren't counted twice.
+    storage
+        .insert_solution_into_pool(solutions[0].clone())
+        .await
+        .unwrap();
+    assert_eq!(storage.pool_size().await.unwrap(), 3);
+
+    storage
+        .move_solutions_to_failed(&[(
+            essential_hash::hash(&solutions[1]),
+            SolutionFailReason::Evicted,
+        )])
+        .await
+        .unwrap();
+    assert_eq!(storage.pool_size().await.unwrap(), 2);
+    let hash = essential_hash::hash(&solutions[1]);
+    assert!(!storage.is_in_pool(hash).await.unwrap());
+}
+
+create_test!(stream_solutions_pool);
+
+async fn stream_solutions_pool<S: Storage + Clone>(storage: S) {
+    let solutions: Vec<_> = (0..250)
+        .map(|i| solution_with_all_inputs_fixed_size(i, 1))
+        .collect();
+
+    for solution in &solutions {
+        storage
+            .insert_solution_into_pool(solution.clone())
+            .await
+            .unwrap();
+    }
+
+    // Stream the whole pool across multiple pages.
+    let result: Vec<_> = storage
+        .clone()
+        .stream_solutions_pool(None)
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(result, solutions);
+
+    // Stream up to a limit.
+    let result: Vec<_> = storage
+        .clone()
+        .stream_solutions_pool(Some(150))
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(&result[..], &solutions[..150]);
+
+    let result: Vec<_> = storage
+        .clone()
+        .stream_solutions_pool(Some(0))
+        .try_collect()
+        .await
+        .unwrap();
+    assert!(result.is_empty());
+
+    // Solutions that have left the pool are not streamed.
+    let solved: Vec<_> = solutions[..120].iter().map(essential_hash::hash).collect();
+    storage
+        .move_solutions_to_solved(0, Duration::from_secs(1), &solved)
+        .await
+        .unwrap();
+    let result: Vec<_> = storage
+        .stream_solutions_pool(None)
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(&result[..], &solutions[120..]);
+}
+
 create_test!(list_failed_solutions_pool);
 
 async fn list_failed_solutions_pool<S: Storage>(storage: S) {
@@ -438,18 +536,26 @@ async fn list_failed_solutions_pool<S: Storage>(storage: S) {
             .unwrap();
     }
 
+    let before = std::time::UNIX_EPOCH.elapsed().unwrap();
     storage.move_solutions_to_failed(&hashes).await.unwrap();
+    let after = std::time::UNIX_EPOCH.elapsed().unwrap();
 
+    let result = storage.list_failed_solutions_pool(None).await.unwrap();
+    assert!(result
+        .iter()
+        .all(|f| before <= f.timestamp && f.timestamp <= after));
+
+    // The solutions were all failed at once so share a timestamp.
     let solutions: Vec<_> = solutions
         .into_iter()
         .map(|s| FailedSolution {
             solution: s,
             reason: SolutionFailReason::NotComposable,
+            timestamp: result[0].timestamp,
         })
         .collect();
 
     // List up to page size
-    let result = storage.list_failed_solutions_pool(None).await.unwrap();
     assert_eq!(result.len(), 100);
     assert_eq!(&result[..], &solutions[0..100]);
 
@@ -688,12 +794,14 @@ async fn get_solution<S: Storage>(storage: S) {
     let result = storage.get_solution(hashes[0]).await.unwrap().unwrap();
     assert_eq!(result.solution, solutions[0]);
     assert!(result.outcome.is_empty());
+    assert!(result.timestamps.is_empty());
 
     // Get existing solution in solved
     let result = storage.get_solution(hashes[1]).await.unwrap().unwrap();
     assert_eq!(result.solution, solutions[1]);
     assert_eq!(result.outcome.len(), 1);
     assert_eq!(result.outcome[0], CheckOutcome::Success(0));
+    assert_eq!(result.timestamps, vec![Duration::from_secs(1)]);
 
     // Get existing solution in failed
     let result = storage.get_solution(hashes[2]).await.unwrap().unwrap();
@@ -703,6 +811,7 @@ async fn get_solution<S: Storage>(storage: S) {
         result.outcome[0],
         CheckOutcome::Fail(SolutionFailReason::NotComposable)
     );
+    assert_eq!(result.timestamps.len(), 1);
 
     // Get missing solution
     let result = storage
@@ -809,6 +918,113 @@ async fn prune_failed_solutions<S: Storage>(storage: S) {
     assert_eq!(result.len(), 0);
 }
 
+create_test!(prune_excess_failed_solutions);
+
+async fn prune_excess_failed_solutions<S: Storage>(storage: S) {
+    let solutions: Vec<_> = (0..4).map(solution_with_all_inputs).collect();
+    let hashes: Vec<_> = solutions.iter().map(essential_hash::hash).collect();
+
+    for (solution, hash) in solutions.iter().zip(&hashes) {
+        storage
+            .insert_solution_into_pool(solution.clone())
+            .await
+            .unwrap();
+        storage
+            .move_solutions_to_failed(&[(*hash, SolutionFailReason::NotComposable)])
+            .await
+            .unwrap();
+    }
+
+    // Nothing failed before the epoch and every failure is kept.
+    let prunable = storage
+        .list_prunable_failed_solutions(Duration::ZERO, None)
+        .await
+        .unwrap();
+    assert!(prunable.is_empty());
+
+    // The oldest failures beyond the count are prunable.
+    let prunable = storage
+        .list_prunable_failed_solutions(Duration::ZERO, Some(1))
+        .await
+        .unwrap();
+    let prunable: Vec<_> = prunable.into_iter().map(|f| f.solution).collect();
+    assert_eq!(prunable, solutions[..3]);
+
+    // Everything failed before the future.
+    let future = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        + Duration::from_secs(100);
+    let prunable = storage
+        .list_prunable_failed_solutions(future, Some(3))
+        .await
+        .unwrap();
+    assert_eq!(prunable.len(), 4);
+    assert!(prunable
+        .iter()
+        .all(|f| f.reason == SolutionFailReason::NotComposable));
+
+    storage.prune_excess_failed_solutions(4).await.unwrap();
+    let result = storage.list_failed_solutions_pool(None).await.unwrap();
+    assert_eq!(result.len(), 4);
+
+    storage.prune_excess_failed_solutions(2).await.unwrap();
+    let result = storage.list_failed_solutions_pool(None).await.unwrap();
+    let result: Vec<_> = result.into_iter().map(|f| f.solution).collect();
+    assert_eq!(result, solutions[2..]);
+}
+
+create_test!(expire_and_evict_solutions);
+
+async fn expire_and_evict_solutions<S: Storage>(storage: S) {
+    let solutions: Vec<_> = (0..5).map(solution_with_all_inputs).collect();
+    let hashes: Vec<_> = solutions.iter().map(essential_hash::hash).collect();
+
+    for solution in &solutions[..2] {
+        storage
+            .insert_solution_into_pool(solution.clone())
+            .await
+            .unwrap();
+    }
+    std::thread::sleep(Duration::from_millis(1));
+    let added_before = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(1));
+    for solution in &solutions[2..] {
+        storage
+            .insert_solution_into_pool(solution.clone())
+            .await
+            .unwrap();
+    }
+
+    // Only solutions added before the time expire.
+    let expired = storage.expire_solutions(added_before).await.unwrap();
+    assert_eq!(expired, hashes[..2]);
+    assert!(storage
+        .expire_solutions(added_before)
+        .await
+        .unwrap()
+        .is_empty());
+
+    // The oldest solutions are evicted.
+    assert!(storage.evict_solutions(3).await.unwrap().is_empty());
+    let evicted = storage.evict_solutions(1).await.unwrap();
+    assert_eq!(evicted, hashes[2..4]);
+    let result = storage.list_solutions_pool(None).await.unwrap();
+    assert_eq!(result, solutions[4..]);
+
+    for (hash, reason) in [
+        (hashes[0], SolutionFailReason::Expired),
+        (hashes[3], SolutionFailReason::Evicted),
+    ] {
+        let outcome = storage.get_solution(hash).await.unwrap().unwrap();
+        assert_eq!(outcome.outcome, vec![CheckOutcome::Fail(reason)]);
+    }
+    let failed = storage.list_failed_solutions_pool(None).await.unwrap();
+    assert_eq!(failed.len(), 4);
+}
+
 create_test!(commit_block);
 
 async fn commit_block<S: Storage>(storage: S) {
@@ -843,9 +1059,24 @@ async fn commit_block<S: Storage>(storage: S) {
         state_updates: Box::new(state_updates),
         block_number: 0,
         block_timestamp: Duration::from_secs(1),
+        gas_used: 123,
     };
     storage.commit_block(data).await.unwrap();
 
+    let state: Vec<_> = (0..10)
+        .map(|i| (address.clone(), vec![i as Word], vec![i as Word]))
+        .collect();
+    let headers = storage.list_block_headers(None, None).await.unwrap();
+    assert_eq!(
+        headers,
+        vec![BlockHeader {
+            number: 0,
+            timestamp: Duration::from_secs(1),
+            gas_used: 123,
+            state_root: root_of(state.iter().map(|(a, k, v)| (a, k, v))),
+        }]
+    );
+
     let result = storage.get_solution(hashes[0]).await.unwrap().unwrap();
     assert_eq!(result.solution, solutions[0]);
     assert!(result.outcome.is_empty());
@@ -872,6 +1103,605 @@ async fn commit_block<S: Storage>(storage: S) {
     }
 }
 
+create_test!(list_block_headers);
+
+async fn list_block_headers<S: Storage>(storage: S) {
+    let solutions: Vec<_> = (0..3).map(solution_with_all_inputs).collect();
+    for solution in &solutions {
+        storage
+            .insert_solution_into_pool(solution.clone())
+            .await
+            .unwrap();
+    }
+
+    for (i, solution) in solutions.iter().enumerate() {
+        let data = CommitData {
+            failed: &[],
+            solved: &[essential_hash::hash(solution)],
+            state_updates: Box::new(std::iter::empty()),
+            block_number: i as u64,
+            block_timestamp: Duration::from_secs(i as u64 + 1),
+            gas_used: i as u64 * 100,
+        };
+        storage.commit_block(data).await.unwrap();
+    }
+
+    // Committing without any solved solutions doesn't create a block.
+    let data = CommitData {
+        failed: &[],
+        solved: &[],
+        state_updates: Box::new(std::iter::empty()),
+        block_number: 3,
+        block_timestamp: Duration::from_secs(4),
+        gas_used: 1,
+    };
+    storage.commit_block(data).await.unwrap();
+
+    let expected: Vec<_> = (0..3)
+        .map(|i| BlockHeader {
+            number: i,
+            timestamp: Duration::from_secs(i + 1),
+            gas_used: i * 100,
+            state_root: EMPTY,
+        })
+        .collect();
+
+    let headers = storage.list_block_headers(None, None).await.unwrap();
+    assert_eq!(headers, expected);
+
+    let headers = storage.list_block_headers(Some(1), None).await.unwrap();
+    assert_eq!(headers, expected[1..]);
+
+    let headers = storage.list_block_headers(None, Some(1)).await.unwrap();
+    assert!(headers.is_empty());
+}
+
+create_test!(state_root);
+
+async fn state_root<S: Storage>(storage: S) {
+    let solutions: Vec<_> = (0..3).map(solution_with_all_inputs).collect();
+    for solution in &solutions {
+        storage
+            .insert_solution_into_pool(solution.clone())
+            .await
+            .unwrap();
+    }
+
+    let contract = sign_contract_with_random_keypair(vec![predicate_with_salt(0)]);
+    storage.insert_contract(contract.clone()).await.unwrap();
+    let address = essential_hash::contract_addr::from_contract(&contract.contract);
+
+    let blocks = [
+        // Set keys 0 and 1.
+        vec![
+            (address.clone(), vec![0], vec![1]),
+            (address.clone(), vec![1], vec![2]),
+        ],
+        // Update key 0, clear key 1 and set key 2.
+        vec![
+            (address.clone(), vec![0], vec![3]),
+            (address.clone(), vec![1], vec![]),
+            (address.clone(), vec![2], vec![4, 5]),
+        ],
+        // Clear everything.
+        vec![
+            (address.clone(), vec![0], vec![]),
+            (address.clone(), vec![2], vec![]),
+        ],
+    ];
+    for (i, state_updates) in blocks.into_iter().enumerate() {
+        let data = CommitData {
+            failed: &[],
+            solved: &[essential_hash::hash(&solutions[i])],
+            state_updates: Box::new(state_updates.into_iter()),
+            block_number: i as u64,
+            block_timestamp: Duration::from_secs(i as u64 + 1),
+            gas_used: 0,
+        };
+        storage.commit_block(data).await.unwrap();
+    }
+
+    let expected = [
+        vec![
+            (address.clone(), vec![0], vec![1]),
+            (address.clone(), vec![1], vec![2]),
+        ],
+        vec![
+            (address.clone(), vec![0], vec![3]),
+            (address.clone(), vec![2], vec![4, 5]),
+        ],
+        vec![],
+    ]
+    .map(|state| root_of(state.iter().map(|(a, k, v)| (a, k, v))));
+
+    let headers = storage.list_block_headers(None, None).await.unwrap();
+    let roots: Vec<_> = headers.iter().map(|h| h.state_root).collect();
+    assert_eq!(roots, expected);
+    assert_ne!(roots[0], roots[1]);
+    assert_eq!(roots[2], EMPTY);
+}
+
+create_test!(list_state_at);
+
+async fn list_state_at<S: Storage>(storage: S) {
+    assert!(storage.list_state_at(0).await.unwrap().is_empty());
+
+    let mut addresses = vec![];
+    for i in 0..2 {
+        let contract = sign_contract_with_random_keypair(vec![predicate_with_salt(i)]);
+        storage.insert_contract(contract.clone()).await.unwrap();
+        addresses.push(essential_hash::contract_addr::from_contract(
+            &contract.contract,
+        ));
+    }
+
+    let mut expected = vec![];
+    for address in &addresses {
+        for i in 0..3 {
+            let key = vec![i as Word];
+            let value = vec![i as Word, 1];
+            storage
+                .update_state(address, &key, value.clone())
+                .await
+                .unwrap();
+            expected.push((address.clone(), key, value));
+        }
+    }
+
+    // Cleared values aren't listed.
+    storage
+        .update_state(&addresses[0], &vec![1], vec![])
+        .await
+        .unwrap();
+    expected.remove(1);
+
+    // With no blocks the updates belong to the first block.
+    let mut result = storage.list_state_at(0).await.unwrap();
+    result.sort();
+    expected.sort();
+    assert_eq!(result, expected);
+}
+
+create_test!(query_state_at);
+
+async fn query_state_at<S: Storage>(storage: S) {
+    let solutions: Vec<_> = (0..2).map(solution_with_all_inputs).collect();
+    for solution in &solutions {
+        storage
+            .insert_solution_into_pool(solution.clone())
+            .await
+            .unwrap();
+    }
+
+    let contract = sign_contract_with_random_keypair(vec![predicate_with_salt(0)]);
+    storage.insert_contract(contract.clone()).await.unwrap();
+    let address = essential_hash::contract_addr::from_contract(&contract.contract);
+
+    let blocks = [
+        vec![
+            (address.clone(), vec![0], vec![1]),
+            (address.clone(), vec![1], vec![2]),
+        ],
+        vec![
+            (address.clone(), vec![0], vec![3]),
+            (address.clone(), vec![1], vec![]),
+        ],
+    ];
+    for (i, state_updates) in blocks.into_iter().enumerate() {
+        let data = CommitData {
+            failed: &[],
+            solved: &[essential_hash::hash(&solutions[i])],
+            state_updates: Box::new(state_updates.into_iter()),
+            block_number: i as u64,
+            block_timestamp: Duration::from_secs(i as u64 + 1),
+            gas_used: 0,
+        };
+        storage.commit_block(data).await.unwrap();
+    }
+
+    // Updates outside of a block belong to the next block.
+    storage
+        .update_state(&address, &vec![2], vec![4])
+        .await
+        .unwrap();
+
+    let expected = [
+        vec![
+            (address.clone(), vec![0], vec![1]),
+            (address.clone(), vec![1], vec![2]),
+        ],
+        vec![(address.clone(), vec![0], vec![3])],
+        vec![
+            (address.clone(), vec![0], vec![3]),
+            (address.clone(), vec![2], vec![4]),
+        ],
+    ];
+    for (block, expected) in expected.iter().enumerate() {
+        let block = block as u64;
+        let mut state = storage.list_state_at(block).await.unwrap();
+        state.sort();
+        assert_eq!(state, *expected);
+
+        for key in 0..3 {
+            let key = vec![key];
+            let value = storage.query_state_at(&address, &key, block).await.unwrap();
+            let expected = expected
+                .iter()
+                .find(|(_, k, _)| *k == key)
+                .map(|(_, _, v)| v.clone())
+                .unwrap_or_default();
+            assert_eq!(value, expected);
+        }
+
+        let range = storage
+            .query_state_range_at(&address, &vec![0], None, 10, block)
+            .await
+            .unwrap();
+        let expected: Vec<_> = expected
+            .iter()
+            .map(|(_, k, v)| (k.clone(), v.clone()))
+            .collect();
+        assert_eq!(range, expected);
+
+        // Keys without a value at the block don't count towards the limit.
+        let range = storage
+            .query_state_range_at(&address, &vec![1], Some(&vec![3]), 1, block)
+            .await
+            .unwrap();
+        let expected: Vec<_> = expected
+            .into_iter()
+            .filter(|(k, _)| *k >= vec![1])
+            .take(1)
+            .collect();
+        assert_eq!(range, expected);
+    }
+
+    // The state at each block matches its root.
+    let headers = storage.list_block_headers(None, None).await.unwrap();
+    assert_eq!(headers.len(), 2);
+    for header in headers {
+        let state = storage.list_state_at(header.number).await.unwrap();
+        let root = root_of(state.iter().map(|(a, k, v)| (a, k, v)));
+        assert_eq!(root, header.state_root);
+    }
+
+    // The current state is the latest version.
+    let value = storage.query_state(&address, &vec![2]).await.unwrap();
+    assert_eq!(value, vec![4]);
+}
+
+create_test!(get_block_state_diff);
+
+async fn get_block_state_diff<S: Storage>(storage: S) {
+    let solutions: Vec<_> = (0..2).map(solution_with_all_inputs).collect();
+    for solution in &solutions {
+        storage
+            .insert_solution_into_pool(solution.clone())
+            .await
+            .unwrap();
+    }
+
+    let contract = sign_contract_with_random_keypair(vec![predicate_with_salt(0)]);
+    storage.insert_contract(contract.clone()).await.unwrap();
+    let address = essential_hash::contract_addr::from_contract(&contract.contract);
+
+    assert!(storage.get_block_state_diff(0).await.unwrap().is_empty());
+
+    let blocks = [
+        vec![
+            (address.clone(), vec![1], vec![2]),
+            (address.clone(), vec![0], vec![1]),
+        ],
+        // Key 0 is written twice so only the final value is in the diff.
+        vec![
+            (address.clone(), vec![0], vec![3]),
+            (address.clone(), vec![1], vec![]),
+            (address.clone(), vec![0], vec![4]),
+        ],
+    ];
+    for (i, state_updates) in blocks.into_iter().enumerate() {
+        let data = CommitData {
+            failed: &[],
+            solved: &[essential_hash::hash(&solutions[i])],
+            state_updates: Box::new(state_updates.into_iter()),
+            block_number: i as u64,
+            block_timestamp: Duration::from_secs(i as u64 + 1),
+            gas_used: 0,
+        };
+        storage.commit_block(data).await.unwrap();
+    }
+
+    let change = |key: Word, old_value: Vec<Word>, new_value: Vec<Word>| StateChange {
+        address: address.clone(),
+        key: vec![key],
+        old_value,
+        new_value,
+    };
+    assert_eq!(
+        storage.get_block_state_diff(0).await.unwrap(),
+        vec![change(0, vec![], vec![1]), change(1, vec![], vec![2])]
+    );
+    assert_eq!(
+        storage.get_block_state_diff(1).await.unwrap(),
+        vec![change(0, vec![1], vec![4]), change(1, vec![2], vec![])]
+    );
+
+    // Updates outside of a block are part of the next block.
+    storage
+        .update_state(&address, &vec![0], vec![5])
+        .await
+        .unwrap();
+    assert_eq!(
+        storage.get_block_state_diff(2).await.unwrap(),
+        vec![change(0, vec![4], vec![5])]
+    );
+}
+
+create_test!(revert_to_block);
+
+async fn revert_to_block<S: Storage>(storage: S) {
+    let solutions: Vec<_> = (0..4).map(solution_with_all_inputs).collect();
+    let hashes: Vec<_> = solutions.iter().map(essential_hash::hash).collect();
+    for solution in &solutions {
+        storage
+            .insert_solution_into_pool(solution.clone())
+            .await
+            .unwrap();
+    }
+
+    let contract = sign_contract_with_random_keypair(vec![predicate_with_salt(0)]);
+    storage.insert_contract(contract.clone()).await.unwrap();
+    let address = essential_hash::contract_addr::from_contract(&contract.contract);
+
+    let blocks = [
+        vec![
+            (address.clone(), vec![0], vec![1]),
+            (address.clone(), vec![1], vec![2]),
+        ],
+        vec![
+            (address.clone(), vec![0], vec![3]),
+            (address.clone(), vec![2], vec![4]),
+        ],
+        vec![(address.clone(), vec![1], vec![])],
+    ];
+    for (i, state_updates) in blocks.into_iter().enumerate() {
+        let data = CommitData {
+            failed: &[],
+            solved: &[hashes[i]],
+            state_updates: Box::new(state_updates.into_iter()),
+            block_number: i as u64,
+            block_timestamp: Duration::from_secs(i as u64 + 1),
+            gas_used: 0,
+        };
+        storage.commit_block(data).await.unwrap();
+    }
+
+    // Updates outside of a block are reverted too.
+    storage
+        .update_state(&address, &vec![3], vec![5])
+        .await
+        .unwrap();
+
+    storage.revert_to_block(0).await.unwrap();
+
+    // The state is back to the end of the first block.
+    let expected = vec![
+        (address.clone(), vec![0], vec![1]),
+        (address.clone(), vec![1], vec![2]),
+    ];
+    for (_, key, value) in &expected {
+        assert_eq!(storage.query_state(&address, key).await.unwrap(), *value);
+    }
+    for key in [vec![2], vec![3]] {
+        assert!(storage
+            .query_state(&address, &key)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+    let mut state = storage.list_state_at(3).await.unwrap();
+    state.sort();
+    assert_eq!(state, expected);
+    assert!(storage.get_block_state_diff(1).await.unwrap().is_empty());
+
+    // Later blocks are gone.
+    let headers = storage.list_block_headers(None, None).await.unwrap();
+    assert_eq!(headers.len(), 1);
+    assert_eq!(headers[0].number, 0);
+    let latest = storage.get_latest_block().await.unwrap().unwrap();
+    assert_eq!(latest.number, 0);
+
+    // Their solutions are back in the pool behind the solution that was already there.
+    let pool = storage.list_solutions_pool(None).await.unwrap();
+    assert_eq!(
+        pool,
+        vec![
+            solutions[3].clone(),
+            solutions[1].clone(),
+            solutions[2].clone()
+        ]
+    );
+    let outcome = storage.get_solution(hashes[1]).await.unwrap().unwrap();
+    assert!(outcome.outcome.is_empty());
+    let outcome = storage.get_solution(hashes[0]).await.unwrap().unwrap();
+    assert_eq!(outcome.outcome, vec![CheckOutcome::Success(0)]);
+
+    // The next block takes the number of the first reverted block.
+    let data = CommitData {
+        failed: &[],
+        solved: &[hashes[2]],
+        state_updates: Box::new(vec![(address.clone(), vec![2], vec![6])].into_iter()),
+        block_number: 1,
+        block_timestamp: Duration::from_secs(4),
+        gas_used: 0,
+    };
+    storage.commit_block(data).await.unwrap();
+    let headers = storage.list_block_headers(None, None).await.unwrap();
+    assert_eq!(headers.len(), 2);
+    assert_eq!(headers[1].number, 1);
+    let state = storage.list_state_at(1).await.unwrap();
+    let root = root_of(state.iter().map(|(a, k, v)| (a, k, v)));
+    assert_eq!(root, headers[1].state_root);
+    assert_eq!(
+        storage.get_block_state_diff(1).await.unwrap(),
+        vec![StateChange {
+            address: address.clone(),
+            key: vec![2],
+            old_value: vec![],
+            new_value: vec![6],
+        }]
+    );
+
+    // Reverting to the latest block only reverts updates outside of a block.
+    storage
+        .update_state(&address, &vec![3], vec![7])
+        .await
+        .unwrap();
+    storage.revert_to_block(1).await.unwrap();
+    assert!(storage
+        .query_state(&address, &vec![3])
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        storage.list_block_headers(None, None).await.unwrap().len(),
+        2
+    );
+}
+
+create_test!(query_state_range);
+
+async fn query_state_range<S: Storage + Sync>(storage: S) {
+    let contract = sign_contract_with_random_keypair(vec![predicate_with_salt(0)]);
+    storage.insert_contract(contract.clone()).await.unwrap();
+    let address = essential_hash::contract_addr::from_contract(&contract.contract);
+    let other = sign_contract_with_random_keypair(vec![predicate_with_salt(1)]);
+    storage.insert_contract(other.clone()).await.unwrap();
+    let other = essential_hash::contract_addr::from_contract(&other.contract);
+
+    let keys = [
+        vec![],
+        vec![Word::MIN],
+        vec![-1],
+        vec![-1, 3],
+        vec![0],
+        vec![1],
+        vec![1, -5],
+        vec![1, 0],
+        vec![1, 0, 0],
+        vec![1, 300],
+        vec![2],
+        vec![Word::MAX],
+    ];
+    // Insert out of order.
+    for (i, key) in keys.iter().enumerate().rev() {
+        storage
+            .update_state(&address, key, vec![i as Word])
+            .await
+            .unwrap();
+        storage.update_state(&other, key, vec![100]).await.unwrap();
+    }
+    // Cleared keys aren't returned.
+    storage
+        .update_state(&address, &vec![0], vec![])
+        .await
+        .unwrap();
+    let all: Vec<_> = keys
+        .iter()
+        .enumerate()
+        .filter(|(_, key)| **key != vec![0])
+        .map(|(i, key)| (key.clone(), vec![i as Word]))
+        .collect();
+
+    let r = storage
+        .query_state_range(&address, &vec![], None, 100)
+        .await
+        .unwrap();
+    assert_eq!(r, all);
+
+    // Limited.
+    let r = storage
+        .query_state_range(&address, &vec![], None, 3)
+        .await
+        .unwrap();
+    assert_eq!(r, all[..3]);
+
+    // The start is included and the end isn't.
+    let r = storage
+        .query_state_range(&address, &vec![-1, 3], Some(&vec![1, 0, 0]), 100)
+        .await
+        .unwrap();
+    let start = all.iter().position(|(k, _)| *k == vec![-1, 3]).unwrap();
+    let end = all.iter().position(|(k, _)| *k == vec![1, 0, 0]).unwrap();
+    assert_eq!(r, all[start..end]);
+
+    // Empty ranges.
+    for (start, end) in [(vec![1], vec![1]), (vec![2], vec![1])] {
+        let r = storage
+            .query_state_range(&address, &start, Some(&end), 100)
+            .await
+            .unwrap();
+        assert!(r.is_empty());
+    }
+
+    // Prefixes.
+    let r = storage
+        .query_state_prefix(&address, &vec![1], 100)
+        .await
+        .unwrap();
+    let keys: Vec<_> = r.into_iter().map(|(k, _)| k).collect();
+    assert_eq!(
+        keys,
+        vec![
+            vec![1],
+            vec![1, -5],
+            vec![1, 0],
+            vec![1, 0, 0],
+            vec![1, 300]
+        ]
+    );
+    let r = storage
+        .query_state_prefix(&address, &vec![Word::MAX], 100)
+        .await
+        .unwrap();
+    assert_eq!(r, all[all.len() - 1..]);
+    let r = storage
+        .query_state_prefix(&address, &vec![], 100)
+        .await
+        .unwrap();
+    assert_eq!(r, all);
+}
+
+create_test!(query_state_batch);
+
+async fn query_state_batch<S: Storage + Sync>(storage: S) {
+    let contract = sign_contract_with_random_keypair(vec![predicate_with_salt(0)]);
+    storage.insert_contract(contract.clone()).await.unwrap();
+    let address = essential_hash::contract_addr::from_contract(&contract.contract);
+
+    assert!(storage
+        .query_state_batch(&address, &[])
+        .await
+        .unwrap()
+        .is_empty());
+
+    for i in 0..4 {
+        storage
+            .update_state(&address, &vec![1, i], vec![i, 1])
+            .await
+            .unwrap();
+    }
+    storage
+        .update_state(&address, &vec![1, 2], vec![])
+        .await
+        .unwrap();
+
+    // Missing, cleared and repeated keys.
+    let keys = [vec![1, 3], vec![1, 0], vec![1, 2], vec![2], vec![1, 0]];
+    let r = storage.query_state_batch(&address, &keys).await.unwrap();
+    assert_eq!(r, vec![vec![3, 1], vec![0, 1], vec![], vec![], vec![0, 1]]);
+}
+
 create_test!(update_state);
 
 async fn update_state<S: Storage>(storage: S) {
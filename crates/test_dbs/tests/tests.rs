@@ -217,6 +217,58 @@ async fn solutions<S: Storage>(storage: S) {
     assert!(result.is_empty());
 }
 
+create_test!(subscribe_solution_outcomes);
+
+async fn subscribe_solution_outcomes<S: Storage + Clone + Send + Sync + 'static>(storage: S) {
+    let solutions: Vec<_> = (0..3)
+        .map(|i| solution_with_all_inputs_fixed_size(i, 1))
+        .collect();
+    let hashes: Vec<_> = solutions.iter().map(essential_hash::hash).collect();
+    for solution in &solutions {
+        storage
+            .insert_solution_into_pool(solution.clone())
+            .await
+            .unwrap();
+    }
+
+    // Outcomes from before subscribing are returned first.
+    storage
+        .move_solutions_to_solved(0, Duration::from_secs(1), &hashes[0..1])
+        .await
+        .unwrap();
+
+    let stream = storage.clone().subscribe_solution_outcomes(hashes.clone());
+    futures::pin_mut!(stream);
+    let result = stream.next().await.unwrap().unwrap();
+    assert_eq!(result, (hashes[0], CheckOutcome::Success(0)));
+
+    let r = tokio::time::timeout(Duration::from_millis(50), stream.next()).await;
+    assert!(r.is_err());
+
+    storage
+        .move_solutions_to_failed(&[(hashes[1], SolutionFailReason::NotComposable)])
+        .await
+        .unwrap();
+    let result = stream.next().await.unwrap().unwrap();
+    assert_eq!(
+        result,
+        (
+            hashes[1],
+            CheckOutcome::Fail(SolutionFailReason::NotComposable)
+        )
+    );
+
+    storage
+        .move_solutions_to_solved(1, Duration::from_secs(2), &hashes[2..3])
+        .await
+        .unwrap();
+    let result = stream.next().await.unwrap().unwrap();
+    assert_eq!(result, (hashes[2], CheckOutcome::Success(1)));
+
+    let r = tokio::time::timeout(Duration::from_millis(50), stream.next()).await;
+    assert!(r.is_err());
+}
+
 create_test!(subscribe_blocks);
 
 async fn subscribe_blocks<S: Storage + Clone + Send + Sync + 'static>(storage: S) {
@@ -4,11 +4,11 @@
 //! Provides a transactional layer on top of a state storage.
 
 use essential_state_read_vm::StateRead;
-use essential_storage::{key_range, QueryState, StateStorage};
+use essential_storage::{key_in_range, key_range, QueryState, StateStorage};
 use essential_types::{ContentAddress, Key, Value, Word};
 use futures::future::FutureExt;
 use imbl::HashMap;
-use std::{pin::Pin, sync::Arc};
+use std::{collections::BTreeMap, pin::Pin, sync::Arc};
 use thiserror::Error;
 
 #[cfg(test)]
@@ -220,6 +220,82 @@ impl<S> TransactionStorage<S> {
             None => self.storage.query_state(address, key).await,
         }
     }
+
+    /// Query many keys of this transaction.
+    ///
+    /// Keys that aren't mutated in this transaction are read from the storage in one batch.
+    pub async fn query_state_batch(
+        &self,
+        address: &ContentAddress,
+        keys: &[Key],
+    ) -> anyhow::Result<Vec<Vec<Word>>>
+    where
+        S: QueryState + Sync,
+    {
+        let mutations = self.state.get(address);
+        let mutation = |key: &Key| mutations.and_then(|m| m.get(key));
+        let unmutated: Vec<_> = keys
+            .iter()
+            .filter(|key| mutation(key).is_none())
+            .cloned()
+            .collect();
+        let mut stored = self
+            .storage
+            .query_state_batch(address, &unmutated)
+            .await?
+            .into_iter();
+        keys.iter()
+            .map(|key| match mutation(key) {
+                Some(Mutation::Insert(v)) => Ok(v.clone()),
+                Some(Mutation::Delete) => Ok(Vec::new()),
+                None => stored
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Missing value from storage")),
+            })
+            .collect()
+    }
+
+    /// Query a range of keys of this transaction.
+    ///
+    /// Mutations in this transaction take precedence over the storage.
+    pub async fn query_state_range(
+        &self,
+        address: &ContentAddress,
+        start_key: &Key,
+        end_key: Option<&Key>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<(Key, Vec<Word>)>>
+    where
+        S: QueryState,
+    {
+        let mutations: Vec<_> = self
+            .state
+            .get(address)
+            .into_iter()
+            .flatten()
+            .filter(|(key, _)| key_in_range(key, start_key, end_key))
+            .collect();
+
+        // Each mutation can hide at most one key from the storage.
+        let stored = self
+            .storage
+            .query_state_range(
+                address,
+                start_key,
+                end_key,
+                limit.saturating_add(mutations.len()),
+            )
+            .await?;
+
+        let mut merged: BTreeMap<_, _> = stored.into_iter().collect();
+        for (key, mutation) in mutations {
+            match mutation {
+                Mutation::Insert(v) => merged.insert(key.clone(), v.clone()),
+                Mutation::Delete => merged.remove(key),
+            };
+        }
+        Ok(merged.into_iter().take(limit).collect())
+    }
 }
 
 impl<S> QueryState for TransactionView<S>
@@ -229,4 +305,24 @@ where
     async fn query_state(&self, address: &ContentAddress, key: &Key) -> anyhow::Result<Vec<Word>> {
         self.0.query_state(address, key).await
     }
+
+    async fn query_state_batch(
+        &self,
+        address: &ContentAddress,
+        keys: &[Key],
+    ) -> anyhow::Result<Vec<Vec<Word>>> {
+        self.0.query_state_batch(address, keys).await
+    }
+
+    async fn query_state_range(
+        &self,
+        address: &ContentAddress,
+        start_key: &Key,
+        end_key: Option<&Key>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<(Key, Vec<Word>)>> {
+        self.0
+            .query_state_range(address, start_key, end_key, limit)
+            .await
+    }
 }
@@ -89,3 +89,105 @@ async fn test_can_query() {
     let r = storage.storage.query_state(&address, &key).await.unwrap();
     assert_eq!(r, vec![4]);
 }
+
+#[tokio::test]
+async fn test_query_state_range() {
+    let storage = MemoryStorage::new();
+    let predicate = Predicate::empty();
+    let address = essential_hash::contract_addr::from_contract(&vec![predicate.clone()].into());
+    let signed = sign_contract_with_random_keypair(vec![predicate]);
+    storage.insert_contract(signed).await.unwrap();
+    for i in 0..5 {
+        storage
+            .update_state(&address, &vec![i], vec![i])
+            .await
+            .unwrap();
+    }
+
+    let mut storage = storage.transaction();
+    storage.apply_state(&address, vec![0], vec![]);
+    storage.apply_state(&address, vec![1], vec![]);
+    storage.apply_state(&address, vec![2], vec![20]);
+    storage.apply_state(&address, vec![2, 0], vec![21]);
+    storage.apply_state(&address, vec![9], vec![9]);
+
+    // Deleted keys don't use up the limit.
+    let r = storage
+        .query_state_range(&address, &vec![], None, 3)
+        .await
+        .unwrap();
+    assert_eq!(
+        r,
+        vec![
+            (vec![2], vec![20]),
+            (vec![2, 0], vec![21]),
+            (vec![3], vec![3])
+        ]
+    );
+
+    let r = storage
+        .query_state_range(&address, &vec![2, 0], Some(&vec![9]), 10)
+        .await
+        .unwrap();
+    assert_eq!(
+        r,
+        vec![
+            (vec![2, 0], vec![21]),
+            (vec![3], vec![3]),
+            (vec![4], vec![4])
+        ]
+    );
+
+    let r = storage
+        .view()
+        .query_state_prefix(&address, &vec![2], 10)
+        .await
+        .unwrap();
+    assert_eq!(r, vec![(vec![2], vec![20]), (vec![2, 0], vec![21])]);
+
+    // The storage is unchanged.
+    let r = storage
+        .storage
+        .query_state_range(&address, &vec![], None, 10)
+        .await
+        .unwrap();
+    assert_eq!(r, (0..5).map(|i| (vec![i], vec![i])).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn test_query_state_batch() {
+    let storage = MemoryStorage::new();
+    let predicate = Predicate::empty();
+    let address = essential_hash::contract_addr::from_contract(&vec![predicate.clone()].into());
+    let signed = sign_contract_with_random_keypair(vec![predicate]);
+    storage.insert_contract(signed).await.unwrap();
+    for i in 0..3 {
+        storage
+            .update_state(&address, &vec![i], vec![i])
+            .await
+            .unwrap();
+    }
+
+    let mut storage = storage.transaction();
+    storage.apply_state(&address, vec![0], vec![]);
+    storage.apply_state(&address, vec![2], vec![20]);
+    storage.apply_state(&address, vec![3], vec![30]);
+
+    let keys: Vec<_> = (0..5).map(|i| vec![i]).collect();
+    let r = storage.query_state_batch(&address, &keys).await.unwrap();
+    assert_eq!(r, vec![vec![], vec![1], vec![20], vec![30], vec![]]);
+
+    let r = storage
+        .view()
+        .query_state_batch(&address, &keys)
+        .await
+        .unwrap();
+    assert_eq!(r, vec![vec![], vec![1], vec![20], vec![30], vec![]]);
+
+    let r = storage
+        .storage
+        .query_state_batch(&address, &keys)
+        .await
+        .unwrap();
+    assert_eq!(r, vec![vec![0], vec![1], vec![2], vec![], vec![]]);
+}
@@ -2,13 +2,13 @@
 
 //! # Types for interacting with the Essential Server.
 
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, time::Duration};
 
 use essential_types::{
     contract::Contract,
     predicate::Predicate,
     solution::{Solution, SolutionData, SolutionDataIndex},
-    ContentAddress, Key, PredicateAddress, StateReadBytecode, Value,
+    ContentAddress, Hash, Key, PredicateAddress, StateReadBytecode, Value,
 };
 
 const ZEROED_PREDICATE: PredicateAddress = PredicateAddress {
@@ -17,6 +17,9 @@ const ZEROED_PREDICATE: PredicateAddress = PredicateAddress {
 };
 
 pub mod ser;
+pub mod state_root;
+
+pub use state_root::{verify_state_proof, StateProof};
 
 /// Utility and gas used as a result of checking a solution's state transitions.
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
@@ -38,6 +41,177 @@ pub enum SolutionOutcome {
     Fail(String),
 }
 
+/// A new outcome for one of many subscribed solutions.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct SolutionOutcomeUpdate {
+    /// The content address of the solution.
+    pub solution: ContentAddress,
+    /// The new outcome of the solution.
+    pub outcome: SolutionOutcome,
+}
+
+/// The chain was reverted to the end of a block.
+///
+/// Blocks after it that were already received are no longer part of the chain.
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+pub struct Reorg {
+    /// The number of the last block that was kept.
+    pub block_number: u64,
+}
+
+/// The JSON body of an error response from the server.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+pub struct ErrorResponse {
+    /// The kind of failure.
+    pub code: ErrorCode,
+    /// Description of the failure.
+    pub message: String,
+    /// The underlying causes of the failure, outermost first.
+    pub details: Vec<String>,
+    /// Why a submitted solution was rejected, if that's what failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rejection: Option<SolutionRejection>,
+}
+
+/// Why a solution was rejected when it was submitted
+/// rather than being added to the pool.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SolutionRejection {
+    /// The solution is already in the pool.
+    Duplicate,
+    /// The solution mutates a key that a solution in the pool also mutates.
+    ConflictingMutation {
+        /// The content address of the solution in the pool.
+        solution: ContentAddress,
+        /// The contract the key belongs to.
+        contract: ContentAddress,
+        /// The key both solutions mutate.
+        key: Key,
+    },
+    /// The solution fails when checked against the current state
+    /// with the mutations of the pool applied.
+    Failed(String),
+}
+
+/// The kind of failure in an [`ErrorResponse`].
+///
+/// Each kind is returned with its own HTTP status code.
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The request was malformed, for example an address that isn't valid hex (400).
+    BadRequest,
+    /// Something the request refers to doesn't exist (404).
+    NotFound,
+    /// The request conflicts with the current state of the server (409).
+    Conflict,
+    /// The request is for an admin route and isn't authenticated (401).
+    Unauthorized,
+    /// The request was well formed but failed validation,
+    /// for example a bad signature or failed constraints (422).
+    Invalid,
+    /// The server's storage is unavailable (503).
+    Unavailable,
+    /// Any other failure (500).
+    Internal,
+}
+
+impl std::fmt::Display for SolutionRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SolutionRejection::Duplicate => write!(f, "Solution is already in the pool"),
+            SolutionRejection::ConflictingMutation {
+                solution,
+                contract,
+                key,
+            } => write!(
+                f,
+                "Solution mutates key {key:?} of contract {contract} which pending solution {solution} also mutates"
+            ),
+            SolutionRejection::Failed(reason) => write!(f, "Solution failed: {reason}"),
+        }
+    }
+}
+
+/// Whether the server is ready to serve requests and build blocks.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+pub struct Readiness {
+    /// Whether the storage answered and the main loop is running if there is one.
+    pub ready: bool,
+    /// Whether the storage answered.
+    pub storage: bool,
+    /// Whether the main loop that builds blocks is running.
+    /// `None` if the server isn't building blocks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub main_loop_running: Option<bool>,
+    /// The number of the latest block.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_block_number: Option<u64>,
+    /// When the latest block was built in seconds since the unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_block_time: Option<u64>,
+    /// The number of solutions waiting in the pool.
+    /// `None` if the storage didn't answer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pool_size: Option<usize>,
+    /// The status of the main loop.
+    /// `None` if the server isn't building blocks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub main_loop: Option<MainLoopStatus>,
+}
+
+/// How the main loop that builds blocks is doing.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+pub struct MainLoopStatus {
+    /// The number of times in a row building a block has failed.
+    pub consecutive_failures: usize,
+    /// The error from the last time building a block failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// When building a block last failed since the unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_failure: Option<Duration>,
+    /// When building a block last succeeded since the unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_success: Option<Duration>,
+    /// How long the main loop is waiting before trying again.
+    /// `None` if the last attempt succeeded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backoff: Option<Duration>,
+}
+
+/// What the signature of a signed admin request is over.
+///
+/// The request is signed by signing the hash of this
+/// and sending the signature with the timestamp in the request's headers.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+pub struct AdminRequest {
+    /// The HTTP method, for example `POST`.
+    pub method: String,
+    /// The path and query of the request, for example `/admin/pause`.
+    pub path: String,
+    /// When the request was signed in seconds since the unix epoch.
+    pub timestamp: u64,
+    /// The body of the request.
+    pub body: Vec<u8>,
+}
+
+/// The value of a key at a block with a proof against the block's state root.
+///
+/// Check it with [`verify_state_proof`].
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+pub struct QueryStateProof {
+    /// The block the value is proven at.
+    pub block_number: u64,
+    /// The state root of the block.
+    pub state_root: Hash,
+    /// The value of the key. Empty if the key has no value.
+    pub value: Value,
+    /// The proof of the value against the state root.
+    pub proof: StateProof,
+}
+
 /// Solution with contract read from storage that will be used for checking.
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct CheckSolution {
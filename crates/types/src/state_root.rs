@@ -0,0 +1,343 @@
+//! A sparse Merkle tree commitment over contract state.
+//!
+//! Each `(ContentAddress, Key) -> Value` pair is a leaf at the 256 bit path
+//! `sha256(address ++ key)`. A subtree that contains a single leaf is
+//! represented by that leaf and an empty subtree is represented by [`EMPTY`],
+//! so the tree only ever holds as many nodes as it needs to tell the leaves apart.
+//!
+//! - `leaf = sha256(0x00 ++ path ++ sha256(value))`
+//! - `node = sha256(0x01 ++ left ++ right)`
+//!
+//! Empty values are treated as missing state and are not part of the tree.
+//!
+//! A [`StateProof`] proves the value of a single key, or that it has no value,
+//! and can be checked against a root with [`verify_state_proof`].
+//!
+//! [`StateTree`] keeps the tree in memory so it can be updated one key at a time.
+
+use std::sync::Arc;
+
+use essential_types::{convert::bytes_from_word, ContentAddress, Hash, Key, Value};
+
+#[cfg(test)]
+mod tests;
+
+/// The root of a tree with no leaves.
+pub const EMPTY: Hash = [0; 32];
+
+/// A leaf as its path and the hash of its value.
+pub type Leaf = (Hash, Hash);
+
+/// A proof of the value of a key against a state root.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct StateProof {
+    /// The hashes of the siblings on the path from the root
+    /// down to the subtree that holds the key, root first.
+    pub siblings: Vec<Hash>,
+    /// The leaf in the key's place when the key has no value.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub leaf: Option<Leaf>,
+}
+
+/// A sparse Merkle tree that is updated in place.
+///
+/// Updating a key only rehashes the nodes on its path.
+/// Nodes are shared between clones so a clone is cheap and
+/// only the nodes that are updated afterwards are copied.
+#[derive(Debug, Clone, Default)]
+pub struct StateTree(Arc<Node>);
+
+#[derive(Debug, Clone, Default)]
+enum Node {
+    #[default]
+    Empty,
+    Leaf(Leaf),
+    /// A subtree with at least two leaves.
+    Branch {
+        hash: Hash,
+        left: Arc<Node>,
+        right: Arc<Node>,
+    },
+}
+
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+/// The path of a leaf in the tree.
+pub fn leaf_path(address: &ContentAddress, key: &Key) -> Hash {
+    let key: Vec<u8> = key.iter().copied().flat_map(bytes_from_word).collect();
+    essential_hash::hash_bytes_iter([&address.0[..], &key[..]])
+}
+
+/// The hash of the leaf at `path` with the value hash `value_hash`.
+pub fn leaf_hash(path: &Hash, value_hash: &Hash) -> Hash {
+    essential_hash::hash_bytes_iter([&[LEAF_PREFIX][..], &path[..], &value_hash[..]])
+}
+
+/// The hash of a node from the hashes of its children.
+pub fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    essential_hash::hash_bytes_iter([&[NODE_PREFIX][..], &left[..], &right[..]])
+}
+
+/// The leaf for a state value.
+pub fn leaf(address: &ContentAddress, key: &Key, value: &Value) -> Leaf {
+    (leaf_path(address, key), essential_hash::hash_words(value))
+}
+
+/// Compute the state root of the given state.
+pub fn state_root<'a>(
+    state: impl IntoIterator<Item = (&'a ContentAddress, &'a Key, &'a Value)>,
+) -> Hash {
+    subtree_root(&leaves(state), 0)
+}
+
+/// Prove the value of `key` at `address` in the given state.
+///
+/// The proof is against the [`state_root`] of the same state.
+pub fn state_proof<'a>(
+    state: impl IntoIterator<Item = (&'a ContentAddress, &'a Key, &'a Value)>,
+    address: &ContentAddress,
+    key: &Key,
+) -> StateProof {
+    let leaves = leaves(state);
+    let path = leaf_path(address, key);
+    let mut siblings = Vec::new();
+    let mut subtree = &leaves[..];
+    while subtree.len() > 1 {
+        let depth = siblings.len();
+        let (left, right) = split(subtree, depth);
+        let sibling;
+        (subtree, sibling) = if bit(&path, depth) {
+            (right, left)
+        } else {
+            (left, right)
+        };
+        siblings.push(subtree_root(sibling, depth + 1));
+    }
+    let leaf = match subtree {
+        [leaf] if leaf.0 != path => Some(*leaf),
+        _ => None,
+    };
+    StateProof { siblings, leaf }
+}
+
+/// Check that `key` at `address` has `value` in the state with the given `root`.
+///
+/// An empty `value` checks that the key has no value.
+pub fn verify_state_proof(
+    root: &Hash,
+    address: &ContentAddress,
+    key: &Key,
+    value: &Value,
+    proof: &StateProof,
+) -> bool {
+    let path = leaf_path(address, key);
+    let depth = proof.siblings.len();
+    if depth > 256 {
+        return false;
+    }
+    let mut hash = match (value.is_empty(), &proof.leaf) {
+        (false, None) => leaf_hash(&path, &essential_hash::hash_words(value)),
+        (true, None) => EMPTY,
+        // The other leaf must be in the key's place but not at its path.
+        (true, Some((other, value_hash))) => {
+            if *other == path || (0..depth).any(|d| bit(other, d) != bit(&path, d)) {
+                return false;
+            }
+            leaf_hash(other, value_hash)
+        }
+        (false, Some(_)) => return false,
+    };
+    for (d, sibling) in proof.siblings.iter().enumerate().rev() {
+        hash = if bit(&path, d) {
+            node_hash(sibling, &hash)
+        } else {
+            node_hash(&hash, sibling)
+        };
+    }
+    hash == *root
+}
+
+/// The leaves of the state sorted by path.
+fn leaves<'a>(
+    state: impl IntoIterator<Item = (&'a ContentAddress, &'a Key, &'a Value)>,
+) -> Vec<Leaf> {
+    let mut leaves: Vec<_> = state
+        .into_iter()
+        .filter(|(_, _, value)| !value.is_empty())
+        .map(|(address, key, value)| leaf(address, key, value))
+        .collect();
+    leaves.sort_unstable();
+    // A repeated key would never split so only keep one.
+    leaves.dedup_by_key(|(path, _)| *path);
+    leaves
+}
+
+impl StateTree {
+    /// An empty tree.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build the tree of the given state.
+    pub fn from_state<'a>(
+        state: impl IntoIterator<Item = (&'a ContentAddress, &'a Key, &'a Value)>,
+    ) -> Self {
+        let mut tree = Self::new();
+        for (address, key, value) in state {
+            tree.update(address, key, value);
+        }
+        tree
+    }
+
+    /// Set the value of `key` at `address`.
+    ///
+    /// An empty `value` removes the key.
+    pub fn update(&mut self, address: &ContentAddress, key: &Key, value: &Value) {
+        if value.is_empty() {
+            remove(&mut self.0, 0, &leaf_path(address, key));
+        } else {
+            insert(&mut self.0, 0, leaf(address, key, value));
+        }
+    }
+
+    /// The state root of the tree.
+    pub fn root(&self) -> Hash {
+        self.0.hash()
+    }
+
+    /// Prove the value of `key` at `address` against the [`root`](Self::root).
+    pub fn proof(&self, address: &ContentAddress, key: &Key) -> StateProof {
+        let path = leaf_path(address, key);
+        let mut siblings = Vec::new();
+        let mut node = &self.0;
+        while let Node::Branch { left, right, .. } = &**node {
+            let sibling;
+            (node, sibling) = if bit(&path, siblings.len()) {
+                (right, left)
+            } else {
+                (left, right)
+            };
+            siblings.push(sibling.hash());
+        }
+        let leaf = match &**node {
+            Node::Leaf(leaf) if leaf.0 != path => Some(*leaf),
+            _ => None,
+        };
+        StateProof { siblings, leaf }
+    }
+}
+
+impl Node {
+    fn hash(&self) -> Hash {
+        match self {
+            Node::Empty => EMPTY,
+            Node::Leaf((path, value_hash)) => leaf_hash(path, value_hash),
+            Node::Branch { hash, .. } => *hash,
+        }
+    }
+
+    fn branch(left: Arc<Node>, right: Arc<Node>) -> Self {
+        Node::Branch {
+            hash: node_hash(&left.hash(), &right.hash()),
+            left,
+            right,
+        }
+    }
+}
+
+/// Insert a leaf into the subtree at `depth`.
+fn insert(node: &mut Arc<Node>, depth: usize, new: Leaf) {
+    match Arc::make_mut(node) {
+        n @ Node::Empty => *n = Node::Leaf(new),
+        Node::Leaf(old) if old.0 == new.0 => *old = new,
+        n @ Node::Leaf(_) => {
+            let Node::Leaf(old) = std::mem::take(n) else {
+                unreachable!()
+            };
+            *n = join(old, new, depth);
+        }
+        Node::Branch { hash, left, right } => {
+            let child = if bit(&new.0, depth) {
+                &mut *right
+            } else {
+                &mut *left
+            };
+            insert(child, depth + 1, new);
+            *hash = node_hash(&left.hash(), &right.hash());
+        }
+    }
+}
+
+/// The subtree at `depth` that holds two leaves with different paths.
+fn join(a: Leaf, b: Leaf, depth: usize) -> Node {
+    let (a_bit, b_bit) = (bit(&a.0, depth), bit(&b.0, depth));
+    let (left, right) = match (a_bit, b_bit) {
+        (false, true) => (Node::Leaf(a), Node::Leaf(b)),
+        (true, false) => (Node::Leaf(b), Node::Leaf(a)),
+        (false, false) => (join(a, b, depth + 1), Node::Empty),
+        (true, true) => (Node::Empty, join(a, b, depth + 1)),
+    };
+    Node::branch(Arc::new(left), Arc::new(right))
+}
+
+/// Remove the leaf at `path` from the subtree at `depth`.
+fn remove(node: &mut Arc<Node>, depth: usize, path: &Hash) {
+    match &**node {
+        Node::Empty => return,
+        Node::Leaf(leaf) if leaf.0 != *path => return,
+        Node::Leaf(_) => {
+            *node = Arc::new(Node::Empty);
+            return;
+        }
+        Node::Branch { .. } => (),
+    }
+    let Node::Branch { hash, left, right } = Arc::make_mut(node) else {
+        unreachable!()
+    };
+    let child = if bit(path, depth) {
+        &mut *right
+    } else {
+        &mut *left
+    };
+    remove(child, depth + 1, path);
+    // A subtree with a single leaf is the leaf.
+    let collapsed = match (&**left, &**right) {
+        (Node::Empty, Node::Empty) => Some(Node::Empty),
+        (Node::Leaf(leaf), Node::Empty) | (Node::Empty, Node::Leaf(leaf)) => {
+            Some(Node::Leaf(*leaf))
+        }
+        _ => None,
+    };
+    match collapsed {
+        Some(collapsed) => *node = Arc::new(collapsed),
+        None => *hash = node_hash(&left.hash(), &right.hash()),
+    }
+}
+
+/// Whether the bit at `depth` of the path is set.
+/// Zero is the most significant bit.
+pub(crate) fn bit(path: &Hash, depth: usize) -> bool {
+    path[depth / 8] & (0x80 >> (depth % 8)) != 0
+}
+
+/// Split leaves that are sorted by path into the left and right subtrees at `depth`.
+pub(crate) fn split(leaves: &[Leaf], depth: usize) -> (&[Leaf], &[Leaf]) {
+    let mid = leaves.partition_point(|(path, _)| !bit(path, depth));
+    leaves.split_at(mid)
+}
+
+/// The root of the subtree at `depth` that holds `leaves`, which are sorted by path.
+pub(crate) fn subtree_root(leaves: &[Leaf], depth: usize) -> Hash {
+    match leaves {
+        [] => EMPTY,
+        [(path, value_hash)] => leaf_hash(path, value_hash),
+        _ => {
+            let (left, right) = split(leaves, depth);
+            node_hash(
+                &subtree_root(left, depth + 1),
+                &subtree_root(right, depth + 1),
+            )
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use super::*;
+
+fn state(n: u8) -> Vec<(ContentAddress, Key, Value)> {
+    (0..n)
+        .map(|i| {
+            (
+                ContentAddress([i % 3; 32]),
+                vec![i as i64],
+                vec![i as i64 + 1],
+            )
+        })
+        .collect()
+}
+
+fn root_of(state: &[(ContentAddress, Key, Value)]) -> Hash {
+    state_root(state.iter().map(|(a, k, v)| (a, k, v)))
+}
+
+#[test]
+fn test_empty() {
+    assert_eq!(root_of(&[]), EMPTY);
+}
+
+#[test]
+fn test_single_leaf() {
+    let s = state(1);
+    let (path, value_hash) = leaf(&s[0].0, &s[0].1, &s[0].2);
+    assert_eq!(root_of(&s), leaf_hash(&path, &value_hash));
+}
+
+#[test]
+fn test_two_leaves() {
+    let s = state(2);
+    let a = leaf(&s[0].0, &s[0].1, &s[0].2);
+    let b = leaf(&s[1].0, &s[1].1, &s[1].2);
+    let (left, right) = if a.0 < b.0 { (a, b) } else { (b, a) };
+
+    // Walk down the shared prefix.
+    let depth = (0..256)
+        .find(|d| bit(&left.0, *d) != bit(&right.0, *d))
+        .unwrap();
+    let mut expected = node_hash(&leaf_hash(&left.0, &left.1), &leaf_hash(&right.0, &right.1));
+    for d in (0..depth).rev() {
+        expected = if bit(&left.0, d) {
+            node_hash(&EMPTY, &expected)
+        } else {
+            node_hash(&expected, &EMPTY)
+        };
+    }
+    assert_eq!(root_of(&s), expected);
+}
+
+#[test]
+fn test_order_independent() {
+    let s = state(50);
+    let mut r = s.clone();
+    r.reverse();
+    assert_eq!(root_of(&s), root_of(&r));
+}
+
+#[test]
+fn test_value_changes_root() {
+    let s = state(50);
+    let mut changed = s.clone();
+    changed[20].2 = vec![100];
+    assert_ne!(root_of(&s), root_of(&changed));
+
+    let mut removed = s.clone();
+    removed.remove(20);
+    assert_ne!(root_of(&s), root_of(&removed));
+}
+
+#[test]
+fn test_empty_values_ignored() {
+    let s = state(10);
+    let mut with_empty = s.clone();
+    with_empty.push((ContentAddress([9; 32]), vec![1], vec![]));
+    assert_eq!(root_of(&s), root_of(&with_empty));
+}
+
+fn proof_of(
+    state: &[(ContentAddress, Key, Value)],
+    address: &ContentAddress,
+    key: &Key,
+) -> StateProof {
+    state_proof(state.iter().map(|(a, k, v)| (a, k, v)), address, key)
+}
+
+#[test]
+fn test_inclusion_proofs() {
+    let s = state(50);
+    let root = root_of(&s);
+    for (address, key, value) in &s {
+        let proof = proof_of(&s, address, key);
+        assert!(proof.leaf.is_none());
+        assert!(verify_state_proof(&root, address, key, value, &proof));
+
+        // The wrong value or a missing value doesn't verify.
+        assert!(!verify_state_proof(&root, address, key, &vec![100], &proof));
+        assert!(!verify_state_proof(&root, address, key, &vec![], &proof));
+    }
+}
+
+#[test]
+fn test_non_inclusion_proofs() {
+    let s = state(50);
+    let root = root_of(&s);
+    let mut other_leaves = 0;
+    for i in 50..100 {
+        let address = ContentAddress([i as u8 % 3; 32]);
+        let key = vec![i];
+        let proof = proof_of(&s, &address, &key);
+        other_leaves += proof.leaf.is_some() as usize;
+        assert!(verify_state_proof(&root, &address, &key, &vec![], &proof));
+        assert!(!verify_state_proof(&root, &address, &key, &vec![1], &proof));
+    }
+    // Both kinds of non-inclusion are covered.
+    assert!(other_leaves > 0 && other_leaves < 50);
+
+    // Anything proves nothing in an empty state.
+    let proof = proof_of(&[], &s[0].0, &s[0].1);
+    assert_eq!(proof.siblings, Vec::<Hash>::new());
+    assert!(verify_state_proof(
+        &EMPTY,
+        &s[0].0,
+        &s[0].1,
+        &vec![],
+        &proof
+    ));
+}
+
+#[test]
+fn test_tampered_proofs() {
+    let s = state(50);
+    let root = root_of(&s);
+    let (address, key, value) = &s[10];
+    let proof = proof_of(&s, address, key);
+
+    let mut wrong_sibling = proof.clone();
+    wrong_sibling.siblings[0][0] ^= 1;
+    assert!(!verify_state_proof(
+        &root,
+        address,
+        key,
+        value,
+        &wrong_sibling
+    ));
+
+    let mut missing_sibling = proof.clone();
+    missing_sibling.siblings.pop();
+    assert!(!verify_state_proof(
+        &root,
+        address,
+        key,
+        value,
+        &missing_sibling
+    ));
+
+    // Another key's leaf can't stand in for this key.
+    let (other_address, other_key, _) = &s[11];
+    let other_proof = proof_of(&s, other_address, other_key);
+    let mut claimed = other_proof.clone();
+    claimed.leaf = Some(leaf(other_address, other_key, &s[11].2));
+    assert!(!verify_state_proof(&root, address, key, &vec![], &claimed));
+
+    // Proofs are checked against the root.
+    assert!(!verify_state_proof(&EMPTY, address, key, value, &proof));
+}
+
+#[test]
+fn test_proof_serde() {
+    let s = state(10);
+    let proof = proof_of(&s, &s[0].0, &s[0].1);
+    let json = serde_json::to_string(&proof).unwrap();
+    assert!(!json.contains("leaf"));
+    assert_eq!(serde_json::from_str::<StateProof>(&json).unwrap(), proof);
+
+    let proof = proof_of(&s, &ContentAddress([7; 32]), &vec![1]);
+    let json = serde_json::to_string(&proof).unwrap();
+    assert_eq!(serde_json::from_str::<StateProof>(&json).unwrap(), proof);
+}
+
+fn tree_of(state: &[(ContentAddress, Key, Value)]) -> StateTree {
+    StateTree::from_state(state.iter().map(|(a, k, v)| (a, k, v)))
+}
+
+#[test]
+fn test_tree_matches_root() {
+    assert_eq!(StateTree::new().root(), EMPTY);
+    for n in [1, 2, 3, 50] {
+        let s = state(n);
+        assert_eq!(tree_of(&s).root(), root_of(&s));
+    }
+}
+
+#[test]
+fn test_tree_updates() {
+    let mut s = state(50);
+    let mut tree = tree_of(&s);
+
+    // Change a value.
+    s[20].2 = vec![100];
+    tree.update(&s[20].0, &s[20].1, &s[20].2);
+    assert_eq!(tree.root(), root_of(&s));
+
+    // Remove keys until the tree is empty.
+    let before = tree.clone();
+    while let Some((address, key, _)) = s.pop() {
+        tree.update(&address, &key, &vec![]);
+        assert_eq!(tree.root(), root_of(&s));
+    }
+    assert_eq!(tree.root(), EMPTY);
+
+    // Removing a missing key changes nothing.
+    tree.update(&ContentAddress([1; 32]), &vec![1], &vec![]);
+    assert_eq!(tree.root(), EMPTY);
+
+    // Clones aren't changed by updates.
+    let mut s = state(50);
+    s[20].2 = vec![100];
+    assert_eq!(before.root(), root_of(&s));
+}
+
+#[test]
+fn test_tree_proofs() {
+    let s = state(50);
+    let tree = tree_of(&s);
+    for (address, key, _) in &s {
+        assert_eq!(tree.proof(address, key), proof_of(&s, address, key));
+    }
+    for i in 50..100 {
+        let address = ContentAddress([i as u8 % 3; 32]);
+        let key = vec![i];
+        assert_eq!(tree.proof(&address, &key), proof_of(&s, &address, &key));
+    }
+    assert_eq!(
+        StateTree::new().proof(&s[0].0, &s[0].1),
+        proof_of(&[], &s[0].0, &s[0].1)
+    );
+}